@@ -0,0 +1,9 @@
+// Generate the gRPC server from proto/geomatch.proto, using a bundled protoc so one doesn't
+// need to be installed. Clients are generated by the services calling it
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&["proto/geomatch.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::error::Error;
+use std::fs::{rename, File};
+use std::io::{Read, Write};
+
+// Outputs are written under a temporary name next to where they go and renamed into place once
+// complete, so a crash or a full disk never leaves a truncated file that looks finished. The
+// rename also swaps out an earlier output in one step
+pub fn temp_path(path: &str) -> String {
+    format!("{}.tmp", path)
+}
+
+// Fetches and stopped matches keep their rows under this name instead, since `fetch resume`
+// carries on from what's there rather than it being thrown away
+pub fn partial_path(path: &str) -> String {
+    format!("{}.partial", path)
+}
+
+// Open the temporary file an output is written to until finish
+pub fn create(path: &str) -> std::io::Result<File> {
+    File::create(temp_path(path))
+}
+
+// Make sure everything written reached the disk, then move the file into place
+pub fn finish(file: File, path: &str) -> std::io::Result<()> {
+    finish_from(file, &temp_path(path), path)
+}
+
+fn finish_from(file: File, temp: &str, path: &str) -> std::io::Result<()> {
+    file.sync_all()?;
+    rename(temp, path)
+}
+
+// Move an output written straight to its temporary name, like a database, into place
+pub fn finish_path(path: &str) -> std::io::Result<()> {
+    rename(temp_path(path), path)
+}
+
+// Write a whole output at once
+pub fn write(path: &str, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let mut file = create(path)?;
+    file.write_all(contents.as_ref())?;
+    finish(file, path)
+}
+
+// How an output is compressed as it's written
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    // gzip or zstd, None for off
+    pub fn parse(name: &str) -> Result<Option<Compression>, Box<dyn Error>> {
+        match name.to_lowercase().as_str() {
+            "gzip" | "gz" => Ok(Some(Compression::Gzip)),
+            "zstd" | "zst" => Ok(Some(Compression::Zstd)),
+            "off" => Ok(None),
+            _ => Err("Compression must be gzip, zstd or off")?
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    // Added to the names of compressed outputs
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    // What a name ending in .gz or .zst is compressed with
+    pub fn from_path(path: &str) -> Option<Compression> {
+        [Compression::Gzip, Compression::Zstd].iter().copied().find(|e| path.ends_with(e.extension()))
+    }
+}
+
+// An output written through create_output, compressed or not
+pub enum Output {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+    Zstd(zstd::Encoder<'static, File>),
+}
+
+impl Output {
+    // Write what's left of the compressed stream, giving back the file
+    pub fn finish(self) -> std::io::Result<File> {
+        match self {
+            Output::Plain(file) => Ok(file),
+            Output::Gzip(gzip) => gzip.finish(),
+            Output::Zstd(zstd) => zstd.finish(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(file) => file.write(buf),
+            Output::Gzip(gzip) => gzip.write(buf),
+            Output::Zstd(zstd) => zstd.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(file) => file.flush(),
+            Output::Gzip(gzip) => gzip.flush(),
+            Output::Zstd(zstd) => zstd.flush(),
+        }
+    }
+}
+
+// Compress what's written to a file
+pub fn compress(file: File, compression: Option<Compression>) -> std::io::Result<Output> {
+    match compression {
+        Some(Compression::Gzip) => Ok(Output::Gzip(GzEncoder::new(file, flate2::Compression::default()))),
+        Some(Compression::Zstd) => Ok(Output::Zstd(zstd::Encoder::new(file, 0)?)),
+        None => Ok(Output::Plain(file)),
+    }
+}
+
+// Read back a file written through compress
+pub fn decompress(file: File, compression: Option<Compression>) -> std::io::Result<Box<dyn Read>> {
+    match compression {
+        Some(Compression::Gzip) => Ok(Box::new(MultiGzDecoder::new(file))),
+        Some(Compression::Zstd) => Ok(Box::new(zstd::Decoder::new(file)?)),
+        None => Ok(Box::new(file)),
+    }
+}
+
+// Open an output that is optionally compressed as it's written
+pub fn create_output(path: &str, compression: Option<Compression>) -> std::io::Result<Output> {
+    compress(create(path)?, compression)
+}
+
+pub fn finish_output(output: Output, path: &str) -> std::io::Result<()> {
+    finish(output.finish()?, path)
+}
+
+// Open the partial file an output is written to until finish_partial, compressed like the output
+pub fn create_partial(path: &str) -> std::io::Result<Output> {
+    compress(File::create(partial_path(path))?, Compression::from_path(path))
+}
+
+// Read back the partial file of an output
+pub fn open_partial(path: &str) -> std::io::Result<Box<dyn Read>> {
+    decompress(File::open(partial_path(path))?, Compression::from_path(path))
+}
+
+pub fn finish_partial(output: Output, path: &str) -> std::io::Result<()> {
+    finish_from(output.finish()?, &partial_path(path), path)
+}
+
+// Write a whole output at once, optionally compressed
+pub fn write_output(path: &str, contents: impl AsRef<[u8]>, compression: Option<Compression>) -> std::io::Result<()> {
+    let mut output = create_output(path, compression)?;
+    output.write_all(contents.as_ref())?;
+    finish_output(output, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::fs::{read, remove_file};
+
+    fn round_trip(compression: Compression, decode: fn(&[u8]) -> Vec<u8>) {
+        let path = std::env::temp_dir()
+            .join(format!("geomatch_atomic_{}_{}", std::process::id(), compression.name()))
+            .to_string_lossy()
+            .to_string();
+        let contents: Vec<u8> = (0..200_000).map(|e| format!("{}|row {}\n", e % 97, e)).collect::<String>().into_bytes();
+
+        write_output(&path, &contents, Some(compression)).unwrap();
+        let written = read(&path).unwrap();
+        let _ = remove_file(&path);
+
+        assert!(written.len() < contents.len());
+        assert_eq!(decode(&written), contents);
+    }
+
+    #[test]
+    fn gzip_reads_back_with_a_standard_decoder() {
+        round_trip(Compression::Gzip, |e| {
+            let mut decoded = Vec::new();
+            GzDecoder::new(e).read_to_end(&mut decoded).unwrap();
+            decoded
+        });
+    }
+
+    #[test]
+    fn zstd_reads_back_with_a_standard_decoder() {
+        round_trip(Compression::Zstd, |e| zstd::decode_all(e).unwrap());
+    }
+
+    #[test]
+    fn compression_follows_the_extension() {
+        assert_eq!(Compression::from_path("matches.csv.gz"), Some(Compression::Gzip));
+        assert_eq!(Compression::from_path("matches.csv.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_path("matches.csv"), None);
+    }
+}
@@ -0,0 +1,96 @@
+use keyring::Entry;
+use std::error::Error;
+use std::io::{self, stdin, stdout, IsTerminal, Write};
+
+// Keys are stored in the OS keyring under this service, one entry per provider
+const SERVICE: &str = "geomatch";
+
+// Providers a key can be stored for: google for geocoding and routing, w3w for what3words and
+// mapbox for the Mapbox access token
+pub const PROVIDERS: [&str; 3] = ["google", "w3w", "mapbox"];
+
+fn entry(provider: &str) -> Result<Entry, Box<dyn Error>> {
+    if !PROVIDERS.contains(&provider) {
+        return Err(format!("Unknown provider '{}', expected {}", provider, PROVIDERS.join(" or ")).into());
+    }
+    Ok(Entry::new(SERVICE, provider)?)
+}
+
+// Key stored for a provider, None if there isn't one or there's no keyring to look in
+pub fn stored_key(provider: &str) -> Option<String> {
+    entry(provider).ok()?.get_password().ok()
+}
+
+pub fn set_key(provider: &str, key: &str) -> Result<(), Box<dyn Error>> {
+    if key.trim().is_empty() {
+        return Err("No key given".into());
+    }
+    entry(provider)?.set_password(key.trim()).map_err(|e| format!("Couldn't store the key in the OS keyring: {}", e))?;
+    Ok(())
+}
+
+pub fn remove_key(provider: &str) -> Result<(), Box<dyn Error>> {
+    match entry(provider)?.delete_credential() {
+        Ok(_) => Ok(()),
+        Err(keyring::Error::NoEntry) => Err(format!("No {} key is stored", provider).into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Read a key without echoing it at a terminal, so it doesn't end up on screen. Piped in keys are
+// read as they are, e.g. from a secrets manager
+pub fn read_key(prompt: &str) -> io::Result<String> {
+    let mut key = String::new();
+    if !stdin().is_terminal() {
+        stdin().read_line(&mut key)?;
+        return Ok(key.trim().to_string());
+    }
+
+    print!("{}", prompt);
+    stdout().flush()?;
+    let echo = Echo::off();
+    let read = stdin().read_line(&mut key);
+    drop(echo);
+    println!();
+    read?;
+
+    Ok(key.trim().to_string())
+}
+
+// Turns terminal echo back on when dropped
+struct Echo {
+    #[cfg(unix)]
+    saved: Option<libc::termios>,
+}
+
+impl Echo {
+    #[cfg(unix)]
+    fn off() -> Echo {
+        unsafe {
+            let mut term: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut term) != 0 {
+                return Echo {saved: None};
+            }
+            let saved = term;
+            term.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term);
+            Echo {saved: Some(saved)}
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn off() -> Echo {
+        Echo {}
+    }
+}
+
+impl Drop for Echo {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(saved) = self.saved {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved);
+            }
+        }
+    }
+}
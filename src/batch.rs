@@ -0,0 +1,54 @@
+use arrow_array::RecordBatch;
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression as ParquetCompression, GzipLevel, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use std::error::Error;
+use std::sync::Arc;
+use super::atomic::{self, Compression};
+use super::state::MatchRun;
+
+// Arrow record batch of the rows written by a match run, with the same columns as
+// the match file. Dictionary encoded columns stay dictionary encoded
+pub fn match_batch(run: &MatchRun) -> Result<RecordBatch, Box<dyn Error>> {
+    let rows = run.written_rows();
+
+    let mut fields = Vec::new();
+    let mut arrays = Vec::new();
+    for (header, col) in run.output.output_headers().iter().zip(run.output.output_columns()) {
+        let array = col.to_arrow(&rows)?;
+        fields.push(Field::new(header, array.data_type().clone(), false));
+        arrays.push(array);
+    }
+
+    Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+}
+
+// Write the last match as an Arrow IPC file, compressed as a whole like the csv outputs
+pub fn write_ipc(path: &str, run: &MatchRun, compression: Option<Compression>) -> Result<(), Box<dyn Error>> {
+    let batch = match_batch(run)?;
+    let mut writer = FileWriter::try_new(atomic::create_output(path, compression)?, &batch.schema())?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    atomic::finish_output(writer.into_inner()?, path)?;
+
+    Ok(())
+}
+
+// Write the last match as a Parquet file. Parquet compresses its own pages, so the file keeps
+// its name and readers don't need to unpack it first
+pub fn write_parquet(path: &str, run: &MatchRun, compression: Option<Compression>) -> Result<(), Box<dyn Error>> {
+    let batch = match_batch(run)?;
+    let codec = match compression {
+        Some(Compression::Gzip) => ParquetCompression::GZIP(GzipLevel::default()),
+        Some(Compression::Zstd) => ParquetCompression::ZSTD(ZstdLevel::default()),
+        None => ParquetCompression::UNCOMPRESSED,
+    };
+    let properties = WriterProperties::builder().set_compression(codec).build();
+    let mut writer = ArrowWriter::try_new(atomic::create(path)?, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    atomic::finish(writer.into_inner()?, path)?;
+
+    Ok(())
+}
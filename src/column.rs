@@ -0,0 +1,492 @@
+use arrow_array::cast::AsArray;
+use arrow_array::types::UInt32Type;
+use arrow_array::{Array, ArrayRef, BooleanArray, DictionaryArray, StringArray, UInt32Array};
+use arrow_schema::ArrowError;
+use arrow_select::{concat::concat, filter::filter, take::take};
+use std::collections::HashMap;
+use std::io;
+use std::ops::Index;
+use std::sync::Arc;
+use super::spill::{Spill, SpillWriter};
+
+// Columns with at most this share of distinct values are dictionary encoded
+const DICTIONARY_RATIO: f64 = 0.5;
+
+// Too short to be worth the lookup table
+const DICTIONARY_MIN_ROWS: usize = 64;
+
+// Row of a spilled column standing for a blank value
+const BLANK: u32 = u32::MAX;
+
+// A column of text values. Once a file is read its columns are sealed into Arrow arrays, so
+// filtering, reordering and appending rows go through Arrow's kernels and the Arrow and Parquet
+// exports take the arrays as they are. Low-cardinality columns like state, city or status flags
+// are dictionary encoded, keeping each distinct value once and a small code per row. Columns
+// still being built a row at a time, like the match output, or changed a value at a time are
+// plain strings until they're sealed again. Columns too big to keep in memory are spilled to
+// disk, keeping only which value of the spill each row has
+#[derive(Clone, Debug)]
+pub enum Column {
+    Plain(Vec<String>),
+    Strings(StringArray),
+    Dictionary(Dictionary),
+    Spilled(Spilled),
+}
+
+// The two halves of an Arrow DictionaryArray, kept apart so reading a value doesn't go through
+// a downcast every time
+#[derive(Clone, Debug)]
+pub struct Dictionary {
+    keys: UInt32Array,
+    values: StringArray,
+}
+
+// Rows can share a spill, so the matches output refers to the values of the files it came from
+#[derive(Clone, Debug)]
+pub struct Spilled {
+    spill: Arc<Spill>,
+    rows: Vec<u32>,
+    extra: Vec<String>,     // values set since spilling, numbered on from the end of the spill
+}
+
+impl Spilled {
+    fn new(spill: Spill) -> Spilled {
+        Spilled {
+            rows: (0..spill.len() as u32).collect(),
+            spill: Arc::new(spill),
+            extra: Vec::new(),
+        }
+    }
+
+    fn get(&self, code: u32) -> &str {
+        match code as usize {
+            _ if code == BLANK => "",
+            code if code < self.spill.len() => self.spill.get(code),
+            code => &self.extra[code - self.spill.len()]
+        }
+    }
+
+    // Whether a code is blank or in the spill, which another column sharing it reads the same
+    fn shared(&self, code: u32) -> bool {
+        code == BLANK || (code as usize) < self.spill.len()
+    }
+
+    // Whether another value can be kept without running into BLANK
+    fn has_room(&self) -> bool {
+        self.spill.len() + self.extra.len() < BLANK as usize
+    }
+
+    // Code for a value set after spilling, kept in memory since it's only one value
+    fn code(&mut self, val: String) -> u32 {
+        if val.is_empty() {
+            return BLANK;
+        }
+        self.extra.push(val);
+        (self.spill.len() + self.extra.len() - 1) as u32
+    }
+}
+
+impl Default for Column {
+    fn default() -> Column {
+        Column::Plain(Vec::new())
+    }
+}
+
+impl From<Vec<String>> for Column {
+    fn from(values: Vec<String>) -> Column {
+        let mut col = Column::Plain(values);
+        col.seal();
+        col
+    }
+}
+
+impl Index<usize> for Column {
+    type Output = str;
+
+    fn index(&self, row: usize) -> &str {
+        self.get(row)
+    }
+}
+
+impl Column {
+    pub fn with_capacity(capacity: usize) -> Column {
+        Column::Plain(Vec::with_capacity(capacity))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Plain(values) => values.len(),
+            Column::Strings(array) => array.len(),
+            Column::Dictionary(dict) => dict.keys.len(),
+            Column::Spilled(spilled) => spilled.rows.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, row: usize) -> &str {
+        match self {
+            Column::Plain(values) => &values[row],
+            Column::Strings(array) => array.value(row),
+            Column::Dictionary(dict) => dict.values.value(dict.keys.value(row) as usize),
+            Column::Spilled(spilled) => spilled.get(spilled.rows[row]),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&str> {
+        (0..self.len()).map(move |row| self.get(row))
+    }
+
+    pub fn push(&mut self, val: String) {
+        match self {
+            Column::Plain(values) => values.push(val),
+            Column::Spilled(spilled) if spilled.has_room() => {
+                let code = spilled.code(val);
+                spilled.rows.push(code);
+            }
+            _ => {
+                self.load();
+                self.push(val);
+            }
+        }
+    }
+
+    pub fn set(&mut self, row: usize, val: String) {
+        match self {
+            Column::Plain(values) => values[row] = val,
+            // A value only this row has is replaced where it is, anything else gets a new one
+            Column::Spilled(spilled) if !spilled.shared(spilled.rows[row]) && !val.is_empty() => {
+                let index = spilled.rows[row] as usize - spilled.spill.len();
+                spilled.extra[index] = val;
+            }
+            Column::Spilled(spilled) if spilled.has_room() => spilled.rows[row] = spilled.code(val),
+            _ => {
+                self.load();
+                self.set(row, val);
+            }
+        }
+    }
+
+    // Add a row of another column, only noting which one it is if both share a spill
+    pub fn push_from(&mut self, source: &Column, row: usize) {
+        match (&mut *self, source) {
+            (Column::Spilled(spilled), Column::Spilled(other))
+                if Arc::ptr_eq(&spilled.spill, &other.spill) && other.shared(other.rows[row]) => {
+                spilled.rows.push(other.rows[row]);
+            }
+            _ => self.push(source.get(row).to_string()),
+        }
+    }
+
+    pub fn set_from(&mut self, row: usize, source: &Column, source_row: usize) {
+        match (&mut *self, source) {
+            (Column::Spilled(spilled), Column::Spilled(other))
+                if Arc::ptr_eq(&spilled.spill, &other.spill) && other.shared(other.rows[source_row]) => {
+                spilled.rows[row] = other.rows[source_row];
+            }
+            _ => self.set(row, source.get(source_row).to_string()),
+        }
+    }
+
+    pub fn remove(&mut self, row: usize) {
+        match self {
+            Column::Plain(values) => { values.remove(row); }
+            Column::Spilled(spilled) => { spilled.rows.remove(row); }
+            _ => {
+                let keep: Vec<bool> = (0..self.len()).map(|e| e != row).collect();
+                self.retain(&keep);
+            }
+        }
+    }
+
+    pub fn extend(&mut self, other: Column) {
+        if let Column::Spilled(spilled) = self {
+            // Two spills are joined into one on disk, so neither has to come back into memory
+            let separate = matches!(&other, Column::Spilled(other) if !Arc::ptr_eq(&spilled.spill, &other.spill));
+            if !separate {
+                for row in 0..other.len() {
+                    self.push_from(&other, row);
+                }
+                return;
+            }
+            match Spill::write(self.iter().chain(other.iter())) {
+                Ok(spill) => {
+                    *self = Column::Spilled(Spilled::new(spill));
+                    return;
+                }
+                Err(_) => self.load(),
+            }
+        }
+
+        // Two sealed columns of the same kind are joined by Arrow, anything else goes through
+        // strings and is sealed again after
+        if let (Some(array), Some(other_array)) = (self.array(), other.array()) {
+            if let Ok(joined) = concat(&[array.as_ref(), other_array.as_ref()]) {
+                *self = Column::from_arrow(joined);
+                return;
+            }
+        }
+
+        let sealed = self.is_sealed();
+        match (&mut *self, other) {
+            (Column::Plain(values), Column::Plain(other)) => values.extend(other),
+            (Column::Plain(values), other) => values.extend(other.iter().map(String::from)),
+            (_, other) => {
+                self.load();
+                self.extend(other);
+            }
+        }
+        if sealed {
+            self.seal();
+        }
+    }
+
+    // Replace every value, only calling f once per distinct value of a dictionary column
+    pub fn map_values<F: Fn(&str) -> String>(&mut self, f: F) {
+        match self {
+            Column::Plain(values) => {
+                for val in values.iter_mut() {
+                    *val = f(val);
+                }
+            }
+            Column::Strings(array) => {
+                *array = StringArray::from_iter_values(array.iter().map(|e| f(e.unwrap_or_default())));
+            }
+            // Values can come out the same after mapping, which a dictionary is fine with
+            Column::Dictionary(dict) => {
+                dict.values = StringArray::from_iter_values(dict.values.iter().map(|e| f(e.unwrap_or_default())));
+            }
+            Column::Spilled(_) => {
+                self.load();
+                self.map_values(f);
+                self.seal();
+            }
+        }
+    }
+
+    // Keep only the rows whose mask entry is true
+    pub fn retain(&mut self, keep: &[bool]) {
+        let mut row = 0;
+        match self {
+            Column::Plain(values) => values.retain(|_| { row += 1; keep[row-1] }),
+            Column::Spilled(spilled) => spilled.rows.retain(|_| { row += 1; keep[row-1] }),
+            Column::Strings(array) => {
+                let mask = BooleanArray::from(keep.to_vec());
+                *array = filter(array, &mask).expect("mask is as long as the column").as_string::<i32>().clone();
+            }
+            Column::Dictionary(dict) => {
+                let mask = BooleanArray::from(keep.to_vec());
+                dict.keys = filter(&dict.keys, &mask).expect("mask is as long as the column").as_primitive::<UInt32Type>().clone();
+            }
+        }
+    }
+
+    // Rearrange rows so that row i becomes the row at order[i]
+    pub fn reorder(&mut self, order: &[usize]) {
+        match self {
+            Column::Plain(values) => {
+                *values = order.iter().map(|row| std::mem::take(&mut values[*row])).collect();
+            }
+            Column::Spilled(spilled) => {
+                spilled.rows = order.iter().map(|row| spilled.rows[*row]).collect();
+            }
+            Column::Strings(array) => {
+                *array = take(array, &indices(order), None).expect("rows are in the column").as_string::<i32>().clone();
+            }
+            Column::Dictionary(dict) => {
+                dict.keys = take(&dict.keys, &indices(order), None).expect("rows are in the column").as_primitive::<UInt32Type>().clone();
+            }
+        }
+    }
+
+    // Move plain values into an Arrow array, dictionary encoded if few enough of them are distinct
+    pub fn seal(&mut self) {
+        let values = match self {
+            Column::Plain(values) => std::mem::take(values),
+            _ => return
+        };
+
+        if values.len() >= DICTIONARY_MIN_ROWS {
+            let max_distinct = (values.len() as f64 * DICTIONARY_RATIO) as usize;
+            let mut lookup: HashMap<&str, u32> = HashMap::new();
+            let mut distinct = Vec::new();
+            let mut keys = Vec::with_capacity(values.len());
+            for val in values.iter() {
+                let key = *lookup.entry(val.as_str()).or_insert_with(|| {
+                    distinct.push(val.as_str());
+                    distinct.len() as u32 - 1
+                });
+                if distinct.len() > max_distinct {
+                    break;
+                }
+                keys.push(key);
+            }
+
+            if keys.len() == values.len() {
+                *self = Column::Dictionary(Dictionary {
+                    keys: UInt32Array::from(keys),
+                    values: StringArray::from_iter_values(distinct),
+                });
+                return;
+            }
+        }
+
+        *self = Column::Strings(StringArray::from_iter_values(values.iter()));
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        matches!(self, Column::Strings(_) | Column::Dictionary(_))
+    }
+
+    // Whole column as an Arrow array, None unless it's sealed
+    fn array(&self) -> Option<ArrayRef> {
+        match self {
+            Column::Strings(array) => Some(Arc::new(array.clone())),
+            Column::Dictionary(dict) => Some(Arc::new(DictionaryArray::new(dict.keys.clone(), Arc::new(dict.values.clone())))),
+            _ => None
+        }
+    }
+
+    // Column of a sealed Arrow array, either strings or strings dictionary encoded by u32 keys
+    fn from_arrow(array: ArrayRef) -> Column {
+        if let Some(dict) = array.as_dictionary_opt::<UInt32Type>() {
+            if let Some(values) = dict.values().as_string_opt::<i32>() {
+                return Column::Dictionary(Dictionary { keys: dict.keys().clone(), values: values.clone() });
+            }
+        }
+        match array.as_string_opt::<i32>() {
+            Some(array) => Column::Strings(array.clone()),
+            None => Column::Plain(Vec::new()),
+        }
+    }
+
+    // Arrow array of the given rows, taken from the column's own arrays so dictionary encoded
+    // columns stay encoded
+    pub fn to_arrow(&self, rows: &[usize]) -> Result<ArrayRef, ArrowError> {
+        match self {
+            Column::Strings(array) => take(array, &indices(rows), None),
+            Column::Dictionary(dict) => {
+                let keys = take(&dict.keys, &indices(rows), None)?.as_primitive::<UInt32Type>().clone();
+                Ok(Arc::new(DictionaryArray::new(keys, Arc::new(dict.values.clone()))))
+            }
+            _ => Ok(Arc::new(StringArray::from_iter_values(rows.iter().map(|e| self.get(*e))))),
+        }
+    }
+
+    // Write the values to disk, keeping only a small index in memory. Values are read back from
+    // the spill file as they're used. Values set one at a time are kept in memory alongside, only
+    // changing every value loads the column back first
+    pub fn spill(&mut self) -> io::Result<()> {
+        if matches!(self, Column::Spilled(_)) {
+            return Ok(());
+        }
+        if self.len() >= BLANK as usize {
+            return Err(io::Error::other("too many rows to spill"));
+        }
+
+        *self = Column::Spilled(Spilled::new(Spill::write(self.iter())?));
+        Ok(())
+    }
+
+    // Column of the values in a spill written while reading a file
+    pub fn from_spill(writer: SpillWriter) -> io::Result<Column> {
+        let spill = writer.finish()?;
+        if spill.len() >= BLANK as usize {
+            return Err(io::Error::other("too many rows to spill"));
+        }
+        Ok(Column::Spilled(Spilled::new(spill)))
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        matches!(self, Column::Spilled(_))
+    }
+
+    // An empty column that push_from and set_from fill with rows of this one without copying them
+    pub fn share(&self) -> Column {
+        match self {
+            Column::Spilled(spilled) => Column::Spilled(Spilled {
+                spill: spilled.spill.clone(),
+                rows: Vec::new(),
+                extra: Vec::new(),
+            }),
+            _ => Column::default(),
+        }
+    }
+
+    // Bring a spilled or sealed column back to plain strings, so values can be changed one at a time
+    fn load(&mut self) {
+        if !matches!(self, Column::Plain(_)) {
+            *self = Column::Plain(self.iter().map(String::from).collect());
+        }
+    }
+
+    // Rough number of bytes the column takes in memory
+    pub fn heap_size(&self) -> usize {
+        let string = std::mem::size_of::<String>();
+        match self {
+            Column::Plain(values) => values.iter().map(|e| e.capacity() + string).sum(),
+            Column::Strings(array) => array.get_array_memory_size(),
+            Column::Dictionary(dict) => dict.keys.get_array_memory_size() + dict.values.get_array_memory_size(),
+            Column::Spilled(spilled) => {
+                spilled.rows.capacity() * 4 + spilled.spill.heap_size() + spilled.extra.iter().map(|e| e.capacity() + string).sum::<usize>()
+            }
+        }
+    }
+}
+
+fn indices(rows: &[usize]) -> UInt32Array {
+    UInt32Array::from_iter_values(rows.iter().map(|e| *e as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(col: &Column) -> Vec<&str> {
+        col.iter().collect()
+    }
+
+    #[test]
+    fn sealing_dictionary_encodes_repeated_values() {
+        let states: Vec<String> = (0..100).map(|e| ["MN", "WI", "IA"][e % 3].to_string()).collect();
+        let names: Vec<String> = (0..100).map(|e| format!("store {}", e)).collect();
+        let (states_col, names_col) = (Column::from(states.clone()), Column::from(names.clone()));
+
+        assert!(matches!(states_col, Column::Dictionary(_)));
+        assert!(matches!(names_col, Column::Strings(_)));
+        assert_eq!(values(&states_col), states);
+        assert_eq!(values(&names_col), names);
+        assert!(matches!(states_col.to_arrow(&[2, 0]).unwrap().data_type(), arrow_schema::DataType::Dictionary(_, _)));
+    }
+
+    #[test]
+    fn kernels_keep_rows_in_step() {
+        for mut col in [Column::from(vec!["a".to_string(), "b".to_string(), "c".to_string()]), Column::from((0..90).map(|e| ["a", "b", "c"][e % 3].to_string()).collect::<Vec<_>>())] {
+            col.retain(&(0..col.len()).map(|e| e < 3).collect::<Vec<_>>());
+            assert_eq!(values(&col), ["a", "b", "c"]);
+
+            col.reorder(&[2, 0, 1]);
+            assert_eq!(values(&col), ["c", "a", "b"]);
+
+            col.extend(Column::from(vec!["d".to_string()]));
+            col.remove(1);
+            assert_eq!(values(&col), ["c", "b", "d"]);
+            assert!(col.is_sealed());
+        }
+    }
+
+    #[test]
+    fn sealed_columns_can_still_change_a_value() {
+        let mut col = Column::from(vec!["a".to_string(), "b".to_string()]);
+        col.set(1, "z".to_string());
+        col.push("y".to_string());
+        assert_eq!(values(&col), ["a", "z", "y"]);
+
+        col.seal();
+        col.map_values(|e| e.to_uppercase());
+        assert_eq!(values(&col), ["A", "Z", "Y"]);
+        assert!(col.is_sealed());
+    }
+}
@@ -0,0 +1,220 @@
+use std::error::Error;
+use super::config::expand_alias;
+use super::pager::page;
+use super::state::State;
+
+// Commands that only print or write extra outputs. They're left out of export script and aren't
+// run again when a session is restored
+pub const OUTPUT_COMMANDS: [&str; 11] = ["list", "config", "describe", "validate", "geocode", "lint", "near", "nn-stats", "matrix", "diff", "export"];
+
+// Split a line on whitespace, keeping double quoted sections together
+pub fn split_input(line: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = line.trim();
+
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            parts.push(&quoted[..end]);
+            rest = quoted.get(end+1..).unwrap_or("");
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            parts.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+
+    parts
+}
+
+// Join split input back into a line, quoting the parts split_input would otherwise break up
+pub fn join_input(input: &[&str]) -> String {
+    let parts: Vec<String> = input.iter()
+        .map(|e| if e.is_empty() || e.contains(char::is_whitespace) {
+            format!("\"{}\"", e)
+        } else {
+            e.to_string()
+        })
+        .collect();
+    parts.join(" ")
+}
+
+// Run a single command against the state. quit and help are left to the caller
+pub async fn run_command(cli_state: &mut State, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+    let cmd = match input.first() {
+        Some(cmd) if !cmd.starts_with('#') => *cmd,
+        _ => return Ok(())  // blank lines and comments
+    };
+    let line = input.clone();
+
+    let result = match cmd {
+        "list" => {
+            let filtered = input.len() > 2;
+            let lines: Vec<String> = cli_state.get_columns(input)?.iter().map(|e| format!("\t{}", e)).collect();
+            if filtered && lines.is_empty() {
+                println!("No columns match");
+            }
+            page(&lines);
+            Ok(())
+        },
+        "config" => {
+            cli_state.print();
+            Ok(())
+        },
+        "describe" => {
+            cli_state.describe(input)
+        },
+        "set" => {
+            cli_state.set_param(input)
+        },
+        "fetch" => {
+            if cli_state.ready_to_fetch() {
+                cli_state.fetch(input).await
+            } else {
+                Err(cli_state.blocked("fetch"))
+            }
+        },
+        "match" => {
+            if cli_state.ready_to_match() {
+                cli_state.find_matches().await
+            } else {
+                Err(cli_state.blocked("match"))
+            }
+        },
+        "validate" => {
+            cli_state.validate().await
+        },
+        "add" => {
+            cli_state.add_match_column(input)
+        }
+        "method" => {
+            cli_state.set_method(input)
+        }
+        "radius" => {
+            cli_state.set_radius(input)
+        },
+        "exclusive" => {
+            cli_state.set_exclusive(input)
+        }
+        "bearing" => {
+            cli_state.set_bearing(input)
+        }
+        "enrich" => {
+            cli_state.enrich(input).await
+        },
+        "distance" => {
+            cli_state.distance(input)
+        },
+        "geocode" => {
+            cli_state.geocode(input).await
+        },
+        "lint" => {
+            cli_state.lint(input)
+        },
+        "near" => {
+            cli_state.near(input)
+        },
+        "matrix" => {
+            cli_state.matrix(input)
+        },
+        "nn-stats" => {
+            cli_state.nn_stats(input)
+        },
+        "sidecar" => {
+            cli_state.set_sidecar(input)
+        },
+        "split" => {
+            cli_state.set_split(input)
+        },
+        "precision" => {
+            cli_state.set_precision(input)
+        },
+        "seed" => {
+            cli_state.set_seed(input)
+        },
+        "exact-precision" => {
+            cli_state.set_exact_precision(input)
+        },
+        "review" => {
+            cli_state.set_review(input)
+        },
+        "provider" => {
+            cli_state.set_provider(input)
+        },
+        "missing" => {
+            cli_state.set_missing(input)
+        },
+        "dates" => {
+            cli_state.set_dates(input)
+        },
+        "header-line" => {
+            cli_state.set_header_line(input)
+        },
+        "limit" => {
+            cli_state.limit(input)
+        },
+        "country" => {
+            cli_state.set_country_scope(input)
+        },
+        "route" => {
+            cli_state.set_routing(input)
+        },
+        "road-radius" => {
+            cli_state.set_road_radius(input)
+        },
+        "diff" => {
+            cli_state.diff(input)
+        },
+        "export" => {
+            cli_state.export(input)
+        },
+        "map" => {
+            cli_state.apply_mapping(input)
+        },
+        "prefix" => {
+            cli_state.set_prefix(input)
+        },
+        "alias" => {
+            cli_state.set_alias(input)
+        },
+        "transform" => {
+            cli_state.transform(input)
+        },
+        "concat" => {
+            cli_state.concat(input)
+        },
+        "sort" => {
+            cli_state.sort(input)
+        },
+        "project" => {
+            cli_state.project(input)
+        },
+        "datum" => {
+            cli_state.set_datum(input)
+        },
+        "clip" => {
+            cli_state.clip(input)
+        },
+        "dedup-exact" => {
+            cli_state.dedup_exact(input)
+        },
+        _ => match cli_state.alias(cmd) {
+            // Runs each command in turn, stopping at the first that fails. They're recorded for
+            // export script in place of the alias
+            Some(commands) => {
+                for line in expand_alias(cmd, &commands, &input[1..])? {
+                    Box::pin(run_command(cli_state, split_input(&line))).await
+                        .map_err(|e| format!("{}: {}", line, e))?;
+                }
+                return Ok(());
+            }
+            None => Err(format!("Unknown command: '{}', type help to see commands", cmd).into())
+        }
+    };
+
+    if result.is_ok() {
+        cli_state.record_command(&line);
+    }
+    result
+}
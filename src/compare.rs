@@ -0,0 +1,145 @@
+use fuzzywuzzy::fuzz::token_sort_ratio;
+use super::dates::{normalize as normalize_date, DateOrder};
+use super::phonetic::{metaphone, soundex};
+
+// Legal forms at the end of a business name, which one file often leaves out
+const LEGAL_SUFFIXES: [&str; 18] = [
+    "llc", "inc", "incorporated", "corp", "corporation", "co", "company", "ltd", "limited", "lp", "llp", "lllp", "plc",
+    "pllc", "pc", "pa", "gmbh", "sa",
+];
+
+// Words that don't tell two business names apart. Not a, which is as often an initial like in A & W
+const STOPWORDS: [&str; 5] = ["the", "and", "of", "an", "at"];
+
+// Fewest digits a phone number can be matched on, a local number without its area code
+const MIN_PHONE_DIGITS: usize = 7;
+
+// How the values of a compare column are compared, when picking between candidates at the same
+// location
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compare {
+    Text,   // fuzzy compared, with dates compared by day
+    Phone,  // compared by digits, ignoring formatting, country codes and extensions
+    Name,   // business names, fuzzy compared without legal suffixes, punctuation and stopwords
+    Soundex,    // names compared by how each word sounds, with soundex
+    Metaphone,  // names compared by how each word sounds, with metaphone
+}
+
+impl Compare {
+    // The type given to add
+    pub fn parse(text: &str) -> Option<Compare> {
+        match text.to_lowercase().as_str() {
+            "compare" => Some(Compare::Text),
+            "phone" => Some(Compare::Phone),
+            "name" => Some(Compare::Name),
+            "soundex" => Some(Compare::Soundex),
+            "metaphone" => Some(Compare::Metaphone),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compare::Text => "compare",
+            Compare::Phone => "phone",
+            Compare::Name => "name",
+            Compare::Soundex => "soundex",
+            Compare::Metaphone => "metaphone",
+        }
+    }
+
+    // 0 for the same value up to 100 for nothing alike, None when there's nothing to compare
+    pub fn distance(&self, a: &str, b: &str, dates: Option<DateOrder>) -> Option<usize> {
+        match self {
+            Compare::Text => {
+                let date = |e: &str| dates.and_then(|order| normalize_date(e, order)).unwrap_or_else(|| e.to_string());
+                Some(100 - token_sort_ratio(&date(a), &date(b), true, true) as usize)
+            }
+            Compare::Phone => {
+                let (a, b) = (phone_digits(a), phone_digits(b));
+                let (short, long) = if a.len() <= b.len() {(a, b)} else {(b, a)};
+                if short.len() < MIN_PHONE_DIGITS {
+                    return None;
+                }
+
+                // Whichever is written with more of the country code, the rest has to agree
+                Some(if long.ends_with(&short) {0} else {100})
+            }
+            Compare::Name => Some(100 - token_sort_ratio(&business_name(a), &business_name(b), true, true) as usize),
+            Compare::Soundex => Some(100 - token_sort_ratio(&sounds(a, soundex), &sounds(b, soundex), true, true) as usize),
+            Compare::Metaphone => Some(100 - token_sort_ratio(&sounds(a, metaphone), &sounds(b, metaphone), true, true) as usize),
+        }
+    }
+}
+
+// Digits of a phone number without its extension, or the 00 or trunk 0 in front of it, so
+// +1 (612) 555-0100 x12 is 16125550100 and 020 7946 0958 is 2079460958
+pub fn phone_digits(value: &str) -> String {
+    let mut digits = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+        } else if !digits.is_empty() && (c.is_alphabetic() || c == '#') {
+            break;
+        }
+    }
+    digits.trim_start_matches('0').to_string()
+}
+
+// The words of a business name that tell it apart, so Joe's Pizza, L.L.C. is joes pizza and The
+// Home Depot is home depot. A name that's nothing but those words is kept whole
+pub fn business_name(value: &str) -> String {
+    let cleaned: String = value.to_lowercase().chars()
+        .filter(|c| *c != '.' && *c != '\'' && *c != '’')
+        .map(|c| if c.is_alphanumeric() {c} else {' '})
+        .collect();
+    let words: Vec<&str> = cleaned.split_whitespace().collect();
+
+    let mut end = words.len();
+    while end > 0 && LEGAL_SUFFIXES.contains(&words[end-1]) {
+        end -= 1;
+    }
+    let kept: Vec<&str> = words[..end].iter().copied().filter(|e| !STOPWORDS.contains(e)).collect();
+
+    if kept.is_empty() {words.join(" ")} else {kept.join(" ")}
+}
+
+// The phonetic code of each word of a name, cleaned up like a business name first so Smyth
+// Holdings LLC and Smith Holdings sound the same
+fn sounds(value: &str, code: fn(&str) -> String) -> String {
+    business_name(value).split(' ').map(code).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phone_digits_drop_formatting_and_extensions() {
+        assert_eq!(phone_digits("+1 (612) 555-0100 x12"), "16125550100");
+        assert_eq!(phone_digits("020 7946 0958"), "2079460958");
+        assert_eq!(phone_digits("612.555.0100 #4"), "6125550100");
+    }
+
+    #[test]
+    fn phones_match_with_or_without_a_country_code() {
+        assert_eq!(Compare::Phone.distance("612-555-0100", "+1 612 555 0100", None), Some(0));
+        assert_eq!(Compare::Phone.distance("612-555-0100", "612-555-0199", None), Some(100));
+        assert_eq!(Compare::Phone.distance("555-01", "612-555-0100", None), None);
+    }
+
+    #[test]
+    fn business_names_lose_suffixes_punctuation_and_stopwords() {
+        assert_eq!(business_name("Joe's Pizza, L.L.C."), "joes pizza");
+        assert_eq!(business_name("The Home Depot"), "home depot");
+        assert_eq!(business_name("A & W Restaurants Inc"), "a w restaurants");
+        assert_eq!(business_name("The Co"), "the co");
+    }
+
+    #[test]
+    fn names_that_sound_alike_compare_equal() {
+        assert_eq!(Compare::Soundex.distance("Smyth Holdings LLC", "Smith Holdings", None), Some(0));
+        assert_eq!(Compare::Metaphone.distance("Knight Plumbing", "Night Plumbing", None), Some(0));
+        assert!(Compare::Soundex.distance("Smith Holdings", "Jones Holdings", None).unwrap() > 0);
+    }
+}
@@ -0,0 +1,131 @@
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+use super::command::join_input;
+use super::roles::{Role, Synonyms};
+
+// Read from the current directory when --config isn't given
+pub const DEFAULT_CONFIG: &str = "geomatch.json";
+
+// Settings kept in a json file so they apply to every run, e.g.
+//   {"synonyms": {"addr1": ["SITE_ADDR_LN1"], "city": ["MAIL_CITY_NM"]},
+//    "user_agent": "acme-geocoding (ops@acme.com)", "headers": {"Proxy-Authorization": "Bearer ..."},
+//    "aliases": {"std": "set $1 addr1 Address; set $1 city City"}}
+#[derive(Clone, Default)]
+pub struct Config {
+    pub synonyms: Synonyms,     // extra headers for each role, tried before the built in ones
+    pub user_agent: Option<String>,     // sent with geocoding requests instead of geomatch's own
+    pub headers: Vec<(String, String)>, // extra headers for geocoding requests
+    pub aliases: HashMap<String, Vec<String>>,  // commands run by typing the alias's name
+    pub w3w_key: Option<String>,        // what3words api key, for writing three word addresses when fetching
+    pub mapbox_token: Option<String>,   // Mapbox access token, for the mapbox provider
+}
+
+impl Config {
+    // Load the given file, or the default one if there is one
+    pub fn load(path: Option<&str>) -> Result<Config, Box<dyn Error>> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG).exists() => DEFAULT_CONFIG,
+            None => return Ok(Config::default())
+        };
+
+        let text = read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let json: Value = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+        Config::parse(&json).map_err(|e| format!("{}: {}", path, e).into())
+    }
+
+    fn parse(json: &Value) -> Result<Config, Box<dyn Error>> {
+        let mut config = Config::default();
+
+        if let Some(synonyms) = json.get("synonyms") {
+            let synonyms = synonyms.as_object().ok_or("synonyms must be an object of role to headers")?;
+            for (name, headers) in synonyms {
+                let role = Role::parse(name).ok_or_else(|| format!("Unknown role {} in synonyms", name))?;
+                let headers = headers.as_array()
+                    .and_then(|e| e.iter().map(|e| e.as_str()).collect::<Option<Vec<&str>>>())
+                    .ok_or_else(|| format!("synonyms for {} must be a list of headers", name))?;
+
+                config.synonyms.entry(role).or_default().extend(headers.into_iter().map(String::from));
+            }
+        }
+
+        if let Some(user_agent) = json.get("user_agent") {
+            config.user_agent = Some(user_agent.as_str().ok_or("user_agent must be a string")?.to_string());
+        }
+
+        if let Some(key) = json.get("w3w_key") {
+            config.w3w_key = Some(key.as_str().ok_or("w3w_key must be a string")?.to_string());
+        }
+
+        if let Some(token) = json.get("mapbox_token") {
+            config.mapbox_token = Some(token.as_str().ok_or("mapbox_token must be a string")?.to_string());
+        }
+
+        if let Some(headers) = json.get("headers") {
+            let headers = headers.as_object().ok_or("headers must be an object of header name to value")?;
+            for (name, value) in headers {
+                let value = value.as_str().ok_or_else(|| format!("header {} must be a string", name))?;
+                config.headers.push((name.clone(), value.to_string()));
+            }
+        }
+
+        if let Some(aliases) = json.get("aliases") {
+            let aliases = aliases.as_object().ok_or("aliases must be an object of name to commands")?;
+            for (name, commands) in aliases {
+                if name.is_empty() || name.starts_with('#') || name.contains(char::is_whitespace) {
+                    return Err(format!("Invalid alias name '{}'", name))?;
+                }
+
+                // One string of commands separated by semicolons, or a list of them
+                let commands: Vec<String> = match commands {
+                    Value::String(text) => text.split(';').map(String::from).collect(),
+                    Value::Array(list) => list.iter().map(|e| e.as_str().map(String::from)).collect::<Option<_>>()
+                        .ok_or_else(|| format!("alias {} must be a string or a list of commands", name))?,
+                    _ => return Err(format!("alias {} must be a string or a list of commands", name))?
+                };
+                let commands: Vec<String> = commands.iter().map(|e| e.trim().to_string()).filter(|e| !e.is_empty()).collect();
+                if commands.is_empty() {
+                    return Err(format!("alias {} has no commands", name))?;
+                }
+                config.aliases.insert(name.clone(), commands);
+            }
+
+            // One alias running another could run forever, so each only runs commands
+            for (name, commands) in config.aliases.iter() {
+                for command in commands {
+                    let first = command.split_whitespace().next().unwrap_or("");
+                    if config.aliases.contains_key(first) {
+                        return Err(format!("alias {} runs alias {}, aliases can only run commands", name, first))?;
+                    }
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+// The commands an alias runs, with $1, $2 and so on replaced by the arguments it was given
+pub fn expand_alias(name: &str, commands: &[String], args: &[&str]) -> Result<Vec<String>, Box<dyn Error>> {
+    let placeholder = Regex::new(r"\$(\d+)").unwrap();
+    let wanted = commands.iter()
+        .flat_map(|e| placeholder.captures_iter(e).map(|e| e[1].parse::<usize>().unwrap_or(0)).collect::<Vec<usize>>())
+        .max()
+        .unwrap_or(0);
+    if args.len() != wanted {
+        let plural = if wanted == 1 {""} else {"s"};
+        return Err(format!("{} takes {} argument{}, got {}", name, wanted, plural, args.len()).into());
+    }
+
+    let expanded = commands.iter()
+        .map(|command| placeholder.replace_all(command, |e: &regex::Captures| {
+            let index = e[1].parse::<usize>().unwrap_or(0);
+            args.get(index.wrapping_sub(1)).map_or(String::new(), |e| join_input(&[e]))
+        }).to_string())
+        .collect();
+    Ok(expanded)
+}
@@ -0,0 +1,208 @@
+// Parse a coordinate in decimal degrees ("-93.27") or degrees-minutes-seconds
+// ("93°16'12\"W", "N 44 58 48", "44d58m48s")
+pub fn parse_coord(val: &str) -> Option<f64> {
+    let val = val.trim();
+    if let Ok(coord) = val.parse::<f64>() {
+        return Some(coord);
+    }
+
+    // Hemisphere can be given as a leading or trailing letter. With d and m markers a trailing s
+    // marks the seconds, so 44d58m48s is north and 44d58m48sS south
+    let mut upper = val.to_uppercase();
+    if upper.contains('D') && upper.contains('M') && upper.ends_with('S') {
+        upper.pop();
+    }
+    let hemisphere = upper.chars().next().filter(|e| "NSEW".contains(*e))
+        .or_else(|| upper.chars().last().filter(|e| "NSEW".contains(*e)));
+    let negative = val.starts_with('-') || matches!(hemisphere, Some('S') | Some('W'));
+
+    let parts = val.split(|c: char| !c.is_ascii_digit() && c != '.')
+        .filter(|e| !e.is_empty())
+        .map(|e| e.parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+
+    if parts.is_empty() || parts.len() > 3 || parts[1..].iter().any(|e| *e >= 60.0) {
+        return None;
+    }
+
+    let mut coord = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        coord += part / 60f64.powi(i as i32);
+    }
+
+    Some(if negative {-coord} else {coord})
+}
+
+// Whether a point is on the globe, a lat past 90 or lng past 180 usually means swapped or
+// projected columns. NaN is missing rather than out of range
+pub fn in_range(lat: f64, lng: f64) -> bool {
+    !(lat.abs() > 90.0 || lng.abs() > 180.0)
+}
+
+// Write a coordinate or distance, rounded to a number of decimals if given
+pub fn format_coord(val: f64, precision: Option<usize>) -> String {
+    match precision {
+        Some(decimals) => format!("{:.*}", decimals, val),
+        None => val.to_string()
+    }
+}
+
+// Parse a single "lat,lng" value such as "44.98,-93.27", "(44.98, -93.27)" or
+// "44°58'48\"N 93°16'12\"W"
+pub fn parse_latlng(val: &str) -> Option<(f64, f64)> {
+    let val = val.trim()
+        .trim_start_matches(['(', '['])
+        .trim_end_matches([')', ']']);
+
+    let mut parts = val.split([',', ';'])
+        .map(|e| e.trim())
+        .filter(|e| !e.is_empty())
+        .collect::<Vec<&str>>();
+
+    // Without a separator, DMS pairs can still be split after the latitude's hemisphere
+    if parts.len() == 1 {
+        let end = val.find(['N', 'S', 'n', 's'])?;
+        parts = vec![&val[..end+1], &val[end+1..]];
+    }
+
+    if parts.len() != 2 {
+        return None;
+    }
+
+    Some((parse_coord(parts[0])?, parse_coord(parts[1])?))
+}
+
+// Geodetic datums a file's coordinates can be declared in. Coordinates are always
+// held in WGS84 internally since that is what geocoders return
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Datum {
+    #[default]
+    WGS84,
+    NAD83,
+}
+
+impl Datum {
+    pub fn parse(val: &str) -> Option<Datum> {
+        match val.to_lowercase().as_str() {
+            "wgs84" => Some(Datum::WGS84),
+            "nad83" => Some(Datum::NAD83),
+            _ => None
+        }
+    }
+
+    // Convert a coordinate in this datum to WGS84
+    pub fn to_wgs84(self, lat: f64, lng: f64) -> (f64, f64) {
+        match self {
+            Datum::WGS84 => (lat, lng),
+            Datum::NAD83 => helmert(lat, lng, 1.0),
+        }
+    }
+
+    // Convert a WGS84 coordinate back to this datum
+    pub fn from_wgs84(self, lat: f64, lng: f64) -> (f64, f64) {
+        match self {
+            Datum::WGS84 => (lat, lng),
+            Datum::NAD83 => helmert(lat, lng, -1.0),
+        }
+    }
+}
+
+// GRS80 / WGS84 ellipsoid, the difference between the two is negligible here
+const A: f64 = 6378137.0;
+const F: f64 = 1.0 / 298.257222101;
+
+// NAD83 to WGS84 (EPSG:1515), coordinate frame rotation. Translations in meters,
+// rotations in arc seconds. direction is 1 for NAD83 -> WGS84 and -1 for the reverse
+fn helmert(lat: f64, lng: f64, direction: f64) -> (f64, f64) {
+    const TX: f64 = -0.991;
+    const TY: f64 = 1.9072;
+    const TZ: f64 = 0.5129;
+    const RX: f64 = -0.02579;
+    const RY: f64 = -0.00965;
+    const RZ: f64 = -0.01166;
+
+    if lat.is_nan() || lng.is_nan() {
+        return (lat, lng);
+    }
+
+    let arcsec = (1.0f64 / 3600.0).to_radians();
+    let (rx, ry, rz) = (RX * arcsec * direction, RY * arcsec * direction, RZ * arcsec * direction);
+
+    let e2 = F * (2.0 - F);
+    let phi = lat.to_radians();
+    let lambda = lng.to_radians();
+    let n = A / (1.0 - e2 * phi.sin().powi(2)).sqrt();
+
+    // Geodetic to earth-centered coordinates
+    let x = n * phi.cos() * lambda.cos();
+    let y = n * phi.cos() * lambda.sin();
+    let z = n * (1.0 - e2) * phi.sin();
+
+    let x2 = TX * direction + x + rz * y - ry * z;
+    let y2 = TY * direction - rz * x + y + rx * z;
+    let z2 = TZ * direction + ry * x - rx * y + z;
+
+    // And back, iterating for latitude
+    let p = (x2.powi(2) + y2.powi(2)).sqrt();
+    let mut phi2 = z2.atan2(p * (1.0 - e2));
+    for _ in 0..5 {
+        let n2 = A / (1.0 - e2 * phi2.sin().powi(2)).sqrt();
+        let h = p / phi2.cos() - n2;
+        phi2 = z2.atan2(p * (1.0 - e2 * n2 / (n2 + h)));
+    }
+
+    (phi2.to_degrees(), y2.atan2(x2).to_degrees())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn dms_and_decimal_parse_the_same() {
+        let lat = 44.0 + 58.0 / 60.0 + 48.0 / 3600.0;
+        for val in ["44.98", "44°58'48\"N", "N 44 58 48", "44d58m48s", "44d58m48sN"] {
+            assert!(close(parse_coord(val).unwrap(), if val == "44.98" {44.98} else {lat}), "{}", val);
+        }
+        assert!(close(parse_coord("93°16'12\"W").unwrap(), -93.27));
+        assert!(close(parse_coord("-93 16 12").unwrap(), -93.27));
+        assert!(close(parse_coord("44d58m48sS").unwrap(), -lat));
+        assert_eq!(parse_coord("44 61 00"), None);
+        assert_eq!(parse_coord("north"), None);
+
+        let (lat, lng) = parse_latlng("44°58'48\"N 93°16'12\"W").unwrap();
+        assert!(close(lat, 44.98) && close(lng, -93.27));
+        assert_eq!(parse_latlng("(44.98, -93.27)"), Some((44.98, -93.27)));
+    }
+
+    #[test]
+    fn swapped_coordinates_are_out_of_range() {
+        assert!(in_range(44.98, -93.27));
+        assert!(in_range(-90.0, 180.0));
+        assert!(in_range(f64::NAN, f64::NAN));
+        assert!(!in_range(-93.27, 44.98));
+        assert!(!in_range(44.98, 181.0));
+    }
+
+    // NAD83 and WGS84 are a meter or two apart across the US, and converting back undoes it
+    #[test]
+    fn nad83_is_a_meter_or_two_off_wgs84() {
+        for (lat, lng) in [(44.98, -93.27), (40.71, -74.01), (34.05, -118.24), (61.22, -149.90)] {
+            let (wlat, wlng) = Datum::NAD83.to_wgs84(lat, lng);
+            let meters = ((wlat - lat).powi(2) + ((wlng - lng) * lat.to_radians().cos()).powi(2)).sqrt() * 111_320.0;
+            assert!((0.5..2.5).contains(&meters), "{} {} moved {} meters", lat, lng, meters);
+
+            let (back_lat, back_lng) = Datum::NAD83.from_wgs84(wlat, wlng);
+            assert!((back_lat - lat).abs() < 1e-8 && (back_lng - lng).abs() < 1e-8);
+        }
+
+        assert_eq!(Datum::WGS84.to_wgs84(44.98, -93.27), (44.98, -93.27));
+        assert!(Datum::NAD83.to_wgs84(f64::NAN, f64::NAN).0.is_nan());
+        assert_eq!(Datum::parse("NAD83"), Some(Datum::NAD83));
+        assert_eq!(Datum::parse("nad27"), None);
+    }
+}
@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+// Alpha-2 code, alpha-3 code and common names of countries that show up in address files, for
+// country columns that don't use two letter codes
+const COUNTRIES: [(&str, &str, &[&str]); 40] = [
+    ("US", "USA", &["united states", "united states of america", "america", "u s", "u s a"]),
+    ("CA", "CAN", &["canada"]),
+    ("MX", "MEX", &["mexico"]),
+    ("GB", "GBR", &["united kingdom", "uk", "u k", "great britain", "england", "scotland", "wales", "northern ireland"]),
+    ("IE", "IRL", &["ireland"]),
+    ("FR", "FRA", &["france"]),
+    ("DE", "DEU", &["germany"]),
+    ("ES", "ESP", &["spain"]),
+    ("PT", "PRT", &["portugal"]),
+    ("IT", "ITA", &["italy"]),
+    ("NL", "NLD", &["netherlands", "holland"]),
+    ("BE", "BEL", &["belgium"]),
+    ("CH", "CHE", &["switzerland"]),
+    ("AT", "AUT", &["austria"]),
+    ("SE", "SWE", &["sweden"]),
+    ("NO", "NOR", &["norway"]),
+    ("DK", "DNK", &["denmark"]),
+    ("FI", "FIN", &["finland"]),
+    ("PL", "POL", &["poland"]),
+    ("GR", "GRC", &["greece"]),
+    ("TR", "TUR", &["turkey", "turkiye"]),
+    ("RU", "RUS", &["russia", "russian federation"]),
+    ("UA", "UKR", &["ukraine"]),
+    ("IL", "ISR", &["israel"]),
+    ("AE", "ARE", &["united arab emirates", "uae"]),
+    ("IN", "IND", &["india"]),
+    ("CN", "CHN", &["china"]),
+    ("JP", "JPN", &["japan"]),
+    ("KR", "KOR", &["south korea", "korea"]),
+    ("PH", "PHL", &["philippines"]),
+    ("SG", "SGP", &["singapore"]),
+    ("AU", "AUS", &["australia"]),
+    ("NZ", "NZL", &["new zealand"]),
+    ("BR", "BRA", &["brazil"]),
+    ("AR", "ARG", &["argentina"]),
+    ("CL", "CHL", &["chile"]),
+    ("CO", "COL", &["colombia"]),
+    ("PE", "PER", &["peru"]),
+    ("ZA", "ZAF", &["south africa"]),
+    ("PR", "PRI", &["puerto rico"]),
+];
+
+// Two letter ISO code for a country column value, which can be a code of its own, a three
+// letter code or the name of a common country. Names are looked up first so two letter aliases
+// like UK become their code. None for blanks and names that aren't known
+pub fn country_code(value: &str) -> Option<String> {
+    let plain: String = value.to_lowercase()
+        .chars()
+        .map(|e| if e.is_alphabetic() {e} else {' '})
+        .collect();
+    let plain = plain.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    let known = COUNTRIES.iter()
+        .find(|(_, alpha3, names)| alpha3.eq_ignore_ascii_case(&plain) || names.contains(&plain.as_str()))
+        .map(|e| e.0.to_string());
+    match known {
+        None if plain.len() == 2 && !plain.contains(' ') => Some(plain.to_uppercase()),
+        known => known
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_map_before_two_letter_codes() {
+        assert_eq!(country_code("UK").as_deref(), Some("GB"));
+        assert_eq!(country_code("u.k.").as_deref(), Some("GB"));
+        assert_eq!(country_code("gb").as_deref(), Some("GB"));
+        assert_eq!(country_code("USA").as_deref(), Some("US"));
+        assert_eq!(country_code("Mexico").as_deref(), Some("MX"));
+        assert_eq!(country_code("FJ").as_deref(), Some("FJ"));
+        assert_eq!(country_code(" "), None);
+    }
+}
+
+// Countries rows are limited to. Rows elsewhere are dropped, or only flagged in an in_scope
+// column
+#[derive(Clone, Debug, PartialEq)]
+pub struct CountryScope {
+    pub codes: Vec<String>,
+    pub flag: bool,
+}
+
+impl Display for CountryScope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", if self.flag {"flag "} else {""}, self.codes.join(" "))
+    }
+}
+
+impl CountryScope {
+    // Parse one of
+    //   off
+    //   [countries...]
+    //   flag [countries...]
+    pub fn parse(input: &[&str]) -> Result<Option<CountryScope>, Box<dyn Error>> {
+        if input.first().is_some_and(|e| e.eq_ignore_ascii_case("off")) {
+            return Ok(None);
+        }
+
+        let flag = input.first().is_some_and(|e| e.eq_ignore_ascii_case("flag"));
+        let names = if flag {&input[1..]} else {input};
+        if names.is_empty() {
+            return Err("countries required")?;
+        }
+
+        let mut codes = Vec::with_capacity(names.len());
+        for name in names.iter().flat_map(|e| e.split(',')).filter(|e| !e.is_empty()) {
+            match country_code(name) {
+                Some(code) => codes.push(code),
+                None => return Err(format!("Unknown country {}, use its two letter code", name))?
+            }
+        }
+
+        Ok(Some(CountryScope {codes, flag}))
+    }
+
+    pub fn contains(&self, code: &str) -> bool {
+        self.codes.iter().any(|e| e == code)
+    }
+}
@@ -1,29 +1,228 @@
-use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use csv::{ReaderBuilder, StringRecord, Writer, WriterBuilder};
+use memmap2::Mmap;
 
-use futures::future::join_all;
 use tokio::sync::Semaphore;
+use tokio::sync::mpsc::{channel, Receiver};
 use std::path::Path;
-use std::sync::Mutex;
-use indicatif::ProgressBar;
+use std::fs::{read_to_string, remove_file, File};
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::thread::{self, available_parallelism};
 use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use std::iter::Iterator;
 use std::error::Error;
 use std::sync::Arc;
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::fmt::{Formatter, Display};
 use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
+use std::borrow::Cow;
+use super::transform::Transform;
+use super::dialect::{read_sample, Dialect};
+use super::coords::{format_coord, in_range, parse_coord, parse_latlng, Datum};
+use super::projection::Projection;
+use super::geofence::Geofence;
+use super::column::Column;
+use super::spill::SpillWriter;
+use super::distance::Points;
+use super::compare::Compare;
+use super::roles::{guess_roles, Guess, Role, Synonyms};
+use super::validate::Problem;
+use tracing::{instrument, warn, Instrument, Span};
+use super::interrupt;
+use super::atomic::{self, Output};
+use super::intersection;
+use super::jitter::Jitter;
+use super::pobox::{is_po_box, PoBoxPolicy};
+use super::provider::{census, census_batch, mapbox, Keys, Provider, CENSUS_BATCH_SIZE};
+use super::s2;
+use super::suggest;
+use super::w3w::{three_words, W3W_COLUMN, W3W_FAILED};
+use super::metrics::{count, GEOCODE_FAILURES, GEOCODE_REQUESTS};
+
+const GEOCODE_URL: &str = "https://maps.googleapis.com/maps/api/geocode/json";
+
+// Column fetch writes the S2 cell token of each row to
+pub const S2_COLUMN: &str = "s2_cell";
+
+// Column fetch writes what each row was geocoded from to, when there's a blank address or PO Box
+// policy
+pub const STATUS_COLUMN: &str = "geocode_status";
+
+// Match quality of rows the Census batch geocoder couldn't be reached for
+pub const BATCH_FAILED: &str = "Failed";
+
+// Fewest rows a failed Census batch upload is split down to before giving up on them
+const BATCH_MIN_SPLIT: usize = CENSUS_BATCH_SIZE / 16;
+
+// What to geocode a row on when its street address, city or state is blank
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressFallback {
+    Locality,   // city, state and zip, so the row lands in the right town
+    Zip,        // zip alone, the middle of the zip code
+}
+
+impl AddressFallback {
+    pub fn parse(text: &str) -> Option<AddressFallback> {
+        match text.to_lowercase().as_str() {
+            "city" => Some(AddressFallback::Locality),
+            "zip" => Some(AddressFallback::Zip),
+            _ => None
+        }
+    }
+
+    // Also what the status column says for rows geocoded this way
+    pub fn name(&self) -> &'static str {
+        match self {
+            AddressFallback::Locality => "city",
+            AddressFallback::Zip => "zip",
+        }
+    }
+}
+
+// How synthetic row ids are generated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RowId {
+    Number, // <file index>-<row number>
+    Hash,   // hash of the row contents
+}
+
+// What to do with rows that have more or fewer fields than the header
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum RaggedPolicy {
+    #[default]
+    Error,      // stop loading the file
+    Pad,        // fill short rows with blanks and drop extra fields from long rows
+    Truncate,   // drop extra fields from long rows, skip short rows
+    Skip,       // skip every ragged row and log its line number
+}
+
+// Rows read ahead of loading to guess columns from their values
+const GUESS_SAMPLE_ROWS: usize = 100;
+
+// Files smaller than this are parsed on one thread, starting more would take longer than it saves
+const PARALLEL_MIN_BYTES: u64 = 8 * 1024 * 1024;
+
+// Options that control how files are read into a dataframe
+#[derive(Clone, Default)]
+pub struct LoadOptions {
+    pub ragged: RaggedPolicy,
+    pub keep_coords: bool,  // keep lat/lng text columns in data as well
+    pub synonyms: Synonyms, // extra headers to guess each role from
+    pub strict: bool,       // ragged rows and unparseable coordinates are errors instead of warnings
+    pub header_lines: HashMap<String, usize>,   // line the header is on by path, counting from 1, for exports with banner lines above it
+    pub skip_rows: usize,   // lines skipped at the start of files without a header line
+    pub comment: Option<u8>,    // lines starting with it are skipped
+    pub offset: usize,          // rows skipped after the header, for trying a setup on part of a file
+    pub limit: Option<usize>,   // most rows loaded after the offset
+    pub memory_budget: Option<usize>,   // bytes the file can take while it's read before columns spill to disk
+}
+
+// Options that control what fetch writes
+#[derive(Clone)]
+pub struct FetchOptions {
+    pub norm_address: Option<String>,   // name of the normalized address column, not written if None
+    pub norm_address_position: Option<usize>,  // index among the written columns, after the file's own columns if None
+    pub s2_level: Option<u8>,   // level of the S2 cell token written after lat and lng, not written if None
+    pub w3w: bool,              // write the what3words address of each row last, looked up while geocoding
+    pub blank_address: Option<Vec<AddressFallback>>,  // tried in order for rows with a blank address, flagged in a status column. Rows are skipped without it if None
+    pub po_box: Option<PoBoxPolicy>,    // what to do with PO Box addresses, flagged in a status column. Geocoded like any other if None
+    pub jitter: Option<Jitter>,         // coarsens the coordinates written, which are written as found if None
+}
+
+impl FetchOptions {
+    // Whether fetch writes the status column
+    pub fn status(&self) -> bool {
+        self.blank_address.is_some() || self.po_box.is_some()
+    }
+}
+
+impl Default for FetchOptions {
+    fn default() -> FetchOptions {
+        FetchOptions {
+            norm_address: Some("norm_address".to_string()),
+            norm_address_position: None,
+            s2_level: None,
+            w3w: false,
+            blank_address: None,
+            po_box: None,
+            jitter: None,
+        }
+    }
+}
+
+// Fetched file being written as geocoding results come in
+pub struct FetchWriter {
+    writer: Writer<Output>,
+    input: String,
+    output: String,
+    partial: String,
+    columns: Vec<Option<usize>>,    // data columns in write order, None for the normalized address
+    norm_address: Option<String>,
+    s2_level: Option<u8>,
+    w3w: bool,
+    jitter: Option<Jitter>,
+    statuses: Option<Vec<&'static str>>,    // what each row was geocoded from, for the status column
+    quality: Option<&'static str>,  // column the provider's match quality is written to, if it gives one
+    precision: Option<usize>,
+    pending: HashMap<usize, Geocode>,   // results waiting on an earlier row
+    lat: Vec<f64>,
+    lng: Vec<f64>,
+    addresses: Vec<String>,
+    cells: Vec<String>,
+    words: Vec<String>,
+    qualities: Vec<String>,
+}
+
+impl FetchWriter {
+    // Flush the rows written so far and checkpoint them so `fetch resume` can carry on from
+    // there, for when the fetch can't finish
+    pub fn stop(&mut self) -> Result<String, Box<dyn Error>> {
+        self.checkpoint()?;
+        Ok(format!("{} rows written to {}", self.lat.len(), self.partial))
+    }
+
+    // Flush the rows written so far and record how many there are, also done every few minutes
+    // during a fetch so a crash loses at most that much
+    pub fn checkpoint(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        atomic::write(&checkpoint_path(&self.output), json!({
+            "input": self.input,
+            "output": self.output,
+            "rows": self.lat.len(),
+        }).to_string())?;
+        Ok(())
+    }
+
+    // Rows written so far
+    pub fn rows(&self) -> usize {
+        self.lat.len()
+    }
+}
+
+// Where a stopped fetch records how far it got
+fn checkpoint_path(output: &str) -> String {
+    format!("{}.checkpoint", output)
+}
 
+// Piece of an address template, either literal text or a {field} to fill in
+#[derive(Clone, Debug)]
+enum TemplatePart {
+    Literal(String),
+    Field(String),
+}
 
 #[derive(Default, Clone)]
 pub struct DataFrame {
     path: String,
     headers: Vec<String>,
     pub shape: (usize, usize),
-    delimiter: char,
+    dialect: Dialect,
     pub prefix: String,
 
     // The DATA
-    data: Vec<Vec<String>>,
+    data: Vec<Column>,
 
     // Indexes for matching, fetching, etc (index for data)
     id: Option<usize>,
@@ -32,40 +231,83 @@ pub struct DataFrame {
     city: Option<usize>,
     state: Option<usize>,
     zipcode: Option<usize>,
+    country: Option<usize>,
 
     // Columns (because lat and lng have different type) Excluded from headers
     lat: Option<Vec<f64>>,
     lng: Option<Vec<f64>>,
+    keep_coords: bool,
+    kept_coords: (Option<String>, Option<String>),  // headers of the lat and lng text kept as columns
+    strict: bool,
+    projection: Option<Projection>,
+    datum: Datum,
 
     // Additional Output columns
     pub output_cols: Vec<usize>,
-    compare_cols: Vec<usize>
+    compare_cols: Vec<usize>,
+    compare_kinds: Vec<Compare>,    // how each compare column is compared
+    aliases: HashMap<String, String>,   // header to the name it's written out as, in place of the prefix
+
+    // Optional geocode query template, overrides the default address order
+    template: Option<String>,
+    template_parts: Vec<TemplatePart>,
+    provider: Provider,     // geocoder this file's addresses are sent to
+
+    // Roles more than one header could have been guessed as, with the chosen header first
+    ambiguous: Vec<(Role, Vec<String>)>,
+    from_values: Vec<(Role, String)>,   // roles guessed from column values instead of headers
+    fetched_address: Option<String>,    // column a fetch this session wrote the normalized address to
+    distances: Vec<String>,     // columns distance_from wrote, which it can write again
+    row_ids: bool,      // whether the row_id column was added when loading rather than the file's own
+    set_aside: SetAside,    // rows a filter took out that can be put back
+}
+
+// Rows taken out of a file by a filter that can be undone, like the country scope. Each is kept
+// by header with the row of the file it went before, so it can be put back in place even after
+// columns have come and gone
+#[derive(Clone, Debug, Default)]
+struct SetAside {
+    headers: Vec<String>,
+    rows: Vec<(usize, Vec<String>, f64, f64)>,  // row it goes before, values, lat and lng
 }
 
 impl Display for DataFrame {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{{")?;
         writeln!(f, "\tpath:\t{}", self.path)?;
+        writeln!(f, "\tdialect:\t{}", self.dialect)?;
         writeln!(f, "\tprefix:\t{}\n", self.prefix)?;
 
+        writeln!(f, "\tid:\t\t{}", self.id.map_or("None".to_string(), |e| e.to_string()))?;
         writeln!(f, "\taddr1:\t\t{}", self.addr1.map_or("None".to_string(), |e| e.to_string()))?;
         writeln!(f, "\taddr2:\t\t{}", self.addr2.map_or("None".to_string(), |e| e.to_string()))?;
         writeln!(f, "\tcity:\t\t{}", self.city.map_or("None".to_string(), |e| e.to_string()))?;
         writeln!(f, "\tstate:\t\t{}", self.state.map_or("None".to_string(), |e| e.to_string()))?;
-        writeln!(f, "\tzipcode:\t{}\n", self.zipcode.map_or("None".to_string(), |e| e.to_string()))?;
+        writeln!(f, "\tzipcode:\t{}", self.zipcode.map_or("None".to_string(), |e| e.to_string()))?;
+        writeln!(f, "\tcountry:\t{}", self.country.map_or("None".to_string(), |e| e.to_string()))?;
+        writeln!(f, "\ttemplate:\t{}", self.template.as_deref().unwrap_or("None"))?;
+        writeln!(f, "\tprovider:\t{}\n", self.provider.name())?;
 
         writeln!(f, "\tlat:\t{}", self.lat.as_ref().map_or("Not Found", |_| "Found"))?;
-        writeln!(f, "\tlng:\t{}\n", self.lng.as_ref().map_or("Not Found", |_| "Found"))?;
+        writeln!(f, "\tlng:\t{}", self.lng.as_ref().map_or("Not Found", |_| "Found"))?;
+        writeln!(f, "\tprojected from:\t{}", self.projection.map_or("None".to_string(), |e| e.to_string()))?;
+        writeln!(f, "\tdatum:\t{:?}\n", self.datum)?;
 
         writeln!(f, "\toutput_cols: {{")?;
         for col in self.output_cols.iter() {
-            writeln!(f, "\t\t{}", self.headers[*col])?;
+            match self.aliases.get(&self.headers[*col]) {
+                Some(alias) => writeln!(f, "\t\t{} as {}", self.headers[*col], alias)?,
+                None => writeln!(f, "\t\t{}", self.headers[*col])?,
+            }
         }
         writeln!(f, "\t}}")?;
 
         writeln!(f, "\tcompare_cols: {{")?;
-        for col in self.compare_cols.iter() {
-            writeln!(f, "\t\t{}", self.headers[*col])?;
+        for (col, kind) in self.compare_cols.iter().zip(&self.compare_kinds) {
+            match kind {
+                Compare::Text => writeln!(f, "\t\t{}", self.headers[*col])?,
+                _ => writeln!(f, "\t\t{} ({})", self.headers[*col], kind.name())?,
+            }
         }
         writeln!(f, "\t}}")?;
 
@@ -76,96 +318,118 @@ impl Display for DataFrame {
 
 impl DataFrame {
     // CONSTRUCTORS
-    pub fn from_path(path: &str) -> DataFrame {
-        // Try to guess delimiter based on number of headers returned
-        let comma_count = {
-            let mut reader = ReaderBuilder::new()
-                .delimiter(b',')
-                .from_path(path)
-                .unwrap();
-
-            reader.headers().unwrap().iter().count()
-        };
-
-        let pipe_count = {
-            let mut reader = ReaderBuilder::new()
-                .delimiter(b'|')
-                .from_path(path)
-                .unwrap();
-
-            reader.headers().unwrap().iter().count()
-        };
-
-        let delimiter = if pipe_count > comma_count {'|'} else {','};
-
-        // Read in the file for further analysis
-        let (mut headers, width, height) = {
-            let mut reader = ReaderBuilder::new()
-                .delimiter(delimiter as u8)
-                .from_path(path)
-                .unwrap();
+    pub fn from_path(path: &str, options: &LoadOptions) -> Result<DataFrame, Box<dyn Error>> {
+        // Work out the delimiter, quoting and whether there is a header row from the first
+        // block, then keep reading from where it left off so the file is only read once
+        let mut file = BufReader::new(File::open(path)?);
+        let size = file.get_ref().metadata()?.len();
+
+        // Lines above the header are dropped before sniffing so they can't throw the guess off
+        let header_line = options.header_lines.get(path).copied();
+        let skip = header_line.map_or(options.skip_rows, |e| e - 1);
+        let mut skipped = 0;
+        for _ in 0..skip {
+            match file.read_until(b'\n', &mut Vec::new())? {
+                0 => return Err(format!("{} ends before the {} lines to skip", path, skip))?,
+                read => skipped += read,
+            }
+        }
 
-            // Get headers and size information
-            let headers = reader.headers().unwrap()
-                .iter()
-                .map(|e| e.to_string())
-                .collect::<Vec<String>>();
+        let bytes = read_sample(&mut file)?;
+        let mut dialect = Dialect::sniff(&bytes, options.comment);
+        if header_line.is_some() {
+            dialect.has_headers = true;
+        }
 
-            let width = headers.len();
-            let height = reader.records().count();
+        // Estimate the row count from the sample to size the columns up front
+        let sample_rows = bytes.iter().filter(|e| **e == b'\n').count().max(1);
+        let mut height = (size as f64 / bytes.len().max(1) as f64 * sample_rows as f64) as usize;
+        if let Some(limit) = options.limit {
+            height = height.min(limit);
+        }
 
-            (headers, width, height)
+        // Big files are mapped into memory and split between threads to parse, so the pages are
+        // only read in as they're parsed and can be dropped again once they have been. A slice
+        // of a file is read on one thread, since it stops once it has its rows
+        let threads = available_parallelism().map_or(1, |e| e.get());
+        let parallel = threads > 1 && size >= PARALLEL_MIN_BYTES && options.offset == 0 && options.limit.is_none();
+        let map = match parallel {
+            // Changing the file while it's loaded would change what's read, same as reading it
+            true => Some(unsafe { Mmap::map(file.get_ref())? }),
+            false => None,
         };
+        let mapped = map.as_ref().map(|e| e.get(skipped..).unwrap_or_default());
+        let source: Box<dyn Read + '_> = match mapped {
+            Some(mapped) => Box::new(mapped),
+            None => Box::new(Cursor::new(&bytes[..]).chain(file)),
+        };
+        let mut reader = dialect.reader(source);
+
+        // Get headers, naming columns by position if the file has no header row
+        let mut headers = reader.headers()?
+            .iter()
+            .enumerate()
+            .map(|(i, e)| if dialect.has_headers {e.to_string()} else {format!("column_{}", i+1)})
+            .collect::<Vec<String>>();
+        let width = headers.len();
+
+        // First rows are read ahead so columns can be guessed from their values as well
+        let mut sample = Vec::with_capacity(GUESS_SAMPLE_ROWS);
+        let mut record = StringRecord::new();
+        while sample.len() < GUESS_SAMPLE_ROWS && reader.read_record(&mut record)? {
+            sample.push(record.clone());
+        }
 
-        // Map headers to special column values
+        // Map headers to special column values, taking the best candidate for each and keeping
+        // the others so the guess can be reported
         let mut id = None;
         let mut addr1 = None;
         let mut addr2 = None;
         let mut city = None;
         let mut state = None;
         let mut zipcode = None;
+        let mut country = None;
         let mut lat = None;
         let mut lng = None;
-
-        for (index, header) in headers.iter().enumerate() {
-            let lower = header.to_lowercase();
-            let trim = lower.trim();
-            let match_str = trim.replace(" ", "");
-            match match_str.as_str() {
-                "id" => {
-                    id = Some(index);
-                }
-                "addr1" | "address" | "addr" => {
-                    addr1 = Some(index);
-                }
-                "addr2" | "address2" => {
-                    addr2 = Some(index);
-                }
-                "city" => {
-                    city = Some(index);
-                }
-                "state" => {
-                    state = Some(index);
-                }
-                "zipcode" | "zip" | "postalcode" => {
-                    zipcode = Some(index);
-                }
-                "lat" | "latitude" => {
-                    lat = Some(index);
-                }
-                "lng" | "longitude" => {
-                    lng = Some(index);
-                }
-                _ => {}
-            }
+        let mut ambiguous = Vec::new();
+        let mut from_values = Vec::new();
+
+        // Roles are guessed from the headers as they are, so two zip columns are still both zips.
+        // A repeated header could only ever mean its first column though, so the others are numbered
+        let guesses = guess_roles(&headers, &sample, &options.synonyms);
+        for (col, header, renamed) in number_duplicates(&mut headers) {
+            println!("{}: column {} is another '{}', renamed to '{}'", path, col, header, renamed);
         }
 
-        // Modify headers removing lat and lng columns
+        for Guess {role, candidates, from_values: guessed} in guesses {
+            let index = Some(candidates[0]);
+            match role {
+                Role::Id => id = index,
+                Role::Addr1 => addr1 = index,
+                Role::Addr2 => addr2 = index,
+                Role::City => city = index,
+                Role::State => state = index,
+                Role::Zipcode => zipcode = index,
+                Role::Country => country = index,
+                Role::Lat => lat = index,
+                Role::Lng => lng = index,
+            }
 
-        if lat.is_some() && lng.is_some() {
-            let lat = lat.unwrap();
-            let lng = lng.unwrap();
+            if candidates.len() > 1 {
+                ambiguous.push((role, candidates.iter().map(|e| headers[*e].clone()).collect()));
+            }
+            if guessed {
+                from_values.push((role, headers[candidates[0]].clone()));
+            }
+        }
 
+        // Modify headers removing lat and lng columns, unless they should be kept as text too.
+        // Coordinates guessed from their values alone are always kept, since a wrong guess would
+        // otherwise take two columns of the file with it
+        let keep_coords = options.keep_coords || from_values.iter().any(|e| matches!(e.0, Role::Lat | Role::Lng));
+        if keep_coords {
+            // Nothing to remove
+        } else if let (Some(lat), Some(lng)) = (lat, lng) {
             if lat > lng {
                 headers.remove(lng);
                 headers.remove(lat-1);
@@ -179,66 +443,123 @@ impl DataFrame {
             headers.remove(index);
         }
 
-        // Create empty data vec with capacity for data
-        let mut data = Vec::with_capacity(headers.len());
-        for _ in 0..headers.len() {
-            data.push(Vec::with_capacity(height));
+        // Special columns after a removed coordinate column move left
+        if !keep_coords {
+            let shift = |index: Option<usize>| index.map(|i| {
+                i - [lat, lng].iter().filter(|e| e.is_some_and(|e| e < i)).count()
+            });
+            id = shift(id);
+            addr1 = shift(addr1);
+            addr2 = shift(addr2);
+            city = shift(city);
+            state = shift(state);
+            zipcode = shift(zipcode);
+            country = shift(country);
+        }
+
+        // Add all data to correct columns, first evening out ragged rows
+        // Role columns are used over and over so they stay in memory, anything else can go to disk
+        // as soon as the file is over budget. Each thread gets an even share of the budget
+        let roles = [id, addr1, addr2, city, state, zipcode, country];
+        let layout = Layout {
+            path,
+            width,
+            columns: headers.len(),
+            lat,
+            lng,
+            keep_coords,
+            spillable: (0..headers.len()).map(|e| !roles.contains(&Some(e))).collect(),
+            budget: options.memory_budget.map(|e| if parallel {e / (threads + 1)} else {e}),
+        };
+        let mut part = Part::new(&layout, height);
+        if let Some(mapped) = mapped {
+            for mut record in sample {
+                let line = record.position().map_or(0, |e| e.line());
+                part.add(&mut record, line, &layout, options)?;
+            }
+            part.finish(&layout)?;
+            let start = reader.position().clone();
+            for other in parse_parts(&mapped[start.byte() as usize..], start.line(), &dialect, threads, &layout, options)? {
+                part.extend(other, &layout)?;
+            }
+        } else {
+            for (index, record) in sample.into_iter().map(Ok).chain(reader.into_records()).enumerate() {
+                // Rows outside the slice asked for aren't even checked
+                if index < options.offset {
+                    continue;
+                }
+                if options.limit.is_some_and(|e| part.rows >= e) {
+                    break;
+                }
+
+                let mut record = record?;
+                let line = record.position().map_or(0, |e| e.line());
+                part.add(&mut record, line, &layout, options)?;
+            }
+            part.finish(&layout)?;
+        }
+
+        let spilled = part.data.iter().filter(|e| e.is_spilled()).count();
+        if spilled > 0 {
+            println!("{}: spilled {} columns to disk while reading, to stay in the memory budget", path, spilled);
+        }
+
+        if !part.ragged_lines.is_empty() {
+            let action = match options.ragged {
+                RaggedPolicy::Pad => "padded or truncated",
+                RaggedPolicy::Truncate => "truncated or skipped",
+                _ => "skipped"
+            };
+            println!("{}: {} ragged rows {}", path, part.ragged_lines.len(), action);
+
+            if options.ragged != RaggedPolicy::Pad {
+                let lines = part.ragged_lines.iter().map(|e| e.to_string()).collect::<Vec<String>>();
+                println!("\tlines: {}", lines.join(", "));
+            }
         }
 
+        let kept_coords = match keep_coords {
+            true => (lat.map(|e| headers[e].clone()), lng.map(|e| headers[e].clone())),
+            false => (None, None)
+        };
         let mut data_frame = DataFrame {
             path: path.to_string(),
             headers,
-            shape: (width, height),
-            delimiter,
+            shape: (width, part.rows),
+            dialect,
+            keep_coords,
+            kept_coords,
+            strict: options.strict,
             id,
             addr1,
             addr2,
             city,
             state,
             zipcode,
-            data,
+            country,
+            data: part.data,
+            lat: part.lat,
+            lng: part.lng,
+            ambiguous,
+            from_values,
+            fetched_address: None,
+            distances: Vec::new(),
+            row_ids: false,
+            set_aside: SetAside::default(),
             ..DataFrame::default()
         };
 
-        // Read all data into dataframe
-        let mut reader = ReaderBuilder::new()
-            .delimiter(delimiter as u8)
-            .from_path(path)
-            .unwrap();
-
-        // Create vectors for lat/lng if needed
-        if lat.is_some() {
-            data_frame.lat = Some(Vec::with_capacity(data_frame.shape.1));
-        }
-
-        if lng.is_some() {
-            data_frame.lng = Some(Vec::with_capacity(data_frame.shape.1));
-        }
-
-        // Add all data to correct vector
-        for record in reader.records() {
-            let mut offset=0;
-            let record = record.unwrap();
-            for (col, item) in record.iter().enumerate() {
-                if lat.is_some() && col==lat.unwrap() {
-                    data_frame.lat.as_mut().unwrap().push(item.parse::<f64>().unwrap_or(f64::NAN));
-                    offset += 1;
-                } else if lng.is_some() && col==lng.unwrap() {
-                    data_frame.lng.as_mut().unwrap().push(item.parse::<f64>().unwrap_or(f64::NAN));
-                    offset += 1;
-                } else {
-                    data_frame.data[col-offset].push(item.to_string());
-                }
-            }
+        for col in data_frame.data.iter_mut() {
+            col.seal();
         }
 
-        data_frame
+        Ok(data_frame)
     }
 
     pub fn with_capacity(width: usize, height: usize) -> DataFrame {
         let mut data = Vec::with_capacity(width);
         for _ in 0..width {
-            data.push(Vec::with_capacity(height));
+            data.push(Column::with_capacity(height));
         }
         DataFrame {
             data,
@@ -256,15 +577,17 @@ impl DataFrame {
         if let Some((index, _)) = col_option {
             Ok(index)
         } else {
-            return Err(format!("No column named {}", col))?;
+            return Err(format!("No column named '{}'{}", col, suggest::did_you_mean(col, &self.headers)))?;
         }
     }
 
     // BOOLEAN CHECKS
     pub fn ready_to_fetch(&self) -> bool {
-        self.addr1.is_some() &&
-        self.city.is_some() &&
-        self.state.is_some()
+        self.template.is_some() || (
+            self.addr1.is_some() &&
+            self.city.is_some() &&
+            self.state.is_some()
+        )
     }
 
     pub fn ready_to_match(&self) -> bool {
@@ -272,71 +595,151 @@ impl DataFrame {
         self.lng.is_some()
     }
 
+    // Everything that would stop this file from being fetched or matched, along with things
+    // that are likely mistakes, each with what to do about it
+    pub fn problems(&self, index: usize) -> Vec<Problem> {
+        let name = format!("file {} ({})", index, self.path);
+        let mut problems = Vec::new();
+
+        if !self.ready_to_fetch() {
+            for (role, col) in [(Role::Addr1, self.addr1), (Role::City, self.city), (Role::State, self.state)] {
+                if col.is_none() {
+                    problems.push(Problem::blocks("fetch", format!(
+                        "{} has no {} column. Use `set {} {} [col]`, or set a template instead",
+                        name, role, index, role
+                    )));
+                }
+            }
+        }
+
+        for (role, coords) in [(Role::Lat, &self.lat), (Role::Lng, &self.lng)] {
+            if coords.is_none() {
+                problems.push(Problem::blocks("match", format!(
+                    "{} has no {} column. Fetch it, or use `set {} {} [col]`", name, role, index, role
+                )));
+            }
+        }
+
+        let roles = [
+            (Role::Id, self.id), (Role::Addr1, self.addr1), (Role::Addr2, self.addr2), (Role::City, self.city),
+            (Role::State, self.state), (Role::Zipcode, self.zipcode), (Role::Country, self.country),
+        ];
+        for (role, col) in roles {
+            if col.is_some_and(|e| e >= self.data.len()) {
+                problems.push(Problem::error(format!(
+                    "{}'s {} column no longer exists. Use `set {} {} [col]` again", name, role, index, role
+                )));
+            }
+        }
+        for (kind, cols) in [("output", &self.output_cols), ("compare", &self.compare_cols)] {
+            if cols.iter().any(|e| *e >= self.headers.len()) {
+                problems.push(Problem::error(format!(
+                    "One of {}'s {} columns no longer exists. Load the file again and add its columns again", name, kind
+                )));
+            }
+        }
+
+        // Headers are numbered apart when loading, but an alias or the prefix can still give two
+        // output columns the same name
+        if self.output_indexes().iter().all(|e| *e < self.headers.len()) {
+            let headers = self.output_headers();
+            let mut seen = HashSet::new();
+            let mut duplicates: Vec<&String> = headers.iter().filter(|e| !seen.insert(*e)).collect();
+            duplicates.sort();
+            duplicates.dedup();
+            for header in duplicates {
+                problems.push(Problem::warning(format!(
+                    "{} writes more than one column as '{}'. Change an alias or the prefix to tell them apart", name, header
+                )));
+            }
+        }
+
+        if let (Some(lat), Some(lng)) = (&self.lat, &self.lng) {
+            let missing = lat.iter().zip(lng).filter(|e| e.0.is_nan() || e.1.is_nan()).count();
+            if missing > 0 {
+                problems.push(Problem::warning(format!(
+                    "{} rows in {} have no coordinates and won't match anything", missing, name
+                )));
+            }
+        }
+        let outside = self.out_of_range_rows().len();
+        if outside > 0 {
+            problems.push(Problem::warning(format!(
+                "{} rows in {} have a lat past ±90 or lng past ±180 and are matched as rows without coordinates. \
+                 Check lat and lng aren't swapped, or use `project` for projected coordinates", outside, name
+            )));
+        }
+
+        problems
+    }
+
     // GETTERS
     pub fn get_headers(&self) -> &Vec<String> {
         &self.headers
     }
 
+    pub fn ambiguous_roles(&self) -> &[(Role, Vec<String>)] {
+        &self.ambiguous
+    }
+
+    pub fn roles_from_values(&self) -> &[(Role, String)] {
+        &self.from_values
+    }
+
     pub fn set_headers(&mut self, headers: Vec<String>) {
         self.headers = headers;
     }
 
     // Special Columns
-    pub fn id(&self) -> Option<&Vec<String>> {
-        if self.id.is_none() {return None;}
-        Some(&self.data[self.id.unwrap()])
+    pub fn id(&self) -> Option<&Column> {
+        Some(&self.data[self.id?])
+    }
+
+    pub fn addr1(&self) -> Option<&Column> {
+        Some(&self.data[self.addr1?])
     }
 
-    pub fn addr1(&self) -> Option<&Vec<String>> {
-        if self.addr1.is_none() {return None;}
-        Some(&self.data[self.addr1.unwrap()])
+    pub fn addr2(&self) -> Option<&Column> {
+        Some(&self.data[self.addr2?])
     }
 
-    pub fn addr2(&self) -> Option<&Vec<String>> {
-        if self.addr2.is_none() {return None;}
-        Some(&self.data[self.addr2.unwrap()])
+    pub fn city(&self) -> Option<&Column> {
+        Some(&self.data[self.city?])
     }
 
-    pub fn city(&self) -> Option<&Vec<String>> {
-        if self.city.is_none() {return None;}
-        Some(&self.data[self.city.unwrap()])
+    pub fn state(&self) -> Option<&Column> {
+        Some(&self.data[self.state?])
     }
 
-    pub fn state(&self) -> Option<&Vec<String>> {
-        if self.state.is_none() {return None;}
-        Some(&self.data[self.state.unwrap()])
+    pub fn zipcode(&self) -> Option<&Column> {
+        Some(&self.data[self.zipcode?])
     }
 
-    pub fn zipcode(&self) -> Option<&Vec<String>> {
-        if self.zipcode.is_none() {return None;}
-        Some(&self.data[self.zipcode.unwrap()])
+    pub fn country(&self) -> Option<&Column> {
+        Some(&self.data[self.country?])
     }
 
     pub fn lat(&self) -> Option<&Vec<f64>> {
-        if self.lat.is_none() {return None;}
-        Some(self.lat.as_ref().unwrap())
+        self.lat.as_ref()
     }
 
     pub fn lat_mut(&mut self) -> Option<&mut Vec<f64>> {
-        if self.lat.is_none() {return None;}
-        Some(self.lat.as_mut().unwrap())
+        self.lat.as_mut()
     }
 
     pub fn lng(&self) -> Option<&Vec<f64>> {
-        if self.lng.is_none() {return None;}
-        Some(self.lng.as_ref().unwrap())
+        self.lng.as_ref()
     }
 
     pub fn lng_mut(&mut self) ->Option<&mut Vec<f64>> {
-        if self.lng.is_none() {return None;}
-        Some(self.lng.as_mut().unwrap())
+        self.lng.as_mut()
     }
 
-    pub fn data(&self) -> &Vec<Vec<String>> {
+    pub fn data(&self) -> &Vec<Column> {
         &self.data
     }
 
-    pub fn data_mut(&mut self) -> &mut Vec<Vec<String>> {
+    pub fn data_mut(&mut self) -> &mut Vec<Column> {
         &mut self.data
     }
 
@@ -346,8 +749,9 @@ impl DataFrame {
         Ok(())
     }
 
-    pub fn add_compare_column(&mut self, col: &str) -> Result<(), Box<dyn Error>> {
+    pub fn add_compare_column(&mut self, col: &str, kind: Compare) -> Result<(), Box<dyn Error>> {
         self.compare_cols.push(self.get_col_index(col)?);
+        self.compare_kinds.push(kind);
         Ok(())
     }
 
@@ -355,6 +759,25 @@ impl DataFrame {
         self.prefix = prefix.to_string();
     }
 
+    // Name a column is written out as, or back to its header with the prefix if None
+    pub fn set_alias(&mut self, col: &str, alias: Option<&str>) -> Result<(), Box<dyn Error>> {
+        self.get_col_index(col)?;
+        match alias {
+            Some(alias) => self.aliases.insert(col.to_string(), alias.to_string()),
+            None => self.aliases.remove(col),
+        };
+        Ok(())
+    }
+
+    // Apply a transform to every value of a column in place
+    pub fn transform_column(&mut self, col: &str, transform: &Transform) -> Result<(), Box<dyn Error>> {
+        let index = self.get_col_index(col)?;
+
+        self.data[index].map_values(|e| transform.apply(e));
+
+        Ok(())
+    }
+
     // Special columns
     pub fn set_id(&mut self, col: &str) -> Result<(), Box<dyn Error>> {
         self.id = Some(self.get_col_index(col)?);
@@ -381,16 +804,75 @@ impl DataFrame {
         Ok(())
     }
 
+    pub fn set_country(&mut self, col: &str) -> Result<(), Box<dyn Error>> {
+        self.country = Some(self.get_col_index(col)?);
+        Ok(())
+    }
+
     pub fn set_zipcode(&mut self, col: &str) -> Result<(), Box<dyn Error>> {
         self.zipcode = Some(self.get_col_index(col)?);
         Ok(())
     }
 
+    // Parse a template like "{addr1}, {city}, {state} {zipcode}, USA". Fields can be
+    // either a special column name or any header in the file
+    pub fn set_template(&mut self, template: &str) -> Result<(), Box<dyn Error>> {
+        let template = template.trim().trim_matches('"');
+
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}');
+            if end.is_none() {
+                return Err("Unclosed '{' in template")?;
+            }
+            let end = start + end.unwrap();
+
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_string()));
+            }
+
+            let field = rest[start+1..end].trim();
+            if !is_role(field) && self.get_col_index(field).is_err() {
+                return Err(format!("Unknown template field {}", field))?;
+            }
+            parts.push(TemplatePart::Field(field.to_string()));
+
+            rest = &rest[end+1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+
+        if !parts.iter().any(|e| matches!(e, TemplatePart::Field(_))) {
+            return Err("Template must contain at least one {field}")?;
+        }
+
+        self.template = Some(template.to_string());
+        self.template_parts = parts;
+        Ok(())
+    }
+
+    pub fn set_provider(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        self.provider = Provider::parse(name.trim()).ok_or_else(|| format!("Unknown provider '{}', expected google, census, census-batch or mapbox", name))?;
+        Ok(())
+    }
+
+    pub fn provider(&self) -> Provider {
+        self.provider
+    }
+
     pub fn set_lat(&mut self, col: &str)  -> Result<(), Box<dyn Error>> {
         let index = self.get_col_index(col)?;
 
-        let mut column = self.data.remove(index);
-        self.lat = Some(column.iter_mut().map(|e| e.parse::<f64>().unwrap()).collect());
+        self.lat = Some(parse_coord_column(col, &self.data[index], self.strict)?);
+        if self.keep_coords {
+            self.kept_coords.0 = Some(self.headers[index].clone());
+            return Ok(());
+        }
+
+        self.data.remove(index);
         self.headers.remove(index);
 
         Ok(())
@@ -399,175 +881,683 @@ impl DataFrame {
     pub fn set_lng(&mut self, col: &str) -> Result<(), Box<dyn Error>> {
         let index = self.get_col_index(col)?;
 
-        let mut column = self.data.remove(index);
-        self.lng = Some(column.iter_mut().map(|e| e.parse::<f64>().unwrap()).collect());
+        self.lng = Some(parse_coord_column(col, &self.data[index], self.strict)?);
+        if self.keep_coords {
+            self.kept_coords.1 = Some(self.headers[index].clone());
+            return Ok(());
+        }
+
+        self.data.remove(index);
         self.headers.remove(index);
 
         Ok(())
     }
 
-    pub async fn fetch(&mut self, key: String) -> Result<(), Box<dyn Error>> {
-        println!("Fetching {} coords for {}:", self.shape.1, self.path);
+    // Fill lat and lng from a single column holding both, e.g. "44.98,-93.27".
+    // Unlike lat and lng the combined column stays available as a regular column
+    pub fn set_latlng(&mut self, col: &str) -> Result<(), Box<dyn Error>> {
+        let index = self.get_col_index(col)?;
 
-        // collect addresses into a vec
-        let mut addresses = Vec::with_capacity(self.shape.1);
-        for row in 0..self.shape.1 {
-            addresses.push(self.get_address(row));
-        }
+        let mut lat = Vec::with_capacity(self.shape.1);
+        let mut lng = Vec::with_capacity(self.shape.1);
+        let mut failed = 0;
 
-        // Google's geocoding api will block us if we exceed 50 requests per second
-        let requests_per_second: usize = 30;
-        let dur = Duration::from_secs_f64(1.0/(requests_per_second as f64));
-        let mut clock = tokio::time::interval(dur);
+        for val in self.data[index].iter() {
+            let (row_lat, row_lng) = parse_latlng(val).unwrap_or_else(|| {
+                if !val.trim().is_empty() {
+                    failed += 1;
+                }
+                (f64::NAN, f64::NAN)
+            });
+            lat.push(row_lat);
+            lng.push(row_lng);
+        }
 
-        // Semaphore to make sure we don't max out open http connections
-        let sem = Arc::new(Semaphore::new(30));
+        if failed > 0 {
+            let message = format!("{} values in {} could not be parsed as coordinates", failed, col);
+            if self.strict {
+                return Err(message)?;
+            }
+            println!("{}", message);
+        }
 
-        // Collection of async tasks which we will join on
-        let mut tasks = Vec::with_capacity(self.shape.1);
+        self.lat = Some(lat);
+        self.lng = Some(lng);
+        Ok(())
+    }
 
-        // Progress bar to track fetching  progress
-        let bar = Arc::new(Mutex::new(ProgressBar::new(self.shape.1 as u64)));
+    // Treat lat as northing and lng as easting in a projected coordinate system and
+    // convert them to lat/lng
+    pub fn project(&mut self, projection: Projection) -> Result<(), Box<dyn Error>> {
+        if !self.ready_to_match() {
+            return Err("lat and lng must be set to the northing and easting columns first")?;
+        }
+        if self.projection.is_some() {
+            return Err(format!("{} has already been projected", self.path))?;
+        }
 
-        // Shared client for http requests
-        let client = Arc::new(Client::new());
+        let lat = self.lat.as_mut().unwrap();
+        let lng = self.lng.as_mut().unwrap();
+        for row in 0..lat.len() {
+            let (row_lat, row_lng) = projection.inverse(lng[row], lat[row]);
+            lat[row] = row_lat;
+            lng[row] = row_lng;
+        }
 
-        for row in 0..self.shape.1 {
-            let bar_clone = bar.clone();
-            let client_clone = client.clone();
-            let addr = self.get_address(row);
-            let key_clone = key.clone();
-            let sem_clone = sem.clone();
+        self.projection = Some(projection);
+        Ok(())
+    }
 
-            // Rate limit
-            clock.tick().await;
+    // Declare the datum of the file's coordinates, converting them to WGS84
+    pub fn set_datum(&mut self, datum: Datum) -> Result<(), Box<dyn Error>> {
+        if !self.ready_to_match() {
+            return Err("lat and lng must be set before the datum")?;
+        }
 
-            tasks.push(tokio::spawn(async move {
-                if addr.is_none() {
-                    bar_clone.lock().unwrap().inc(1);
-                    return (f64::NAN, f64::NAN, "".to_string());
-                }
-                let _permit = sem_clone.acquire().await.unwrap();
-                let res = fetch_single(&client_clone, addr.unwrap().as_str(), key_clone.as_str()).await.unwrap();
-                bar_clone.lock().unwrap().inc(1);
-                res
-            }));
+        let old = self.datum;
+        let lat = self.lat.as_mut().unwrap();
+        let lng = self.lng.as_mut().unwrap();
+        for row in 0..lat.len() {
+            // Undo any earlier conversion before applying the new one
+            let (raw_lat, raw_lng) = old.from_wgs84(lat[row], lng[row]);
+            let (row_lat, row_lng) = datum.to_wgs84(raw_lat, raw_lng);
+            lat[row] = row_lat;
+            lng[row] = row_lng;
         }
 
-        let results = join_all(tasks).await;
-        bar.lock().unwrap().finish();
+        self.datum = datum;
+        Ok(())
+    }
+
+    // Configuration of the file for run metadata
+    pub fn metadata(&self) -> Value {
+        let name = |col: Option<usize>| col.map(|e| self.headers[e].clone());
+        let names = |cols: &[usize]| cols.iter().map(|e| self.headers[*e].clone()).collect::<Vec<String>>();
+
+        json!({
+            "path": self.path,
+            "rows": self.shape.1,
+            "dialect": self.dialect.to_string(),
+            "headers": self.headers,
+            "prefix": self.prefix,
+            "columns": {
+                "id": name(self.id),
+                "addr1": name(self.addr1),
+                "addr2": name(self.addr2),
+                "city": name(self.city),
+                "state": name(self.state),
+                "zipcode": name(self.zipcode),
+                "country": name(self.country),
+                "template": self.template,
+                "coordinates": self.ready_to_match(),
+            },
+            "provider": self.provider.name(),
+            "output_cols": names(&self.output_cols),
+            "compare_cols": names(&self.compare_cols),
+            "compare_types": self.compare_kinds.iter().map(|e| e.name()).collect::<Vec<&str>>(),
+            "aliases": self.aliases,
+            "projection": self.projection.map(|e| e.to_string()),
+            "datum": format!("{:?}", self.datum),
+        })
+    }
 
-        // Add lat and lng rows
-        self.lat = Some(Vec::with_capacity(self.shape.1));
-        self.lng = Some(Vec::with_capacity(self.shape.1));
+    pub fn path(&self) -> &str {
+        &self.path
+    }
 
-        // Add row for normalized address
-        self.headers.push("norm_address".to_string());
-        self.data.push(Vec::with_capacity(self.shape.1));
-        let addr_row = self.data.last_mut().unwrap();
+    // File name without its directory or extension
+    pub fn stem(&self) -> String {
+        Path::new(self.path.as_str()).file_stem().unwrap().to_string_lossy().to_string()
+    }
 
-        for result in results {
-            let (lat, lng, addr) = result.unwrap();
-            self.lat.as_mut().unwrap().push(lat);
-            self.lng.as_mut().unwrap().push(lng);
-            addr_row.push(addr);
-        }
+    // Geocoding query for every row, None for rows missing part of the address that none of the
+    // fallbacks can stand in for, and PO Boxes that are skipped
+    pub fn addresses(&self, options: &FetchOptions) -> Vec<Option<String>> {
+        (0..self.shape.1).map(|row| self.get_address(row, options).0.map(|e| e.line)).collect()
+    }
+
+    // The same query as addresses gives for a row, as the street, city, state and zip the Census
+    // batch geocoder takes instead of one line
+    pub fn batch_fields(&self, row: usize, options: &FetchOptions) -> Option<[String; 4]> {
+        self.get_address(row, options).0.map(|e| e.fields)
+    }
+
+    // What each row is geocoded from, address, intersection, city, zip, pobox or skipped
+    pub fn address_statuses(&self, options: &FetchOptions) -> Vec<&'static str> {
+        (0..self.shape.1).map(|row| self.get_address(row, options).1).collect()
+    }
 
-        // Output File
+    // The normalized address only replaces a column an earlier fetch wrote it to, never one of
+    // the file's own
+    pub fn check_norm_address(&self, options: &FetchOptions) -> Result<(), Box<dyn Error>> {
+        match &options.norm_address {
+            Some(name) if self.get_col_index(name).is_ok() && self.fetched_address.as_ref() != Some(name) => {
+                Err(format!("{} already has a column '{}', set normaddress to another name or false so it isn't written over", self.path, name))?
+            }
+            _ => Ok(())
+        }
+    }
 
-        let path = Path::new(self.path.as_str());
-        let path = format!("{}_coords.csv", path.file_stem().unwrap().to_str().unwrap());
+    // Start writing the fetched file. Rows are written with write_fetched as their results
+    // come in, to a .partial file next to the output that is moved into place by finish_fetch,
+    // so an interrupted fetch keeps what it has without replacing the source file
+    pub fn fetch_writer(&self, output: &str, precision: Option<usize>, options: &FetchOptions) -> Result<FetchWriter, Box<dyn Error>> {
+        self.check_norm_address(options)?;
+        let norm_col = options.norm_address.as_ref().and_then(|name| self.get_col_index(name).ok());
+        let cell_col = options.s2_level.and_then(|_| self.get_col_index(S2_COLUMN).ok());
+        let words_col = if options.w3w {self.get_col_index(W3W_COLUMN).ok()} else {None};
+        let status_col = if options.status() {self.get_col_index(STATUS_COLUMN).ok()} else {None};
+        let quality = self.provider.quality_column();
+        let quality_col = quality.and_then(|e| self.get_col_index(e).ok());
+
+        // Order to write columns in, with None for the normalized address
+        let mut columns: Vec<Option<usize>> = (0..self.data.len())
+            .filter(|e| ![norm_col, cell_col, words_col, status_col, quality_col].contains(&Some(*e)))
+            .map(Some)
+            .collect();
+        if options.norm_address.is_some() {
+            let position = options.norm_address_position.unwrap_or(columns.len()).min(columns.len());
+            columns.insert(position, None);
+        }
 
-        println!("Writing output to {}.", path);
+        println!("Writing output to {}.", output);
         let mut writer = WriterBuilder::new()
-            .delimiter(self.delimiter as u8)
-            .from_path(path)?;
+            .delimiter(self.dialect.delimiter)
+            .quote(self.dialect.quote)
+            .from_writer(atomic::create_partial(output)?);
 
         // Print Headers
         let mut new_headers = StringRecord::new();
-
-        for header in self.headers.iter() {
-            new_headers.push_field(header);
+        for col in columns.iter() {
+            match col {
+                Some(col) => new_headers.push_field(&self.headers[*col]),
+                None => new_headers.push_field(options.norm_address.as_ref().unwrap()),
+            }
         }
-
         new_headers.push_field("lat");
         new_headers.push_field("lng");
+        if options.s2_level.is_some() {
+            new_headers.push_field(S2_COLUMN);
+        }
+        if options.status() {
+            new_headers.push_field(STATUS_COLUMN);
+        }
+        if let Some(quality) = quality {
+            new_headers.push_field(quality);
+        }
+        if options.w3w {
+            new_headers.push_field(W3W_COLUMN);
+        }
         writer.write_record(&new_headers)?;
 
-        let width = self.data.len();
-        let height = self.data[0].len();
+        Ok(FetchWriter {
+            writer,
+            input: self.path.clone(),
+            output: output.to_string(),
+            partial: atomic::partial_path(output),
+            columns,
+            norm_address: options.norm_address.clone(),
+            s2_level: options.s2_level,
+            w3w: options.w3w,
+            jitter: options.jitter,
+            statuses: options.status().then(|| self.address_statuses(options)),
+            quality,
+            precision,
+            pending: HashMap::new(),
+            lat: Vec::with_capacity(self.shape.1),
+            lng: Vec::with_capacity(self.shape.1),
+            addresses: Vec::with_capacity(self.shape.1),
+            cells: Vec::new(),
+            words: Vec::new(),
+            qualities: Vec::new(),
+        })
+    }
 
-        // Print data with lat, lng pairs
-        for row in 0..height {
-            let mut record = StringRecord::new();
-            for col in 0..width {
-                record.push_field(self.data[col][row].as_str());
+    // Start writing the fetched file again from the rows a stopped fetch checkpointed, or from
+    // the start if there is no checkpoint for the output
+    pub fn resume_writer(&self, output: &str, precision: Option<usize>, options: &FetchOptions) -> Result<FetchWriter, Box<dyn Error>> {
+        let checkpoint = match read_to_string(checkpoint_path(output)) {
+            Ok(checkpoint) => serde_json::from_str::<Value>(&checkpoint)?,
+            Err(_) => {
+                println!("No checkpoint for {}, fetching every row.", output);
+                return self.fetch_writer(output, precision, options);
             }
-            record.push_field(self.lat.as_ref().unwrap()[row].to_string().as_str());
-            record.push_field(self.lng.as_ref().unwrap()[row].to_string().as_str());
+        };
+        if checkpoint["input"].as_str() != Some(self.path.as_str()) {
+            return Err(format!("Checkpoint for {} is from another file, fetch without resume", output))?;
+        }
+        let rows = checkpoint["rows"].as_u64().ok_or("Checkpoint is missing its row count")? as usize;
+
+        // The partial file is started over, so its rows are read before it's replaced
+        let partial = atomic::partial_path(output);
+        let mut reader = ReaderBuilder::new()
+            .delimiter(self.dialect.delimiter)
+            .quote(self.dialect.quote)
+            .from_reader(atomic::open_partial(output)?);
+        let headers = reader.headers()?.clone();
+        let records = reader.records().take(rows).collect::<Result<Vec<StringRecord>, csv::Error>>()?;
+        if records.len() != rows || rows > self.shape.1 {
+            return Err(format!("{} doesn't have the {} rows its checkpoint says, fetch without resume", partial, rows))?;
+        }
+
+        let mut fetch = self.fetch_writer(output, precision, options)?;
+        let expected = fetch.columns.iter()
+            .map(|col| match col {
+                Some(col) => self.headers[*col].as_str(),
+                None => fetch.norm_address.as_deref().unwrap(),
+            })
+            .chain(["lat", "lng"])
+            .chain(fetch.s2_level.map(|_| S2_COLUMN))
+            .chain(fetch.statuses.as_ref().map(|_| STATUS_COLUMN))
+            .chain(fetch.quality)
+            .chain(fetch.w3w.then_some(W3W_COLUMN));
+        if headers.iter().ne(expected) {
+            return Err(format!("Columns of {} have changed since it was stopped, fetch without resume", output))?;
+        }
 
-            writer.write_record(&record)?;
+        let addr = fetch.columns.iter().position(|e| e.is_none());
+        let width = fetch.columns.len();
+        for record in records {
+            fetch.writer.write_record(&record)?;
+            fetch.lat.push(record[width].parse().unwrap_or(f64::NAN));
+            fetch.lng.push(record[width+1].parse().unwrap_or(f64::NAN));
+            fetch.addresses.push(addr.map_or(String::new(), |e| record[e].to_string()));
+            if fetch.s2_level.is_some() {
+                fetch.cells.push(record[width+2].to_string());
+            }
+            if fetch.quality.is_some() {
+                fetch.qualities.push(record[headers.len() - 1 - fetch.w3w as usize].to_string());
+            }
+            if fetch.w3w {
+                fetch.words.push(record[headers.len()-1].to_string());
+            }
         }
+        println!("Resuming {} from row {}.", output, rows);
+
+        Ok(fetch)
+    }
+
+    // Add the result for a row, writing it and any rows after it that were waiting on it
+    pub fn write_fetched(&self, fetch: &mut FetchWriter, row: usize, result: Geocode) -> Result<(), Box<dyn Error>> {
+        fetch.pending.insert(row, result);
+
+        while let Some(Geocode {lat, lng, address: addr, words, quality, ..}) = fetch.pending.remove(&fetch.lat.len()) {
+            let row = fetch.lat.len();
+            let mut record = StringRecord::new();
+            for col in fetch.columns.iter() {
+                match col {
+                    Some(col) => match self.coarse_value(*col, row, fetch.jitter, fetch.precision) {
+                        Some(coarse) => record.push_field(&coarse),
+                        None => record.push_field(&self.data[*col][row]),
+                    },
+                    None => record.push_field(&addr),
+                }
+            }
+            // Only the written coordinates are coarsened, matching still uses the ones found
+            let (written_lat, written_lng) = fetch.jitter.map_or((lat, lng), |e| e.apply(lat, lng));
+            record.push_field(format_coord(written_lat, fetch.precision).as_str());
+            record.push_field(format_coord(written_lng, fetch.precision).as_str());
+            if let Some(level) = fetch.s2_level {
+                let cell = s2::cell_id(written_lat, written_lng, level).map_or(String::new(), s2::token);
+                record.push_field(&cell);
+                fetch.cells.push(cell);
+            }
+            if let Some(statuses) = &fetch.statuses {
+                record.push_field(statuses[row]);
+            }
+            if fetch.quality.is_some() {
+                record.push_field(&quality);
+                fetch.qualities.push(quality);
+            }
+            if fetch.w3w {
+                record.push_field(&words);
+                fetch.words.push(words);
+            }
+            fetch.writer.write_record(&record)?;
 
-        writer.flush()?;
+            fetch.lat.push(lat);
+            fetch.lng.push(lng);
+            fetch.addresses.push(addr);
+        }
 
         Ok(())
     }
 
-    fn get_address(&self, row: usize) -> Option<String> {
-        let addr1 = self.data[self.addr1.unwrap()][row].as_str();
-        let city = self.data[self.city.unwrap()][row].as_str();
-        let state = self.data[self.state.unwrap()][row].as_str();
+    // Move the finished file into place and store the results for matching
+    pub fn finish_fetch(&mut self, mut fetch: FetchWriter) -> Result<(), Box<dyn Error>> {
+        if fetch.lat.len() != self.shape.1 {
+            return Err(format!("Fetch of {} stopped early, {}", self.path, fetch.stop()?))?;
+        }
 
-        let mut parts = vec![addr1, city, state];
-        if parts.iter().map(|e| e.trim()).any(|e| e.is_empty()) {
-            return None;
+        // Flushes the rows still buffered, then makes sure they reached the disk
+        atomic::finish_partial(fetch.writer.into_inner().map_err(|e| e.to_string())?, &fetch.output)?;
+        let _ = remove_file(checkpoint_path(&fetch.output));
+
+        // Geocoders all return WGS84
+        self.datum = Datum::WGS84;
+        self.lat = Some(fetch.lat);
+        self.lng = Some(fetch.lng);
+
+        if let Some(name) = fetch.norm_address {
+            match self.get_col_index(&name) {
+                Ok(col) => self.data[col] = fetch.addresses.into(),
+                Err(_) => {
+                    self.headers.push(name.clone());
+                    self.data.push(fetch.addresses.into());
+                }
+            }
+            self.fetched_address = Some(name);
         }
 
-        if let Some(zipcode) = self.zipcode {
-            let zipcode = self.data[zipcode][row].as_str();
-            parts.push(zipcode);
+        if fetch.s2_level.is_some() {
+            match self.get_col_index(S2_COLUMN) {
+                Ok(col) => self.data[col] = fetch.cells.into(),
+                Err(_) => {
+                    self.headers.push(S2_COLUMN.to_string());
+                    self.data.push(fetch.cells.into());
+                }
+            }
         }
 
-        if let Some(addr2) = self.addr2 {
-            let addr2 = self.data[addr2][row].as_str();
-            parts.insert(1, addr2);
+        if let Some(statuses) = fetch.statuses {
+            let statuses: Vec<String> = statuses.into_iter().map(String::from).collect();
+            match self.get_col_index(STATUS_COLUMN) {
+                Ok(col) => self.data[col] = statuses.into(),
+                Err(_) => {
+                    self.headers.push(STATUS_COLUMN.to_string());
+                    self.data.push(statuses.into());
+                }
+            }
         }
 
-        Some(parts.join(" "))
-    }
+        if fetch.w3w {
+            match self.get_col_index(W3W_COLUMN) {
+                Ok(col) => self.data[col] = fetch.words.into(),
+                Err(_) => {
+                    self.headers.push(W3W_COLUMN.to_string());
+                    self.data.push(fetch.words.into());
+                }
+            }
+        }
 
-    pub fn output_headers(&self) -> Vec<String> {
-        let mut headers = Vec::new();
-        for col in self.output_cols.iter() {
-            if self.prefix.is_empty() {
-                headers.push(self.headers[*col].clone())
-            } else {
-                headers.push(format!("{}_{}", self.prefix, self.headers[*col].clone()));
+        if let Some(quality) = fetch.quality {
+            match self.get_col_index(quality) {
+                Ok(col) => self.data[col] = fetch.qualities.into(),
+                Err(_) => {
+                    self.headers.push(quality.to_string());
+                    self.data.push(fetch.qualities.into());
+                }
             }
         }
 
-        headers
+        Ok(())
     }
 
-    pub fn output_row(&self, row: usize) -> Vec<String> {
-        let mut output_row = Vec::new();
-        for col in self.output_cols.iter() {
-            output_row.push(self.data[*col][row].clone());
+    // Query for a row and what it was made from. Rows with a blank street address, city or state
+    // try each fallback in turn, and are skipped if none of them have what they need
+    fn get_address(&self, row: usize, options: &FetchOptions) -> (Option<Query>, &'static str) {
+        // Nothing at a PO Box to put a pin on
+        if let Some(policy) = options.po_box {
+            let street = match self.template {
+                Some(_) => self.render_template(row).unwrap_or_default(),
+                None => [self.addr1, self.addr2].iter().flatten().map(|e| &self.data[*e][row]).collect::<Vec<&str>>().join(" "),
+            };
+            if is_po_box(&street) {
+                let query = match policy {
+                    PoBoxPolicy::Skip => None,
+                    PoBoxPolicy::Locality => self.locality(row),
+                    PoBoxPolicy::Geocode => self.get_street_address(row, &[]).0,
+                };
+                return (query, "pobox");
+            }
         }
 
-        output_row
+        self.get_street_address(row, options.blank_address.as_deref().unwrap_or(&[]))
     }
 
-    pub fn compare_row(&self, row: usize) -> Vec<String> {
-        let mut compare_row = Vec::new();
+    fn get_street_address(&self, row: usize, fallbacks: &[AddressFallback]) -> (Option<Query>, &'static str) {
+        // A template is only ever a whole line, so it goes to the batch geocoder as the street
+        if self.template.is_some() {
+            let query = self.render_template(row).map(|line| Query {
+                fields: [line.clone(), String::new(), String::new(), String::new()],
+                line,
+            });
+            let status = if query.is_some() {"address"} else {"skipped"};
+            return (query, status);
+        }
+
+        let addr1 = &self.data[self.addr1.unwrap()][row];
+        let city = &self.data[self.city.unwrap()][row];
+        let state = &self.data[self.state.unwrap()][row];
+        let zipcode = self.zipcode.map_or("", |e| &self.data[e][row]);
+
+        let mut parts = vec![addr1, city, state];
+        if parts.iter().map(|e| e.trim()).any(|e| e.is_empty()) {
+            for fallback in fallbacks {
+                let query = match fallback {
+                    AddressFallback::Locality => self.locality(row),
+                    AddressFallback::Zip if !zipcode.trim().is_empty() => Some(Query {
+                        line: zipcode.trim().to_string(),
+                        fields: [String::new(), String::new(), String::new(), zipcode.trim().to_string()],
+                    }),
+                    _ => None
+                };
+                if query.is_some() {
+                    return (query, fallback.name());
+                }
+            }
+            return (None, "skipped");
+        }
+
+        // A corner has no unit, and the commas keep the streets apart from the city
+        let fields = |street: String| [street, city.trim().to_string(), state.trim().to_string(), zipcode.trim().to_string()];
+        if let Some(corner) = intersection::parse(addr1) {
+            let locality = [state.trim(), zipcode.trim()].iter().filter(|e| !e.is_empty()).copied().collect::<Vec<&str>>().join(" ");
+            let street = corner.query(self.provider);
+            return (Some(Query {
+                line: format!("{}, {}, {}", street, city.trim(), locality),
+                fields: fields(street),
+            }), "intersection");
+        }
+
+        let street = [Some(addr1), self.addr2.map(|e| &self.data[e][row])].iter()
+            .flatten()
+            .map(|e| e.trim())
+            .filter(|e| !e.is_empty())
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        if self.zipcode.is_some() {
+            parts.push(zipcode);
+        }
+
+        if let Some(addr2) = self.addr2 {
+            let addr2 = &self.data[addr2][row];
+            parts.insert(1, addr2);
+        }
+
+        (Some(Query { line: parts.join(" "), fields: fields(street) }), "address")
+    }
+
+    // City, state and zip of a row, None without a city and state
+    fn locality(&self, row: usize) -> Option<Query> {
+        let city = self.city.map_or("", |e| self.data[e][row].trim());
+        let state = self.state.map_or("", |e| self.data[e][row].trim());
+        let zipcode = self.zipcode.map_or("", |e| self.data[e][row].trim());
+        if city.is_empty() || state.is_empty() {
+            return None;
+        }
+
+        Some(Query {
+            line: [city, state, zipcode].iter().filter(|e| !e.is_empty()).copied().collect::<Vec<&str>>().join(" "),
+            fields: [String::new(), city.to_string(), state.to_string(), zipcode.to_string()],
+        })
+    }
+
+    fn render_template(&self, row: usize) -> Option<String> {
+        let mut query = String::new();
+        let mut blank = true;
+
+        for part in self.template_parts.iter() {
+            match part {
+                TemplatePart::Literal(text) => query.push_str(text),
+                TemplatePart::Field(field) => {
+                    let value = self.field_index(field)
+                        .map_or("", |col| self.data[col][row].trim());
+                    blank &= value.is_empty();
+                    query.push_str(value);
+                }
+            }
+        }
+
+        if blank {
+            return None;
+        }
+
+        // Clean up separators left behind by blank fields
+        let mut query = query.split_whitespace().collect::<Vec<&str>>().join(" ");
+        query = query.replace(" ,", ",");
+        while query.contains(",,") {
+            query = query.replace(",,", ",");
+        }
+
+        Some(query.trim_matches(|c: char| c == ',' || c.is_whitespace()).to_string())
+    }
+
+    // Resolve a template field to a column index
+    fn field_index(&self, field: &str) -> Option<usize> {
+        match field.to_lowercase().as_str() {
+            "id" => self.id,
+            "addr1" => self.addr1,
+            "addr2" => self.addr2,
+            "city" => self.city,
+            "state" => self.state,
+            "zip" | "zipcode" => self.zipcode,
+            "country" => self.country,
+            _ => self.get_col_index(field).ok()
+        }
+    }
+
+    // Output columns, led by the id column so the key is never lost
+    fn output_indexes(&self) -> Vec<usize> {
+        let mut cols = Vec::with_capacity(self.output_cols.len() + 1);
+        if let Some(id) = self.id {
+            if !self.output_cols.contains(&id) {
+                cols.push(id);
+            }
+        }
+        cols.extend(self.output_cols.iter());
+
+        cols
+    }
+
+    pub fn output_headers(&self) -> Vec<String> {
+        let mut headers = Vec::new();
+        for col in self.output_indexes().iter() {
+            if let Some(alias) = self.aliases.get(&self.headers[*col]) {
+                headers.push(alias.clone())
+            } else if self.prefix.is_empty() {
+                headers.push(self.headers[*col].clone())
+            } else {
+                headers.push(format!("{}_{}", self.prefix, self.headers[*col].clone()));
+            }
+        }
+
+        headers
+    }
+
+    pub fn output_row(&self, row: usize) -> Vec<String> {
+        let mut output_row = Vec::new();
+        for col in self.output_indexes().iter() {
+            output_row.push(self.data[*col][row].to_string());
+        }
+
+        output_row
+    }
+
+    pub fn output_columns(&self) -> Vec<&Column> {
+        self.output_indexes().iter().map(|e| &self.data[*e]).collect()
+    }
+
+    // Output columns with the kept lat and lng text swapped for the coarsened points, so a
+    // jittered output doesn't still carry the exact coordinates
+    pub fn coarse_output_columns(&self, jitter: Option<Jitter>, precision: Option<usize>) -> Vec<Cow<'_, Column>> {
+        self.output_indexes().iter()
+            .map(|col| match self.coarse_column(*col, jitter, precision) {
+                Some(values) => Cow::Owned(Column::Plain(values)),
+                None => Cow::Borrowed(&self.data[*col])
+            })
+            .collect()
+    }
+
+    pub fn coarse_output_row(&self, row: usize, jitter: Option<Jitter>, precision: Option<usize>) -> Vec<String> {
+        self.output_indexes().iter()
+            .map(|col| self.coarse_value(*col, row, jitter, precision).unwrap_or_else(|| self.data[*col][row].to_string()))
+            .collect()
+    }
+
+    // Which of lat and lng a column is kept text of, if either
+    fn kept_coord(&self, col: usize) -> Option<bool> {
+        let header = Some(&self.headers[col]);
+        if self.kept_coords.0.as_ref() == header {
+            Some(true)
+        } else if self.kept_coords.1.as_ref() == header {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    // A kept lat or lng of a row as the jitter coarsens it, None for other columns or without one
+    fn coarse_value(&self, col: usize, row: usize, jitter: Option<Jitter>, precision: Option<usize>) -> Option<String> {
+        let is_lat = self.kept_coord(col)?;
+        let (lat, lng) = jitter?.apply(self.lat.as_ref()?[row], self.lng.as_ref()?[row]);
+        Some(format_coord(if is_lat {lat} else {lng}, precision))
+    }
+
+    fn coarse_column(&self, col: usize, jitter: Option<Jitter>, precision: Option<usize>) -> Option<Vec<String>> {
+        jitter?;
+        self.kept_coord(col)?;
+        (0..self.shape.1).map(|row| self.coarse_value(col, row, jitter, precision)).collect()
+    }
+
+    // Rough number of bytes the file takes in memory
+    pub fn heap_size(&self) -> usize {
+        let coords = [&self.lat, &self.lng].iter().map(|e| e.as_ref().map_or(0, |e| e.capacity() * 8)).sum::<usize>();
+        self.data.iter().map(|e| e.heap_size()).sum::<usize>() + coords
+    }
+
+    // Columns that can go to disk as (column, bytes). Role and compare columns are read over and
+    // over while matching so they stay in memory, and dictionary columns are already small
+    pub fn spillable_columns(&self) -> Vec<(usize, usize)> {
+        let roles = [self.id, self.addr1, self.addr2, self.city, self.state, self.zipcode, self.country];
+        (0..self.data.len())
+            .filter(|e| !roles.contains(&Some(*e)) && !self.compare_cols.contains(e))
+            .filter(|e| matches!(self.data[*e], Column::Plain(_) | Column::Strings(_)))
+            .map(|e| (e, self.data[e].heap_size()))
+            .collect()
+    }
+
+    // Spill a column to disk, returning the bytes it freed
+    pub fn spill_column(&mut self, col: usize) -> std::io::Result<usize> {
+        let before = self.data[col].heap_size();
+        self.data[col].spill()?;
+        Ok(before.saturating_sub(self.data[col].heap_size()))
+    }
+
+    pub fn compare_row(&self, row: usize) -> Vec<&str> {
+        let mut compare_row = Vec::new();
         for col in self.compare_cols.iter() {
-            compare_row.push(self.data[*col][row].clone());
+            compare_row.push(&self.data[*col][row]);
         }
 
         compare_row
     }
 
+    pub fn compare_kinds(&self) -> &[Compare] {
+        &self.compare_kinds
+    }
+
     pub fn remove_row(&mut self, row: usize) {
         if let Some(lat) = &mut self.lat {
             lat.remove(row);
@@ -583,32 +1573,991 @@ impl DataFrame {
 
         self.shape.1 -= 1;
     }
+
+    // Append all rows of another dataframe with identical headers
+    pub fn append(&mut self, other: DataFrame) -> Result<(), Box<dyn Error>> {
+        if self.headers != other.headers ||
+            self.lat.is_some() != other.lat.is_some() ||
+            self.lng.is_some() != other.lng.is_some() {
+            return Err(format!("Headers of {} do not match {}", other.path, self.path))?;
+        }
+
+        if let (Some(lat), Some(other_lat)) = (&mut self.lat, other.lat) {
+            lat.extend(other_lat);
+        }
+
+        if let (Some(lng), Some(other_lng)) = (&mut self.lng, other.lng) {
+            lng.extend(other_lng);
+        }
+
+        for (col, other_col) in self.data.iter_mut().zip(other.data) {
+            col.extend(other_col);
+        }
+
+        self.shape.1 += other.shape.1;
+        Ok(())
+    }
+
+    // Stable sort of all rows by a column. Values that all parse as numbers are
+    // compared numerically, anything else is compared as text
+    pub fn sort_by_column(&mut self, col: &str, descending: bool) -> Result<(), Box<dyn Error>> {
+        let keys = self.column_values(col)?;
+
+        let numbers: Option<Vec<f64>> = keys.iter()
+            .map(|e| e.trim().parse::<f64>().ok())
+            .collect();
+
+        let mut order: Vec<usize> = (0..self.shape.1).collect();
+        if let Some(numbers) = numbers {
+            order.sort_by(|a, b| numbers[*a].partial_cmp(&numbers[*b]).unwrap_or(Ordering::Equal));
+        } else {
+            order.sort_by(|a, b| keys[*a].cmp(&keys[*b]));
+        }
+
+        if descending {
+            // Reverse within runs of equal keys too, so that ties keep their original order
+            order.reverse();
+            let mut start = 0;
+            while start < order.len() {
+                let mut end = start + 1;
+                while end < order.len() && keys[order[end]] == keys[order[start]] {
+                    end += 1;
+                }
+                order[start..end].reverse();
+                start = end;
+            }
+        }
+
+        self.reorder_rows(&order);
+        Ok(())
+    }
+
+    // Values of a column by name. lat and lng work too once they've been taken out of the columns
+    pub fn column_values(&self, col: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        match self.get_col_index(col) {
+            Ok(index) => Ok(self.data[index].iter().map(String::from).collect()),
+            Err(e) => match col.to_lowercase().as_str() {
+                "lat" if self.lat.is_some() => Ok(self.lat().unwrap().iter().map(|e| e.to_string()).collect()),
+                "lng" if self.lng.is_some() => Ok(self.lng().unwrap().iter().map(|e| e.to_string()).collect()),
+                _ => Err(e)
+            }
+        }
+    }
+
+    // Rearrange rows so that row i becomes the row at order[i]. Rows set aside no longer have
+    // a place among them, so they go back at the end
+    pub fn reorder_rows(&mut self, order: &[usize]) {
+        for row in self.set_aside.rows.iter_mut() {
+            row.0 = self.shape.1;
+        }
+
+        if let Some(lat) = &mut self.lat {
+            *lat = order.iter().map(|row| lat[*row]).collect();
+        }
+
+        if let Some(lng) = &mut self.lng {
+            *lng = order.iter().map(|row| lng[*row]).collect();
+        }
+
+        for col in self.data.iter_mut() {
+            col.reorder(order);
+        }
+    }
+
+    // Add a row_id column and make it an output column. start is the row number of
+    // the first row, so that appended files continue the numbering
+    pub fn add_row_ids(&mut self, file_index: usize, mode: RowId, start: usize) -> Result<(), Box<dyn Error>> {
+        if self.get_col_index("row_id").is_ok() {
+            return Err(format!("{} already has a row_id column", self.path))?;
+        }
+
+        let mut ids = Vec::with_capacity(self.shape.1);
+        let mut seen = HashSet::with_capacity(self.shape.1);
+
+        for row in 0..self.shape.1 {
+            let id = match mode {
+                RowId::Number => format!("{}-{}", file_index, start + row + 1),
+                RowId::Hash => {
+                    let hash = self.row_hash(row);
+
+                    // Identical rows would hash the same, so number the repeats
+                    let mut id = format!("{:016x}", hash);
+                    let mut count = 2;
+                    while seen.contains(&id) {
+                        id = format!("{:016x}-{}", hash, count);
+                        count += 1;
+                    }
+                    id
+                }
+            };
+            seen.insert(id.clone());
+            ids.push(id);
+        }
+
+        self.headers.push("row_id".to_string());
+        self.data.push(ids.into());
+        self.output_cols.insert(0, self.data.len()-1);
+        self.row_ids = true;
+
+        Ok(())
+    }
+
+    // Hash of everything in a row, the same from one run to the next as long as the row
+    // doesn't change
+    fn row_hash(&self, row: usize) -> u64 {
+        let mut hash = FNV_OFFSET;
+        for col in self.data.iter() {
+            hash = fnv1a(hash, col[row].as_bytes());
+            hash = fnv1a(hash, &[0x1f]);
+        }
+        if let Some(lat) = &self.lat {
+            hash = fnv1a(hash, &lat[row].to_bits().to_le_bytes());
+        }
+        if let Some(lng) = &self.lng {
+            hash = fnv1a(hash, &lng[row].to_bits().to_le_bytes());
+        }
+        hash
+    }
+
+    // Names a row the same way in every run of the same file: its id column, the row_id column
+    // added when loading, or a hash of the row when it has neither
+    pub fn stable_id(&self, row: usize) -> String {
+        let col = self.id.or_else(|| self.headers.iter().position(|e| e == "row_id"));
+        match col {
+            Some(col) => self.data[col][row].to_string(),
+            None => format!("{:016x}", self.row_hash(row)),
+        }
+    }
+
+    // Replace a column's values, or add it as an output column if it doesn't exist yet
+    pub fn set_column(&mut self, name: &str, values: Vec<String>) {
+        match self.get_col_index(name) {
+            Ok(col) => self.data[col] = values.into(),
+            Err(_) => {
+                self.headers.push(name.to_string());
+                self.data.push(values.into());
+                self.output_cols.push(self.data.len()-1);
+            }
+        }
+    }
+
+    // Keep only rows inside the geofence, returning how many were removed
+    pub fn clip(&mut self, geofence: &Geofence) -> Result<usize, Box<dyn Error>> {
+        if !self.ready_to_match() {
+            return Err("lat and lng must be set to clip")?;
+        }
+
+        let lat = self.lat().unwrap();
+        let lng = self.lng().unwrap();
+        let keep: Vec<bool> = (0..self.shape.1)
+            .map(|row| geofence.contains(lat[row], lng[row]))
+            .collect();
+
+        let removed = keep.iter().filter(|e| !**e).count();
+        self.retain_rows(&keep);
+
+        Ok(removed)
+    }
+
+    // Add a column of each row's distance in miles from a point, blank for rows without
+    // coordinates. With max, rows further than max miles away are removed, returning how many
+    pub fn distance_from(&mut self, lat: f64, lng: f64, name: &str, max: Option<f64>) -> Result<usize, Box<dyn Error>> {
+        if !self.ready_to_match() {
+            return Err("lat and lng must be set to measure distances")?;
+        }
+        // Only a column from an earlier distance is written over, never one of the file's own
+        if self.get_col_index(name).is_ok() && !self.distances.iter().any(|e| e == name) {
+            return Err(format!("{} already has a column '{}', give the distance another name", self.path, name))?;
+        }
+
+        let mut distances = Vec::with_capacity(self.shape.1);
+        Points::new(self.lat().unwrap(), self.lng().unwrap()).distances(lat, lng, &mut distances);
+        let values = distances.iter()
+            .map(|e| if e.is_nan() {String::new()} else {format!("{:.4}", e)})
+            .collect();
+        self.set_column(name, values);
+        if !self.distances.iter().any(|e| e == name) {
+            self.distances.push(name.to_string());
+        }
+
+        let max = match max {
+            Some(max) => max,
+            None => return Ok(0)
+        };
+        // Rows without coordinates fail the comparison too
+        let keep: Vec<bool> = distances.iter().map(|e| *e <= max).collect();
+        let removed = keep.iter().filter(|e| !**e).count();
+        self.retain_rows(&keep);
+
+        Ok(removed)
+    }
+
+    // Rows whose coordinates aren't on the globe
+    pub fn out_of_range_rows(&self) -> Vec<usize> {
+        match (&self.lat, &self.lng) {
+            (Some(lat), Some(lng)) => (0..self.shape.1).filter(|e| !in_range(lat[*e], lng[*e])).collect(),
+            _ => Vec::new()
+        }
+    }
+
+    // Clear coordinates that aren't on the globe, so the rows are treated as having none
+    pub fn clear_out_of_range(&mut self) -> usize {
+        let rows = self.out_of_range_rows();
+        if let (Some(lat), Some(lng)) = (&mut self.lat, &mut self.lng) {
+            for row in rows.iter() {
+                lat[*row] = f64::NAN;
+                lng[*row] = f64::NAN;
+            }
+        }
+        rows.len()
+    }
+
+    // Take out the rows whose mask entry is false like retain_rows, keeping them aside for
+    // restore_rows to put back
+    pub fn set_aside_rows(&mut self, keep: &[bool]) {
+        if self.set_aside.rows.is_empty() {
+            self.set_aside.headers = self.headers.clone();
+        }
+        let headers = &self.set_aside.headers;
+        let mut kept = 0;
+        let mut rows = Vec::new();
+        for (row, keep) in keep.iter().enumerate() {
+            if *keep {
+                kept += 1;
+                continue;
+            }
+            let values = headers.iter()
+                .map(|e| self.get_col_index(e).map_or(String::new(), |col| self.data[col][row].to_string()))
+                .collect();
+            let coord = |e: &Option<Vec<f64>>| e.as_ref().map_or(f64::NAN, |e| e[row]);
+            rows.push((kept, values, coord(&self.lat), coord(&self.lng)));
+        }
+
+        self.retain_rows(keep);
+        self.set_aside.rows.extend(rows);
+        self.set_aside.rows.sort_by_key(|e| e.0);
+    }
+
+    // Put back every row set_aside_rows took out, returning how many there were. Columns added
+    // since are blank for them, and they're only geocoded if they were before
+    pub fn restore_rows(&mut self) -> usize {
+        let set_aside = std::mem::take(&mut self.set_aside);
+        if set_aside.rows.is_empty() {
+            return 0;
+        }
+
+        let height = self.shape.1 + set_aside.rows.len();
+        let sources: Vec<Option<usize>> = self.headers.iter().map(|e| set_aside.headers.iter().position(|h| h == e)).collect();
+        let mut data: Vec<Column> = self.data.iter().map(|_| Column::with_capacity(height)).collect();
+        let mut lat = self.lat.as_ref().map(|_| Vec::with_capacity(height));
+        let mut lng = self.lng.as_ref().map(|_| Vec::with_capacity(height));
+
+        let mut set_aside_rows = set_aside.rows.into_iter().peekable();
+        for row in 0..=self.shape.1 {
+            while let Some((_, values, row_lat, row_lng)) = set_aside_rows.next_if(|e| e.0 <= row) {
+                for (col, source) in data.iter_mut().zip(sources.iter()) {
+                    col.push(source.map_or(String::new(), |e| values[e].clone()));
+                }
+                lat.iter_mut().for_each(|e| e.push(row_lat));
+                lng.iter_mut().for_each(|e| e.push(row_lng));
+            }
+            if row < self.shape.1 {
+                for (col, source) in data.iter_mut().zip(self.data.iter()) {
+                    col.push_from(source, row);
+                }
+                lat.iter_mut().zip(self.lat.iter()).for_each(|(e, lat)| e.push(lat[row]));
+                lng.iter_mut().zip(self.lng.iter()).for_each(|(e, lng)| e.push(lng[row]));
+            }
+        }
+
+        for col in data.iter_mut() {
+            col.seal();
+        }
+        let restored = height - self.shape.1;
+        self.data = data;
+        self.lat = lat;
+        self.lng = lng;
+        self.shape.1 = height;
+        restored
+    }
+
+    // Rows in the file along with those set aside
+    pub fn total_rows(&self) -> usize {
+        self.shape.1 + self.set_aside.rows.len()
+    }
+
+    // Keep only the rows whose mask entry is true
+    pub fn retain_rows(&mut self, keep: &[bool]) {
+        // Rows set aside go back before the same row, or the next one left if it's gone
+        let mut kept = Vec::with_capacity(keep.len() + 1);
+        kept.push(0);
+        for keep in keep.iter() {
+            kept.push(kept.last().unwrap() + *keep as usize);
+        }
+        for row in self.set_aside.rows.iter_mut() {
+            row.0 = kept[row.0.min(keep.len())];
+        }
+
+        if let Some(lat) = &mut self.lat {
+            let mut row = 0;
+            lat.retain(|_| { row += 1; keep[row-1] });
+        }
+
+        if let Some(lng) = &mut self.lng {
+            let mut row = 0;
+            lng.retain(|_| { row += 1; keep[row-1] });
+        }
+
+        for col in self.data.iter_mut() {
+            col.retain(keep);
+        }
+
+        self.shape.1 = keep.iter().filter(|e| **e).count();
+    }
+
+    // Drop rows that are identical on the given columns (or every column if none are
+    // given), keeping the first occurrence. Returns the number of rows removed
+    pub fn dedup_exact(&mut self, cols: &[&str]) -> Result<usize, Box<dyn Error>> {
+        let mut indexes = Vec::with_capacity(cols.len());
+        for col in cols {
+            indexes.push(self.get_col_index(col)?);
+        }
+        // Added row ids are different on every row, so whole rows are compared without them
+        let all_cols = indexes.is_empty();
+        if all_cols {
+            let row_id = self.get_col_index("row_id").ok().filter(|_| self.row_ids);
+            indexes = (0..self.data.len()).filter(|e| Some(*e) != row_id).collect();
+        }
+
+        let mut seen = HashSet::with_capacity(self.shape.1);
+        let mut keep = Vec::with_capacity(self.shape.1);
+
+        for row in 0..self.shape.1 {
+            let mut key = indexes.iter()
+                .map(|col| &self.data[*col][row])
+                .collect::<Vec<&str>>()
+                .join("\u{1f}");
+
+            // Coordinates live outside of data, so include them when comparing whole rows
+            if all_cols {
+                if let Some(lat) = &self.lat {
+                    key.push_str(&format!("\u{1f}{}", lat[row].to_bits()));
+                }
+                if let Some(lng) = &self.lng {
+                    key.push_str(&format!("\u{1f}{}", lng[row].to_bits()));
+                }
+            }
+
+            keep.push(seen.insert(key));
+        }
+
+        let removed = keep.iter().filter(|e| !**e).count();
+        self.retain_rows(&keep);
+
+        Ok(removed)
+    }
 }
 
-async fn fetch_single(client: &Client, addr: &str, key: &str) -> Result<(f64, f64, String), Box<dyn Error>> {
-    let params = [("address", addr), ("key", key)];
-    let res = client.get("https://maps.googleapis.com/maps/api/geocode/json")
-        .query(&params)
+// Parse a column of decimal or DMS coordinates, reporting how many values failed
+// Blank values become NaN. Other values that aren't coordinates do too, unless strict
+fn parse_coord_column(col: &str, values: &Column, strict: bool) -> Result<Vec<f64>, Box<dyn Error>> {
+    let mut failed = 0;
+    let coords = values.iter()
+        .map(|e| parse_coord(e).unwrap_or_else(|| {
+            if !e.trim().is_empty() {
+                failed += 1;
+            }
+            f64::NAN
+        }))
+        .collect();
+
+    if failed > 0 {
+        let message = format!("{} values in {} could not be parsed as coordinates", failed, col);
+        if strict {
+            return Err(message.into());
+        }
+        println!("{}", message);
+    }
+
+    Ok(coords)
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// FNV-1a, used instead of DefaultHasher since ids need to be stable between releases
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// Where the columns of a file are, for parsing its rows
+struct Layout<'a> {
+    path: &'a str,
+    width: usize,           // fields in every row, counting lat and lng
+    columns: usize,         // columns kept as text
+    lat: Option<usize>,
+    lng: Option<usize>,
+    keep_coords: bool,      // lat and lng are kept as text too
+    spillable: Vec<bool>,   // columns that can go to disk while reading
+    budget: Option<usize>,  // bytes a part can take before its biggest columns spill
+}
+
+// Rows parsed from part of a file. Big files are parsed in parts on several threads and the parts
+// put back together in order
+struct Part {
+    data: Vec<Column>,
+    lat: Option<Vec<f64>>,
+    lng: Option<Vec<f64>>,
+    ragged_lines: Vec<u64>,
+    rows: usize,
+    writers: Vec<Option<SpillWriter>>,  // columns going straight to disk once over budget
+    bytes: usize,                       // rough size of the columns still in memory
+}
+
+impl Part {
+    fn new(layout: &Layout, height: usize) -> Part {
+        // Columns aren't sized up front for a budget they'd blow through before a row is read
+        let height = if layout.budget.is_some() {0} else {height};
+        Part {
+            data: (0..layout.columns).map(|_| Column::with_capacity(height)).collect(),
+            lat: layout.lat.map(|_| Vec::with_capacity(height)),
+            lng: layout.lng.map(|_| Vec::with_capacity(height)),
+            ragged_lines: Vec::new(),
+            rows: 0,
+            writers: (0..layout.columns).map(|_| None).collect(),
+            bytes: 0,
+        }
+    }
+
+    fn push(&mut self, col: usize, item: &str, layout: &Layout) -> Result<(), String> {
+        match &mut self.writers[col] {
+            Some(writer) => writer.push(item).map_err(|e| format!("{}: couldn't spill to disk: {}", layout.path, e)),
+            None => {
+                self.bytes += item.len() + std::mem::size_of::<String>();
+                self.data[col].push(item.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    // Send the biggest columns that can go to disk there until the part is back under budget.
+    // Rows read after that are written straight to the spill
+    fn spill(&mut self, layout: &Layout) -> Result<(), String> {
+        let budget = match layout.budget {
+            Some(budget) if self.bytes > budget => budget,
+            _ => return Ok(())
+        };
+
+        let mut columns: Vec<(usize, usize)> = (0..self.data.len())
+            .filter(|e| layout.spillable[*e] && self.writers[*e].is_none())
+            .map(|e| (e, self.data[e].heap_size()))
+            .collect();
+        columns.sort_by_key(|e| std::cmp::Reverse(e.1));
+
+        let error = |e: std::io::Error| format!("{}: couldn't spill to disk: {}", layout.path, e);
+        for (col, size) in columns {
+            if self.bytes <= budget {
+                break;
+            }
+            let mut writer = SpillWriter::new().map_err(error)?;
+            for val in self.data[col].iter() {
+                writer.push(val).map_err(error)?;
+            }
+            self.data[col] = Column::default();
+            self.writers[col] = Some(writer);
+            self.bytes = self.bytes.saturating_sub(size);
+        }
+
+        Ok(())
+    }
+
+    // Map the spilled columns back in once every row is read
+    fn finish(&mut self, layout: &Layout) -> Result<(), String> {
+        for col in 0..self.data.len() {
+            if let Some(writer) = self.writers[col].take() {
+                self.data[col] = Column::from_spill(writer).map_err(|e| format!("{}: couldn't spill to disk: {}", layout.path, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Add a row, evening it out first if it's ragged. Errors are text so they can come back from
+    // the threads parsing other parts
+    fn add(&mut self, record: &mut StringRecord, line: u64, layout: &Layout, options: &LoadOptions) -> Result<(), String> {
+        let width = layout.width;
+        if record.len() != width {
+            match options.ragged {
+                _ if options.strict => {
+                    return Err(format!("{}: line {} has {} fields, expected {}", layout.path, line, record.len(), width));
+                }
+                RaggedPolicy::Error => {
+                    return Err(format!("{}: line {} has {} fields, expected {}", layout.path, line, record.len(), width));
+                }
+                RaggedPolicy::Skip => {
+                    self.ragged_lines.push(line);
+                    return Ok(());
+                }
+                RaggedPolicy::Truncate if record.len() < width => {
+                    self.ragged_lines.push(line);
+                    return Ok(());
+                }
+                _ => {
+                    self.ragged_lines.push(line);
+                    record.truncate(width);
+                    while record.len() < width {
+                        record.push_field("");
+                    }
+                }
+            }
+        }
+
+        let mut offset = 0;
+        for (col, item) in record.iter().enumerate() {
+            let is_lat = layout.lat == Some(col);
+            if is_lat || layout.lng == Some(col) {
+                let coord = parse_coord(item);
+                if coord.is_none() && options.strict && !item.trim().is_empty() {
+                    return Err(format!("{}: line {} has {} '{}', which is not a coordinate", layout.path, line, if is_lat {"lat"} else {"lng"}, item));
+                }
+
+                let coords = if is_lat {&mut self.lat} else {&mut self.lng};
+                coords.as_mut().unwrap().push(coord.unwrap_or(f64::NAN));
+            } else {
+                self.push(col-offset, item, layout)?;
+                continue;
+            }
+
+            if layout.keep_coords {
+                self.push(col-offset, item, layout)?;
+            } else {
+                offset += 1;
+            }
+        }
+        self.rows += 1;
+
+        self.spill(layout)
+    }
+
+    // Add the rows of the part that comes after this one, both finished. A column either part
+    // spilled stays on disk
+    fn extend(&mut self, other: Part, layout: &Layout) -> Result<(), String> {
+        for (col, mut other_col) in self.data.iter_mut().zip(other.data) {
+            if col.is_spilled() != other_col.is_spilled() {
+                col.spill().and_then(|_| other_col.spill()).map_err(|e| format!("{}: couldn't spill to disk: {}", layout.path, e))?;
+            }
+            col.extend(other_col);
+        }
+        if let (Some(lat), Some(other_lat)) = (&mut self.lat, other.lat) {
+            lat.extend(other_lat);
+        }
+        if let (Some(lng), Some(other_lng)) = (&mut self.lng, other.lng) {
+            lng.extend(other_lng);
+        }
+        self.ragged_lines.extend(other.ragged_lines);
+        self.rows += other.rows;
+        Ok(())
+    }
+}
+
+// Parse the rows of a file after the ones already read, a part per thread. first_line is the line
+// bytes starts on, so errors and ragged rows give the same lines as parsing on one thread
+fn parse_parts(bytes: &[u8], first_line: u64, dialect: &Dialect, threads: usize, layout: &Layout, options: &LoadOptions) -> Result<Vec<Part>, String> {
+    let bounds = split_records(bytes, dialect, threads);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = bounds.iter()
+            .map(|(start, end, lines)| scope.spawn(move || {
+                let mut reader = dialect.builder().has_headers(false).from_reader(&bytes[*start..*end]);
+                let mut part = Part::new(layout, 0);
+                let mut record = StringRecord::new();
+                while reader.read_record(&mut record).map_err(|e| format!("{}: {}", layout.path, e))? {
+                    let line = first_line + lines + record.position().map_or(1, |e| e.line()) - 1;
+                    part.add(&mut record, line, layout, options)?;
+                }
+                part.finish(layout)?;
+                Ok(part)
+            }))
+            .collect();
+
+        handles.into_iter()
+            .map(|e| e.join().unwrap_or_else(|_| Err(format!("{}: parsing stopped unexpectedly", layout.path))))
+            .collect()
+    })
+}
+
+// Split bytes into about equal parts, only ever between records. Quotes are followed the way the
+// csv reader does, so a newline in a quoted field isn't taken for the end of a record: only a
+// quote at the start of a field opens one, and a quote anywhere else, like 12" sub, is part of
+// the value. Quotes in comment lines are passed over. Each part comes with the number of lines
+// before it
+fn split_records(bytes: &[u8], dialect: &Dialect, parts: usize) -> Vec<(usize, usize, u64)> {
+    let target = bytes.len() / parts.max(1) + 1;
+    let mut bounds = Vec::with_capacity(parts);
+    let mut start = 0;
+    let mut start_lines = 0;
+    let mut lines = 0;
+    let mut quoted = false;
+    let mut field_start = true;
+    let mut comment = false;
+
+    let mut index = 0;
+    while index < bytes.len() {
+        let byte = bytes[index];
+        if quoted {
+            // Two quotes are a quote in the value, one on its own closes the field
+            if byte == dialect.quote && bytes.get(index+1) == Some(&dialect.quote) {
+                index += 1;
+            } else if byte == dialect.quote {
+                quoted = false;
+            } else if byte == b'\n' {
+                lines += 1;
+            }
+        } else if byte == b'\n' {
+            lines += 1;
+            comment = false;
+            field_start = true;
+            if index + 1 - start >= target {
+                bounds.push((start, index + 1, start_lines));
+                start = index + 1;
+                start_lines = lines;
+            }
+        } else if comment {
+            // Passed over to the end of the line
+        } else if field_start && byte == dialect.quote {
+            quoted = true;
+            field_start = false;
+        } else if Some(byte) == dialect.comment && (index == 0 || bytes[index-1] == b'\n') {
+            comment = true;
+        } else {
+            field_start = byte == dialect.delimiter || byte == b'\r';
+        }
+        index += 1;
+    }
+    if start < bytes.len() {
+        bounds.push((start, bytes.len(), start_lines));
+    }
+
+    bounds
+}
+
+fn is_role(field: &str) -> bool {
+    matches!(field.to_lowercase().as_str(), "id" | "addr1" | "addr2" | "city" | "state" | "zip" | "zipcode" | "country")
+}
+
+// Rename the second and later columns sharing a header to Phone_2, Phone_3 and so on, skipping
+// names another column already has. Returns the index, header and new name of each one renamed
+fn number_duplicates(headers: &mut [String]) -> Vec<(usize, String, String)> {
+    let mut taken: HashSet<String> = headers.iter().cloned().collect();
+    let mut seen = HashSet::new();
+    let mut renamed = Vec::new();
+
+    for (col, header) in headers.iter_mut().enumerate() {
+        if seen.insert(header.clone()) {
+            continue;
+        }
+        let name = (2..).map(|e| format!("{}_{}", header, e)).find(|e| !taken.contains(e)).unwrap();
+        taken.insert(name.clone());
+        renamed.push((col, std::mem::replace(header, name.clone()), name));
+    }
+
+    renamed
+}
+
+// What a row is geocoded from: one line for most providers, and the same split into street, city,
+// state and zip for the Census batch geocoder
+struct Query {
+    line: String,
+    fields: [String; 4],
+}
+
+// What the geocoder found for an address, NaN coordinates if it found nothing
+#[derive(Clone, Debug)]
+pub struct Geocode {
+    pub lat: f64,
+    pub lng: f64,
+    pub address: String,    // normalized address
+    pub country: String,    // two letter code, blank if unknown
+    pub words: String,      // what3words address, blank unless asked for
+    pub quality: String,    // how sure the provider is, blank unless it says
+}
+
+impl Geocode {
+    pub fn not_found() -> Geocode {
+        Geocode {
+            lat: f64::NAN,
+            lng: f64::NAN,
+            address: String::new(),
+            country: String::new(),
+            words: String::new(),
+            quality: String::new(),
+        }
+    }
+
+    pub fn found(&self) -> bool {
+        !self.lat.is_nan() && !self.lng.is_nan()
+    }
+}
+
+// Geocoded row, or why the request failed
+pub type Geocoded = Result<Geocode, String>;
+
+// Geocoded rows as they finish, along with the index of each one's address
+pub type GeocodeStream = Receiver<(usize, Geocoded)>;
+
+// Geocode many addresses under one rate limit, sending each result back with its index as
+// soon as it finishes. Addresses that are None are sent straight back without using up any of
+// the limit. Requests stop being made once the receiver is dropped, or on Ctrl-C, when the
+// requests already made still finish. With a what3words key, the three word address of every
+// point found is looked up too. Each address goes to the provider it's paired with
+pub fn geocode_stream(addresses: Vec<(Provider, Option<String>)>, keys: Keys, words_key: Option<String>, client: Client) -> GeocodeStream {
+    // Semaphore to make sure we don't max out open http connections, and a channel only as big
+    // so unwritten results can't pile up
+    let connections = 30;
+    let (tx, rx) = channel(connections);
+
+    // Requests are traced as part of the command that asked for them
+    let parent = Span::current();
+
+    tokio::spawn(async move {
+        // Google's geocoding api will block us if we exceed 50 requests per second
+        let requests_per_second: usize = 30;
+        let dur = Duration::from_secs_f64(1.0/(requests_per_second as f64));
+        let mut clock = tokio::time::interval(dur);
+
+        let sem = Arc::new(Semaphore::new(connections));
+
+        for (index, (provider, addr)) in addresses.into_iter().enumerate() {
+            if tx.is_closed() || interrupt::requested() {
+                break;
+            }
+
+            let addr = match addr {
+                Some(addr) => addr,
+                None => {
+                    let _ = tx.send((index, Ok(Geocode::not_found()))).await;
+                    continue;
+                }
+            };
+
+            // Rate limit
+            clock.tick().await;
+            let permit = match sem.clone().acquire_owned().await {
+                Ok(permit) if !interrupt::requested() => permit,
+                _ => break
+            };
+
+            let tx = tx.clone();
+            let client = client.clone();
+            let keys = keys.clone();
+            let words_key = words_key.clone();
+            tokio::spawn(async move {
+                let res = fetch_single(&client, provider, &addr, &keys).await.map_err(|e| format!("error fetching {}: {}", addr, e));
+                // A failed three word lookup only costs the row its words, the coordinates are kept
+                let res = match (res, words_key) {
+                    (Ok(mut found), Some(words_key)) if found.found() => {
+                        found.words = three_words(&client, found.lat, found.lng, &words_key).await
+                            .unwrap_or_else(|e| {
+                                warn!(address = %addr, error = %e, "three word lookup failed");
+                                W3W_FAILED.to_string()
+                            });
+                        Ok(found)
+                    }
+                    (res, _) => res,
+                };
+                let _ = tx.send((index, res)).await;
+                drop(permit);
+            }.instrument(parent.clone()));
+        }
+    });
+
+    rx
+}
+
+// Geocode rows with the Census batch geocoder, uploading CENSUS_BATCH_SIZE at a time and
+// sending each result back with its index once its upload is done. Rows that are None are sent
+// straight back. Like geocode_stream, uploads stop once the receiver is dropped or on Ctrl-C
+pub fn batch_stream(rows: Vec<Option<[String; 4]>>, client: Client) -> GeocodeStream {
+    let (tx, rx) = channel(CENSUS_BATCH_SIZE);
+    let parent = Span::current();
+
+    tokio::spawn(async move {
+        let mut batch: Vec<(usize, [String; 4])> = Vec::with_capacity(CENSUS_BATCH_SIZE);
+        let mut rows = rows.into_iter().enumerate().peekable();
+        while let Some((index, row)) = rows.next() {
+            match row {
+                Some(row) => batch.push((index, row)),
+                None => {
+                    if tx.send((index, Ok(Geocode::not_found()))).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            // Uploaded once the batch is full or there are no rows left
+            let full = batch.len() == CENSUS_BATCH_SIZE || rows.peek().is_none();
+            if batch.is_empty() || !full {
+                continue;
+            }
+            if tx.is_closed() || interrupt::requested() {
+                return;
+            }
+
+            let (indexes, fields): (Vec<usize>, Vec<[String; 4]>) = batch.drain(..).unzip();
+            count(&GEOCODE_REQUESTS, fields.len());
+            let results = upload_batch(&client, &fields).instrument(parent.clone()).await;
+            for (index, result) in indexes.into_iter().zip(results) {
+                if !result.found() {
+                    count(&GEOCODE_FAILURES, 1);
+                }
+                if tx.send((index, Ok(result))).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    rx
+}
+
+// Upload rows to the Census batch geocoder, splitting an upload that fails in half and trying
+// each half again, so a row the geocoder chokes on or a dropped connection only costs the rows
+// it has to. Once an upload is down to BATCH_MIN_SPLIT rows it isn't split any further, and its
+// rows are left without coordinates with a match quality of Failed rather than stopping the fetch
+async fn upload_batch(client: &Client, rows: &[[String; 4]]) -> Vec<Geocode> {
+    let mut results = Vec::with_capacity(rows.len());
+    let mut uploads = vec![rows];
+    while let Some(upload) = uploads.pop() {
+        match census_batch(client, upload).await {
+            Ok(found) => results.extend(found),
+            Err(e) if upload.len() > BATCH_MIN_SPLIT => {
+                warn!(error = %e, rows = upload.len(), "census batch upload failed, splitting it");
+                let (first, second) = upload.split_at(upload.len() / 2);
+                uploads.push(second);
+                uploads.push(first);
+            }
+            Err(e) => {
+                warn!(error = %e, rows = upload.len(), "census batch upload failed");
+                results.extend(upload.iter().map(|_| Geocode {
+                    quality: BATCH_FAILED.to_string(),
+                    ..Geocode::not_found()
+                }));
+            }
+        }
+    }
+
+    results
+}
+
+// One stream out of several, mapping each result's index through the indexes of its stream
+pub fn merge_streams(streams: Vec<(GeocodeStream, Vec<usize>)>) -> GeocodeStream {
+    let (tx, rx) = channel(1);
+    for (mut stream, indexes) in streams {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            while let Some((index, result)) = stream.recv().await {
+                if tx.send((indexes[index], result)).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    rx
+}
+
+// Client for geocoding requests, sending a User-Agent that names geomatch unless another is
+// given, along with any extra headers. Some providers like Nominatim turn away requests without
+// an identifying User-Agent, and proxies can need an auth header
+pub fn geocode_client(user_agent: Option<&str>, headers: &[(String, String)]) -> Result<Client, Box<dyn Error>> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| format!("Invalid header name '{}'", name))?;
+        let value = HeaderValue::from_str(value.trim()).map_err(|_| format!("Invalid value for header {}", name))?;
+        map.insert(name, value);
+    }
+
+    let default_agent = concat!("geomatch/", env!("CARGO_PKG_VERSION"));
+    let client = Client::builder()
+        .user_agent(user_agent.unwrap_or(default_agent))
+        .default_headers(map)
+        .build()?;
+    Ok(client)
+}
+
+// Geocode a single address, NaN coordinates if nothing was found
+#[instrument(name = "geocode_request", skip_all, fields(provider = provider.name(), found))]
+pub async fn fetch_single(client: &Client, provider: Provider, addr: &str, keys: &Keys) -> Result<Geocode, Box<dyn Error>> {
+    let key = keys.get(provider)?;
+    count(&GEOCODE_REQUESTS, 1);
+    let result = match provider {
+        Provider::Google => request_geocode(client, addr, key).await,
+        // A single row of a batched file is sent on its own
+        Provider::Census | Provider::CensusBatch => census(client, addr).await,
+        Provider::Mapbox => mapbox(client, addr, key).await,
+    };
+    if !result.as_ref().is_ok_and(|e| e.found()) {
+        count(&GEOCODE_FAILURES, 1);
+    }
+
+    Span::current().record("found", result.as_ref().is_ok_and(|e| e.found()));
+    if let Err(e) = &result {
+        warn!(error = %e, "geocode request failed");
+    }
+    result
+}
+
+// Check the geocoder can be reached and takes the key, without geocoding anything. A request
+// without an address is turned away as invalid, but only once the key has been accepted
+pub async fn check_geocoder(client: &Client, key: &str) -> Result<(), Box<dyn Error>> {
+    let json: Value = client.get(GEOCODE_URL)
+        .query(&[("address", ""), ("key", key)])
         .send()
+        .await?
+        .json()
         .await?;
 
-    if !res.status().is_success() {
-        println!("error fetching {}", addr);
+    match json["status"].as_str() {
+        Some("REQUEST_DENIED") | Some("OVER_QUERY_LIMIT") | Some("OVER_DAILY_LIMIT") => {
+            Err(format!("{}: {}", json["status"].as_str().unwrap(), json["error_message"].as_str().unwrap_or("no reason given")))?
+        }
+        _ => Ok(())
     }
+}
 
-    let text = res.text().await?;
+async fn request_geocode(client: &Client, addr: &str, key: &str) -> Result<Geocode, Box<dyn Error>> {
+    let params = [("address", addr), ("key", key)];
+    let res = client.get(GEOCODE_URL)
+        .query(&params)
+        .send()
+        .await?;
 
-    let json: Value = serde_json::from_str(text.as_str()).unwrap();
+    // An error page from a proxy or an outage is the row's error rather than a panic
+    let status = res.status();
+    let text = res.text().await?;
+    let json: Value = match serde_json::from_str(text.as_str()) {
+        Ok(json) => json,
+        Err(_) if !status.is_success() => return Err(format!("HTTP {}", status))?,
+        Err(e) => return Err(format!("unreadable response: {}", e))?
+    };
     let lat = json["results"][0]["geometry"]["location"]["lat"].as_f64();
     let lng = json["results"][0]["geometry"]["location"]["lng"].as_f64();
     let addr = json["results"][0]["formatted_address"].as_str();
-
-    if lat.is_some() || lng.is_some() {
-        let lat = lat.unwrap();
-        let lng = lng.unwrap();
-        let addr = addr.unwrap_or("").to_string();
-
-        Ok((lat, lng, addr))
+    let country = json["results"][0]["address_components"].as_array()
+        .and_then(|e| e.iter().find(|e| e["types"].as_array().is_some_and(|e| e.iter().any(|e| e == "country"))))
+        .and_then(|e| e["short_name"].as_str());
+
+    if let (Some(lat), Some(lng)) = (lat, lng) {
+        Ok(Geocode {
+            lat,
+            lng,
+            address: addr.unwrap_or("").to_string(),
+            country: country.unwrap_or("").to_string(),
+            words: String::new(),
+            quality: String::new(),
+        })
     } else {
         println!("{}", json);
         if let Some(status) = json["status"].as_str() {
@@ -616,6 +2565,38 @@ async fn fetch_single(client: &Client, addr: &str, key: &str) -> Result<(f64, f6
                 println!("\nMaxed Out API KEY\n");
             }
         }
-        Ok((f64::NAN, f64::NAN, "".to_string()))
+        Ok(Geocode::not_found())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records of each part split_records cuts bytes into, with the line each starts on
+    fn split_parse(bytes: &[u8], dialect: &Dialect, parts: usize) -> Vec<(u64, Vec<String>)> {
+        let mut records = Vec::new();
+        for (start, end, lines) in split_records(bytes, dialect, parts) {
+            for record in dialect.builder().from_reader(&bytes[start..end]).into_records() {
+                let record = record.unwrap();
+                records.push((lines + record.position().unwrap().line(), record.iter().map(String::from).collect()));
+            }
+        }
+        records
+    }
+
+    #[test]
+    fn split_records_follows_quotes_like_the_reader() {
+        let bytes = b"1,12\" sub,plain\n2,\"two\nlines\",x\n3,\"a \"\"quoted\"\" word\",y\n4,\"\",z\n";
+        let dialect = Dialect { has_headers: false, ..Dialect::default() };
+        let whole = split_parse(bytes, &dialect, 1);
+        assert_eq!(whole.len(), 4);
+        assert_eq!(whole[0].1, ["1", "12\" sub", "plain"]);
+        assert_eq!(whole[1], (2, vec!["2".to_string(), "two\nlines".to_string(), "x".to_string()]));
+
+        // However many parts it's cut into, never inside a record
+        for parts in 2..=bytes.len() {
+            assert_eq!(split_parse(bytes, &dialect, parts), whole, "{} parts", parts);
+        }
     }
 }
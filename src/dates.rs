@@ -0,0 +1,125 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+// Which comes first in a date like 01/05/2023, where either could be the month
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DateOrder {
+    MonthFirst,     // 01/05/2023 is January 5th, as in the US
+    DayFirst,       // 01/05/2023 is May 1st
+}
+
+impl DateOrder {
+    pub fn parse(text: &str) -> Option<DateOrder> {
+        match text.to_lowercase().as_str() {
+            "mdy" => Some(DateOrder::MonthFirst),
+            "dmy" => Some(DateOrder::DayFirst),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DateOrder::MonthFirst => "mdy",
+            DateOrder::DayFirst => "dmy",
+        }
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "january", "february", "march", "april", "may", "june", "july", "august", "september", "october", "november", "december"
+];
+
+const WEEKDAYS: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+// A time after the date, e.g. T10:00:00Z or " 3:15 PM", which doesn't change the day
+fn time_suffix() -> &'static Regex {
+    static TIME: OnceLock<Regex> = OnceLock::new();
+    TIME.get_or_init(|| {
+        Regex::new(r"(?i)[t\s]+\d{1,2}:\d{2}(:\d{2}(\.\d+)?)?\s*(am|pm)?\s*(z|utc|[+-]\d{2}:?\d{2})?$").unwrap()
+    })
+}
+
+// A date in any of the usual formats as YYYY-MM-DD, so the same day written two ways compares
+// equal. 2023-01-05, 01/05/2023, 1/5/23, Jan 5, 2023, 5 January 2023 and 05-JAN-23 all work.
+// None for anything that isn't a whole date
+pub fn normalize(value: &str, order: DateOrder) -> Option<String> {
+    let value = time_suffix().replace(value.trim(), "");
+    let mut parts: Vec<String> = value
+        .split(|c: char| c == '/' || c == '-' || c == '.' || c == ',' || c.is_whitespace())
+        .filter(|e| !e.is_empty())
+        .map(|e| e.to_lowercase())
+        .collect();
+
+    // Thursday, January 5, 2023
+    if parts.len() == 4 && weekday(&parts[0]) {
+        parts.remove(0);
+    }
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let (year, month, day) = match [month(&parts[0]), month(&parts[1]), month(&parts[2])] {
+        [Some(month), None, None] => (&parts[2], month, day(&parts[1])?),
+        [None, Some(month), None] if parts[0].len() == 4 => (&parts[0], month, day(&parts[2])?),
+        [None, Some(month), None] => (&parts[2], month, day(&parts[0])?),
+        [None, None, None] if parts[0].len() == 4 => (&parts[0], number(&parts[1])?, number(&parts[2])?),
+        [None, None, None] => {
+            let (first, second) = (number(&parts[0])?, number(&parts[1])?);
+            let (month, day) = match order {
+                DateOrder::MonthFirst => (first, second),
+                DateOrder::DayFirst => (second, first),
+            };
+
+            // A number over 12 can only be the day
+            if month > 12 && day <= 12 {(&parts[2], day, month)} else {(&parts[2], month, day)}
+        }
+        _ => return None
+    };
+
+    let year = match (year.len(), number(year)?) {
+        (4, year) => year,
+        // Two digit years pivot like strptime, 69 and up are the 1900s
+        (2, year) if year < 69 => 2000 + year,
+        (2, year) => 1900 + year,
+        _ => return None
+    };
+
+    if month == 0 || month > 12 || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+fn number(part: &str) -> Option<u32> {
+    if part.is_empty() || part.len() > 4 || !part.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    part.parse().ok()
+}
+
+// Day of the month, allowing 1st, 2nd, 3rd and so on
+fn day(part: &str) -> Option<u32> {
+    let digits = ["st", "nd", "rd", "th"].iter().find_map(|e| part.strip_suffix(e)).unwrap_or(part);
+    number(digits).filter(|_| digits.len() <= 2)
+}
+
+// Month of a name or an abbreviation of at least 3 letters, like jan or sept
+fn month(part: &str) -> Option<u32> {
+    if part.len() < 3 {
+        return None;
+    }
+    MONTHS.iter().position(|e| e.starts_with(part)).map(|e| e as u32 + 1)
+}
+
+fn weekday(part: &str) -> bool {
+    part.len() >= 3 && WEEKDAYS.iter().any(|e| e.starts_with(part))
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+
+// What a column holds, for deciding whether it makes a good compare or output column
+pub struct Summary {
+    pub rows: usize,
+    pub blank: usize,                   // empty or NaN
+    pub distinct: usize,                // distinct values, not counting blanks
+    pub top: Vec<(String, usize)>,      // most common values and their counts, most common first
+    pub range: Option<(f64, f64)>,      // min and max, if every value that isn't blank is a number
+}
+
+pub fn summarize(values: &[String], top: usize) -> Summary {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut blank = 0;
+    let mut range: Option<(f64, f64)> = None;
+    let mut numeric = true;
+
+    for value in values {
+        // Rows without coordinates have NaN for lat and lng
+        let value = value.trim();
+        if value.is_empty() || value.parse::<f64>().is_ok_and(f64::is_nan) {
+            blank += 1;
+            continue;
+        }
+        *counts.entry(value).or_insert(0) += 1;
+
+        match value.parse::<f64>() {
+            Ok(number) if numeric && number.is_finite() => {
+                range = Some(range.map_or((number, number), |(min, max)| (min.min(number), max.max(number))));
+            }
+            _ => numeric = false,
+        }
+    }
+
+    // Ties go alphabetically so the same file always shows the same values
+    let mut sorted: Vec<(&str, usize)> = counts.iter().map(|(value, count)| (*value, *count)).collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    Summary {
+        rows: values.len(),
+        blank,
+        distinct: counts.len(),
+        top: sorted.into_iter().take(top).map(|(value, count)| (value.to_string(), count)).collect(),
+        range: if numeric {range} else {None},
+    }
+}
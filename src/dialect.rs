@@ -0,0 +1,218 @@
+use csv::{Reader, ReaderBuilder};
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Result};
+
+// Number of bytes read from the start of a file when guessing its dialect
+const SAMPLE_SIZE: usize = 64 * 1024;
+
+// Delimiters we know how to detect, in order of preference on ties
+const DELIMITERS: [u8; 4] = [b',', b'|', b'\t', b';'];
+
+// Describes how a csv file is laid out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub has_headers: bool,
+    pub comment: Option<u8>,    // lines starting with it are skipped
+}
+
+impl Default for Dialect {
+    fn default() -> Dialect {
+        Dialect {
+            delimiter: b',',
+            quote: b'"',
+            has_headers: true,
+            comment: None,
+        }
+    }
+}
+
+impl Display for Dialect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let delimiter = match self.delimiter {
+            b',' => "comma",
+            b'|' => "pipe",
+            b'\t' => "tab",
+            b';' => "semicolon",
+            _ => "other"
+        };
+        let quote = if self.quote == b'\'' {"single"} else {"double"};
+        let headers = if self.has_headers {"headers"} else {"no headers"};
+
+        write!(f, "{}, {} quotes, {}", delimiter, quote, headers)?;
+        if let Some(comment) = self.comment {
+            write!(f, ", {} comments", comment as char)?;
+        }
+        Ok(())
+    }
+}
+
+// Read the first block of a file to sniff, leaving the reader just after it
+pub fn read_sample<R: Read>(source: &mut R) -> Result<Vec<u8>> {
+    let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+    source.take(SAMPLE_SIZE as u64).read_to_end(&mut sample)?;
+
+    Ok(sample)
+}
+
+impl Dialect {
+    // Guess the dialect of a file from a sample of its first rows, leaving out comment lines
+    pub fn sniff(sample: &[u8], comment: Option<u8>) -> Dialect {
+        // Drop the last line since it is probably cut off
+        let mut sample = sample;
+        if sample.len() == SAMPLE_SIZE {
+            if let Some(end) = sample.iter().rposition(|e| *e == b'\n') {
+                sample = &sample[..end+1];
+            }
+        }
+
+        let uncommented: Vec<u8>;
+        if let Some(comment) = comment {
+            uncommented = sample.split_inclusive(|e| *e == b'\n')
+                .filter(|e| e.first() != Some(&comment))
+                .flatten()
+                .cloned()
+                .collect();
+            sample = &uncommented;
+        }
+
+        let quote = sniff_quote(sample);
+
+        // Pick the delimiter that splits rows most consistently into the most fields
+        let mut best: Option<(u8, usize, usize)> = None;
+        for delimiter in DELIMITERS.iter() {
+            let rows = split_rows(sample, *delimiter, quote);
+            if rows.is_empty() {
+                continue;
+            }
+
+            let width = rows[0].len();
+            if width < 2 {
+                continue;
+            }
+            let consistent = rows.iter().filter(|e| e.len() == width).count();
+
+            if best.is_none() || (consistent, width) > (best.unwrap().1, best.unwrap().2) {
+                best = Some((*delimiter, consistent, width));
+            }
+        }
+
+        let delimiter = best.map_or(b',', |e| e.0);
+        let rows = split_rows(sample, delimiter, quote);
+
+        Dialect {
+            delimiter,
+            quote,
+            has_headers: sniff_headers(&rows),
+            comment,
+        }
+    }
+
+    pub fn reader<R: Read>(&self, source: R) -> Reader<R> {
+        self.builder().from_reader(source)
+    }
+
+    // Reader settings for the dialect, to change before reading
+    pub fn builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .has_headers(self.has_headers)
+            .comment(self.comment)
+            .flexible(true);
+        builder
+    }
+}
+
+// Split sample into rows of fields
+fn split_rows(sample: &[u8], delimiter: u8, quote: u8) -> Vec<Vec<String>> {
+    ReaderBuilder::new()
+        .delimiter(delimiter)
+        .quote(quote)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(sample)
+        .records()
+        .filter_map(|e| e.ok())
+        .map(|e| e.iter().map(|e| e.to_string()).collect())
+        .collect()
+}
+
+// Use single quotes only if more fields start with them than with double quotes
+fn sniff_quote(sample: &[u8]) -> u8 {
+    let mut double = 0;
+    let mut single = 0;
+
+    for window in sample.windows(2) {
+        if DELIMITERS.contains(&window[0]) || window[0] == b'\n' {
+            match window[1] {
+                b'"' => double += 1,
+                b'\'' => single += 1,
+                _ => {}
+            }
+        }
+    }
+
+    if single > double {b'\''} else {b'"'}
+}
+
+// Whether the first row is a header, going by whether it looks like the rows under it column by
+// column. A column of numbers under text, or of values all the same length under one that isn't,
+// counts towards a header, and one that fits right in counts against. Blank cells and columns
+// that mix text and numbers say nothing, so a header like 2020 or an empty trailing column
+// doesn't outweigh the rest. Without anything to go on the first row is taken as the header
+fn sniff_headers(rows: &[Vec<String>]) -> bool {
+    let (first, body) = match rows.split_first() {
+        Some(rows) => rows,
+        None => return true
+    };
+    let is_number = |e: &str| e.parse::<f64>().is_ok();
+
+    // Only a row of numbers says anything without rows to compare it to
+    if body.is_empty() {
+        return !first.iter().map(|e| e.trim()).any(|e| !e.is_empty() && is_number(e));
+    }
+
+    let mut votes = 0;
+    for (col, header) in first.iter().enumerate() {
+        let header = header.trim();
+        let values: Vec<&str> = body.iter()
+            .filter_map(|e| e.get(col))
+            .map(|e| e.trim())
+            .filter(|e| !e.is_empty())
+            .collect();
+        if header.is_empty() || values.is_empty() {
+            continue;
+        }
+
+        if values.iter().all(|e| is_number(e)) {
+            votes += if is_number(header) {-1} else {1};
+        } else if values.iter().all(|e| !is_number(e)) {
+            let length = values[0].chars().count();
+            if values.iter().all(|e| e.chars().count() == length) {
+                votes += if header.chars().count() == length {-1} else {1};
+            }
+        }
+    }
+
+    votes >= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has_headers(sample: &str) -> bool {
+        Dialect::sniff(sample.as_bytes(), None).has_headers
+    }
+
+    #[test]
+    fn headers_are_told_apart_by_the_rows_under_them() {
+        assert!(has_headers("id,name,2020,\n1,Ann,5.5,\n2,Bob,6.25,\n"));
+        assert!(has_headers("name,zip\nAnn,55401\nBob,55402\n"));
+        assert!(!has_headers("1,Ann,5.5\n2,Bob,6.25\n"));
+        assert!(!has_headers("MN,55401\nWI,53703\n"));
+    }
+}
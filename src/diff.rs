@@ -0,0 +1,192 @@
+use csv::ReaderBuilder;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+// How a row's match differs between two match files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    Gained,     // matched in the new file only
+    Lost,       // matched in the old file only
+    Partner,    // matched in both, to something else
+    Distance,   // matched to the same thing, at a different distance
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Change::Gained => "gained",
+            Change::Lost => "lost",
+            Change::Partner => "partner",
+            Change::Distance => "distance",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Change {
+    pub const ALL: [Change; 4] = [Change::Gained, Change::Lost, Change::Partner, Change::Distance];
+}
+
+pub struct Difference {
+    pub change: Change,
+    pub key: String,
+    pub old: Option<String>,    // the row's match in each file, none if it had none
+    pub new: Option<String>,
+}
+
+// One row of a match file, split into what it matched and how far away
+struct Row {
+    partner: Vec<String>,
+    dists: Vec<f64>,
+}
+
+impl Row {
+    fn matched(&self) -> bool {
+        self.dists.iter().any(|e| e.is_finite())
+    }
+
+    // Distances only differ by float noise when precision isn't set
+    fn same_dists(&self, other: &Row) -> bool {
+        self.dists.iter().zip(other.dists.iter())
+            .all(|(a, b)| a.is_finite() == b.is_finite() && (!a.is_finite() || (a - b).abs() < 1e-9))
+    }
+
+    fn describe(&self) -> String {
+        let partner: Vec<&str> = self.partner.iter().map(|e| e.as_str()).filter(|e| !e.is_empty()).collect();
+        let dists: Vec<String> = self.dists.iter().filter(|e| e.is_finite()).map(|e| e.to_string()).collect();
+        format!("{} ({} mi)", partner.join(", "), dists.join(", "))
+    }
+}
+
+// Distance, bearing and road columns, written after each matched file's output columns
+fn is_metric(header: &str) -> bool {
+    header.ends_with("_dist") || header.ends_with("_bearing") || header.ends_with("_road_time")
+}
+
+fn is_dist(header: &str) -> bool {
+    header.ends_with("_dist") && !header.ends_with("_road_dist")
+}
+
+// Rows of a match file by key, in the order they were written
+struct MatchFile {
+    rows: HashMap<String, Row>,
+    order: Vec<String>,
+    repeated: usize,    // rows with a key seen before, which aren't compared
+}
+
+// Only columns in shared are compared, so a column added or dropped between runs isn't a change
+fn read_rows(path: &str, key: &str, shared: &[String]) -> Result<MatchFile, Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(b'|')
+        .from_path(path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    let headers: Vec<String> = reader.headers()?.iter().map(String::from).collect();
+    let key_col = headers.iter().position(|e| e == key)
+        .ok_or(format!("{} has no column named {}", path, key))?;
+    let partner_cols: Vec<usize> = headers.iter().enumerate()
+        .filter(|(i, e)| *i != key_col && !is_metric(e) && shared.contains(e))
+        .map(|(i, _)| i)
+        .collect();
+    let dist_cols: Vec<usize> = headers.iter().enumerate()
+        .filter(|(_, e)| is_dist(e) && shared.contains(e))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut rows = HashMap::new();
+    let mut order = Vec::new();
+    let mut repeated = 0;
+    for record in reader.records() {
+        let record = record?;
+        let key = record.get(key_col).unwrap_or("").to_string();
+        if key.is_empty() {
+            continue;
+        }
+        if rows.contains_key(&key) {
+            repeated += 1;
+            continue;
+        }
+
+        let row = Row {
+            partner: partner_cols.iter().map(|e| record.get(*e).unwrap_or("").to_string()).collect(),
+            dists: dist_cols.iter().map(|e| record.get(*e).and_then(|e| e.parse().ok()).unwrap_or(f64::NAN)).collect(),
+        };
+        order.push(key.clone());
+        rows.insert(key, row);
+    }
+
+    Ok(MatchFile {rows, order, repeated})
+}
+
+fn read_headers(path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(b'|')
+        .from_path(path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    Ok(reader.headers()?.iter().map(String::from).collect())
+}
+
+// Compare two match files row by row, joined on the key column, the first column unless given.
+// Also returns the number of matched rows that didn't change
+pub fn diff_matches(old_path: &str, new_path: &str, key: Option<&str>) -> Result<(Vec<Difference>, usize), Box<dyn Error>> {
+    let old_headers = read_headers(old_path)?;
+    let new_headers = read_headers(new_path)?;
+    let key = match key {
+        Some(key) => key.to_string(),
+        None => old_headers.first().ok_or(format!("{} is empty", old_path))?.clone(),
+    };
+
+    let shared: Vec<String> = old_headers.iter().filter(|e| new_headers.contains(e)).cloned().collect();
+    for (path, headers) in [(old_path, &old_headers), (new_path, &new_headers)] {
+        let only: Vec<&str> = headers.iter().filter(|e| !shared.contains(e)).map(|e| e.as_str()).collect();
+        if !only.is_empty() {
+            println!("Only {} has {}, not comparing them", path, only.join(", "));
+        }
+    }
+
+    let old_file = read_rows(old_path, &key, &shared)?;
+    let new_file = read_rows(new_path, &key, &shared)?;
+    for (path, file) in [(old_path, &old_file), (new_path, &new_file)] {
+        if file.repeated > 0 {
+            println!("Warning: {} rows of {} repeat a {} already seen, only the first is compared. Pass a key column with unique values",
+                file.repeated, path, key);
+        }
+    }
+    let (old_rows, new_rows) = (&old_file.rows, &new_file.rows);
+
+    let mut differences = Vec::new();
+    let mut unchanged = 0;
+    for key in new_file.order.iter() {
+        let new = &new_rows[key];
+        let old = old_rows.get(key).filter(|e| e.matched());
+        if !new.matched() {
+            continue;
+        }
+
+        let change = match old {
+            None => Some(Change::Gained),
+            Some(old) if old.partner != new.partner => Some(Change::Partner),
+            Some(old) if !old.same_dists(new) => Some(Change::Distance),
+            Some(_) => None,
+        };
+        match change {
+            Some(change) => differences.push(Difference {
+                change,
+                key: key.clone(),
+                old: old.map(|e| e.describe()),
+                new: Some(new.describe()),
+            }),
+            None => unchanged += 1,
+        }
+    }
+
+    // Matched before, but unmatched or missing now
+    for key in old_file.order.iter() {
+        let old = &old_rows[key];
+        if old.matched() && !new_rows.get(key).is_some_and(|e| e.matched()) {
+            differences.push(Difference {change: Change::Lost, key: key.clone(), old: Some(old.describe()), new: None});
+        }
+    }
+
+    Ok((differences, unchanged))
+}
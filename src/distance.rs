@@ -0,0 +1,141 @@
+pub const R: f64 = 3958.8; // Radius of Earth (miles)
+
+pub fn haversine(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let delta_lat = (lat2-lat1).to_radians();
+    let delta_lng = (lng2-lng1).to_radians();
+
+    let a = (delta_lat*0.5).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (delta_lng*0.5).sin().powi(2);
+    let c = 2.0 * a.sqrt().atan2((1.0-a).sqrt());
+    R * c
+}
+
+// Coordinates of every row as unit vectors, precomputed once so that the distance from a
+// point to every row is a pass over contiguous slices with no trig in the inner loop, which
+// the compiler can vectorize. The chord between two unit vectors gives the same great circle
+// distance as haversine
+pub struct Points {
+    x: Vec<f64>,
+    y: Vec<f64>,
+    z: Vec<f64>,
+}
+
+impl Points {
+    pub fn new(lat: &[f64], lng: &[f64]) -> Points {
+        let mut points = Points {
+            x: Vec::with_capacity(lat.len()),
+            y: Vec::with_capacity(lat.len()),
+            z: Vec::with_capacity(lat.len()),
+        };
+
+        for (lat, lng) in lat.iter().zip(lng) {
+            let (x, y, z) = unit_vector(*lat, *lng);
+            points.x.push(x);
+            points.y.push(y);
+            points.z.push(z);
+        }
+
+        points
+    }
+
+    // Squared chord length from a point to every row, NaN for rows without coordinates
+    fn chords(&self, lat: f64, lng: f64, out: &mut Vec<f64>) {
+        let (x, y, z) = unit_vector(lat, lng);
+        out.clear();
+        out.extend(self.x.iter().zip(&self.y).zip(&self.z).map(|((x2, y2), z2)| {
+            (x2-x)*(x2-x) + (y2-y)*(y2-y) + (z2-z)*(z2-z)
+        }));
+    }
+
+    // Distance in miles from a point to every row, NaN for rows without coordinates
+    pub fn distances(&self, lat: f64, lng: f64, out: &mut Vec<f64>) {
+        self.chords(lat, lng, out);
+        for val in out.iter_mut() {
+            *val = chord_to_miles(*val);
+        }
+    }
+
+    // Closest row to a point and its distance in miles, leaving out the row skip, e.g. the point
+    // itself when a file is compared with itself. None if no row has coordinates
+    pub fn nearest(&self, lat: f64, lng: f64, skip: Option<usize>, chords: &mut Vec<f64>) -> Option<(usize, f64)> {
+        self.chords(lat, lng, chords);
+
+        let mut min: Option<(usize, f64)> = None;
+        for (row, chord) in chords.iter().cloned().enumerate() {
+            if Some(row) != skip && !chord.is_nan() && min.is_none_or(|e| chord < e.1) {
+                min = Some((row, chord));
+            }
+        }
+        min.map(|(row, chord)| (row, chord_to_miles(chord)))
+    }
+
+    // Rows within radius miles of a point along with their distance. Rows are filtered on the
+    // chord length so only the rows kept need converting back to miles
+    pub fn within(&self, lat: f64, lng: f64, radius: f64, out: &mut Vec<(usize, f64)>) {
+        let mut chords = Vec::with_capacity(self.x.len());
+        self.chords(lat, lng, &mut chords);
+
+        let max = miles_to_chord(radius);
+        out.clear();
+        for (row, chord) in chords.into_iter().enumerate() {
+            // NaN chords fail the comparison too
+            if chord <= max {
+                out.push((row, chord_to_miles(chord)));
+            }
+        }
+    }
+}
+
+fn unit_vector(lat: f64, lng: f64) -> (f64, f64, f64) {
+    let (lat, lng) = (lat.to_radians(), lng.to_radians());
+    (lat.cos() * lng.cos(), lat.cos() * lng.sin(), lat.sin())
+}
+
+// Squared chord length to great circle distance in miles. Clamped rather than min'd so NaN
+// chords stay NaN, min would turn them into the far side of the earth
+fn chord_to_miles(chord: f64) -> f64 {
+    2.0 * R * (chord.sqrt() * 0.5).clamp(0.0, 1.0).asin()
+}
+
+// Great circle distance in miles to squared chord length, radii past the far side of the
+// earth cover everything
+fn miles_to_chord(miles: f64) -> f64 {
+    let angle = (miles / R).min(std::f64::consts::PI);
+    let chord = 2.0 * (angle * 0.5).sin();
+    chord * chord
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Minneapolis, Chicago, a row without coordinates and the far side of the earth
+    const LAT: [f64; 4] = [44.9778, 41.8781, f64::NAN, -44.9778];
+    const LNG: [f64; 4] = [-93.2650, -87.6298, f64::NAN, 86.7350];
+
+    #[test]
+    fn chords_agree_with_haversine() {
+        let points = Points::new(&LAT, &LNG);
+        let mut out = Vec::new();
+        points.distances(LAT[0], LNG[0], &mut out);
+
+        assert_eq!(out[0], 0.0);
+        assert!((out[1] - 354.8).abs() < 0.5, "{}", out[1]);
+        assert!((out[1] - haversine(LAT[0], LNG[0], LAT[1], LNG[1])).abs() < 1e-6);
+        assert!((out[3] - std::f64::consts::PI * R).abs() < 1e-6, "{}", out[3]);
+    }
+
+    #[test]
+    fn rows_without_coordinates_stay_nan() {
+        let points = Points::new(&LAT, &LNG);
+        let mut out = Vec::new();
+        points.distances(LAT[0], LNG[0], &mut out);
+        assert!(out[2].is_nan());
+
+        let mut chords = Vec::new();
+        assert_eq!(points.nearest(LAT[0], LNG[0], Some(0), &mut chords).map(|e| e.0), Some(1));
+
+        let mut within = Vec::new();
+        points.within(LAT[0], LNG[0], 400.0, &mut within);
+        assert_eq!(within.iter().map(|e| e.0).collect::<Vec<_>>(), [0, 1]);
+    }
+}
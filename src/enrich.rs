@@ -0,0 +1,186 @@
+use futures::future::join_all;
+use indicatif::ProgressBar;
+use reqwest::Client;
+use serde_json::Value;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use super::geofence::Geofence;
+
+const CENSUS_URL: &str = "https://geocoding.geo.census.gov/geocoder/geographies/coordinates";
+
+// Columns that can be derived from which boundary a point falls in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Enrichment {
+    County,
+    Tract,
+    BlockGroup,
+    Timezone,
+}
+
+impl Enrichment {
+    pub fn parse(name: &str) -> Result<Enrichment, Box<dyn Error>> {
+        match name.to_lowercase().as_str() {
+            "county" => Ok(Enrichment::County),
+            "tract" => Ok(Enrichment::Tract),
+            "blockgroup" => Ok(Enrichment::BlockGroup),
+            "timezone" => Ok(Enrichment::Timezone),
+            _ => Err("Invalid enrichment, expected county, tract, blockgroup or timezone".into())
+        }
+    }
+
+    // Names of the columns written for this enrichment
+    pub fn columns(&self) -> Vec<&'static str> {
+        match self {
+            Enrichment::County => vec!["county", "county_fips"],
+            Enrichment::Tract => vec!["tract"],
+            Enrichment::BlockGroup => vec!["tract", "block_group"],
+            Enrichment::Timezone => vec!["timezone"],
+        }
+    }
+
+    // Census geographies layer with the same boundaries, if there is one
+    pub fn census_layer(&self) -> Option<&'static str> {
+        match self {
+            Enrichment::County => Some("Counties"),
+            Enrichment::Tract => Some("Census Tracts"),
+            Enrichment::BlockGroup => Some("Census Block Groups"),
+            Enrichment::Timezone => None,
+        }
+    }
+
+    // Column values from a boundary's properties. Reads the field names used by the
+    // Census TIGER/Line and cartographic boundary files, the geographies api, and
+    // timezone-boundary-builder
+    fn values(&self, properties: &Value) -> Vec<String> {
+        let field = |name: &str| match &properties[name] {
+            Value::String(val) => val.clone(),
+            Value::Number(val) => val.to_string(),
+            _ => "".to_string()
+        };
+
+        match self {
+            Enrichment::County => {
+                let fips = match field("GEOID") {
+                    geoid if geoid.is_empty() => field("STATEFP") + &field("COUNTYFP"),
+                    geoid => geoid
+                };
+                // The api's NAME includes "County", BASENAME is the bare name like the files
+                let name = match field("BASENAME") {
+                    name if name.is_empty() => field("NAME"),
+                    name => name
+                };
+                vec![name, fips]
+            }
+            // Block group GEOIDs are the tract GEOID plus one digit
+            Enrichment::Tract => vec![field("GEOID").chars().take(11).collect()],
+            Enrichment::BlockGroup => {
+                let geoid = field("GEOID");
+                vec![geoid.chars().take(11).collect(), geoid]
+            }
+            Enrichment::Timezone => vec![field("tzid")],
+        }
+    }
+
+    // Look up every point with the Census geographies api, a few requests at a time. A failed
+    // request is that row's error, the rest still get looked up
+    pub async fn census_lookup_all(&self, points: Vec<(f64, f64)>) -> Vec<Result<Option<Vec<String>>, String>> {
+        let sem = Arc::new(Semaphore::new(10));
+        let bar = Arc::new(ProgressBar::new(points.len() as u64));
+        let client = Client::new();
+        let enrichment = *self;
+
+        let mut tasks = Vec::with_capacity(points.len());
+        for (lat, lng) in points {
+            let sem_clone = sem.clone();
+            let bar_clone = bar.clone();
+            let client_clone = client.clone();
+
+            tasks.push(tokio::spawn(async move {
+                let result = if lat.is_nan() || lng.is_nan() {
+                    Ok(None)
+                } else {
+                    let _permit = sem_clone.acquire().await.unwrap();
+                    enrichment.census_lookup(&client_clone, lat, lng).await.map_err(|e| e.to_string())
+                };
+                bar_clone.inc(1);
+                result
+            }));
+        }
+
+        let results = join_all(tasks).await;
+        bar.finish();
+
+        results.into_iter()
+            .map(|e| e.unwrap_or_else(|e| Err(e.to_string())))
+            .collect()
+    }
+
+    // Look up a point with the Census geographies api, None if it is outside every boundary
+    pub async fn census_lookup(&self, client: &Client, lat: f64, lng: f64) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+        let layer = self.census_layer().ok_or("No Census layer for this enrichment")?;
+        let params = [
+            ("x", lng.to_string()),
+            ("y", lat.to_string()),
+            ("benchmark", "Public_AR_Current".to_string()),
+            ("vintage", "Current_Current".to_string()),
+            ("layers", layer.to_string()),
+            ("format", "json".to_string()),
+        ];
+        let json: Value = client.get(CENSUS_URL)
+            .query(&params)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if let Some(errors) = json["errors"].as_array() {
+            return Err(format!("Census lookup failed: {:?}", errors).into());
+        }
+
+        let geography = &json["result"]["geographies"][layer][0];
+        if geography.is_null() {
+            return Ok(None);
+        }
+
+        Ok(Some(self.values(geography)))
+    }
+}
+
+struct Region {
+    values: Vec<String>,
+    bounds: (f64, f64, f64, f64),
+    area: Geofence,
+}
+
+// Boundaries loaded from a GeoJSON file for looking up points
+pub struct Boundaries {
+    regions: Vec<Region>,
+}
+
+impl Boundaries {
+    pub fn load(enrichment: Enrichment, path: &str) -> Result<Boundaries, Box<dyn Error>> {
+        let regions: Vec<Region> = Geofence::features(path)?
+            .into_iter()
+            .map(|(properties, area)| Region {
+                values: enrichment.values(&properties),
+                bounds: area.bounds(),
+                area,
+            })
+            .collect();
+
+        if regions.is_empty() {
+            return Err(format!("{} contains no polygons", path).into());
+        }
+
+        Ok(Boundaries { regions })
+    }
+
+    // Values of the first boundary containing the point
+    pub fn lookup(&self, lat: f64, lng: f64) -> Option<&[String]> {
+        self.regions.iter()
+            .filter(|e| lat >= e.bounds.0 && lng >= e.bounds.1 && lat <= e.bounds.2 && lng <= e.bounds.3)
+            .find(|e| e.area.contains(lat, lng))
+            .map(|e| e.values.as_slice())
+    }
+}
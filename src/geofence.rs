@@ -0,0 +1,272 @@
+use serde_json::Value;
+use std::error::Error;
+use std::fs::read_to_string;
+
+// Ring of (lng, lat) points, closed or not
+type Ring = Vec<(f64, f64)>;
+
+// Area to restrict rows to, made up of polygons which may have holes
+pub struct Geofence {
+    polygons: Vec<Vec<Ring>>,
+}
+
+impl Geofence {
+    // Parse one of
+    //   bbox [min_lat] [min_lng] [max_lat] [max_lng]
+    //   POLYGON((...)) or MULTIPOLYGON(((...)))
+    //   [path] to a GeoJSON or WKT file
+    pub fn parse(input: &[&str]) -> Result<Geofence, Box<dyn Error>> {
+        let kind = input.first();
+        if kind.is_none() {
+            return Err("area required")?;
+        }
+        let kind = kind.unwrap();
+
+        if kind.eq_ignore_ascii_case("bbox") {
+            if input.len() < 5 {
+                return Err("bbox requires min_lat min_lng max_lat max_lng")?;
+            }
+            let mut bounds = Vec::with_capacity(4);
+            for val in input[1..5].iter() {
+                bounds.push(val.parse::<f64>()?);
+            }
+            let (min_lat, min_lng, max_lat, max_lng) = (bounds[0], bounds[1], bounds[2], bounds[3]);
+
+            return Ok(Geofence {
+                polygons: vec![vec![vec![
+                    (min_lng, min_lat), (max_lng, min_lat), (max_lng, max_lat), (min_lng, max_lat)
+                ]]]
+            });
+        }
+
+        let text = input.join(" ");
+        if is_wkt(&text) {
+            return parse_wkt(&text);
+        }
+
+        let contents = read_to_string(&text)?;
+        if is_wkt(&contents) {
+            parse_wkt(&contents)
+        } else {
+            parse_geojson(&serde_json::from_str(&contents)?)
+        }
+    }
+
+    // Every feature of a GeoJSON file as its own area, along with its properties
+    pub fn features(path: &str) -> Result<Vec<(Value, Geofence)>, Box<dyn Error>> {
+        let json: Value = serde_json::from_str(&read_to_string(path)?)?;
+        let empty = Vec::new();
+        let features = match json["type"].as_str() {
+            Some("FeatureCollection") => json["features"].as_array().unwrap_or(&empty).iter().collect(),
+            Some("Feature") => vec![&json],
+            _ => return Err("Expected a GeoJSON Feature or FeatureCollection".into())
+        };
+
+        let mut areas = Vec::with_capacity(features.len());
+        for feature in features {
+            let mut polygons = Vec::new();
+            collect_geojson(&feature["geometry"], &mut polygons)?;
+            if !polygons.is_empty() {
+                areas.push((feature["properties"].clone(), Geofence { polygons }));
+            }
+        }
+
+        Ok(areas)
+    }
+
+    // Bounding box as (min_lat, min_lng, max_lat, max_lng)
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        let mut bounds = (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for (lng, lat) in self.polygons.iter().flatten().flatten() {
+            bounds.0 = bounds.0.min(*lat);
+            bounds.1 = bounds.1.min(*lng);
+            bounds.2 = bounds.2.max(*lat);
+            bounds.3 = bounds.3.max(*lng);
+        }
+
+        bounds
+    }
+
+    pub fn contains(&self, lat: f64, lng: f64) -> bool {
+        if lat.is_nan() || lng.is_nan() {
+            return false;
+        }
+
+        // Even-odd rule over every ring of a polygon takes care of holes
+        self.polygons.iter().any(|polygon| {
+            polygon.iter().filter(|ring| ring_contains(ring, lng, lat)).count() % 2 == 1
+        })
+    }
+}
+
+// Ray casting point in polygon test
+fn ring_contains(ring: &[(f64, f64)], x: f64, y: f64) -> bool {
+    if ring.len() < 3 {
+        return false;
+    }
+
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+
+    for i in 0..ring.len() {
+        let (xi, yi) = ring[i];
+        let (xj, yj) = ring[j];
+
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+
+    inside
+}
+
+fn is_wkt(text: &str) -> bool {
+    let upper = text.trim_start().to_uppercase();
+    upper.starts_with("POLYGON") || upper.starts_with("MULTIPOLYGON")
+}
+
+// Parses POLYGON and MULTIPOLYGON by treating every innermost parenthesized list as a
+// ring, and using the nesting depth to group rings into polygons
+fn parse_wkt(text: &str) -> Result<Geofence, Box<dyn Error>> {
+    let multi = text.trim_start().to_uppercase().starts_with("MULTIPOLYGON");
+    let polygon_depth = if multi {2} else {1};
+
+    let mut polygons: Vec<Vec<Ring>> = Vec::new();
+    let mut depth = 0;
+    let mut ring_start = None;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => {
+                depth += 1;
+                if depth == polygon_depth {
+                    polygons.push(Vec::new());
+                }
+                ring_start = Some(i + 1);
+            }
+            ')' => {
+                if let Some(start) = ring_start.take() {
+                    let mut ring = Vec::new();
+                    for point in text[start..i].split(',') {
+                        let coords: Vec<&str> = point.split_whitespace().collect();
+                        if coords.len() < 2 {
+                            return Err(format!("Invalid WKT point '{}'", point.trim()).into());
+                        }
+                        ring.push((coords[0].parse::<f64>()?, coords[1].parse::<f64>()?));
+                    }
+                    match polygons.last_mut() {
+                        Some(polygon) => polygon.push(ring),
+                        None => return Err("Invalid WKT polygon")?
+                    }
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+
+    if polygons.is_empty() || polygons.iter().any(|e| e.is_empty()) {
+        return Err("WKT contains no polygons".into());
+    }
+
+    Ok(Geofence { polygons })
+}
+
+// Collects Polygon and MultiPolygon geometries from any GeoJSON object
+fn parse_geojson(json: &Value) -> Result<Geofence, Box<dyn Error>> {
+    let mut polygons = Vec::new();
+    collect_geojson(json, &mut polygons)?;
+
+    if polygons.is_empty() {
+        return Err("GeoJSON contains no polygons".into());
+    }
+
+    Ok(Geofence { polygons })
+}
+
+fn collect_geojson(json: &Value, polygons: &mut Vec<Vec<Ring>>) -> Result<(), Box<dyn Error>> {
+    match json["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in json["features"].as_array().unwrap_or(&Vec::new()) {
+                collect_geojson(feature, polygons)?;
+            }
+        }
+        Some("Feature") => collect_geojson(&json["geometry"], polygons)?,
+        Some("GeometryCollection") => {
+            for geometry in json["geometries"].as_array().unwrap_or(&Vec::new()) {
+                collect_geojson(geometry, polygons)?;
+            }
+        }
+        Some("Polygon") => polygons.push(geojson_polygon(&json["coordinates"])?),
+        Some("MultiPolygon") => {
+            for polygon in json["coordinates"].as_array().unwrap_or(&Vec::new()) {
+                polygons.push(geojson_polygon(polygon)?);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn geojson_polygon(coordinates: &Value) -> Result<Vec<Ring>, Box<dyn Error>> {
+    let mut rings = Vec::new();
+
+    for ring in coordinates.as_array().unwrap_or(&Vec::new()) {
+        let mut points = Vec::new();
+        for point in ring.as_array().unwrap_or(&Vec::new()) {
+            let lng = point[0].as_f64();
+            let lat = point[1].as_f64();
+            if lng.is_none() || lat.is_none() {
+                return Err("Invalid GeoJSON coordinate".into());
+            }
+            points.push((lng.unwrap(), lat.unwrap()));
+        }
+        rings.push(points);
+    }
+
+    Ok(rings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn bbox_keeps_points_inside() {
+        let area = Geofence::parse(&["bbox", "44.8", "-93.5", "45.1", "-93.0"]).unwrap();
+        assert!(area.contains(44.98, -93.27));
+        assert!(!area.contains(44.98, -92.9));
+        assert!(!area.contains(f64::NAN, f64::NAN));
+        assert_eq!(area.bounds(), (44.8, -93.5, 45.1, -93.0));
+    }
+
+    // WKT and GeoJSON are both lng lat, and the hole in the first polygon is left out
+    #[test]
+    fn wkt_and_geojson_polygons_with_holes() {
+        let wkt = "MULTIPOLYGON(((-94 44, -92 44, -92 46, -94 46, -94 44), (-93.5 44.5, -92.5 44.5, -92.5 45.5, -93.5 45.5, -93.5 44.5)), ((0 0, 1 0, 1 1, 0 1, 0 0)))";
+        let geojson = json!({"type": "FeatureCollection", "features": [{
+            "type": "Feature",
+            "geometry": {"type": "MultiPolygon", "coordinates": [
+                [[[-94, 44], [-92, 44], [-92, 46], [-94, 46], [-94, 44]], [[-93.5, 44.5], [-92.5, 44.5], [-92.5, 45.5], [-93.5, 45.5], [-93.5, 44.5]]],
+                [[[0, 0], [1, 0], [1, 1], [0, 1], [0, 0]]],
+            ]},
+        }]});
+
+        for area in [Geofence::parse(&[wkt]).unwrap(), parse_geojson(&geojson).unwrap()] {
+            assert!(area.contains(44.2, -93.8));
+            assert!(!area.contains(45.0, -93.0));
+            assert!(area.contains(0.5, 0.5));
+            assert!(!area.contains(0.5, 1.5));
+        }
+    }
+
+    #[test]
+    fn bad_polygons_are_refused() {
+        assert!(Geofence::parse(&["POLYGON((-94 44, -93))"]).is_err());
+        assert!(Geofence::parse(&["bbox", "44.8", "-93.5"]).is_err());
+        assert!(parse_geojson(&json!({"type": "Point", "coordinates": [-93.27, 44.98]})).is_err());
+    }
+}
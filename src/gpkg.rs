@@ -0,0 +1,223 @@
+use rusqlite::{params, params_from_iter, Connection};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::remove_file;
+use super::atomic;
+use super::jitter::Jitter;
+use super::state::MatchRun;
+
+// 'GPKG' and version 1.3.0, which readers check to tell a GeoPackage from any other SQLite file
+const APPLICATION_ID: i32 = 0x47504B47;
+const USER_VERSION: i32 = 10300;
+const WGS84: i32 = 4326;
+
+const WGS84_WKT: &str = r#"GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4326"]]"#;
+
+// Tables every GeoPackage needs, from the spec
+const SCHEMA: &str = "
+CREATE TABLE gpkg_spatial_ref_sys (
+    srs_name TEXT NOT NULL,
+    srs_id INTEGER PRIMARY KEY,
+    organization TEXT NOT NULL,
+    organization_coordsys_id INTEGER NOT NULL,
+    definition TEXT NOT NULL,
+    description TEXT
+);
+CREATE TABLE gpkg_contents (
+    table_name TEXT NOT NULL PRIMARY KEY,
+    data_type TEXT NOT NULL,
+    identifier TEXT UNIQUE,
+    description TEXT DEFAULT '',
+    last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    min_x DOUBLE,
+    min_y DOUBLE,
+    max_x DOUBLE,
+    max_y DOUBLE,
+    srs_id INTEGER,
+    CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+CREATE TABLE gpkg_geometry_columns (
+    table_name TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    geometry_type_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL,
+    z TINYINT NOT NULL,
+    m TINYINT NOT NULL,
+    CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name),
+    CONSTRAINT uk_gc_table_name UNIQUE (table_name),
+    CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
+    CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+";
+
+// Write the last match as a GeoPackage with two layers: matches, a point for every row of the
+// match file with the same columns, and match_pairs, a line from each row to what it matched
+// with the distance between them. Rows without coordinates have no geometry. Points are
+// coarsened by the jitter if there is one
+pub fn write_gpkg(path: &str, run: &MatchRun, jitter: Option<Jitter>) -> Result<(), Box<dyn Error>> {
+    let coarse = |(lat, lng): (f64, f64)| jitter.map_or((lat, lng), |e| e.apply(lat, lng));
+
+    // Written under the temporary name and moved into place once complete, like other outputs
+    let temp = atomic::temp_path(path);
+    let _ = remove_file(&temp);
+    let mut conn = Connection::open(&temp)?;
+    conn.pragma_update(None, "application_id", APPLICATION_ID)?;
+    conn.pragma_update(None, "user_version", USER_VERSION)?;
+
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    tx.execute(
+        "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params!["Undefined cartesian SRS", -1, "NONE", -1, "undefined"],
+    )?;
+    tx.execute(
+        "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params!["Undefined geographic SRS", 0, "NONE", 0, "undefined"],
+    )?;
+    tx.execute(
+        "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params!["WGS 84 geodetic", WGS84, "EPSG", WGS84, WGS84_WKT],
+    )?;
+
+    // Points, one per row of the match file
+    let headers = column_names(&run.output.output_headers());
+    let columns: Vec<String> = headers.iter().map(|e| format!("{} TEXT", quote(e))).collect();
+    tx.execute_batch(&format!(
+        "CREATE TABLE matches (fid INTEGER PRIMARY KEY AUTOINCREMENT, geom POINT, {})",
+        columns.join(", ")
+    ))?;
+
+    let mut bounds = Bounds::default();
+    let mut fids = vec![None; run.origins.len()];
+    {
+        let names: Vec<String> = headers.iter().map(|e| quote(e)).collect();
+        let slots: Vec<String> = (0..headers.len()).map(|e| format!("?{}", e+2)).collect();
+        let mut insert = tx.prepare(&format!(
+            "INSERT INTO matches (geom, {}) VALUES (?1, {})",
+            names.join(", "), slots.join(", ")
+        ))?;
+
+        for row in run.written_rows() {
+            let (_, lat, lng) = run.origins[row];
+            let geom = if lat.is_nan() || lng.is_nan() {
+                None
+            } else {
+                let (lat, lng) = coarse((lat, lng));
+                bounds.add(lat, lng);
+                Some(geometry(&point(lat, lng)))
+            };
+
+            let mut values: Vec<rusqlite::types::Value> = vec![geom.into()];
+            values.extend(run.output.output_row(row).into_iter().map(rusqlite::types::Value::from));
+            insert.execute(params_from_iter(values))?;
+            fids[row] = Some(tx.last_insert_rowid());
+        }
+    }
+    add_layer(&tx, "matches", "POINT", &bounds)?;
+
+    // Lines from each row to its match, along with the file it came from and the distance
+    tx.execute_batch(
+        "CREATE TABLE match_pairs (fid INTEGER PRIMARY KEY AUTOINCREMENT, geom LINESTRING, match_fid INTEGER, file INTEGER, dist DOUBLE)"
+    )?;
+    let mut bounds = Bounds::default();
+    {
+        let mut insert = tx.prepare("INSERT INTO match_pairs (geom, match_fid, file, dist) VALUES (?1, ?2, ?3, ?4)")?;
+        for pair in run.pairs.iter() {
+            let fid = match fids[pair.row] {
+                Some(fid) => fid,
+                None => continue
+            };
+            let (from, to) = (coarse(pair.from), coarse(pair.to));
+            bounds.add(from.0, from.1);
+            bounds.add(to.0, to.1);
+            insert.execute(params![geometry(&line(from, to)), fid, pair.file as i64, pair.dist])?;
+        }
+    }
+    add_layer(&tx, "match_pairs", "LINESTRING", &bounds)?;
+
+    tx.commit()?;
+    conn.close().map_err(|e| e.1)?;
+    atomic::finish_path(path)?;
+
+    Ok(())
+}
+
+fn add_layer(tx: &rusqlite::Transaction, table: &str, geometry_type: &str, bounds: &Bounds) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO gpkg_contents (table_name, data_type, identifier, min_x, min_y, max_x, max_y, srs_id) VALUES (?1, 'features', ?1, ?2, ?3, ?4, ?5, ?6)",
+        params![table, bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y, WGS84],
+    )?;
+    tx.execute(
+        "INSERT INTO gpkg_geometry_columns (table_name, column_name, geometry_type_name, srs_id, z, m) VALUES (?1, 'geom', ?2, ?3, 0, 0)",
+        params![table, geometry_type, WGS84],
+    )?;
+    Ok(())
+}
+
+// SQLite column names ignore case, and fid and geom are taken, so headers that clash are numbered
+fn column_names(headers: &[String]) -> Vec<String> {
+    let mut taken: HashSet<String> = HashSet::from(["fid".to_string(), "geom".to_string()]);
+    headers.iter()
+        .map(|header| {
+            let mut name = header.clone();
+            let mut attempt = 2;
+            while !taken.insert(name.to_lowercase()) {
+                name = format!("{}_{}", header, attempt);
+                attempt += 1;
+            }
+            name
+        })
+        .collect()
+}
+
+// Headers are quoted so any text can be a column name
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// Little endian WKB of a point, which is x then y so longitude first
+fn point(lat: f64, lng: f64) -> Vec<u8> {
+    let mut wkb = vec![1];
+    wkb.extend(1u32.to_le_bytes());
+    wkb.extend(lng.to_le_bytes());
+    wkb.extend(lat.to_le_bytes());
+    wkb
+}
+
+fn line(from: (f64, f64), to: (f64, f64)) -> Vec<u8> {
+    let mut wkb = vec![1];
+    wkb.extend(2u32.to_le_bytes());
+    wkb.extend(2u32.to_le_bytes());
+    for (lat, lng) in [from, to] {
+        wkb.extend(lng.to_le_bytes());
+        wkb.extend(lat.to_le_bytes());
+    }
+    wkb
+}
+
+// GeoPackage geometry: a "GP" header with the version, flags for a little endian header without
+// an envelope, and the SRS, followed by the WKB
+fn geometry(wkb: &[u8]) -> Vec<u8> {
+    let mut blob = vec![b'G', b'P', 0, 1];
+    blob.extend(WGS84.to_le_bytes());
+    blob.extend_from_slice(wkb);
+    blob
+}
+
+// Extent of a layer for gpkg_contents, left blank for a layer without geometry
+#[derive(Default)]
+struct Bounds {
+    min_x: Option<f64>,
+    min_y: Option<f64>,
+    max_x: Option<f64>,
+    max_y: Option<f64>,
+}
+
+impl Bounds {
+    fn add(&mut self, lat: f64, lng: f64) {
+        self.min_x = Some(self.min_x.map_or(lng, |e| e.min(lng)));
+        self.min_y = Some(self.min_y.map_or(lat, |e| e.min(lat)));
+        self.max_x = Some(self.max_x.map_or(lng, |e| e.max(lng)));
+        self.max_y = Some(self.max_y.map_or(lat, |e| e.max(lat)));
+    }
+}
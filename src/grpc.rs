@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::channel;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use super::command::{run_command, split_input};
+use super::state::State;
+
+pub mod proto {
+    tonic::include_proto!("geomatch");
+}
+
+use proto::geomatch_server::{Geomatch, GeomatchServer};
+use proto::{GeocodeReply, GeocodeRequest, MatchRequest, Row};
+
+struct Service {
+    new_state: Box<dyn Fn() -> State + Send + Sync>,
+    requests: AtomicUsize,  // numbers each request's working directory
+}
+
+// Serve the service in proto/geomatch.proto until the process is stopped
+pub async fn serve_grpc<F>(addr: SocketAddr, new_state: F) -> Result<(), Box<dyn Error>>
+    where F: Fn() -> State + Send + Sync + 'static {
+    let service = Service {
+        new_state: Box::new(new_state),
+        requests: AtomicUsize::new(0),
+    };
+
+    println!("Listening for gRPC on {}", addr);
+    Server::builder()
+        .add_service(GeomatchServer::new(service))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl Geomatch for Service {
+    async fn geocode(&self, request: Request<GeocodeRequest>) -> Result<Response<GeocodeReply>, Status> {
+        let address = request.into_inner().address;
+        // Same provider, keys and client as a session started with these options
+        let result = (self.new_state)().lookup(&address).await
+            .map_err(|e| e.to_string());
+        let result = result.map_err(Status::unavailable)?;
+
+        Ok(Response::new(GeocodeReply {
+            found: result.found(),
+            lat: result.lat,
+            lng: result.lng,
+            norm_address: result.address,
+            country: result.country,
+        }))
+    }
+
+    type MatchStream = ReceiverStream<Result<Row, Status>>;
+
+    async fn r#match(&self, request: Request<MatchRequest>) -> Result<Response<Self::MatchStream>, Status> {
+        let request = request.into_inner();
+        if request.files.is_empty() {
+            return Err(Status::invalid_argument("files required"));
+        }
+
+        // Files are written to a directory of their own, along with anything the commands write
+        let id = self.requests.fetch_add(1, Ordering::SeqCst);
+        let dir = std::env::temp_dir().join(format!("geomatch_grpc_{}_{}", std::process::id(), id));
+        create_dir_all(&dir).map_err(|e| Status::internal(e.to_string()))?;
+
+        let mut paths = Vec::with_capacity(request.files.len());
+        for (index, file) in request.files.iter().enumerate() {
+            // Only keep the file name so uploads can't be written outside of the directory
+            let name = Path::new(&file.name).file_name()
+                .map_or(format!("file_{}.csv", index), |e| e.to_string_lossy().to_string());
+            let path = dir.join(name).to_string_lossy().to_string();
+            write(&path, &file.content).map_err(|e| Status::internal(e.to_string()))?;
+            paths.push(path);
+        }
+
+        let mut lines = request.commands;
+        if request.fetch {
+            lines.push("fetch".to_string());
+        }
+        lines.push("match".to_string());
+
+        let mut state = (self.new_state)();
+        state.set_output_dir(&dir.to_string_lossy());
+
+        // Commands run on a blocking thread since they aren't written to be sent between
+        // threads while they await
+        let handle = Handle::current();
+        let result = tokio::task::spawn_blocking(move || handle.block_on(async move {
+            for path in paths {
+                state.add_file(&path).map_err(|e| e.to_string())?;
+            }
+
+            for line in lines.iter() {
+                if let Err(e) = run_command(&mut state, split_input(line)).await {
+                    return Err(format!("{}: {}", line, e));
+                }
+            }
+
+            let run = state.last_match().unwrap();
+            let mut rows = vec![run.output.output_headers()];
+            rows.extend(run.written_rows().into_iter().map(|e| run.output.output_row(e)));
+            Ok(rows)
+        })).await;
+
+        let _ = remove_dir_all(&dir);
+        let rows = match result {
+            Ok(rows) => rows.map_err(Status::invalid_argument)?,
+            Err(e) => return Err(Status::internal(e.to_string()))
+        };
+
+        let (tx, rx) = channel(64);
+        tokio::spawn(async move {
+            for values in rows {
+                if tx.send(Ok(Row { values })).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::File;
+
+    fn service() -> Service {
+        Service {
+            new_state: Box::new(|| State::new(None)),
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    fn request(commands: &[&str]) -> Request<MatchRequest> {
+        let content = b"name,lat,lng\none,44.97,-93.26\ntwo,44.98,-93.27\n".to_vec();
+        Request::new(MatchRequest {
+            files: vec![
+                File { name: "a.csv".to_string(), content: content.clone() },
+                File { name: "../b.csv".to_string(), content },
+            ],
+            commands: commands.iter().map(|e| e.to_string()).collect(),
+            fetch: false,
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn match_streams_rows() {
+        let response = service().r#match(request(&[])).await.unwrap();
+        let mut rows = response.into_inner().into_inner();
+        let headers = rows.recv().await.unwrap().unwrap();
+        assert!(!headers.values.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn match_refuses_output_outside_request_dir() {
+        for line in ["set matchoutput ../../etc/x", "set matchoutput /etc/x"] {
+            let status = service().r#match(request(&[line])).await.err().unwrap();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert!(status.message().contains("outside the output directory"), "{}", status.message());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn geocode_uses_the_session_provider() {
+        let service = Service {
+            new_state: Box::new(|| {
+                let mut state = State::new(None);
+                state.set_provider(vec!["provider", "mapbox"]).unwrap();
+                state
+            }),
+            requests: AtomicUsize::new(0),
+        };
+        let request = Request::new(GeocodeRequest { address: "1 Main St".to_string() });
+        let status = service.geocode(request).await.err().unwrap();
+        assert!(status.message().contains("mapbox needs an access token"), "{}", status.message());
+    }
+}
@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Whether a fetch or match is running, and whether Ctrl-C asked it to stop
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Catch Ctrl-C so a running fetch or match can stop cleanly and keep what it finished. Ctrl-C
+// with nothing running, or a second one while stopping, quits straight away like before
+pub fn install() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if RUNNING.load(Ordering::SeqCst) && !INTERRUPTED.swap(true, Ordering::SeqCst) {
+                println!("\nStopping after the requests in flight, press Ctrl-C again to quit now");
+            } else {
+                std::process::exit(130);
+            }
+        }
+    });
+}
+
+// Mark a fetch or match as running until the guard is dropped
+pub fn running() -> Running {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    RUNNING.store(true, Ordering::SeqCst);
+    Running
+}
+
+pub struct Running;
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.store(false, Ordering::SeqCst);
+        INTERRUPTED.store(false, Ordering::SeqCst);
+    }
+}
+
+// Whether the running fetch or match should stop
+pub fn requested() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,104 @@
+use super::provider::Provider;
+
+// Street addresses that are the corner of two streets rather than a house, like Main St & 2nd Ave
+// or 5th St and Elm Ave, which crash reports and utility records are full of
+
+// What goes between the two streets, longest first so " & " wins over "&"
+const SEPARATORS: [&str; 6] = [" & ", "&", " and ", " at ", " @ ", " / "];
+
+// Words that end a street name, so both sides of a corner have to look like streets. Shops at
+// Main St and AT&T Plaza are places, not corners
+const SUFFIXES: [&str; 44] = [
+    "street", "st", "avenue", "ave", "av", "road", "rd", "boulevard", "blvd", "drive", "dr", "lane", "ln",
+    "way", "court", "ct", "place", "pl", "highway", "hwy", "parkway", "pkwy", "circle", "cir", "terrace",
+    "ter", "trail", "trl", "plaza", "plz", "square", "sq", "expressway", "expy", "freeway", "fwy", "pike",
+    "alley", "aly", "loop", "crescent", "cres", "broadway", "row",
+];
+
+// Words a numbered road starts with, like Hwy 52, County Rd 12 or I-94
+const ROUTES: [&str; 10] = ["hwy", "highway", "route", "rte", "us", "sr", "state", "county", "interstate", "i"];
+
+pub struct Intersection {
+    pub first: String,
+    pub second: String,
+}
+
+impl Intersection {
+    // The corner the way the provider finds intersections. Google takes the two streets joined
+    // by &, Mapbox by and. The Census geocoder doesn't place them, it's sent the Google form
+    pub fn query(&self, provider: Provider) -> String {
+        match provider {
+            Provider::Mapbox => format!("{} and {}", self.first, self.second),
+            Provider::Google | Provider::Census | Provider::CensusBatch => format!("{} & {}", self.first, self.second),
+        }
+    }
+}
+
+// The two streets of an intersection, None for anything else. An address starting with a house
+// number is never an intersection, so 12 Rock and Roll Ave stays an address, and both sides
+// need to be streets
+pub fn parse(addr: &str) -> Option<Intersection> {
+    let addr = addr.trim();
+    let lower = addr.to_ascii_lowercase();
+
+    let (start, separator) = SEPARATORS.iter().find_map(|e| lower.find(e).map(|start| (start, e.len())))?;
+    let first = addr[..start].trim().trim_end_matches(',');
+    let second = addr[start+separator..].trim().trim_start_matches(',');
+    if house_number(first) || house_number(second) || !street(first) || !street(second) {
+        return None;
+    }
+
+    Some(Intersection {
+        first: first.trim().to_string(),
+        second: second.trim().to_string(),
+    })
+}
+
+// 1600, 12B or 1/2 in front of the street name. Numbered streets like 2nd Ave don't count
+fn house_number(street: &str) -> bool {
+    let first = street.split_whitespace().next().unwrap_or("");
+    first.starts_with(|c: char| c.is_ascii_digit())
+        && !["st", "nd", "rd", "th"].iter().any(|e| first.to_ascii_lowercase().ends_with(e))
+}
+
+// A name with a street suffix after it, like Main St or 5th Ave N, or a numbered road
+fn street(name: &str) -> bool {
+    let words: Vec<String> = name.split_whitespace()
+        .map(|e| e.trim_end_matches(['.', ',']).to_ascii_lowercase())
+        .collect();
+    let route = words.first().is_some_and(|first| {
+        let first = first.split('-').next().unwrap_or("");
+        ROUTES.contains(&first) && (words.len() > 1 || first.len() < words[0].len())
+    });
+
+    route || words == ["broadway"] || words.iter().skip(1).any(|e| SUFFIXES.contains(&e.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corner(addr: &str) -> Option<(String, String)> {
+        parse(addr).map(|e| (e.first, e.second))
+    }
+
+    #[test]
+    fn corners_need_a_street_on_both_sides() {
+        let pair = |a: &str, b: &str| Some((a.to_string(), b.to_string()));
+        assert_eq!(corner("Main St & 2nd Ave"), pair("Main St", "2nd Ave"));
+        assert_eq!(corner("5th St. and Elm Ave N"), pair("5th St.", "Elm Ave N"));
+        assert_eq!(corner("Hwy 52 at County Rd 12"), pair("Hwy 52", "County Rd 12"));
+        assert_eq!(corner("I-94 @ Broadway"), pair("I-94", "Broadway"));
+
+        for addr in ["Shops at Main St", "AT&T Plaza", "12 Rock and Roll Ave", "1600 Main St & 2nd Ave", "5th St and Elm"] {
+            assert!(parse(addr).is_none(), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn corners_are_written_for_the_provider() {
+        let corner = parse("Main St & 2nd Ave").unwrap();
+        assert_eq!(corner.query(Provider::Google), "Main St & 2nd Ave");
+        assert_eq!(corner.query(Provider::Mapbox), "Main St and 2nd Ave");
+    }
+}
@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt::{Display, Formatter};
+use super::distance::R;
+use super::state::splitmix64;
+
+// How coordinates are coarsened as they're written out, so outputs can be shared without giving
+// away exactly where anyone is
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Jitter {
+    Snap(f64),          // to the middle of a grid cell this many miles across
+    Random(f64, u64),   // up to this many miles in a direction picked from the seed and the point. The seed is
+                        // the secret, with it the offsets can be worked back out
+}
+
+impl Jitter {
+    // Parse `snap [distance] [unit]` or `random [distance] [unit] [seed]`, with the unit mi
+    // (default), km or m, or off for None. random has no default seed, since a known one undoes it
+    pub fn parse(input: &[&str]) -> Result<Option<Jitter>, Box<dyn Error>> {
+        let mode = match input.first() {
+            Some(mode) => mode.to_lowercase(),
+            None => return Err("snap, random or off required")?
+        };
+        if mode == "off" {
+            return Ok(None);
+        }
+
+        let distance = match input.get(1).map(|e| e.parse::<f64>()) {
+            Some(Ok(distance)) if distance > 0.0 => distance,
+            _ => return Err("distance must be a number above 0")?
+        };
+        let mut rest = &input[2..];
+        let scale = match rest.first().map(|e| e.to_lowercase()).as_deref() {
+            Some("mi") | Some("miles") => 1.0,
+            Some("km") => 0.621371,
+            Some("m") => 0.000621371,
+            _ => return Jitter::build(&mode, distance, rest),
+        };
+        rest = &rest[1..];
+
+        Jitter::build(&mode, distance * scale, rest)
+    }
+
+    fn build(mode: &str, miles: f64, rest: &[&str]) -> Result<Option<Jitter>, Box<dyn Error>> {
+        match (mode, rest) {
+            ("snap", []) => Ok(Some(Jitter::Snap(miles))),
+            ("random", []) => Err("random needs a seed of your own that's kept private, e.g. random 500 m 8817")?,
+            ("random", [seed]) => Ok(Some(Jitter::Random(miles, seed.parse::<u64>().map_err(|_| format!("Invalid seed '{}'", seed))?))),
+            ("snap", _) | ("random", _) => Err(format!("Unexpected '{}', the unit must be mi, km or m", rest.join(" ")))?,
+            _ => Err(format!("Unknown jitter '{}', use snap, random or off", mode))?
+        }
+    }
+
+    // Coarsened point, NaN coordinates stay NaN. The same point always comes out the same, so an
+    // entity is moved the same way in every output and run and can't be averaged back out
+    pub fn apply(&self, lat: f64, lng: f64) -> (f64, f64) {
+        if lat.is_nan() || lng.is_nan() {
+            return (lat, lng);
+        }
+
+        match *self {
+            Jitter::Snap(miles) => {
+                let step = (miles / R).to_degrees();
+                let lat = ((lat / step).floor() + 0.5) * step;
+                // Cells are as wide as they are tall, so they get more degrees of longitude
+                // towards the poles
+                let lng_step = step / lat.to_radians().cos().max(0.01);
+                let lng = ((lng / lng_step).floor() + 0.5) * lng_step;
+                (lat.clamp(-90.0, 90.0), wrap(lng))
+            }
+            Jitter::Random(miles, seed) => {
+                let hash = splitmix64(seed ^ splitmix64(lat.to_bits()) ^ splitmix64(lng.to_bits()).rotate_left(32));
+                let angle = unit(hash) * 2.0 * PI;
+                // The square root spreads points evenly over the disc instead of bunching them
+                // in the middle
+                let distance = miles * unit(splitmix64(hash)).sqrt();
+
+                let lat2 = lat + (distance * angle.cos() / R).to_degrees();
+                let lng2 = lng + (distance * angle.sin() / R).to_degrees() / lat.to_radians().cos().max(0.01);
+                (lat2.clamp(-90.0, 90.0), wrap(lng2))
+            }
+        }
+    }
+}
+
+impl Display for Jitter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Jitter::Snap(miles) => write!(f, "snap {:.3} mi", miles),
+            // The seed is left out so it doesn't end up in configs and sidecar files
+            Jitter::Random(miles, _) => write!(f, "random {:.3} mi", miles),
+        }
+    }
+}
+
+fn wrap(lng: f64) -> f64 {
+    (lng + 180.0).rem_euclid(360.0) - 180.0
+}
+
+// Top 53 bits as a number from 0 up to 1
+fn unit(value: u64) -> f64 {
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
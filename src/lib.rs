@@ -1,2 +1,50 @@
+pub mod atomic;
+pub mod auth;
+pub mod batch;
+pub mod column;
+pub mod command;
+pub mod compare;
+pub mod config;
+pub mod coords;
+pub mod country;
 pub mod data_frame;
+pub mod dates;
+pub mod describe;
+pub mod diff;
+pub mod dialect;
+pub mod distance;
+pub mod enrich;
+pub mod geofence;
+pub mod gpkg;
+pub mod grpc;
+pub mod interrupt;
+pub mod intersection;
+pub mod jitter;
+pub mod lint;
+pub mod manifest;
+pub mod map;
+pub mod mapping;
+pub mod metrics;
+pub mod notify;
+pub mod pager;
+pub mod phonetic;
+pub mod pobox;
+pub mod profile;
+pub mod projection;
+pub mod prompt;
+pub mod provider;
+pub mod review;
+pub mod roles;
+pub mod routing;
+pub mod s2;
+pub mod server;
+pub mod session;
+pub mod sidecar;
+pub mod spill;
 pub mod state;
+pub mod suggest;
+pub mod telemetry;
+pub mod transform;
+pub mod validate;
+pub mod w3w;
+pub mod watch;
@@ -0,0 +1,86 @@
+use regex::Regex;
+use std::fmt::{Display, Formatter};
+
+// Problems with an address that usually mean it won't geocode to the right place
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Issue {
+    Blank,
+    Placeholder,    // "unknown", "N/A" and the like instead of an address
+    PoBox,          // geocodes to the post office, not where anyone is
+    NoNumber,       // no street number, so only the street can be found
+    CareOf,         // c/o or attn line mixed into the address
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Issue::Blank => "blank address",
+            Issue::Placeholder => "placeholder",
+            Issue::PoBox => "PO box",
+            Issue::NoNumber => "missing street number",
+            Issue::CareOf => "care-of line",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Issue {
+    pub const ALL: [Issue; 5] = [Issue::Blank, Issue::Placeholder, Issue::PoBox, Issue::NoNumber, Issue::CareOf];
+}
+
+// Values used in place of an address, compared lowercase without punctuation
+const PLACEHOLDERS: [&str; 14] = [
+    "unknown", "unk", "na", "n a", "none", "null", "nil", "tbd", "tba", "x", "xx", "xxx", "no address", "not available",
+];
+
+pub struct Linter {
+    po_box: Regex,
+    care_of: Regex,
+}
+
+impl Linter {
+    pub fn new() -> Linter {
+        Linter {
+            po_box: Regex::new(r"(?i)\b(p\.?\s*o\.?\s*box|post\s+office\s+box|pob\s+\d|box\s+\d)").unwrap(),
+            care_of: Regex::new(r"(?i)(\bc/o\b|\bc\.o\.|\bcare\s+of\b|\battn\b|\battention\b)").unwrap(),
+        }
+    }
+
+    // Issues with one row's address lines, most serious first
+    pub fn check(&self, addr1: &str, addr2: &str) -> Vec<Issue> {
+        let addr1 = addr1.trim();
+        if addr1.is_empty() {
+            return vec![Issue::Blank];
+        }
+
+        // Only punctuation, like "-" or "?", counts as a placeholder too
+        let plain: String = addr1.to_lowercase()
+            .chars()
+            .map(|e| if e.is_alphanumeric() {e} else {' '})
+            .collect();
+        let plain = plain.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if plain.is_empty() || PLACEHOLDERS.contains(&plain.as_str()) {
+            return vec![Issue::Placeholder];
+        }
+
+        // A care-of line in front of the street hides its number, so it's only reported once
+        let care_of = self.care_of.is_match(addr1);
+        let mut issues = Vec::new();
+        if self.po_box.is_match(addr1) {
+            issues.push(Issue::PoBox);
+        } else if !care_of && !addr1.starts_with(|e: char| e.is_ascii_digit()) {
+            issues.push(Issue::NoNumber);
+        }
+        if care_of || self.care_of.is_match(addr2) {
+            issues.push(Issue::CareOf);
+        }
+
+        issues
+    }
+}
+
+impl Default for Linter {
+    fn default() -> Linter {
+        Linter::new()
+    }
+}
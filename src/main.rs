@@ -1,90 +1,450 @@
-use clap::{Arg, App};
-use std::io::{stdin, stdout, Write};
-use geomatch::state::State;
+use clap::{Arg, App, AppSettings, Shell, SubCommand};
+use std::io::{stdin, stdout, IsTerminal};
+use std::collections::HashMap;
+use geomatch::state::{Overwrite, State};
+use geomatch::config::Config;
+use geomatch::command::{run_command, split_input};
+use geomatch::interrupt;
+use geomatch::pager;
+use geomatch::prompt::Prompt;
+use geomatch::telemetry;
+use geomatch::notify::notify;
+use geomatch::server::serve;
+use geomatch::session::{clear_recovery, recover, Session, RECOVERY_FILE};
+use geomatch::grpc::serve_grpc;
+use geomatch::watch::{watch, WatchOptions};
+use geomatch::manifest::{read_manifest, run_manifest};
+use geomatch::spill::parse_size;
+use geomatch::auth::{read_key, remove_key, set_key, stored_key, PROVIDERS};
+use std::time::Duration;
+use serde_json::json;
+use geomatch::data_frame::{geocode_client, LoadOptions, RaggedPolicy, RowId};
 
-#[tokio::main]
-async fn main() -> Result<(),()> {
-    // Get cli options
-    let matches = App::new("GeoMatch")
+// Cli options, also used to generate shell completions
+fn app() -> App<'static, 'static> {
+    App::new("GeoMatch")
         .version("1.0")
         .author("Devin Vander Stelt <devin@vstelt.dev>")
         .about("Utility for fetching and matching csv files")
         .arg(Arg::with_name("files").required(true).min_values(1))
-        .arg(Arg::with_name("api-key").short("k").takes_value(true).required(true).env("API_KEY"))
-        .get_matches();
+        .arg(Arg::with_name("api-key").short("k").takes_value(true).env("API_KEY")
+            .help("Google api key, or the one stored with `geomatch auth set google`"))
+        .arg(Arg::with_name("row-id").long("row-id").takes_value(true).possible_values(&["number", "hash"])
+            .help("Add a row_id column to every file, either <file>-<row> or a hash of the row"))
+        .arg(Arg::with_name("ragged").long("ragged").takes_value(true).possible_values(&["error", "pad", "truncate", "skip"])
+            .default_value("error").help("How to handle rows with more or fewer fields than the header"))
+        .arg(Arg::with_name("header-line").long("header-line").takes_value(true).multiple(true).number_of_values(1)
+            .help("Line a file's header is on as file=line, counting from 1, for exports with banner lines above it"))
+        .arg(Arg::with_name("skip-rows").long("skip-rows").takes_value(true)
+            .help("Lines to skip at the start of every file, before the header"))
+        .arg(Arg::with_name("comment").long("comment").takes_value(true)
+            .help("Skip lines starting with this character, like #"))
+        .arg(Arg::with_name("offset").long("offset").takes_value(true)
+            .help("Rows to skip after the header of every file, with --limit to try a setup on part of a big file"))
+        .arg(Arg::with_name("limit").long("limit").takes_value(true)
+            .help("Most rows to load from every file, after the offset"))
+        .arg(Arg::with_name("memory-budget").long("memory-budget").takes_value(true)
+            .help("Memory the files can take, like 8G, before columns not needed for matching spill to disk"))
+        .arg(Arg::with_name("keep-coords").long("keep-coords")
+            .help("Keep the original lat/lng columns so they can be used as output or compare columns"))
+        .arg(Arg::with_name("no-pager").long("no-pager")
+            .help("Print long listings all at once instead of a screen at a time"))
+        .arg(Arg::with_name("force").long("force")
+            .help("Replace fetch and match outputs left by an earlier run without asking"))
+        .arg(Arg::with_name("strict").long("strict")
+            .help("Stop with an error on ragged rows, bad coordinates, missing addresses and failed geocodes"))
+        .arg(Arg::with_name("profile").long("profile")
+            .help("Report the time spent loading, geocoding, matching and writing after each command"))
+        .arg(Arg::with_name("config").long("config").takes_value(true).global(true).env("GEOMATCH_CONFIG")
+            .help("Json settings file, like header synonyms. Defaults to geomatch.json if there is one"))
+        .arg(Arg::with_name("notify").long("notify").takes_value(true).global(true)
+            .help("Webhook url to post a json summary of row counts, outputs and failures to when a job finishes"))
+        .arg(Arg::with_name("user-agent").long("user-agent").takes_value(true).global(true)
+            .help("User-Agent for geocoding requests, some providers need one that identifies you"))
+        .arg(Arg::with_name("w3w-key").long("w3w-key").takes_value(true).global(true).env("W3W_API_KEY")
+            .help("what3words api key, for `set w3w true`"))
+        .arg(Arg::with_name("mapbox-token").long("mapbox-token").takes_value(true).global(true).env("MAPBOX_ACCESS_TOKEN")
+            .help("Mapbox access token, for the mapbox provider"))
+        .arg(Arg::with_name("header").long("header").takes_value(true).multiple(true).number_of_values(1).global(true)
+            .help("Extra header for geocoding requests as \"Name: value\", e.g. an auth header for a proxy"))
+        .arg(Arg::with_name("trace").long("trace").takes_value(true).global(true)
+            .help("Record fetch and match spans, request latencies and phase times to a json lines file, or to an OTLP collector given as an http(s) url"))
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(SubCommand::with_name("serve")
+            .about("Serve a REST api for uploading files and running fetch and match jobs")
+            .arg(Arg::with_name("addr").long("addr").takes_value(true).default_value("127.0.0.1:8080")
+                .help("Address to listen on"))
+            .arg(Arg::with_name("dir").long("dir").takes_value(true).default_value("geomatch_jobs")
+                .help("Directory to keep each job's files in")))
+        .subcommand(SubCommand::with_name("grpc")
+            .about("Serve geocoding and matching as a gRPC service, described in proto/geomatch.proto")
+            .arg(Arg::with_name("addr").long("addr").takes_value(true).default_value("127.0.0.1:50051")
+                .help("Address to listen on")))
+        .subcommand(SubCommand::with_name("watch")
+            .about("Watch a directory and run a pipeline of commands on every new csv file")
+            .arg(Arg::with_name("dir").required(true))
+            .arg(Arg::with_name("pipeline").long("pipeline").takes_value(true).required(true)
+                .help("File of commands to run on each new file, one per line. The new file is file 0"))
+            .arg(Arg::with_name("pattern").long("pattern").takes_value(true).default_value("*.csv")
+                .help("File names to pick up, with * and ? wildcards"))
+            .arg(Arg::with_name("out").long("out").takes_value(true).default_value("out")
+                .help("Directory to write results to, in a folder named after each file"))
+            .arg(Arg::with_name("with").long("with").takes_value(true).multiple(true).number_of_values(1)
+                .help("File to load after the new one, e.g. a reference file to match against"))
+            .arg(Arg::with_name("interval").long("interval").takes_value(true).default_value("5")
+                .help("Seconds between checks of the directory"))
+            .arg(Arg::with_name("metrics").long("metrics").takes_value(true)
+                .help("Address to serve Prometheus metrics on at /metrics, e.g. 127.0.0.1:9184")))
+        .subcommand(SubCommand::with_name("run")
+            .about("Run the fetch and match jobs in a toml jobs file, in order or several at once")
+            .arg(Arg::with_name("jobs").required(true))
+            .arg(Arg::with_name("parallel").long("parallel").takes_value(true)
+                .help("Jobs to run at once, in place of the jobs file's parallel")))
+        .subcommand(SubCommand::with_name("auth")
+            .about("Store api keys in the OS keyring, so they don't have to be in scripts, env vars or shell history")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("set")
+                .about("Store a provider's key, typed in without echoing or piped in")
+                .arg(Arg::with_name("provider").required(true).possible_values(&PROVIDERS)))
+            .subcommand(SubCommand::with_name("remove")
+                .about("Remove a provider's stored key")
+                .arg(Arg::with_name("provider").required(true).possible_values(&PROVIDERS))))
+        .subcommand(SubCommand::with_name("completions")
+            .about("Print a completion script for the flags and subcommands, e.g. geomatch completions bash > /etc/bash_completion.d/geomatch")
+            .arg(Arg::with_name("shell").required(true).possible_values(&Shell::variants())))
+}
+
+#[tokio::main]
+async fn main() -> Result<(),()> {
+    // Get cli options
+    let matches = app().get_matches();
+
+    if let Some(options) = matches.subcommand_matches("completions") {
+        let shell = options.value_of("shell").unwrap().parse::<Shell>().unwrap();
+        app().gen_completions_to(env!("CARGO_PKG_NAME"), shell, &mut stdout());
+        return Ok(());
+    }
+
+    if let Some(options) = matches.subcommand_matches("auth") {
+        let result = match options.subcommand() {
+            ("set", Some(options)) => {
+                let provider = options.value_of("provider").unwrap();
+                read_key(&format!("{} key: ", provider))
+                    .map_err(|e| e.into())
+                    .and_then(|key| set_key(provider, &key))
+                    .map(|_| format!("Stored the {} key in the keyring", provider))
+            }
+            ("remove", Some(options)) => {
+                let provider = options.value_of("provider").unwrap();
+                remove_key(provider).map(|_| format!("Removed the {} key from the keyring", provider))
+            }
+            _ => unreachable!()
+        };
+        return match result {
+            Ok(message) => {
+                println!("{}", message);
+                Ok(())
+            }
+            Err(e) => {
+                println!("{}", e);
+                Err(())
+            }
+        };
+    }
+
+    let ragged = match matches.value_of("ragged") {
+        Some("pad") => RaggedPolicy::Pad,
+        Some("truncate") => RaggedPolicy::Truncate,
+        Some("skip") => RaggedPolicy::Skip,
+        _ => RaggedPolicy::Error
+    };
+    if let Some(dest) = matches.value_of("trace") {
+        if let Err(e) = telemetry::init(dest) {
+            println!("Couldn't start tracing: {}", e);
+            return Err(());
+        }
+    }
+
+    let config = match Config::load(matches.value_of("config")) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return Err(());
+        }
+    };
+
+    // Headers given on the command line replace ones from the config file with the same name
+    let mut headers = config.headers.clone();
+    for header in matches.values_of("header").into_iter().flatten() {
+        match header.split_once(':') {
+            Some((name, value)) => headers.push((name.to_string(), value.to_string())),
+            None => {
+                println!("Invalid header '{}', expected \"Name: value\"", header);
+                return Err(());
+            }
+        }
+    }
+    let user_agent = matches.value_of("user-agent").or(config.user_agent.as_deref());
+    let client = match geocode_client(user_agent, &headers) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", e);
+            return Err(());
+        }
+    };
 
-    let mut cli_state = State::new(matches.value_of("api-key").unwrap().to_string());
+    let mut header_lines = HashMap::new();
+    for header_line in matches.values_of("header-line").into_iter().flatten() {
+        match header_line.rsplit_once('=').map(|(file, line)| (file, line.parse::<usize>())) {
+            Some((file, Ok(line))) if line > 0 => {
+                header_lines.insert(file.to_string(), line);
+            }
+            _ => {
+                println!("Invalid header line '{}', expected file=line counting from 1", header_line);
+                return Err(());
+            }
+        }
+    }
+
+    let skip_rows = match matches.value_of("skip-rows").map(|e| e.parse::<usize>()) {
+        Some(Ok(rows)) => rows,
+        Some(Err(_)) => {
+            println!("--skip-rows must be a number of lines");
+            return Err(());
+        }
+        None => 0
+    };
+    let offset = match matches.value_of("offset").map(|e| e.parse::<usize>()) {
+        Some(Ok(rows)) => rows,
+        Some(Err(_)) => {
+            println!("--offset must be a number of rows");
+            return Err(());
+        }
+        None => 0
+    };
+    let limit = match matches.value_of("limit").map(|e| e.parse::<usize>()) {
+        Some(Ok(rows)) => Some(rows),
+        Some(Err(_)) => {
+            println!("--limit must be a number of rows");
+            return Err(());
+        }
+        None => None
+    };
+    let memory_budget = match matches.value_of("memory-budget").map(parse_size) {
+        Some(Ok(size)) => Some(size),
+        Some(Err(e)) => {
+            println!("--memory-budget: {}", e);
+            return Err(());
+        }
+        None => None
+    };
+    let comment = match matches.value_of("comment").map(|e| e.as_bytes()) {
+        Some([comment]) => Some(*comment),
+        Some(_) => {
+            println!("--comment must be a single character");
+            return Err(());
+        }
+        None => None
+    };
 
-    // Load config and try to guess good defaults
-    for file_name in matches.values_of("files").unwrap() {
-        cli_state.add_file(file_name);
+    let load_options = LoadOptions {
+        ragged,
+        keep_coords: matches.is_present("keep-coords"),
+        synonyms: config.synonyms,
+        strict: matches.is_present("strict"),
+        header_lines,
+        skip_rows,
+        comment,
+        offset,
+        limit,
+        memory_budget: None,    // what's left of --memory-budget is worked out as each file loads
+    };
+    if load_options.strict && ragged != RaggedPolicy::Error {
+        println!("--strict can't be used with --ragged {}", matches.value_of("ragged").unwrap());
+        return Err(());
+    }
+
+    let row_ids = match matches.value_of("row-id") {
+        Some("number") => Some(RowId::Number),
+        Some("hash") => Some(RowId::Hash),
+        _ => None
+    };
+
+    // The cli and every server job start out with the command line options
+    // Only google needs the key, so files that all use census or mapbox can go without
+    let api_key = matches.value_of("api-key").map(String::from).or_else(|| stored_key("google"));
+    let profile = matches.is_present("profile");
+    let webhook = matches.value_of("notify").map(String::from);
+    let aliases = config.aliases.clone();
+    let w3w_key = matches.value_of("w3w-key").map(String::from).or(config.w3w_key).or_else(|| stored_key("w3w"));
+    let mapbox_token = matches.value_of("mapbox-token").map(String::from).or(config.mapbox_token).or_else(|| stored_key("mapbox"));
+    let new_state = move || {
+        let mut state = State::new(api_key.clone());
+        state.set_client(client.clone());
+        state.set_aliases(aliases.clone());
+        state.set_w3w_key(w3w_key.clone());
+        state.set_mapbox_token(mapbox_token.clone());
+        state.set_memory_budget(memory_budget);
+        state.set_load_options(load_options.clone());
+        if let Some(mode) = row_ids {
+            state.set_row_ids(mode);
+        }
+        if profile {
+            state.set_profile();
+        }
+        state
+    };
+
+    if let Some(options) = matches.subcommand_matches("serve") {
+        let addr = match options.value_of("addr").unwrap().parse() {
+            Ok(addr) => addr,
+            Err(e) => {
+                println!("Invalid address: {}", e);
+                return Err(());
+            }
+        };
+
+        if let Err(e) = serve(addr, options.value_of("dir").unwrap(), webhook, new_state).await {
+            println!("{}", e);
+            return Err(());
+        }
+        return Ok(());
     }
 
+    if let Some(options) = matches.subcommand_matches("grpc") {
+        let addr = match options.value_of("addr").unwrap().parse() {
+            Ok(addr) => addr,
+            Err(e) => {
+                println!("Invalid address: {}", e);
+                return Err(());
+            }
+        };
+
+        if let Err(e) = serve_grpc(addr, new_state).await {
+            println!("{}", e);
+            return Err(());
+        }
+        return Ok(());
+    }
+
+    if let Some(options) = matches.subcommand_matches("watch") {
+        let interval = match options.value_of("interval").unwrap().parse::<f64>() {
+            Ok(secs) if secs > 0.0 => Duration::from_secs_f64(secs),
+            _ => {
+                println!("Invalid interval");
+                return Err(());
+            }
+        };
+        let metrics = match options.value_of("metrics").map(|e| e.parse()).transpose() {
+            Ok(metrics) => metrics,
+            Err(e) => {
+                println!("Invalid metrics address: {}", e);
+                return Err(());
+            }
+        };
+
+        let options = WatchOptions {
+            dir: options.value_of("dir").unwrap().to_string(),
+            pattern: options.value_of("pattern").unwrap().to_string(),
+            pipeline: options.value_of("pipeline").unwrap().to_string(),
+            out: options.value_of("out").unwrap().to_string(),
+            with: options.values_of("with").map_or(Vec::new(), |e| e.map(String::from).collect()),
+            interval,
+            notify: webhook,
+            metrics,
+        };
+
+        if let Err(e) = watch(options, new_state).await {
+            println!("{}", e);
+            return Err(());
+        }
+        return Ok(());
+    }
+
+    if let Some(options) = matches.subcommand_matches("run") {
+        let mut manifest = match read_manifest(options.value_of("jobs").unwrap()) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                println!("{}", e);
+                return Err(());
+            }
+        };
+        match options.value_of("parallel").map(|e| e.parse::<usize>()) {
+            Some(Ok(parallel)) if parallel > 0 => manifest.parallel = parallel,
+            Some(_) => {
+                println!("--parallel must be a number of jobs");
+                return Err(());
+            }
+            None => {}
+        }
+
+        // Nobody is there to ask, so outputs from an earlier run are only replaced with --force
+        let overwrite = if matches.is_present("force") {Overwrite::Replace} else {Overwrite::Refuse};
+        let new_state = move || {
+            let mut state = new_state();
+            state.set_overwrite(overwrite);
+            state
+        };
+
+        if let Err(e) = run_manifest(manifest, new_state, webhook).await {
+            println!("{}", e);
+            return Err(());
+        }
+        return Ok(());
+    }
+
+    // Ctrl-C stops a running fetch or match cleanly instead of quitting mid-write
+    interrupt::install();
+    if !matches.is_present("no-pager") {
+        pager::enable();
+    }
+
+    // Load config and try to guess good defaults, unless restoring a session that crashed
+    let (mut cli_state, mut session) = match recover(&new_state).await {
+        Some(restored) => restored,
+        None => {
+            let mut state = new_state();
+            let mut session = Session::default();
+            for file_name in matches.values_of("files").unwrap() {
+                if let Err(e) = state.add_file(file_name) {
+                    println!("{}", e);
+                    return Err(());
+                }
+                session.files.push(file_name.to_string());
+            }
+            (state, session)
+        }
+    };
+    cli_state.report_profile();
+    autosave(&session, None);
+
+    // Outputs from an earlier run are only replaced once someone says so
+    cli_state.set_interactive();
+    cli_state.set_overwrite(match (matches.is_present("force"), stdin().is_terminal()) {
+        (true, _) => Overwrite::Replace,
+        (false, true) => Overwrite::Ask,
+        (false, false) => Overwrite::Refuse,
+    });
+
     // Init cli interface
     print_splash();
-    print_prompt();
+    let mut prompt = Prompt::new();
 
     // Keep processing commands until user quits
-    let mut input_buffer = String::with_capacity(20);
-    while let Ok(_) = stdin().read_line(&mut input_buffer) {
-        let input: Vec<&str> = input_buffer.trim().split_whitespace().to_owned().collect();
-        let cmd = input.get(0);
+    let mut failures = Vec::new();
+    let mut runs = Vec::new();
+    // End of input, like the end of a script piped in, quits too
+    while let Some(input_buffer) = prompt.read() {
+        prompt.remember(&input_buffer);
+        let input = split_input(&input_buffer);
+        let cmd = input.first();
 
         if cmd.is_none() {
-            input_buffer.clear();
-            print_prompt();
             continue;
         }
         let cmd = cmd.unwrap();
 
         let result = match *cmd {
-            "list" => {
-                let columns = cli_state.get_columns(input);
-                match columns {
-                    Ok(columns) => {
-                        for col in columns {
-                            println!("\t{}", col);
-                        }
-                        Ok(())
-                    }
-                    Err(e) => {
-                        Err(e)
-                    }
-                }
-            },
-            "config" => {
-                cli_state.print();
-                Ok(())
-            },
-            "set" => {
-                cli_state.set_param(input)
-            },
-            "fetch" => {
-                if cli_state.ready_to_fetch() {
-                    cli_state.fetch().await
-                } else {
-                    Err("Invalid config for fetch".into())
-                }
-            },
-            "match" => {
-                if cli_state.ready_to_match() {
-                    cli_state.find_matches()
-                } else {
-                    Err("Invalid config for match".into())
-                }
-            },
-            "add" => {
-                cli_state.add_match_column(input)
-            }
-            "method" => {
-                cli_state.set_method(input)
-            }
-            "radius" => {
-                cli_state.set_radius(input)
-            },
-            "exclusive" => {
-                cli_state.set_exclusive(input)
-            }
             "quit" => {
                 break;
             },
@@ -92,62 +452,227 @@ async fn main() -> Result<(),()> {
                 print_help();
                 Ok(())
             },
-            "prefix" => {
-                cli_state.set_prefix(input)
-            },
-            _ => {
-                println!("Unknown command: '{}'", cmd);
-                print_help();
-                Ok(())
+            "fetch" | "match" => {
+                autosave(&session, Some(input_buffer.trim()));
+                run_command(&mut cli_state, input).await
             }
+            _ => run_command(&mut cli_state, input).await
         };
 
         // Print error, if any
+        let completed = cli_state.take_runs();
         match result {
-            Err(e) => println!("{}", e),
-            Ok(_) => {}
+            Ok(_) => session.record(input_buffer.trim(), &completed),
+            Err(e) => {
+                println!("{}", e);
+                failures.push(json!({"command": input_buffer.trim(), "error": e.to_string()}));
+            }
         }
+        runs.extend(completed);
+        autosave(&session, None);
+        cli_state.report_profile();
+    }
+
+    clear_recovery();
+    telemetry::shutdown();
 
-        input_buffer.clear();
-        print_prompt();
+    // Report on everything run in the session, e.g. a script piped in overnight
+    if let Some(url) = webhook {
+        notify(&url, &json!({
+            "mode": "cli",
+            "status": if failures.is_empty() {"done"} else {"failed"},
+            "runs": runs,
+            "failures": failures,
+        })).await;
     }
 
     Ok(())
 }
 
 
+// Keep the recovery file up to date, a failure only costs crash recovery so it doesn't stop anything
+fn autosave(session: &Session, running: Option<&str>) {
+    if let Err(e) = session.autosave(running) {
+        println!("Couldn't save the session to {}: {}", RECOVERY_FILE, e);
+    }
+}
+
 fn print_help() {
     const HELP_MSG: &str = {
         r#"HELP:
-    list [index]        List out all columns in the file with index
+    list [index] [pattern]  List out all columns in the file with index, or only those matching
+        pattern, a case insensitive regex or text
+    describe [index] [col]  Show blank and distinct counts, top values, and min and max of numbers
+        for a column
     set [index] [var] [col]     Assign a column to a runtime variable
+        var Options:
+            id      [optional] Always written to the match output
+            country [optional] Country of each row, for the country command
         fetch var Options:
             addr1   [required]
             addr2   [optional]
             city    [required]
             state   [required]
             zipcode [required]
+            template [optional] Query template, e.g. "{addr1}, {city}, {state} {zipcode}, USA"
+            provider [optional] Geocoder for the file, google (default), census, census-batch, which
+                uploads 10,000 rows at a time and writes a match_quality column, or mapbox, which writes
+                a relevance column
         match var Options:
             lat     [required]
             lng     [required]
+            latlng  [optional] Sets lat and lng from one column like "44.98,-93.27"
+    set fetchoutput [template]  Name of each fetched file, defaults to "{stem}_coords.csv"
+        Fields: {stem} {index} {date} {time}
+    set matchoutput [template]  Name of the match file, defaults to "matches.csv"
+        Fields: {radius} {method} {date} {time}
+    set normaddress [name] [position]   Write the normalized address as true (default), false or a column name,
+        optionally at a column index counting from 0. It goes after the file's own columns otherwise. A column
+        the file already has is never written over
+    set s2 [level]      Defaults to off. Write the S2 cell token of each row at a level from 0 to 30 after lat
+        and lng when fetching, in an s2_cell column
+    set w3w [true or false]     Defaults to false. Write the what3words address of each row last when
+        fetching, in a w3w column. Needs --w3w-key
+    set blankaddress [city] [zip]   Defaults to off. Geocode rows with a blank street address, city or state
+        on city, state and zip or on zip alone, tried in order, or skip them. Either way fetch writes what each
+        row was geocoded from in a geocode_status column
+    set pobox [policy]  Defaults to off. What fetch does with PO Box and PMB addresses, skip, city to geocode
+        on city, state and zip, or geocode. They're flagged pobox in the geocode_status column
+    set jitter [mode] [distance] [unit] [seed]  Defaults to off. Coarsen the coordinates fetch, match, matrix
+        and export write, snap to the middle of a grid cell distance across, or random to move them up to distance away.
+        Unit is mi (default), km or m. random needs a private seed, which picks the offsets
+    set compress [gzip, zstd or off]  Defaults to off. Compress the match, matrix, fetch and export outputs as
+        they're written, adding .gz or .zst to their names. An output named .gz or .zst is always compressed
+    set memory [size]   Defaults to off. Memory the files can take, like 8G, before the biggest columns not used
+        for matching spill to disk. Same as --memory-budget
     add [index] [type] [col]       Add a column for a specific purpose
         type Options:
             output      Write the column to the csv file
             compare     Use the column to differentiate between duplicate locations
+            phone       Compare column of phone numbers, compared by digits ignoring formatting, country
+                codes and extensions
+            name        Compare column of business names, compared without legal suffixes like LLC and Inc,
+                punctuation and stopwords
+            soundex     Compare column of names, compared by how each word sounds with soundex
+            metaphone   Compare column of names, compared by how each word sounds with metaphone
     prefix [index] [val]    Set prefix for a specified file's columns
+    alias [index] [col] [name]  Write a column under another name, in place of its header and prefix, to
+        fit the schema an output is loaded into. Leave out name to go back to the header
+    map [index] [mapping]   Apply the roles, prefix, and output and compare columns in a csv or toml
+        mapping file to a file
+    transform [index] [col] [expr]  Clean up a column in place
+        expr Options:
+            trim                        Remove leading and trailing whitespace
+            upper                       Convert to uppercase
+            lower                       Convert to lowercase
+            replace [regex] [text]      Replace every match of regex with text
+            substr [start] [len]        Keep len characters starting at start
+    header-line [index] [line]      Load a file again with its header on a line counting from 1, skipping
+        banner lines above it. Columns set on the file are reset
+    limit [index] [count] [offset]  Keep count rows of a file starting at offset, 0 by default, to try a
+        setup end to end on part of it. --limit and --offset do the same for every file as it loads
+    concat [index] [files...]       Append files with identical headers to the file at index
+    sort [index] [col] [desc]       Sort rows by a column. Rows are matched in this order
+    project [index] [projection]    Convert projected coordinates to lat/lng. Set lat to the northing
+        and lng to the easting column first
+        projection Options:
+            epsg [code]     UTM zones (EPSG:326xx, 327xx, 269xx) and Minnesota State Plane (EPSG:26991-26993)
+            utm [zone][N|S]
+            tmerc [lat0] [lon0] [k0] [x0] [y0] [m|ft|us-ft]
+            lcc [lat1] [lat2] [lat0] [lon0] [x0] [y0] [m|ft|us-ft]
+    datum [index] [datum]   Declare the datum of a file's coordinates, either wgs84 (default) or nad83.
+        Coordinates are converted to wgs84, the datum geocoded coordinates are in
+    clip [index] [area]     Remove rows outside of an area, including rows without coordinates
+        area Options:
+            bbox [min_lat] [min_lng] [max_lat] [max_lng]
+            "POLYGON((lng lat, ...))"   WKT polygon or multipolygon
+            [path]                      GeoJSON or WKT file
+    enrich [index] [kind] [path]    Add output columns for the boundary each row falls in, from a GeoJSON file
+        such as the Census cartographic boundary files, or the Census geographies api if no path is given.
+        Census lookups that fail are left blank with the reason in enrich_error
+        kind Options:
+            county      county and county_fips
+            tract       tract
+            blockgroup  tract and block_group
+            timezone    IANA timezone, needs a timezone-boundary-builder file
+    distance [index] [lat] [lng] [name] [max]   Add an output column of each row's distance in miles from a point,
+        named distance by default. With max, rows further away than max miles are removed
+    dedup-exact [index] [cols...]   Remove rows that are identical on every column, or only on cols if given
     method [method]     Set method for matching
         method Options:
             left    Include all entries from the first file its matches
             inner   Include all entries that had a positive match
-    radius [radius]     Defaults to 0.25 miles. Max radius for two locations to be considered a match.
+            cross   Pair every entry of the first file with every entry of the second, with the distance
+                    between them. Only for two small files
+    radius [radius] [unit]  Defaults to 0.25 miles. Max radius for two locations to be considered a match.
+        Unit is miles by default, or min for a drive time radius, which needs route set
     exclusive [true or false]   Defaults to true. Determines whether an entry can match to more than
         one entry. Non-Exclusive makes the most sense when combined with a left join, effectively giving
         the closest match per each location.
+    bearing [true or false]     Defaults to false. Write the bearing in degrees from each entry to its
+        match alongside the distance, which helps spot systematic offsets between files.
+    sidecar [true or false]     Defaults to false. Write a json file of run metadata next to every output file
+    split [col]     Defaults to off. Write one match file per value of an output column
+    precision [decimals]    Defaults to full. Round written coordinates and distances to a number of decimals
+    seed [number]   Defaults to off. Break ties between equally close candidates with a seeded pick, the
+        same for the same seed every run, instead of taking the first in the file
+    exact-precision [decimals]  Defaults to full. Round coordinates to a number of decimals before checking
+        whether two rows are at exactly the same place, which picks between them with compare columns
+    review [near] [similarity] [policy]  Defaults to off. Ask before keeping matches further apart than near
+        of the radius (0.8), or with compare columns less than similarity alike out of 100 (50). Answers are
+        saved to review_decisions.csv by row id and applied again on every later reviewed match. The policy
+        accept keeps unanswered matches and reject turns them down instead of asking, which serve, grpc,
+        watch and run need
+    missing [policy]    Defaults to skip. What match does with rows that have no coordinates, which are
+        always counted
+        policy Options:
+            skip        Leave them out of matching
+            unmatched   Leave them out and list them in matches_no_coords.csv
+            geocode     Geocode their addresses first, leaving out the ones still not found
+            abort       Don't match
+    dates [order]   Defaults to off. Compare values that are dates are compared by day, whatever their format,
+        so 01/05/2023 and 2023-01-05 are equal. order is mdy or dmy for dates like 01/05/2023, or off
+    route [provider]    Defaults to off. Re-score matches by driving distance, picking whichever of the
+        nearest candidates inside the radius is closest by road. Writes road distance (miles) and time (minutes)
+        provider Options:
+            off
+            osrm [url]      Defaults to the public OSRM demo server
+            google          Google Directions, using the api key
+    road-radius [radius]    Defaults to none. Max driving distance in miles for a routed match.
+    country [countries...]  Defaults to off. Set aside rows whose country column is outside the countries, and
+        leave rows geocoded outside them without coordinates. With flag [countries...] rows are kept and marked in
+        an in_scope column instead. Turning it off or changing it puts the rows back
+    provider [name]     Defaults to google. Geocoder for every file, files loaded after and the geocode
+        command: google, census, census-batch or mapbox. Mapbox needs --mapbox-token
     config  Print out the current configuration
-    fetch   Fetch all the coordinate pairs and write to new csv file
+    fetch [inplace] [resume]    Fetch all the coordinate pairs and write to new csv file. With inplace the columns
+        are added to the original files instead, keeping a .bak copy of each. With resume a stopped fetch carries
+        on from its checkpoint. Addresses like "Main St & 2nd Ave" are geocoded as intersections
     match   Match all the files together and write to new csv file
+    geocode [address]   Geocode a single address and print its coordinates and normalized address
+    validate    Check the files and settings, and whether the geocoder can be reached, listing each problem
+        with how to fix it
+    lint [index]    Report blank, placeholder, PO box, care-of and unnumbered addresses, which are likely
+        to geocode badly, before fetching
+    near [lat] [lng] [radius]   List rows from every file within radius miles of a point, closest first.
+        Radius defaults to the match radius
+    matrix [from] [to] [path] [max_dist]    Write the distance in miles between every pair of rows in two
+        files, one pair per row. Only pairs within max_dist are written if given
+    nn-stats [from] [to]    Show the min, median, p90 and max distance from each row of one file to its nearest
+        row in another, and how many are within the radius, for picking a radius before matching
+    diff [old] [new] [key]  Compare two match files, listing matches gained, lost, and changed partner or
+        distance. Rows are joined on the key column, the first column by default
+    export [type] [path]    Export the results of the last match, or the session as a script
+        type Options:
+            map     Html map of matched pairs and unmatched entries. Add a directory with leaflet.js and
+                    leaflet.css to write Leaflet into the page instead of loading it from unpkg.com
+            arrow   Arrow IPC file of the match output
+            parquet Parquet file of the match output
+            gpkg    GeoPackage of the match output as points, and lines between matched pairs
+            script  The commands that set up this session, to replay or use as a watch pipeline
     quit    Quit the application
     help    List out this help message
+    [alias] [args]  Run the commands of an alias from the config file, with $1, $2... replaced by args
         "#
     };
     println!("{}", HELP_MSG);
@@ -163,8 +688,27 @@ type help to see commands and options
     println!("{}", SPLASH);
 }
 
-fn print_prompt() {
-    print!("geomatch> ");
-    stdout().flush().unwrap();
-}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The scripts come from app(), so every subcommand and long flag should be in each of them
+    #[test]
+    fn completions_cover_every_subcommand_and_flag() {
+        let cli = app();
+        let mut names: Vec<String> = cli.p.subcommands.iter().map(|e| e.p.meta.name.clone()).collect();
+        names.extend(cli.p.flags.iter().filter_map(|e| e.s.long).map(String::from));
+        names.extend(cli.p.opts.iter().filter_map(|e| e.s.long).map(String::from));
+        assert!(names.contains(&"watch".to_string()) && names.contains(&"row-id".to_string()));
+
+        for shell in Shell::variants().iter() {
+            let mut script = Vec::new();
+            app().gen_completions_to("geomatch", shell.parse::<Shell>().unwrap(), &mut script);
+            let script = String::from_utf8(script).unwrap();
+            for name in names.iter() {
+                assert!(script.contains(name.as_str()), "{} completions are missing {}", shell, name);
+            }
+        }
+    }
+}
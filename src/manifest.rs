@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{create_dir_all, read_to_string};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use serde_json::json;
+use tokio::runtime::Handle;
+use super::command::{run_command, split_input};
+use super::notify::notify;
+use super::state::State;
+
+// Keys a job can have, anything else is a mistake
+const JOB_KEYS: [&str; 8] = ["name", "inputs", "mappings", "radius", "method", "commands", "steps", "output"];
+
+// One fetch or match to run, from a [[job]] table
+pub struct Job {
+    pub name: String,
+    pub inputs: Vec<String>,
+    pub mappings: Vec<Option<String>>,  // applied to the input at the same position
+    pub commands: Vec<String>,          // settings run after loading, before the steps
+    pub steps: Vec<String>,             // fetch, match and the like, match if not given
+    pub out: Option<String>,            // directory outputs go in
+}
+
+// A jobs file, run with `geomatch run jobs.toml`:
+//   parallel = 4                   # jobs run at once, 1 (default) runs them in order
+//   out = "results"                # outputs go in a folder per job under this
+//
+//   [[job]]
+//   name = "hennepin"
+//   inputs = ["hennepin.csv", "stores.csv"]
+//   mappings = ["county.toml", ""] # applied with map, "" leaves a file as loaded
+//   radius = 0.5
+//   method = "left"
+//   commands = ["exclusive false"]
+//   steps = ["fetch", "match"]
+//   output = "matches_{date}.csv"  # the matchoutput template
+// Relative paths are from the jobs file's directory
+pub struct Manifest {
+    pub parallel: usize,
+    pub jobs: Vec<Job>,
+}
+
+pub fn read_manifest(path: &str) -> Result<Manifest, Box<dyn Error>> {
+    let text = read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut table: toml::Table = text.parse().map_err(|e| format!("{}: {}", path, e))?;
+    let base = Path::new(path).parent().unwrap_or(Path::new(""));
+    let resolve = |file: &str| base.join(file).to_string_lossy().to_string();
+
+    let parallel = match table.remove("parallel") {
+        Some(toml::Value::Integer(parallel)) if parallel > 0 => parallel as usize,
+        Some(_) => return Err(format!("{}: parallel must be a number of jobs", path).into()),
+        None => 1,
+    };
+    let out = match table.remove("out") {
+        Some(toml::Value::String(out)) => Some(resolve(&out)),
+        Some(_) => return Err(format!("{}: out must be a directory", path).into()),
+        None => None,
+    };
+    let tables = match table.remove("job") {
+        Some(toml::Value::Array(jobs)) => jobs,
+        _ => return Err(format!("{}: no [[job]] tables", path).into()),
+    };
+    if let Some(key) = table.keys().next() {
+        return Err(format!("{}: unknown setting '{}'", path, key).into());
+    }
+
+    let mut jobs = Vec::new();
+    for (index, job) in tables.into_iter().enumerate() {
+        let job = match job {
+            toml::Value::Table(job) => job,
+            _ => return Err(format!("{}: job {} must be a table", path, index).into()),
+        };
+        if let Some(key) = job.keys().find(|e| !JOB_KEYS.contains(&e.as_str())) {
+            return Err(format!("{}: unknown job setting '{}'", path, key).into());
+        }
+
+        let name = match job.get("name") {
+            Some(name) => name.as_str().ok_or_else(|| format!("{}: job {}'s name must be text", path, index))?.to_string(),
+            None => index.to_string(),
+        };
+        let list = |key: &str| -> Result<Vec<String>, String> {
+            match job.get(key) {
+                None => Ok(Vec::new()),
+                Some(toml::Value::Array(list)) => list.iter().map(|e| e.as_str().map(String::from)).collect::<Option<_>>()
+                    .ok_or_else(|| format!("{}: {}'s {} must be a list of text", path, name, key)),
+                Some(_) => Err(format!("{}: {}'s {} must be a list of text", path, name, key)),
+            }
+        };
+
+        let inputs: Vec<String> = list("inputs")?.iter().map(|e| resolve(e)).collect();
+        if inputs.is_empty() {
+            return Err(format!("{}: {} has no inputs", path, name).into());
+        }
+        let mappings: Vec<Option<String>> = list("mappings")?.iter()
+            .map(|e| if e.is_empty() {None} else {Some(resolve(e))})
+            .collect();
+        if mappings.len() > inputs.len() {
+            return Err(format!("{}: {} has more mappings than inputs", path, name).into());
+        }
+
+        let mut commands = Vec::new();
+        match job.get("radius") {
+            Some(toml::Value::Integer(radius)) => commands.push(format!("radius {}", radius)),
+            Some(toml::Value::Float(radius)) => commands.push(format!("radius {}", radius)),
+            Some(toml::Value::String(radius)) => commands.push(format!("radius {}", radius)),
+            Some(_) => return Err(format!("{}: {}'s radius must be a number", path, name).into()),
+            None => {}
+        }
+        match job.get("method") {
+            Some(toml::Value::String(method)) => commands.push(format!("method {}", method)),
+            Some(_) => return Err(format!("{}: {}'s method must be text", path, name).into()),
+            None => {}
+        }
+        match job.get("output") {
+            Some(toml::Value::String(output)) => commands.push(format!("set matchoutput {}", output)),
+            Some(_) => return Err(format!("{}: {}'s output must be a file name", path, name).into()),
+            None => {}
+        }
+        commands.extend(list("commands")?);
+
+        let mut steps = list("steps")?;
+        if steps.is_empty() {
+            steps.push("match".to_string());
+        }
+
+        let out = out.as_ref().map(|e| Path::new(e).join(&name).to_string_lossy().to_string());
+        jobs.push(Job {name, inputs, mappings, commands, steps, out});
+    }
+
+    Ok(Manifest {parallel, jobs})
+}
+
+// Run every job, up to parallel at once, each with a state of its own. A job that fails doesn't
+// stop the others, the failures are listed at the end
+pub async fn run_manifest<F>(manifest: Manifest, new_state: F, webhook: Option<String>) -> Result<(), Box<dyn Error>>
+    where F: Fn() -> State + Send + Sync + 'static {
+    let total = manifest.jobs.len();
+    let workers = manifest.parallel.min(total);
+    let queue = Arc::new(Mutex::new(manifest.jobs.into_iter().collect::<VecDeque<Job>>()));
+    let failures = Arc::new(Mutex::new(Vec::new()));
+    let new_state = Arc::new(new_state);
+    println!("Running {} jobs, {} at a time", total, workers);
+
+    // Commands run on blocking threads since they aren't written to be sent between threads
+    // while they await, the same as in serve mode
+    let mut handles = Vec::new();
+    for _ in 0..workers {
+        let (queue, failures, new_state, webhook) = (queue.clone(), failures.clone(), new_state.clone(), webhook.clone());
+        let handle = Handle::current();
+        handles.push(tokio::task::spawn_blocking(move || {
+            loop {
+                // Taken on its own line so the queue isn't locked while the job runs
+                let next = queue.lock().unwrap().pop_front();
+                let job = match next {
+                    Some(job) => job,
+                    None => break,
+                };
+
+                let mut state = new_state();
+                let result = handle.block_on(run_job(&job, &mut state));
+                match &result {
+                    Ok(_) => println!("Finished job {}", job.name),
+                    Err(e) => {
+                        println!("Job {} failed: {}", job.name, e);
+                        failures.lock().unwrap().push(job.name.clone());
+                    }
+                }
+
+                if let Some(url) = webhook.as_ref() {
+                    handle.block_on(notify(url, &json!({
+                        "mode": "run",
+                        "job": job.name,
+                        "status": if result.is_ok() {"done"} else {"failed"},
+                        "error": result.as_ref().err(),
+                        "runs": state.take_runs(),
+                    })));
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        handle.await?;
+    }
+
+    let failures = failures.lock().unwrap();
+    if failures.is_empty() {
+        println!("All {} jobs finished", total);
+        Ok(())
+    } else {
+        Err(format!("{} of {} jobs failed: {}", failures.len(), total, failures.join(", ")).into())
+    }
+}
+
+async fn run_job(job: &Job, state: &mut State) -> Result<(), String> {
+    println!("Starting job {}", job.name);
+    if let Some(out) = job.out.as_ref() {
+        create_dir_all(out).map_err(|e| format!("{}: {}", out, e))?;
+        state.set_output_dir(out);
+    }
+
+    for input in job.inputs.iter() {
+        state.add_file(input).map_err(|e| format!("{}: {}", input, e))?;
+    }
+    for (index, mapping) in job.mappings.iter().enumerate() {
+        if let Some(mapping) = mapping {
+            let index = index.to_string();
+            run_command(state, vec!["map", &index, mapping]).await.map_err(|e| format!("map {}: {}", mapping, e))?;
+        }
+    }
+
+    for line in job.commands.iter().chain(job.steps.iter()) {
+        let input = split_input(line);
+        if matches!(input.first(), Some(&"quit") | Some(&"help")) {
+            continue;
+        }
+        run_command(state, input).await.map_err(|e| format!("{}: {}", line, e))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+use super::atomic::{self, Compression};
+use super::jitter::Jitter;
+use super::state::MatchRun;
+
+// Leaflet from the CDN, pinned to its published hashes so a changed file isn't run
+const LEAFLET_CDN: &str = r#"<link rel="stylesheet" href="https://unpkg.com/leaflet@1.9.4/dist/leaflet.css"
+    integrity="sha256-p4NxAoJBhIIN+hmNHrzRCf9tD/miZyoHS5obTRR9BMY=" crossorigin="">
+<script src="https://unpkg.com/leaflet@1.9.4/dist/leaflet.js"
+    integrity="sha256-20nQCchB9co0qIjJZRGuk2/Z9VM+kNiyxNV1lvTlZBo=" crossorigin=""></script>"#;
+
+const TEMPLATE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>geomatch results</title>
+__LEAFLET__
+<style>
+    html, body, #map { height: 100%; margin: 0; }
+    .legend { background: white; padding: 6px 10px; font: 12px sans-serif; line-height: 18px; }
+</style>
+</head>
+<body>
+<div id="map"></div>
+<script>
+const data = __DATA__;
+const colors = ["#1f77b4", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#17becf"];
+
+const map = L.map("map");
+L.tileLayer("https://{s}.tile.openstreetmap.org/{z}/{x}/{y}.png", {
+    attribution: "&copy; OpenStreetMap contributors",
+    maxZoom: 19,
+}).addTo(map);
+
+function escape(text) {
+    const div = document.createElement("div");
+    div.textContent = text;
+    return div.innerHTML;
+}
+
+function popup(row) {
+    return row.info.map(e => "<b>" + escape(e[0]) + "</b>: " + escape(e[1])).join("<br>");
+}
+
+// Green for exact matches through red at the edge of the radius
+function distColor(dist) {
+    const ratio = data.radius > 0 ? Math.min(dist / data.radius, 1) : 0;
+    return "hsl(" + Math.round((1 - ratio) * 120) + ", 80%, 40%)";
+}
+
+const bounds = [];
+const matched = L.layerGroup().addTo(map);
+const unmatched = L.layerGroup().addTo(map);
+
+for (const row of data.rows) {
+    const marker = L.circleMarker([row.lat, row.lng], {
+        radius: 5,
+        color: row.matched ? colors[row.file % colors.length] : "#d62728",
+        fillOpacity: 0.8,
+    }).bindPopup("File " + row.file + (row.matched ? "" : " (unmatched)") + "<br>" + popup(row));
+    marker.addTo(row.matched ? matched : unmatched);
+    bounds.push([row.lat, row.lng]);
+}
+
+for (const pair of data.pairs) {
+    L.polyline([pair.from, pair.to], { color: distColor(pair.dist), weight: 3 })
+        .bindPopup("File " + pair.file + " match, " + pair.dist.toFixed(4) + " miles<br>" + popup(data.rows[pair.row]))
+        .addTo(matched);
+    L.circleMarker(pair.to, { radius: 4, color: colors[pair.file % colors.length], fillOpacity: 0.8 })
+        .addTo(matched);
+    bounds.push(pair.to);
+}
+
+if (bounds.length > 0) {
+    map.fitBounds(bounds, { padding: [20, 20] });
+} else {
+    map.setView([39.8, -98.6], 4);
+}
+
+L.control.layers(null, { "Matches": matched, "Unmatched": unmatched }).addTo(map);
+
+const legend = L.control({ position: "bottomright" });
+legend.onAdd = function () {
+    const div = L.DomUtil.create("div", "legend");
+    div.innerHTML = "<span style='color:" + distColor(0) + "'>&#9644;</span> 0 miles<br>" +
+        "<span style='color:" + distColor(data.radius) + "'>&#9644;</span> " + data.radius + " miles<br>" +
+        "<span style='color:#d62728'>&#9679;</span> unmatched";
+    return div;
+};
+legend.addTo(map);
+</script>
+</body>
+</html>
+"##;
+
+// Leaflet read from a directory with leaflet.js and leaflet.css, like the dist folder of its
+// download, to write into the page so the map opens offline. The CDN without one
+fn leaflet(dir: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let dir = match dir {
+        Some(dir) => Path::new(dir),
+        None => return Ok(LEAFLET_CDN.to_string())
+    };
+    let read = |name: &str| read_to_string(dir.join(name))
+        .map_err(|e| format!("Couldn't read {}: {}", dir.join(name).display(), e));
+    let (script, style) = (read("leaflet.js")?, read("leaflet.css")?);
+
+    Ok(format!("<style>\n{}\n</style>\n<script>\n{}\n</script>", style, script.replace("</script", "<\\/script")))
+}
+
+// Write a Leaflet map of the match run as a single html file, with every point coarsened by
+// the jitter if there is one. Leaflet comes from the leaflet directory if given
+pub fn write_map(path: &str, run: &MatchRun, jitter: Option<Jitter>, leaflet_dir: Option<&str>, compression: Option<Compression>) -> Result<(), Box<dyn Error>> {
+    let leaflet = leaflet(leaflet_dir)?;
+    let headers = run.output.output_headers();
+    let coarse = |(lat, lng): (f64, f64)| jitter.map_or((lat, lng), |e| e.apply(lat, lng));
+
+    // Output rows keep their index so pairs can refer to them
+    let mut rows = Vec::with_capacity(run.origins.len());
+    let mut index = vec![None; run.origins.len()];
+    for (row, (file, lat, lng)) in run.origins.iter().enumerate() {
+        if !run.is_written(row) || lat.is_nan() || lng.is_nan() {
+            continue;
+        }
+
+        let info: Vec<Value> = headers.iter()
+            .zip(run.output.output_row(row))
+            .filter(|(_, val)| !val.is_empty())
+            .map(|(header, val)| json!([header, val]))
+            .collect();
+
+        let (lat, lng) = coarse((*lat, *lng));
+        index[row] = Some(rows.len());
+        rows.push(json!({
+            "lat": lat,
+            "lng": lng,
+            "file": file,
+            "matched": run.match_mask[row],
+            "info": info,
+        }));
+    }
+
+    let mut pairs = Vec::with_capacity(run.pairs.len());
+    for pair in run.pairs.iter() {
+        if let Some(row) = index[pair.row] {
+            let (from, to) = (coarse(pair.from), coarse(pair.to));
+            pairs.push(json!({
+                "row": row,
+                "file": pair.file,
+                "from": [from.0, from.1],
+                "to": [to.0, to.1],
+                "dist": pair.dist,
+            }));
+        }
+    }
+
+    let data = json!({
+        "radius": run.radius,
+        "rows": rows,
+        "pairs": pairs,
+    });
+
+    // Keep values from closing the script tag early
+    let data = data.to_string().replace("</", "<\\/");
+    // Split at the placeholders rather than replacing them, so neither can turn up in the other
+    let (head, rest) = TEMPLATE.split_once("__LEAFLET__").unwrap();
+    let (middle, tail) = rest.split_once("__DATA__").unwrap();
+    atomic::write_output(path, [head, &leaflet, middle, &data, tail].concat(), compression)?;
+
+    Ok(())
+}
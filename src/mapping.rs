@@ -0,0 +1,76 @@
+use csv::ReaderBuilder;
+use std::error::Error;
+use std::fs::read_to_string;
+use super::roles::Role;
+
+// Settings a mapping file can give besides the roles
+const SETTINGS: [&str; 10] = [
+    "latlng", "template", "provider", "prefix", "output", "compare", "phone", "name", "soundex", "metaphone"
+];
+
+// Role to column assignments, prefix, and output and compare columns for one file, applied
+// in order by the map command. Either a csv of setting,column pairs:
+//   setting,column
+//   addr1,Site Address
+//   output,Store Name
+// or a toml file of the same, with a list for settings given more than once:
+//   addr1 = "Site Address"
+//   output = ["Store Name", "Phone"]
+pub fn read_mapping(path: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let pairs = if path.to_lowercase().ends_with(".toml") {
+        read_toml(path)?
+    } else {
+        read_csv(path)?
+    };
+
+    pairs.into_iter()
+        .map(|(setting, value)| {
+            let setting = match Role::parse(&setting) {
+                Some(role) => role.name().to_string(),
+                None if SETTINGS.contains(&setting.to_lowercase().as_str()) => setting.to_lowercase(),
+                None => return Err(format!("{}: unknown setting '{}'", path, setting).into())
+            };
+            Ok((setting, value))
+        })
+        .collect()
+}
+
+fn read_csv(path: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_path(path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+
+    let mut pairs = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("{}: {}", path, e))?;
+        let fields: Vec<&str> = record.iter().map(|e| e.trim()).collect();
+        let line = record.position().map_or(index+1, |e| e.line() as usize);
+        match fields.as_slice() {
+            [setting, column] if index == 0 && setting.eq_ignore_ascii_case("setting") && column.eq_ignore_ascii_case("column") => {}
+            [setting, column] => pairs.push((setting.to_string(), column.to_string())),
+            [""] => {}
+            _ => return Err(format!("{}: line {} should be a setting and a column", path, line).into())
+        }
+    }
+    Ok(pairs)
+}
+
+fn read_toml(path: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let text = read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let table: toml::Table = text.parse().map_err(|e| format!("{}: {}", path, e))?;
+
+    let mut pairs = Vec::new();
+    for (setting, value) in table {
+        let values = match value {
+            toml::Value::String(column) => vec![column],
+            toml::Value::Array(list) => list.into_iter().map(|e| e.as_str().map(String::from)).collect::<Option<_>>()
+                .ok_or_else(|| format!("{}: {} must be a column or a list of columns", path, setting))?,
+            _ => return Err(format!("{}: {} must be a column or a list of columns", path, setting).into())
+        };
+        pairs.extend(values.into_iter().map(|e| (setting.clone(), e)));
+    }
+    Ok(pairs)
+}
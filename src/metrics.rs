@@ -0,0 +1,92 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Counters kept for the life of the process, served in the Prometheus text format by serve mode
+pub static GEOCODE_REQUESTS: AtomicU64 = AtomicU64::new(0);
+pub static GEOCODE_FAILURES: AtomicU64 = AtomicU64::new(0);     // errors and addresses with no result
+pub static ROUTE_REQUESTS: AtomicU64 = AtomicU64::new(0);
+pub static ROWS_MATCHED: AtomicU64 = AtomicU64::new(0);
+pub static MATCH_RUNS: AtomicU64 = AtomicU64::new(0);
+
+pub fn count(counter: &AtomicU64, n: usize) {
+    counter.fetch_add(n as u64, Ordering::Relaxed);
+}
+
+// Name, help and labelled values of a metric the caller keeps itself
+pub type Series<'a> = (&'a str, &'a str, Vec<(String, f64)>);
+
+// Every counter, followed by the caller's own counters and gauges
+pub fn render(extra: &[Series], gauges: &[Series]) -> String {
+    let counters = [
+        ("geomatch_requests_total", "Requests sent to geocoding and routing providers", vec![
+            ("service=\"geocode\"", &GEOCODE_REQUESTS),
+            ("service=\"route\"", &ROUTE_REQUESTS),
+        ]),
+        ("geomatch_geocode_failures_total", "Geocoding requests that failed or found nothing", vec![("", &GEOCODE_FAILURES)]),
+        ("geomatch_rows_matched_total", "Rows matched across every match run", vec![("", &ROWS_MATCHED)]),
+        ("geomatch_match_runs_total", "Match runs finished", vec![("", &MATCH_RUNS)]),
+    ];
+
+    let mut out = String::new();
+    for (name, help, values) in counters.iter() {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+        for (labels, counter) in values {
+            let _ = writeln!(out, "{} {}", series(name, labels), counter.load(Ordering::Relaxed));
+        }
+    }
+
+    for (kind, metrics) in [("counter", extra), ("gauge", gauges)] {
+        for (name, help, values) in metrics {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            for (labels, value) in values {
+                let _ = writeln!(out, "{} {}", series(name, labels), value);
+            }
+        }
+    }
+
+    out
+}
+
+pub fn response(text: String) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(Body::from(text))
+        .unwrap()
+}
+
+// Serve GET /metrics in the background, for modes without an http server of their own like
+// watch. text is called for every scrape. Fails straight away if the address can't be bound
+pub fn serve_metrics<F>(addr: SocketAddr, text: F) -> Result<(), Box<dyn Error>>
+    where F: Fn() -> String + Send + Sync + Copy + 'static {
+    let make_service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+            Ok::<_, Infallible>(match (req.method(), req.uri().path().trim_matches('/')) {
+                (&Method::GET, "metrics") => response(text()),
+                _ => Response::builder().status(StatusCode::NOT_FOUND).body(Body::from("Not found")).unwrap()
+            })
+        }))
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    println!("Serving metrics on http://{}/metrics", addr);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            println!("Metrics stopped: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+fn series(name: &str, labels: &str) -> String {
+    if labels.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}{{{}}}", name, labels)
+    }
+}
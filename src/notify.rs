@@ -0,0 +1,17 @@
+use reqwest::Client;
+use serde_json::Value;
+
+// POST a summary of a finished job to a webhook as json. A webhook that can't be reached
+// shouldn't fail the job it reports on, so errors are only printed
+pub async fn notify(url: &str, summary: &Value) {
+    let result = Client::new()
+        .post(url)
+        .json(summary)
+        .send()
+        .await
+        .and_then(|e| e.error_for_status());
+
+    if let Err(e) = result {
+        println!("Failed to notify {}: {}", url, e);
+    }
+}
@@ -0,0 +1,43 @@
+use console::Term;
+use std::io::{stdin, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Only the cli pages, the server, watch and piped in scripts have no one to press enter
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+// Print lines a screen at a time, waiting for enter between screens. q stops early. Everything
+// is printed at once when paging is off or output isn't a terminal
+pub fn page(lines: &[String]) {
+    let term = Term::stdout();
+    let height = term.size().0 as usize;
+    if !ENABLED.load(Ordering::SeqCst) || !term.is_term() || !stdin().is_terminal() || lines.len() < height {
+        for line in lines {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    // Leave a line for the prompt
+    let screen = height.saturating_sub(1).max(1);
+    let mut shown = 0;
+    for chunk in lines.chunks(screen) {
+        for line in chunk {
+            println!("{}", line);
+        }
+        shown += chunk.len();
+        if shown == lines.len() {
+            break;
+        }
+
+        let _ = term.write_str(&format!("-- {} of {} lines, enter for more, q to stop --", shown, lines.len()));
+        let mut answer = String::new();
+        if stdin().read_line(&mut answer).is_err() || answer.trim().eq_ignore_ascii_case("q") {
+            break;
+        }
+        let _ = term.clear_last_lines(1);
+    }
+}
@@ -0,0 +1,202 @@
+// Phonetic codes for names, so the same name spelled differently in two files, like Smith and
+// Smyth or Catherine and Kathryn, gets the same code
+
+// Soundex, the first letter and three digits for the consonants after it, e.g. Robert is R163
+pub fn soundex(word: &str) -> String {
+    let letters: Vec<char> = word.chars().filter(|c| c.is_ascii_alphabetic()).map(|c| c.to_ascii_uppercase()).collect();
+    let first = match letters.first() {
+        Some(first) => *first,
+        None => return word.to_string(),
+    };
+
+    let mut code = first.to_string();
+    let mut last = soundex_digit(first);
+    for c in &letters[1..] {
+        let digit = soundex_digit(*c);
+        if digit != '0' && digit != last {
+            code.push(digit);
+            if code.len() == 4 {
+                break;
+            }
+        }
+
+        // H and W don't separate consonants with the same digit, vowels do
+        if *c != 'H' && *c != 'W' {
+            last = digit;
+        }
+    }
+
+    while code.len() < 4 {
+        code.push('0');
+    }
+    code
+}
+
+fn soundex_digit(c: char) -> char {
+    match c {
+        'B' | 'F' | 'P' | 'V' => '1',
+        'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => '2',
+        'D' | 'T' => '3',
+        'L' => '4',
+        'M' | 'N' => '5',
+        'R' => '6',
+        _ => '0',
+    }
+}
+
+fn vowel(c: Option<&char>) -> bool {
+    matches!(c, Some('A' | 'E' | 'I' | 'O' | 'U'))
+}
+
+fn front_vowel(c: Option<&char>) -> bool {
+    matches!(c, Some('E' | 'I' | 'Y'))
+}
+
+// Metaphone, which knows more of how English is spelled than soundex, so Knight and Night or
+// Philips and Filips get the same code. 0 stands for th
+pub fn metaphone(word: &str) -> String {
+    let mut letters: Vec<char> = word.chars().filter(|c| c.is_ascii_alphabetic()).map(|c| c.to_ascii_uppercase()).collect();
+    if letters.is_empty() {
+        return word.to_string();
+    }
+
+    // Doubled letters sound like one, except cc as in accent
+    letters.dedup_by(|a, b| a == b && *a != 'C');
+
+    // Silent first letters
+    let start = match (letters[0], letters.get(1)) {
+        ('A', Some('E')) | ('G', Some('N')) | ('K', Some('N')) | ('P', Some('N')) | ('W', Some('R')) => 1,
+        _ => 0,
+    };
+    let letters = &letters[start..];
+
+    let mut code = String::new();
+    for i in 0..letters.len() {
+        let c = letters[i];
+        let prev = if i > 0 {letters.get(i - 1)} else {None};
+        let next = letters.get(i + 1);
+        let after = letters.get(i + 2);
+
+        match c {
+            'A' | 'E' | 'I' | 'O' | 'U' => {
+                if i == 0 {
+                    code.push(c);
+                }
+            }
+            'B' => {
+                // Dumb and lamb
+                if !(prev == Some(&'M') && next.is_none()) {
+                    code.push('B');
+                }
+            }
+            'C' => {
+                if next == Some(&'I') && after == Some(&'A') {
+                    code.push('X');
+                } else if next == Some(&'H') {
+                    code.push(if prev == Some(&'S') {'K'} else {'X'});
+                } else if front_vowel(next) {
+                    if prev != Some(&'S') {
+                        code.push('S');
+                    }
+                } else {
+                    code.push('K');
+                }
+            }
+            'D' => {
+                if next == Some(&'G') && front_vowel(after) {
+                    code.push('J');
+                } else {
+                    code.push('T');
+                }
+            }
+            'G' => {
+                if next == Some(&'H') && !(after.is_none() || vowel(after)) {
+                    // Silent in night and light
+                } else if next == Some(&'N') && (after.is_none() || (after == Some(&'E') && letters.get(i + 3) == Some(&'D') && i + 4 == letters.len())) {
+                    // Silent in sign and signed
+                } else if prev == Some(&'D') && front_vowel(next) {
+                    // Already the j of dge
+                } else if front_vowel(next) {
+                    code.push('J');
+                } else {
+                    code.push('K');
+                }
+            }
+            'H' => {
+                // Silent after a vowel unless another follows, as in ah and ahead
+                let after_consonant = matches!(prev, Some('C' | 'S' | 'P' | 'T' | 'G')) || (i == 1 && prev == Some(&'W'));
+                if !after_consonant && (!vowel(prev) || vowel(next)) {
+                    code.push('H');
+                }
+            }
+            'K' => {
+                if prev != Some(&'C') {
+                    code.push('K');
+                }
+            }
+            'P' => code.push(if next == Some(&'H') {'F'} else {'P'}),
+            'Q' => code.push('K'),
+            'S' => {
+                if next == Some(&'H') || (next == Some(&'I') && matches!(after, Some('O' | 'A'))) {
+                    code.push('X');
+                } else {
+                    code.push('S');
+                }
+            }
+            'T' => {
+                if next == Some(&'I') && matches!(after, Some('O' | 'A')) {
+                    code.push('X');
+                } else if next == Some(&'H') {
+                    code.push('0');
+                } else if !(next == Some(&'C') && after == Some(&'H')) {
+                    code.push('T');
+                }
+            }
+            'V' => code.push('F'),
+            'W' | 'Y' => {
+                // Whitney starts with a w
+                if vowel(next) || (c == 'W' && i == 0 && next == Some(&'H')) {
+                    code.push(c);
+                }
+            }
+            'X' => {
+                // Xavier sounds like an s
+                if i == 0 {
+                    code.push('S');
+                } else {
+                    code.push_str("KS");
+                }
+            }
+            'Z' => code.push('S'),
+            _ => code.push(c),
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soundex_known_answers() {
+        for (word, code) in [("Robert", "R163"), ("Rupert", "R163"), ("Rubin", "R150"), ("Ashcraft", "A261"),
+            ("Tymczak", "T522"), ("Pfister", "P236"), ("Honeyman", "H555"), ("Lee", "L000")] {
+            assert_eq!(soundex(word), code, "{}", word);
+        }
+    }
+
+    #[test]
+    fn metaphone_known_answers() {
+        for (word, code) in [("Knight", "NT"), ("Night", "NT"), ("Philips", "FLPS"), ("Filips", "FLPS"), ("Smith", "SM0"),
+            ("Smyth", "SM0"), ("Xavier", "SFR"), ("Whitney", "WTN"), ("Dumb", "TM"), ("Edge", "EJ")] {
+            assert_eq!(metaphone(word), code, "{}", word);
+        }
+    }
+
+    #[test]
+    fn words_without_letters_are_kept() {
+        assert_eq!(soundex("123"), "123");
+        assert_eq!(metaphone(""), "");
+    }
+}
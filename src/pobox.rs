@@ -0,0 +1,43 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+// What fetch does with PO Box and private mailbox addresses, which are a post office counter or
+// a mailbox store rather than where anyone is
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PoBoxPolicy {
+    Skip,       // leave them without coordinates
+    Locality,   // geocode on city, state and zip alone
+    Geocode,    // geocode the address as it is
+}
+
+impl PoBoxPolicy {
+    pub fn parse(text: &str) -> Option<PoBoxPolicy> {
+        match text.to_lowercase().as_str() {
+            "skip" => Some(PoBoxPolicy::Skip),
+            "city" => Some(PoBoxPolicy::Locality),
+            "geocode" => Some(PoBoxPolicy::Geocode),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PoBoxPolicy::Skip => "skip",
+            PoBoxPolicy::Locality => "city",
+            PoBoxPolicy::Geocode => "geocode",
+        }
+    }
+}
+
+// PO Box 12, P.O. Box 12, POB 12, Post Office Box 12, Box 12, PMB 12 and Private Mailbox 12.
+// Box needs a number after it so Box Elder Rd is still a street
+fn po_box() -> &'static Regex {
+    static PO_BOX: OnceLock<Regex> = OnceLock::new();
+    PO_BOX.get_or_init(|| {
+        Regex::new(r"(?i)\b(p\s*\.?\s*o\s*\.?\s*(box|b\b\.?)|post\s+office\s+box|private\s+mail\s*box|pmb|box)\s*#?\s*\d").unwrap()
+    })
+}
+
+pub fn is_po_box(address: &str) -> bool {
+    po_box().is_match(address)
+}
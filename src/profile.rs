@@ -0,0 +1,51 @@
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// Time spent in each phase of a command, so a slow run can be pinned on the network or the
+// CPU. Phases are reported in the order they were first timed
+#[derive(Default)]
+pub struct Profile {
+    phases: Vec<(String, Duration)>,
+}
+
+impl Profile {
+    pub fn add(&mut self, phase: &str, elapsed: Duration) {
+        match self.phases.iter_mut().find(|e| e.0 == phase) {
+            Some(e) => e.1 += elapsed,
+            None => self.phases.push((phase.to_string(), elapsed)),
+        }
+    }
+
+    // Add the time since start to a phase
+    pub fn since(&mut self, phase: &str, start: Instant) {
+        self.add(phase, start.elapsed());
+    }
+
+    pub fn merge(&mut self, other: Profile) {
+        for (phase, elapsed) in other.phases {
+            self.add(&phase, elapsed);
+        }
+    }
+
+    // Record each phase as an event in the command's span, for when tracing is on
+    pub fn trace(&self) {
+        for (phase, elapsed) in self.phases.iter() {
+            info!(phase = phase.as_str(), seconds = elapsed.as_secs_f64(), "phase");
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.phases.is_empty()
+    }
+
+    pub fn print(&self) {
+        let total: Duration = self.phases.iter().map(|e| e.1).sum();
+
+        println!("Profile:");
+        for (phase, elapsed) in self.phases.iter() {
+            let share = if total.is_zero() {0.0} else {elapsed.as_secs_f64() / total.as_secs_f64() * 100.0};
+            println!("\t{:<24}{:>10.3}s{:>7.1}%", phase, elapsed.as_secs_f64(), share);
+        }
+        println!("\t{:<24}{:>10.3}s", "total", total.as_secs_f64());
+    }
+}
@@ -0,0 +1,288 @@
+use std::error::Error;
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+use std::fmt::{Display, Formatter};
+
+// GRS80 ellipsoid, used by NAD83. WGS84 differs by well under a millimeter
+const A: f64 = 6378137.0;
+const F: f64 = 1.0 / 298.257222101;
+
+const METER: f64 = 1.0;
+const FOOT: f64 = 0.3048;
+const US_FOOT: f64 = 1200.0 / 3937.0;
+
+// Projected coordinate systems that can be converted back to lat/lng
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    TransverseMercator {
+        lat0: f64,
+        lon0: f64,
+        k0: f64,
+        x0: f64,    // false easting (meters)
+        y0: f64,    // false northing (meters)
+        unit: f64,  // meters per coordinate unit
+    },
+    LambertConformal {
+        lat1: f64,
+        lat2: f64,
+        lat0: f64,
+        lon0: f64,
+        x0: f64,
+        y0: f64,
+        unit: f64,
+    },
+}
+
+impl Display for Projection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Projection::TransverseMercator { lat0, lon0, k0, x0, y0, unit } => {
+                write!(f, "tmerc {} {} {} {} {} {}", lat0, lon0, k0, x0, y0, unit_name(*unit))
+            }
+            Projection::LambertConformal { lat1, lat2, lat0, lon0, x0, y0, unit } => {
+                write!(f, "lcc {} {} {} {} {} {} {}", lat1, lat2, lat0, lon0, x0, y0, unit_name(*unit))
+            }
+        }
+    }
+}
+
+impl Projection {
+    // Parse one of
+    //   epsg [code]
+    //   utm [zone][N|S]
+    //   tmerc [lat0] [lon0] [k0] [x0] [y0] [unit]
+    //   lcc [lat1] [lat2] [lat0] [lon0] [x0] [y0] [unit]
+    // where false eastings and northings are in meters and unit is m, ft or us-ft
+    pub fn parse(input: &[&str]) -> Result<Projection, Box<dyn Error>> {
+        let kind = input.first();
+        if kind.is_none() {
+            return Err("projection required")?;
+        }
+
+        let numbers = |count: usize| -> Result<Vec<f64>, Box<dyn Error>> {
+            if input.len() < count + 1 {
+                return Err(format!("{} requires {} parameters", input[0], count))?;
+            }
+            let mut numbers = Vec::with_capacity(count);
+            for val in input[1..count+1].iter() {
+                numbers.push(val.parse::<f64>()?);
+            }
+            Ok(numbers)
+        };
+        let unit = |index: usize| parse_unit(input.get(index).unwrap_or(&"m"));
+
+        match kind.unwrap().to_lowercase().as_str() {
+            "epsg" => {
+                let code = input.get(1);
+                if code.is_none() {
+                    return Err("code required")?;
+                }
+                let code = code.unwrap().trim_start_matches("EPSG:").parse::<u32>()?;
+                match Projection::from_epsg(code) {
+                    Some(projection) => Ok(projection),
+                    None => Err(format!("EPSG:{} is not supported, use tmerc or lcc with its parameters", code))?
+                }
+            }
+            "utm" => {
+                let zone = input.get(1);
+                if zone.is_none() {
+                    return Err("zone required")?;
+                }
+                let zone = zone.unwrap().to_uppercase();
+                let south = zone.ends_with('S');
+                let zone = zone.trim_end_matches(['N', 'S']).parse::<u32>()?;
+                if !(1..=60).contains(&zone) {
+                    return Err("zone must be between 1 and 60")?;
+                }
+                Ok(Projection::utm(zone, south))
+            }
+            "tmerc" => {
+                let p = numbers(5)?;
+                Ok(Projection::TransverseMercator {
+                    lat0: p[0], lon0: p[1], k0: p[2], x0: p[3], y0: p[4], unit: unit(6)?
+                })
+            }
+            "lcc" => {
+                let p = numbers(6)?;
+                Ok(Projection::LambertConformal {
+                    lat1: p[0], lat2: p[1], lat0: p[2], lon0: p[3], x0: p[4], y0: p[5], unit: unit(7)?
+                })
+            }
+            _ => Err("Invalid projection, expected epsg, utm, tmerc or lcc")?
+        }
+    }
+
+    // UTM zones on WGS84 and NAD83, plus a few State Plane zones
+    pub fn from_epsg(code: u32) -> Option<Projection> {
+        match code {
+            32601..=32660 => Some(Projection::utm(code - 32600, false)),
+            32701..=32760 => Some(Projection::utm(code - 32700, true)),
+            26901..=26923 => Some(Projection::utm(code - 26900, false)),
+            // NAD83 / Minnesota North, Central and South
+            26991 => Some(Projection::lcc(48.633333333, 47.033333333, 46.5, -93.1, 800000.0, 100000.0, METER)),
+            26992 => Some(Projection::lcc(47.05, 45.616666667, 45.0, -94.25, 800000.0, 100000.0, METER)),
+            26993 => Some(Projection::lcc(45.216666667, 43.783333333, 43.0, -94.0, 800000.0, 100000.0, METER)),
+            _ => None
+        }
+    }
+
+    fn utm(zone: u32, south: bool) -> Projection {
+        Projection::TransverseMercator {
+            lat0: 0.0,
+            lon0: zone as f64 * 6.0 - 183.0,
+            k0: 0.9996,
+            x0: 500000.0,
+            y0: if south {10000000.0} else {0.0},
+            unit: METER,
+        }
+    }
+
+    fn lcc(lat1: f64, lat2: f64, lat0: f64, lon0: f64, x0: f64, y0: f64, unit: f64) -> Projection {
+        Projection::LambertConformal { lat1, lat2, lat0, lon0, x0, y0, unit }
+    }
+
+    // Convert projected x (easting) and y (northing) to lat/lng in degrees
+    pub fn inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        match *self {
+            Projection::TransverseMercator { lat0, lon0, k0, x0, y0, unit } => {
+                tmerc_inverse(x * unit - x0, y * unit - y0, lat0, lon0, k0)
+            }
+            Projection::LambertConformal { lat1, lat2, lat0, lon0, x0, y0, unit } => {
+                lcc_inverse(x * unit - x0, y * unit - y0, lat1, lat2, lat0, lon0)
+            }
+        }
+    }
+}
+
+fn parse_unit(unit: &str) -> Result<f64, Box<dyn Error>> {
+    match unit.to_lowercase().as_str() {
+        "m" => Ok(METER),
+        "ft" => Ok(FOOT),
+        "us-ft" => Ok(US_FOOT),
+        _ => Err("unit must be m, ft or us-ft")?
+    }
+}
+
+fn unit_name(unit: f64) -> &'static str {
+    if unit == FOOT {"ft"} else if unit == US_FOOT {"us-ft"} else {"m"}
+}
+
+// Meridional arc length from the equator (Snyder 3-21)
+fn meridian_arc(phi: f64, e2: f64) -> f64 {
+    A * ((1.0 - e2/4.0 - 3.0*e2.powi(2)/64.0 - 5.0*e2.powi(3)/256.0) * phi
+        - (3.0*e2/8.0 + 3.0*e2.powi(2)/32.0 + 45.0*e2.powi(3)/1024.0) * (2.0*phi).sin()
+        + (15.0*e2.powi(2)/256.0 + 45.0*e2.powi(3)/1024.0) * (4.0*phi).sin()
+        - (35.0*e2.powi(3)/3072.0) * (6.0*phi).sin())
+}
+
+// Transverse Mercator inverse (Snyder 8-18 to 8-25)
+fn tmerc_inverse(x: f64, y: f64, lat0: f64, lon0: f64, k0: f64) -> (f64, f64) {
+    let e2 = F * (2.0 - F);
+    let ep2 = e2 / (1.0 - e2);
+
+    let m = meridian_arc(lat0.to_radians(), e2) + y / k0;
+    let mu = m / (A * (1.0 - e2/4.0 - 3.0*e2.powi(2)/64.0 - 5.0*e2.powi(3)/256.0));
+    let e1 = (1.0 - (1.0 - e2).sqrt()) / (1.0 + (1.0 - e2).sqrt());
+
+    let phi1 = mu
+        + (3.0*e1/2.0 - 27.0*e1.powi(3)/32.0) * (2.0*mu).sin()
+        + (21.0*e1.powi(2)/16.0 - 55.0*e1.powi(4)/32.0) * (4.0*mu).sin()
+        + (151.0*e1.powi(3)/96.0) * (6.0*mu).sin()
+        + (1097.0*e1.powi(4)/512.0) * (8.0*mu).sin();
+
+    let c1 = ep2 * phi1.cos().powi(2);
+    let t1 = phi1.tan().powi(2);
+    let n1 = A / (1.0 - e2 * phi1.sin().powi(2)).sqrt();
+    let r1 = A * (1.0 - e2) / (1.0 - e2 * phi1.sin().powi(2)).powf(1.5);
+    let d = x / (n1 * k0);
+
+    let lat = phi1 - (n1 * phi1.tan() / r1) * (
+        d.powi(2)/2.0
+        - (5.0 + 3.0*t1 + 10.0*c1 - 4.0*c1.powi(2) - 9.0*ep2) * d.powi(4)/24.0
+        + (61.0 + 90.0*t1 + 298.0*c1 + 45.0*t1.powi(2) - 252.0*ep2 - 3.0*c1.powi(2)) * d.powi(6)/720.0
+    );
+    let lng = lon0.to_radians() + (
+        d
+        - (1.0 + 2.0*t1 + c1) * d.powi(3)/6.0
+        + (5.0 - 2.0*c1 + 28.0*t1 - 3.0*c1.powi(2) + 8.0*ep2 + 24.0*t1.powi(2)) * d.powi(5)/120.0
+    ) / phi1.cos();
+
+    (lat.to_degrees(), lng.to_degrees())
+}
+
+// Lambert Conformal Conic inverse with two standard parallels (Snyder 15-1 to 15-11)
+fn lcc_inverse(x: f64, y: f64, lat1: f64, lat2: f64, lat0: f64, lon0: f64) -> (f64, f64) {
+    let e = (F * (2.0 - F)).sqrt();
+    let m = |phi: f64| phi.cos() / (1.0 - (e * phi.sin()).powi(2)).sqrt();
+    let t = |phi: f64| (FRAC_PI_4 - phi/2.0).tan() / ((1.0 - e*phi.sin()) / (1.0 + e*phi.sin())).powf(e/2.0);
+
+    let (phi1, phi2, phi0) = (lat1.to_radians(), lat2.to_radians(), lat0.to_radians());
+    let n = if (lat1 - lat2).abs() < 1e-10 {
+        phi1.sin()
+    } else {
+        (m(phi1).ln() - m(phi2).ln()) / (t(phi1).ln() - t(phi2).ln())
+    };
+    let big_f = m(phi1) / (n * t(phi1).powf(n));
+    let rho0 = A * big_f * t(phi0).powf(n);
+
+    let sign = n.signum();
+    let rho = sign * (x.powi(2) + (rho0 - y).powi(2)).sqrt();
+    let theta = (sign * x).atan2(sign * (rho0 - y));
+    let t_val = (rho / (A * big_f)).powf(1.0 / n);
+
+    // Latitude has no closed form, iterate until it settles
+    let mut phi = FRAC_PI_2 - 2.0 * t_val.atan();
+    for _ in 0..15 {
+        let next = FRAC_PI_2 - 2.0 * (t_val * ((1.0 - e*phi.sin()) / (1.0 + e*phi.sin())).powf(e/2.0)).atan();
+        if (next - phi).abs() < 1e-12 {
+            phi = next;
+            break;
+        }
+        phi = next;
+    }
+
+    (phi.to_degrees(), (theta / n).to_degrees() + lon0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(found: (f64, f64), expected: (f64, f64)) {
+        assert!((found.0 - expected.0).abs() < 1e-6 && (found.1 - expected.1).abs() < 1e-6, "{:?} isn't {:?}", found, expected);
+    }
+
+    // 166021.44 is the easting of a zone's west edge at the equator, and 4982950.40 the northing
+    // of 45N on the central meridian, k0 times the meridian arc of 4984944.38
+    #[test]
+    fn utm_known_answers() {
+        assert_near(Projection::utm(31, false).inverse(166021.44, 0.0), (0.0, 0.0));
+        assert_near(Projection::utm(31, false).inverse(833978.56, 0.0), (0.0, 6.0));
+        assert_near(Projection::utm(15, false).inverse(500000.0, 4982950.40), (45.0, -93.0));
+        assert_near(Projection::utm(56, true).inverse(500000.0, 5017049.60), (-45.0, 153.0));
+    }
+
+    #[test]
+    fn epsg_codes_and_units_parse() {
+        assert_eq!(Projection::parse(&["epsg", "EPSG:32615"]).unwrap(), Projection::utm(15, false));
+        assert_eq!(Projection::parse(&["epsg", "26915"]).unwrap(), Projection::utm(15, false));
+        assert_eq!(Projection::parse(&["utm", "56s"]).unwrap(), Projection::utm(56, true));
+        assert!(Projection::parse(&["utm", "61"]).is_err());
+        assert!(Projection::parse(&["epsg", "4326"]).is_err());
+
+        let feet = Projection::parse(&["tmerc", "0", "3", "0.9996", "500000", "0", "us-ft"]).unwrap();
+        assert_near(feet.inverse(166021.44 / US_FOOT, 0.0), (0.0, 0.0));
+        assert_eq!(feet.to_string(), "tmerc 0 3 0.9996 500000 0 us-ft");
+    }
+
+    // The false origin is the latitude of origin on the central meridian
+    #[test]
+    fn lambert_conformal_origin_and_meridian() {
+        let south = Projection::from_epsg(26993).unwrap();
+        assert_near(south.inverse(800000.0, 100000.0), (43.0, -94.0));
+
+        let (lat, lng) = south.inverse(800000.0, 300000.0);
+        assert!((lng + 94.0).abs() < 1e-9 && lat > 44.7 && lat < 44.9, "{} {}", lat, lng);
+        let (_, east) = south.inverse(900000.0, 100000.0);
+        assert!(east > -94.0);
+    }
+}
@@ -0,0 +1,85 @@
+use rustyline::error::ReadlineError;
+use rustyline::{Config, DefaultEditor};
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::path::PathBuf;
+
+const PROMPT: &str = "geomatch> ";
+
+// Kept in the home directory so commands typed in one session can be recalled in the next
+pub const HISTORY_FILE: &str = ".geomatch_history";
+const HISTORY_SIZE: usize = 1000;
+
+// Reads commands for the cli. A terminal gets line editing and history, anything else, like a
+// script piped in, is read a line at a time and kept out of the history
+pub enum Prompt {
+    Editor(Box<DefaultEditor>, Option<PathBuf>),
+    Plain,
+}
+
+impl Prompt {
+    pub fn new() -> Prompt {
+        if !stdin().is_terminal() {
+            return Prompt::Plain;
+        }
+
+        let config = Config::builder()
+            .max_history_size(HISTORY_SIZE)
+            .and_then(|e| e.history_ignore_dups(true))
+            .map(|e| e.auto_add_history(false).build());
+        let mut editor = match config.and_then(DefaultEditor::with_config) {
+            Ok(editor) => editor,
+            Err(_) => return Prompt::Plain
+        };
+
+        // No history yet is fine, it's created by the first command
+        let path = history_path();
+        if let Some(path) = &path {
+            let _ = editor.load_history(path);
+        }
+        Prompt::Editor(Box::new(editor), path)
+    }
+
+    // Next line typed, none at the end of input
+    pub fn read(&mut self) -> Option<String> {
+        match self {
+            Prompt::Editor(editor, _) => match editor.readline(PROMPT) {
+                Ok(line) => Some(line),
+                // Ctrl-C at the prompt quits straight away, like it does without line editing
+                Err(ReadlineError::Interrupted) => std::process::exit(130),
+                Err(_) => None
+            },
+            Prompt::Plain => {
+                print!("{}", PROMPT);
+                let _ = stdout().flush();
+                let mut line = String::new();
+                match stdin().read_line(&mut line) {
+                    Ok(0) | Err(_) => None,
+                    Ok(_) => Some(line)
+                }
+            }
+        }
+    }
+
+    // Add a command to the history, saving it straight away so a crash doesn't lose it
+    pub fn remember(&mut self, line: &str) {
+        if let Prompt::Editor(editor, path) = self {
+            if line.trim().is_empty() || !editor.add_history_entry(line.trim()).unwrap_or(false) {
+                return;
+            }
+            if let Some(path) = path {
+                let _ = editor.append_history(path);
+            }
+        }
+    }
+}
+
+impl Default for Prompt {
+    fn default() -> Prompt {
+        Prompt::new()
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(HISTORY_FILE))
+}
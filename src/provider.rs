@@ -0,0 +1,224 @@
+use csv::{ReaderBuilder, WriterBuilder};
+use reqwest::{Client, Url};
+use reqwest::header::CONTENT_TYPE;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use super::data_frame::Geocode;
+
+const CENSUS_URL: &str = "https://geocoding.geo.census.gov/geocoder/locations/onelineaddress";
+const CENSUS_BATCH_URL: &str = "https://geocoding.geo.census.gov/geocoder/locations/addressbatch";
+const MAPBOX_URL: &str = "https://api.mapbox.com/geocoding/v5/mapbox.places/";
+
+// Most rows the Census batch geocoder takes in one upload
+pub const CENSUS_BATCH_SIZE: usize = 10000;
+
+// Separates the parts of a batch upload, long enough that no address will contain it
+const BOUNDARY: &str = "geomatch-census-batch-4b1e9f27c3d5a860";
+
+// Geocoder a file's addresses are sent to. Google is paid and more accurate, the US Census
+// geocoder is free but only knows US street addresses, Mapbox is paid and worldwide
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Provider {
+    #[default]
+    Google,
+    Census,
+    CensusBatch,    // the Census geocoder, sent whole files of street, city, state and zip at once
+    Mapbox,
+}
+
+impl Provider {
+    pub fn parse(text: &str) -> Option<Provider> {
+        match text.to_lowercase().as_str() {
+            "google" => Some(Provider::Google),
+            "census" => Some(Provider::Census),
+            "census-batch" => Some(Provider::CensusBatch),
+            "mapbox" => Some(Provider::Mapbox),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::Google => "google",
+            Provider::Census => "census",
+            Provider::CensusBatch => "census-batch",
+            Provider::Mapbox => "mapbox",
+        }
+    }
+
+    // Whether rows are uploaded together instead of geocoded one at a time
+    pub fn batched(&self) -> bool {
+        *self == Provider::CensusBatch
+    }
+
+    // Column fetch writes how sure the provider is about each row to, for providers that say
+    pub fn quality_column(&self) -> Option<&'static str> {
+        match self {
+            Provider::CensusBatch => Some("match_quality"),
+            Provider::Mapbox => Some("relevance"),
+            _ => None
+        }
+    }
+}
+
+// Keys for the providers that take one, so each is only ever sent its own
+#[derive(Clone, Default, Debug)]
+pub struct Keys {
+    pub google: Option<String>,     // api key, from -k, API_KEY or the stored key. Only needed for google
+    pub mapbox: Option<String>,     // access token, from --mapbox-token or the config file
+}
+
+impl Keys {
+    // Key to send the provider, empty for the Census geocoder which doesn't take one
+    pub fn get(&self, provider: Provider) -> Result<&str, Box<dyn Error>> {
+        match provider {
+            Provider::Google => match &self.google {
+                Some(key) => Ok(key),
+                None => Err("google needs an api key, pass -k, set API_KEY or run `geomatch auth set google`".into())
+            },
+            Provider::Census | Provider::CensusBatch => Ok(""),
+            Provider::Mapbox => match &self.mapbox {
+                Some(token) => Ok(token),
+                None => Err("mapbox needs an access token, pass --mapbox-token, set MAPBOX_ACCESS_TOKEN, add mapbox_token to the config file or run `geomatch auth set mapbox`".into())
+            }
+        }
+    }
+}
+
+// Geocode with the Census geocoder, which needs no key. Only US addresses are matched so the
+// country is always US
+pub async fn census(client: &Client, addr: &str) -> Result<Geocode, Box<dyn Error>> {
+    let json: Value = client.get(CENSUS_URL)
+        .query(&[("address", addr), ("benchmark", "Public_AR_Current"), ("format", "json")])
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    // Errors come back as {"errors": ["..."], "status": "400"}
+    if let Some(errors) = json["errors"].as_array() {
+        let errors: Vec<&str> = errors.iter().filter_map(|e| e.as_str()).collect();
+        return Err(format!("census geocoder: {}", errors.join(", ")).into());
+    }
+
+    let found = &json["result"]["addressMatches"][0];
+    match (found["coordinates"]["y"].as_f64(), found["coordinates"]["x"].as_f64()) {
+        (Some(lat), Some(lng)) => Ok(Geocode {
+            lat,
+            lng,
+            address: found["matchedAddress"].as_str().unwrap_or("").to_string(),
+            country: "US".to_string(),
+            words: String::new(),
+            quality: String::new(),
+        }),
+        _ => Ok(Geocode::not_found())
+    }
+}
+
+// Geocode with Mapbox, using the key as the access token. The quality is Mapbox's relevance,
+// from 0 to 1 for how much of the query the place matched
+pub async fn mapbox(client: &Client, addr: &str, key: &str) -> Result<Geocode, Box<dyn Error>> {
+    // The query is part of the path, which escapes it
+    let mut url = Url::parse(MAPBOX_URL)?;
+    url.path_segments_mut().map_err(|_| "Invalid mapbox url")?.pop_if_empty().push(&format!("{}.json", addr));
+
+    let res = client.get(url)
+        .query(&[("access_token", key), ("limit", "1")])
+        .send()
+        .await?;
+    let status = res.status();
+    let json: Value = res.json().await?;
+
+    // Errors come back as {"message": "..."}, like a bad token
+    if !status.is_success() {
+        return Err(format!("mapbox: {}", json["message"].as_str().unwrap_or(status.as_str())).into());
+    }
+
+    let found = &json["features"][0];
+    match (found["center"][1].as_f64(), found["center"][0].as_f64()) {
+        (Some(lat), Some(lng)) => {
+            // The country is in the context, or the place itself when it's a whole country
+            let country = found["context"].as_array()
+                .and_then(|e| e.iter().find(|e| e["id"].as_str().is_some_and(|e| e.starts_with("country."))))
+                .and_then(|e| e["short_code"].as_str())
+                .or(found["properties"]["short_code"].as_str())
+                .unwrap_or("");
+            Ok(Geocode {
+                lat,
+                lng,
+                address: found["place_name"].as_str().unwrap_or("").to_string(),
+                country: country.to_uppercase(),
+                words: String::new(),
+                quality: found["relevance"].as_f64().map_or(String::new(), |e| e.to_string()),
+            })
+        }
+        _ => Ok(Geocode::not_found())
+    }
+}
+
+// Geocode up to CENSUS_BATCH_SIZE rows of street, city, state and zip in one upload to the
+// Census batch geocoder, returning what was found for each in the same order. The quality is
+// Exact or Non_Exact for a match, Tie when several places fit equally well, which leaves the row
+// without coordinates like No_Match does
+pub async fn census_batch(client: &Client, rows: &[[String; 4]]) -> Result<Vec<Geocode>, Box<dyn Error>> {
+    // Rows are numbered so the results, which come back in any order, can be put back in place
+    let mut upload = WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+    for (id, row) in rows.iter().enumerate() {
+        upload.write_record([id.to_string().as_str(), &row[0], &row[1], &row[2], &row[3]])?;
+    }
+    let upload = upload.into_inner().map_err(|e| e.to_string())?;
+
+    let mut body = Vec::with_capacity(upload.len() + 512);
+    body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"benchmark\"\r\n\r\nPublic_AR_Current\r\n", BOUNDARY).as_bytes());
+    body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"addressFile\"; filename=\"addresses.csv\"\r\n", BOUNDARY).as_bytes());
+    body.extend(b"Content-Type: text/csv\r\n\r\n");
+    body.extend(upload);
+    body.extend(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+    let res = client.post(CENSUS_BATCH_URL)
+        .header(CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+        .body(body)
+        .send()
+        .await?;
+    if !res.status().is_success() {
+        return Err(format!("census batch geocoder: {}", res.status()).into());
+    }
+    let text = res.text().await?;
+
+    // Each line is id, input address, Match, Exact or Non_Exact, matched address, "lng,lat",
+    // then the TIGER line and side of the street. Rows that weren't matched stop after Match
+    let mut found: HashMap<usize, Geocode> = HashMap::new();
+    let mut reader = ReaderBuilder::new().has_headers(false).flexible(true).from_reader(text.as_bytes());
+    for record in reader.records() {
+        let record = record?;
+        let id = match record.get(0).and_then(|e| e.trim().parse::<usize>().ok()) {
+            Some(id) if id < rows.len() => id,
+            _ => continue
+        };
+
+        let coords = record.get(5).and_then(|e| e.split_once(','))
+            .and_then(|(lng, lat)| Some((lat.trim().parse::<f64>().ok()?, lng.trim().parse::<f64>().ok()?)));
+        let geocode = match (record.get(2), coords) {
+            (Some("Match"), Some((lat, lng))) => Geocode {
+                lat,
+                lng,
+                address: record.get(4).unwrap_or("").to_string(),
+                country: "US".to_string(),
+                words: String::new(),
+                quality: record.get(3).unwrap_or("").to_string(),
+            },
+            (indicator, _) => Geocode {
+                quality: indicator.unwrap_or("No_Match").to_string(),
+                ..Geocode::not_found()
+            }
+        };
+        found.insert(id, geocode);
+    }
+
+    // Rows missing from the results weren't matched either
+    Ok((0..rows.len()).map(|e| found.remove(&e).unwrap_or_else(|| Geocode {
+        quality: "No_Match".to_string(),
+        ..Geocode::not_found()
+    })).collect())
+}
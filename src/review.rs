@@ -0,0 +1,218 @@
+use csv::{ReaderBuilder, WriterBuilder};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{stdin, stdout, Write};
+use std::path::Path;
+use super::atomic;
+
+// Widest a value is shown at, so two records fit side by side
+const MAX_WIDTH: usize = 36;
+
+// Where answers are kept, in the output directory, so a rerun of the same files doesn't ask again
+pub const DECISIONS_FILE: &str = "review_decisions.csv";
+
+// A match between two rows: the path and stable id of the row it started as, then the path and
+// stable id of what it matched. Paths rather than file numbers, so loading the files in another
+// order doesn't apply answers to the wrong pairs
+pub type Pair = (String, String, String, String);
+
+// Which matches `match` stops to ask about: those out near the edge of the radius, or whose
+// compare columns don't look much alike
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Review {
+    pub near: f64,          // fraction of the radius past which a match is borderline
+    pub similarity: usize,  // compare similarity out of 100 below which a match is borderline
+    pub policy: Policy,     // what happens to borderline matches without an earlier answer
+}
+
+impl Default for Review {
+    fn default() -> Review {
+        Review { near: 0.8, similarity: 50, policy: Policy::Ask }
+    }
+}
+
+// What happens to a borderline match nobody has answered yet. Only the cli has someone to ask,
+// the server, gRPC, watch and jobs modes need one of the others
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Policy {
+    Ask,
+    Accept,     // keep it, only applying earlier answers
+    Reject,     // leave both rows unmatched
+}
+
+impl Review {
+    // Parse `[near] [similarity] [policy]`, either number left out for its default and the
+    // policy for ask, or off for None
+    pub fn parse(input: &[&str]) -> Result<Option<Review>, Box<dyn Error>> {
+        if input.first().is_some_and(|e| e.eq_ignore_ascii_case("off")) {
+            return Ok(None);
+        }
+
+        let mut review = Review::default();
+        let mut input = input;
+        if let Some((last, rest)) = input.split_last() {
+            let policy = match last.to_lowercase().as_str() {
+                "ask" => Some(Policy::Ask),
+                "accept" => Some(Policy::Accept),
+                "reject" => Some(Policy::Reject),
+                _ => None
+            };
+            if let Some(policy) = policy {
+                review.policy = policy;
+                input = rest;
+            }
+        }
+
+        if let Some(near) = input.first() {
+            review.near = match near.parse::<f64>() {
+                Ok(near) if (0.0..=1.0).contains(&near) => near,
+                _ => return Err("near must be a fraction of the radius from 0 to 1")?
+            };
+        }
+        if let Some(similarity) = input.get(1) {
+            review.similarity = match similarity.parse::<usize>() {
+                Ok(similarity) if similarity <= 100 => similarity,
+                _ => return Err("similarity must be 0 to 100")?
+            };
+        }
+        if input.len() > 2 {
+            return Err(format!("Unexpected '{}'", input[2..].join(" ")))?;
+        }
+
+        Ok(Some(review))
+    }
+
+    // Why a match should be looked at, None when it's clear cut. similarity is None without
+    // compare columns to go by
+    pub fn reason(&self, dist: f64, radius: f64, similarity: Option<usize>) -> Option<String> {
+        let mut reasons = Vec::new();
+        if dist > 0.0 && dist >= self.near * radius {
+            reasons.push(format!("{:.3} miles apart with a {} mile radius", dist, radius));
+        }
+        if let Some(similarity) = similarity.filter(|e| *e < self.similarity) {
+            reasons.push(format!("compare similarity {}", similarity));
+        }
+
+        if reasons.is_empty() {None} else {Some(reasons.join(", "))}
+    }
+}
+
+impl Display for Review {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "near {} similarity {}", self.near, self.similarity)?;
+        match self.policy {
+            Policy::Ask => Ok(()),
+            Policy::Accept => write!(f, " accept"),
+            Policy::Reject => write!(f, " reject"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Decision {
+    Accept,
+    Reject,
+    AcceptRest,     // keep this and every match after it without asking
+}
+
+// Earlier answers, true to keep the match. Nothing yet without the file
+pub fn read_decisions(path: &str) -> Result<HashMap<Pair, bool>, Box<dyn Error>> {
+    let mut decisions = HashMap::new();
+    if !Path::new(path).exists() {
+        return Ok(decisions);
+    }
+
+    let mut reader = ReaderBuilder::new()
+        .delimiter(b'|')
+        .from_path(path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    for (line, record) in reader.records().enumerate() {
+        let record = record?;
+        let bad = || format!("{}: line {} isn't file|row_id|match_file|match_row_id|decision", path, line + 2);
+        let keep = match record.get(4) {
+            Some("accept") => true,
+            Some("reject") => false,
+            _ => return Err(bad())?
+        };
+        decisions.insert((record[0].to_string(), record[1].to_string(), record[2].to_string(), record[3].to_string()), keep);
+    }
+
+    Ok(decisions)
+}
+
+// Every answer so far, sorted so the file changes as little as possible between runs
+pub fn write_decisions(path: &str, decisions: &HashMap<Pair, bool>) -> Result<(), Box<dyn Error>> {
+    let mut pairs: Vec<(&Pair, &bool)> = decisions.iter().collect();
+    pairs.sort();
+
+    let mut writer = WriterBuilder::new()
+        .delimiter(b'|')
+        .from_writer(atomic::create(path)?);
+    writer.write_record(["file", "row_id", "match_file", "match_row_id", "decision"])?;
+    for ((file, id, match_file, match_id), keep) in pairs {
+        let decision = if *keep {"accept"} else {"reject"};
+        writer.write_record([file, id, match_file, match_id, decision])?;
+    }
+    atomic::finish(writer.into_inner().map_err(|e| e.to_string())?, path)?;
+
+    Ok(())
+}
+
+// One side of a borderline match: a title and its columns as (header, value)
+pub struct Record {
+    pub title: String,
+    pub fields: Vec<(String, String)>,
+}
+
+// Show both records side by side and ask whether to keep the match. The end of input keeps the
+// rest, so a script that runs out of answers still finishes
+pub fn ask(reason: &str, left: &Record, right: &Record) -> Result<Decision, Box<dyn Error>> {
+    println!();
+    println!("Borderline match, {}", reason);
+    for line in side_by_side(left, right) {
+        println!("  {}", line);
+    }
+
+    loop {
+        print!("Keep this match? [y]es, [n]o, [a]ccept the rest: ");
+        let _ = stdout().flush();
+        let mut answer = String::new();
+        if stdin().read_line(&mut answer)? == 0 {
+            println!();
+            return Ok(Decision::AcceptRest);
+        }
+
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(Decision::Accept),
+            "n" | "no" => return Ok(Decision::Reject),
+            "a" | "accept" => return Ok(Decision::AcceptRest),
+            _ => println!("Answer y, n or a")
+        }
+    }
+}
+
+fn side_by_side(left: &Record, right: &Record) -> Vec<String> {
+    let field = |e: &(String, String)| shorten(&format!("{}: {}", e.0, e.1));
+    let mut lines = vec![(shorten(&left.title), shorten(&right.title))];
+    for row in 0..left.fields.len().max(right.fields.len()) {
+        lines.push((
+            left.fields.get(row).map(field).unwrap_or_default(),
+            right.fields.get(row).map(field).unwrap_or_default(),
+        ));
+    }
+
+    let width = lines.iter().map(|e| e.0.chars().count()).max().unwrap_or(0);
+    lines.into_iter()
+        .map(|(left, right)| format!("{:<width$}  |  {}", left, right, width = width).trim_end().to_string())
+        .collect()
+}
+
+fn shorten(text: &str) -> String {
+    if text.chars().count() <= MAX_WIDTH {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(MAX_WIDTH - 3).collect();
+    short.push_str("...");
+    short
+}
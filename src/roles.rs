@@ -0,0 +1,206 @@
+use csv::StringRecord;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+// Share of a column's non-blank sampled values that have to look right for it to be guessed from
+// its values
+const VALUE_MATCH_RATIO: f64 = 0.9;
+
+// USPS state, district and territory codes
+const STATE_CODES: [&str; 56] = [
+    "AL", "AK", "AZ", "AR", "CA", "CO", "CT", "DE", "FL", "GA", "HI", "ID", "IL", "IN", "IA", "KS", "KY", "LA", "ME",
+    "MD", "MA", "MI", "MN", "MS", "MO", "MT", "NE", "NV", "NH", "NJ", "NM", "NY", "NC", "ND", "OH", "OK", "OR", "PA",
+    "RI", "SC", "SD", "TN", "TX", "UT", "VT", "VA", "WA", "WV", "WI", "WY", "DC", "PR", "GU", "VI", "AS", "MP",
+];
+
+// Headers given by the user for each role, on top of the built in synonyms
+pub type Synonyms = HashMap<Role, Vec<String>>;
+
+// Special columns a header can be guessed as when a file is loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    Id,
+    Addr1,
+    Addr2,
+    City,
+    State,
+    Zipcode,
+    Country,
+    Lat,
+    Lng,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Role {
+    pub const ALL: [Role; 9] = [
+        Role::Id, Role::Addr1, Role::Addr2, Role::City, Role::State, Role::Zipcode, Role::Country, Role::Lat, Role::Lng,
+    ];
+
+    pub fn parse(name: &str) -> Option<Role> {
+        match name.to_lowercase().as_str() {
+            "zip" => Some(Role::Zipcode),
+            name => Role::ALL.iter().find(|e| e.name() == name).copied()
+        }
+    }
+
+    // Key used to set the role by hand, e.g. set 0 addr1 Street
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Id => "id",
+            Role::Addr1 => "addr1",
+            Role::Addr2 => "addr2",
+            Role::City => "city",
+            Role::State => "state",
+            Role::Zipcode => "zipcode",
+            Role::Country => "country",
+            Role::Lat => "lat",
+            Role::Lng => "lng",
+        }
+    }
+
+    // Normalized headers taken to mean the role, best first
+    fn synonyms(&self) -> &'static [&'static str] {
+        match self {
+            Role::Id => &["id"],
+            Role::Addr1 => &["addr1", "address", "addr", "address1", "streetaddress", "street", "addressline1"],
+            Role::Addr2 => &["addr2", "address2", "addressline2"],
+            Role::City => &["city"],
+            Role::State => &["state"],
+            Role::Zipcode => &["zipcode", "zip", "postalcode", "postcode"],
+            Role::Country => &["country", "countrycode", "countryname", "nation"],
+            Role::Lat => &["lat", "latitude"],
+            Role::Lng => &["lng", "longitude", "lon", "long"],
+        }
+    }
+}
+
+// Lowercase with spaces, underscores and dashes taken out, so "Street Address" and
+// "street_address" both become "streetaddress"
+pub fn normalize(header: &str) -> String {
+    header.trim()
+        .to_lowercase()
+        .chars()
+        .filter(|e| !matches!(e, ' ' | '_' | '-'))
+        .collect()
+}
+
+// Columns that could be a role, best first
+pub struct Guess {
+    pub role: Role,
+    pub candidates: Vec<usize>,
+    pub from_values: bool,  // no header fit, so it was guessed from the sampled values
+}
+
+// Every header that could be each role, best first: headers matching an earlier synonym come
+// first, the user's synonyms before the built in ones, then headers in file order. Roles no
+// header fits are then guessed from the values in the sample rows, using columns no header was
+// guessed as. Roles nothing fits are left out
+pub fn guess_roles(headers: &[String], sample: &[StringRecord], user_synonyms: &Synonyms) -> Vec<Guess> {
+    let normalized: Vec<String> = headers.iter().map(|e| normalize(e)).collect();
+
+    let mut guesses = Vec::new();
+    for role in Role::ALL.iter() {
+        let synonyms: Vec<String> = user_synonyms.get(role).into_iter().flatten()
+            .map(|e| normalize(e))
+            .chain(role.synonyms().iter().map(|e| e.to_string()))
+            .collect();
+
+        let mut candidates: Vec<(usize, usize)> = normalized.iter()
+            .enumerate()
+            .filter_map(|(index, header)| {
+                synonyms.iter().position(|e| e == header).map(|rank| (rank, index))
+            })
+            .collect();
+        candidates.sort_unstable();
+
+        if !candidates.is_empty() {
+            guesses.push(Guess {
+                role: *role,
+                candidates: candidates.into_iter().map(|e| e.1).collect(),
+                from_values: false,
+            });
+        }
+    }
+
+    // Values of each column no header was guessed as, leaving out blanks
+    let mut free: Vec<(usize, Vec<&str>)> = (0..headers.len())
+        .filter(|index| !guesses.iter().any(|e| e.candidates.contains(index)))
+        .map(|index| (index, sample.iter().filter_map(|e| e.get(index)).map(str::trim).filter(|e| !e.is_empty()).collect()))
+        .filter(|(_, values): &(usize, Vec<&str>)| !values.is_empty())
+        .collect();
+    let missing = |guesses: &[Guess], role| !guesses.iter().any(|e| e.role == role);
+
+    // Coordinates only count in pairs, since a lone column of small decimals could be anything.
+    // Longitude is the one with values past 90 when there is a choice
+    if missing(&guesses, Role::Lat) && missing(&guesses, Role::Lng) {
+        let decimals = |range: f64| {
+            free.iter()
+                .filter(|(_, values)| fits(values, |e| e.contains('.') && e.parse::<f64>().is_ok_and(|e| e.abs() <= range)))
+                .map(|e| e.0)
+                .collect::<Vec<usize>>()
+        };
+        let lats = decimals(90.0);
+        let lngs = decimals(180.0);
+        let lng = lngs.iter().find(|e| !lats.contains(e)).or_else(|| lngs.get(1)).copied();
+        let lat = lats.iter().find(|e| Some(**e) != lng).copied();
+
+        if let (Some(lat), Some(lng)) = (lat, lng) {
+            guesses.push(Guess {role: Role::Lat, candidates: vec![lat], from_values: true});
+            guesses.push(Guess {role: Role::Lng, candidates: vec![lng], from_values: true});
+            free.retain(|e| e.0 != lat && e.0 != lng);
+        }
+    }
+
+    let checks: [(Role, Check); 3] = [
+        (Role::Zipcode, is_zipcode),
+        (Role::State, |e| STATE_CODES.contains(&e)),
+        (Role::Addr1, is_street),
+    ];
+    for (role, check) in checks.iter() {
+        if !missing(&guesses, *role) {
+            continue;
+        }
+
+        let candidates: Vec<usize> = free.iter().filter(|e| fits(&e.1, *check)).map(|e| e.0).collect();
+        if !candidates.is_empty() {
+            free.retain(|e| !candidates.contains(&e.0));
+            guesses.push(Guess {role: *role, candidates, from_values: true});
+        }
+    }
+
+    guesses
+}
+
+// Whether a value looks like it belongs to a role
+type Check = fn(&str) -> bool;
+
+// Whether enough of a column's values pass a check
+fn fits(values: &[&str], check: impl Fn(&str) -> bool) -> bool {
+    let passing = values.iter().filter(|e| check(e)).count();
+    passing as f64 >= values.len() as f64 * VALUE_MATCH_RATIO
+}
+
+// 12345 or 12345-6789
+fn is_zipcode(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    let digits = |e: &[u8]| e.iter().all(|e| e.is_ascii_digit());
+    match bytes.len() {
+        5 => digits(bytes),
+        10 => digits(&bytes[..5]) && bytes[5] == b'-' && digits(&bytes[6..]),
+        _ => false
+    }
+}
+
+// A house number followed by a street name, e.g. 100 Main St or 12B Elm Ave
+fn is_street(value: &str) -> bool {
+    let mut words = value.split_whitespace();
+    let number = words.next().unwrap_or("");
+    number.starts_with(|e: char| e.is_ascii_digit())
+        && number.chars().filter(|e| e.is_ascii_alphabetic()).count() <= 1
+        && words.any(|e| e.chars().any(|e| e.is_alphabetic()))
+}
@@ -0,0 +1,96 @@
+use reqwest::Client;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use tracing::instrument;
+use super::metrics::{count, ROUTE_REQUESTS};
+
+const METERS_PER_MILE: f64 = 1609.344;
+const OSRM_URL: &str = "https://router.project-osrm.org";
+
+// Routing services that can give a road distance between two points
+#[derive(Clone, Debug, PartialEq)]
+pub enum Router {
+    Osrm(String),   // base url of the server
+    Google,
+}
+
+impl Display for Router {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Router::Osrm(url) => write!(f, "osrm {}", url),
+            Router::Google => write!(f, "google"),
+        }
+    }
+}
+
+impl Router {
+    // Parse one of
+    //   osrm [url]
+    //   google
+    pub fn parse(input: &[&str]) -> Result<Router, Box<dyn Error>> {
+        let kind = input.first();
+        if kind.is_none() {
+            return Err("provider required")?;
+        }
+
+        match kind.unwrap().to_lowercase().as_str() {
+            "osrm" => {
+                let url = input.get(1).unwrap_or(&OSRM_URL);
+                Ok(Router::Osrm(url.trim_end_matches('/').to_string()))
+            }
+            "google" => Ok(Router::Google),
+            _ => Err("Invalid provider, expected osrm or google")?
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Router::Osrm(_) => "osrm",
+            Router::Google => "google",
+        }
+    }
+
+    // Driving distance in miles and time in minutes between two points, None if there is
+    // no route between them
+    #[instrument(name = "route_request", skip_all, fields(provider = %self))]
+    pub async fn route(&self, client: &Client, key: &str, from: (f64, f64), to: (f64, f64)) -> Result<Option<(f64, f64)>, Box<dyn Error>> {
+        count(&ROUTE_REQUESTS, 1);
+        match self {
+            Router::Osrm(url) => {
+                let url = format!("{}/route/v1/driving/{},{};{},{}", url, from.1, from.0, to.1, to.0);
+                let json: Value = client.get(&url)
+                    .query(&[("overview", "false")])
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+
+                let route = &json["routes"][0];
+                match (route["distance"].as_f64(), route["duration"].as_f64()) {
+                    (Some(meters), Some(seconds)) => Ok(Some((meters / METERS_PER_MILE, seconds / 60.0))),
+                    _ if json["code"].as_str() == Some("NoRoute") => Ok(None),
+                    _ => Err(format!("OSRM request failed: {}", json["message"].as_str().unwrap_or("unknown error")).into())
+                }
+            }
+            Router::Google => {
+                let origin = format!("{},{}", from.0, from.1);
+                let destination = format!("{},{}", to.0, to.1);
+                let params = [("origin", origin.as_str()), ("destination", destination.as_str()), ("key", key)];
+                let json: Value = client.get("https://maps.googleapis.com/maps/api/directions/json")
+                    .query(&params)
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+
+                let leg = &json["routes"][0]["legs"][0];
+                match (leg["distance"]["value"].as_f64(), leg["duration"]["value"].as_f64()) {
+                    (Some(meters), Some(seconds)) => Ok(Some((meters / METERS_PER_MILE, seconds / 60.0))),
+                    _ if json["status"].as_str() == Some("ZERO_RESULTS") => Ok(None),
+                    _ => Err(format!("Directions request failed: {}", json["status"].as_str().unwrap_or("unknown error")).into())
+                }
+            }
+        }
+    }
+}
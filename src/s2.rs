@@ -0,0 +1,125 @@
+// S2 cell ids, which split the earth into the faces of a cube and number the cells of each face
+// along a hilbert curve, so nearby points share a prefix. Matches the S2 geometry library, down to
+// the quadratic projection it uses to even out cell sizes
+
+pub const MAX_LEVEL: u8 = 30;
+
+// Position along the curve of the four children of a cell, by whether i and j are in the upper
+// half, for each orientation of the curve
+const IJ_TO_POS: [[u64; 4]; 4] = [[0, 1, 3, 2], [0, 3, 1, 2], [2, 3, 1, 0], [2, 1, 3, 0]];
+
+// How each child's curve is turned relative to its parent's
+const POS_TO_ORIENTATION: [usize; 4] = [1, 0, 0, 3];
+
+// Id of the cell at a level, 0 to 30, containing a point. None without coordinates
+pub fn cell_id(lat: f64, lng: f64, level: u8) -> Option<u64> {
+    if lat.is_nan() || lng.is_nan() || level > MAX_LEVEL {
+        return None;
+    }
+
+    let (lat, lng) = (lat.to_radians(), lng.to_radians());
+    let point = [lat.cos() * lng.cos(), lat.cos() * lng.sin(), lat.sin()];
+    let (face, u, v) = face_uv(point);
+    let i = st_to_ij(uv_to_st(u));
+    let j = st_to_ij(uv_to_st(v));
+
+    let mut orientation = face as usize & 1;
+    let mut pos = 0u64;
+    for k in (0..MAX_LEVEL).rev() {
+        let ij = (((i >> k) & 1) << 1 | ((j >> k) & 1)) as usize;
+        let bits = IJ_TO_POS[orientation][ij];
+        pos = pos << 2 | bits;
+        orientation ^= POS_TO_ORIENTATION[bits as usize];
+    }
+
+    // The lowest set bit marks the level, everything below it is cleared for a bigger cell
+    let leaf = face << 61 | pos << 1 | 1;
+    let lsb = 1u64 << (2 * (MAX_LEVEL - level));
+    Some(leaf & lsb.wrapping_neg() | lsb)
+}
+
+// Short form of an id, in hex without its trailing zeros
+pub fn token(id: u64) -> String {
+    if id == 0 {
+        return "X".to_string();
+    }
+    format!("{:016x}", id).trim_end_matches('0').to_string()
+}
+
+// Cube face a point is on, and where on the face from -1 to 1
+fn face_uv(point: [f64; 3]) -> (u64, f64, f64) {
+    let [x, y, z] = point;
+    let axis = if x.abs() >= y.abs() && x.abs() >= z.abs() {
+        0
+    } else if y.abs() >= z.abs() {
+        1
+    } else {
+        2
+    };
+    let face = if point[axis] < 0.0 {axis + 3} else {axis};
+
+    match face {
+        0 => (0, y / x, z / x),
+        1 => (1, -x / y, z / y),
+        2 => (2, -x / z, -y / z),
+        3 => (3, z / x, y / x),
+        4 => (4, z / y, -x / y),
+        _ => (5, -y / z, -x / z),
+    }
+}
+
+fn uv_to_st(u: f64) -> f64 {
+    if u >= 0.0 {
+        0.5 * (1.0 + 3.0 * u).sqrt()
+    } else {
+        1.0 - 0.5 * (1.0 - 3.0 * u).sqrt()
+    }
+}
+
+fn st_to_ij(s: f64) -> u64 {
+    let max = (1u64 << MAX_LEVEL) as f64;
+    (s * max).floor().clamp(0.0, max - 1.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference ids and tokens from the S2 geometry library's own tests
+    #[test]
+    fn leaf_cell_matches_the_reference_library() {
+        assert_eq!(cell_id(49.703498679, 11.770681595, MAX_LEVEL), Some(0x47a1cbd595522b39));
+        assert_eq!(cell_id(0.0, 0.0, MAX_LEVEL), Some(0x1000000000000001));
+    }
+
+    #[test]
+    fn level_zero_cells_are_the_cube_faces() {
+        let centers = [(0.0, 0.0), (0.0, 90.0), (90.0, 0.0), (0.0, 180.0), (0.0, -90.0), (-90.0, 0.0)];
+        let tokens: Vec<String> = centers.iter().map(|e| token(cell_id(e.0, e.1, 0).unwrap())).collect();
+        assert_eq!(tokens, ["1", "3", "5", "7", "9", "b"]);
+    }
+
+    #[test]
+    fn bigger_cells_share_the_leaf_prefix() {
+        let leaf = cell_id(49.703498679, 11.770681595, MAX_LEVEL).unwrap();
+        for level in 0..MAX_LEVEL {
+            let lsb = 1u64 << (2 * (MAX_LEVEL - level));
+            assert_eq!(cell_id(49.703498679, 11.770681595, level), Some(leaf & lsb.wrapping_neg() | lsb), "level {}", level);
+        }
+        assert_eq!(token(cell_id(49.703498679, 11.770681595, 8).unwrap()), "47a1d");
+    }
+
+    #[test]
+    fn tokens_match_the_reference_library() {
+        for (id, expected) in [(0x1000000000000000, "1"), (0x0940000000000000, "094"), (0x3fec000000000000, "3fec"),
+            (0x08f569b5c0000000, "08f569b5c"), (0xaa05238e7bd3ee7c, "aa05238e7bd3ee7c"), (0, "X")] {
+            assert_eq!(token(id), expected);
+        }
+    }
+
+    #[test]
+    fn missing_coordinates_have_no_cell() {
+        assert_eq!(cell_id(f64::NAN, 0.0, 10), None);
+        assert_eq!(cell_id(0.0, 0.0, MAX_LEVEL + 1), None);
+    }
+}
@@ -0,0 +1,537 @@
+use hyper::body::to_bytes;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::error::Error;
+use std::fs::{create_dir_all, read, read_dir, read_to_string, write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::{oneshot, Notify};
+use super::atomic;
+use super::command::{run_command, split_input};
+use super::metrics::{render, response};
+use super::notify::notify;
+use super::session::Session;
+use super::state::State;
+
+// Saved in each job's directory so jobs survive a restart
+const JOB_FILE: &str = "job.json";
+
+const STATUSES: [&str; 8] = ["idle", "queued", "fetching", "matching", "running", "done", "failed", "cancelled"];
+
+// One set of uploaded files along with its configuration and results, kept in its own directory
+struct Job {
+    dir: PathBuf,
+    state: Option<State>,       // None while a command is queued or running
+    status: &'static str,       // one of STATUSES
+    command: Option<String>,    // last command run
+    error: Option<String>,
+    pending: Option<String>,    // background command that is queued or running
+    session: Session,           // files and commands to rebuild the job from when it is restored
+    cancel: Option<oneshot::Sender<()>>,
+}
+
+// Jobs by id, along with how to start the state for a new one
+struct Jobs {
+    root: PathBuf,
+    jobs: Mutex<BTreeMap<usize, Job>>,
+    queue: Mutex<VecDeque<(usize, State)>>,     // background commands run one at a time, in order
+    queued: Notify,
+    new_state: Box<dyn Fn() -> State + Send + Sync>,
+    notify: Option<String>,     // webhook to post a summary to whenever a job's commands finish
+}
+
+// Serve the REST api until the process is stopped:
+//   POST /jobs                         create a job
+//   GET  /jobs                         list jobs
+//   GET  /jobs/{id}                    status, configuration and files of a job
+//   PUT  /jobs/{id}/files/{name}       upload a csv file and load it into the job
+//   POST /jobs/{id}/commands           run cli commands, one per line, and wait for them
+//   POST /jobs/{id}/run                queue a command like fetch or match to run in the background
+//   POST /jobs/{id}/cancel             cancel the job's queued or running command
+//   GET  /jobs/{id}/files/{name}       download a file from the job's directory
+//   GET  /metrics                      counters and queue depth in the Prometheus text format
+// Jobs are saved to disk as they change and picked back up when the server restarts
+pub async fn serve<F>(addr: SocketAddr, root: &str, notify: Option<String>, new_state: F) -> Result<(), Box<dyn Error>>
+    where F: Fn() -> State + Send + Sync + 'static {
+    create_dir_all(root)?;
+    let jobs = Arc::new(Jobs {
+        root: PathBuf::from(root),
+        jobs: Mutex::new(BTreeMap::new()),
+        queue: Mutex::new(VecDeque::new()),
+        queued: Notify::new(),
+        new_state: Box::new(new_state),
+        notify,
+    });
+
+    restore(&jobs).await?;
+    tokio::spawn(work(jobs.clone()));
+
+    let make_service = make_service_fn(move |_| {
+        let jobs = jobs.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| handle(jobs.clone(), req)))
+        }
+    });
+
+    println!("Listening on http://{}", addr);
+    Server::try_bind(&addr)?.serve(make_service).await?;
+
+    Ok(())
+}
+
+async fn handle(jobs: Arc<Jobs>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().trim_matches('/').to_string();
+    let parts: Vec<&str> = path.split('/').collect();
+
+    let body = match to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error(StatusCode::BAD_REQUEST, &e.to_string()))
+    };
+
+    let response = match (&method, parts.as_slice()) {
+        (&Method::POST, ["jobs"]) => create_job(&jobs),
+        (&Method::GET, ["jobs"]) => {
+            let list: Vec<Value> = jobs.jobs.lock().unwrap().iter()
+                .map(|(id, job)| json!({"id": id, "status": job.status, "command": job.command}))
+                .collect();
+            Ok(json_response(StatusCode::OK, json!(list)))
+        }
+        (&Method::GET, ["jobs", id]) => job_status(&jobs, id),
+        (&Method::PUT, ["jobs", id, "files", name]) => upload(&jobs, id, name, &body),
+        (&Method::GET, ["jobs", id, "files", name]) => download(&jobs, id, name),
+        (&Method::POST, ["jobs", id, "commands"]) => {
+            let lines = String::from_utf8_lossy(&body).lines().map(String::from).collect();
+            match parse_id(&jobs, id) {
+                Ok(id) => execute(jobs.clone(), id, lines).await
+                    .map(|_| json_response(StatusCode::OK, json!({"status": "done"}))),
+                Err(e) => Err(e)
+            }
+        }
+        (&Method::POST, ["jobs", id, "run"]) => {
+            let line = String::from_utf8_lossy(&body).trim().to_string();
+            match parse_id(&jobs, id) {
+                Ok(id) if !line.is_empty() => enqueue(&jobs, id, line),
+                Ok(_) => Err((StatusCode::BAD_REQUEST, "command required".to_string())),
+                Err(e) => Err(e)
+            }
+        }
+        (&Method::POST, ["jobs", id, "cancel"]) => cancel(&jobs, id),
+        (&Method::GET, ["metrics"]) => Ok(metrics(&jobs)),
+        _ => Err((StatusCode::NOT_FOUND, "Not found".to_string()))
+    };
+
+    Ok(response.unwrap_or_else(|(status, message)| error(status, &message)))
+}
+
+type HttpResult = Result<Response<Body>, (StatusCode, String)>;
+
+fn create_job(jobs: &Jobs) -> HttpResult {
+    let mut map = jobs.jobs.lock().unwrap();
+    let id = map.keys().next_back().map_or(0, |e| e + 1);
+    let dir = jobs.root.join(id.to_string());
+    create_dir_all(&dir).map_err(internal)?;
+
+    let mut state = (jobs.new_state)();
+    state.set_output_dir(&dir.to_string_lossy());
+
+    let job = Job {
+        dir,
+        state: Some(state),
+        status: "idle",
+        command: None,
+        error: None,
+        pending: None,
+        session: Session::default(),
+        cancel: None,
+    };
+    save(id, &job).map_err(internal)?;
+    map.insert(id, job);
+
+    Ok(json_response(StatusCode::CREATED, json!({"id": id})))
+}
+
+fn job_status(jobs: &Jobs, id: &str) -> HttpResult {
+    let id = parse_id(jobs, id)?;
+    let map = jobs.jobs.lock().unwrap();
+    let job = &map[&id];
+
+    let mut files: Vec<String> = read_dir(&job.dir).map_err(internal)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+
+    Ok(json_response(StatusCode::OK, json!({
+        "id": id,
+        "dir": job.dir,
+        "status": job.status,
+        "command": job.command,
+        "error": job.error,
+        "files": files,
+        "state": job.state.as_ref().map(|e| e.metadata()),
+    })))
+}
+
+fn upload(jobs: &Jobs, id: &str, name: &str, body: &[u8]) -> HttpResult {
+    let id = parse_id(jobs, id)?;
+    let name = check_name(name)?;
+
+    let mut map = jobs.jobs.lock().unwrap();
+    let job = map.get_mut(&id).unwrap();
+    let path = job.dir.join(name);
+    let status = job.status;
+    let state = job.state.as_mut().ok_or((StatusCode::CONFLICT, format!("Job is {}", status)))?;
+
+    let path = path.to_string_lossy().to_string();
+    write(&path, body).map_err(internal)?;
+    state.add_file(&path).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let index = state.file_count() - 1;
+
+    job.session.files.push(path);
+    save(id, job).map_err(internal)?;
+    Ok(json_response(StatusCode::CREATED, json!({"index": index})))
+}
+
+fn download(jobs: &Jobs, id: &str, name: &str) -> HttpResult {
+    let id = parse_id(jobs, id)?;
+    let name = check_name(name)?;
+    let path = jobs.jobs.lock().unwrap()[&id].dir.join(name);
+
+    match read(&path) {
+        Ok(bytes) => Ok(Response::new(Body::from(bytes))),
+        Err(_) => Err((StatusCode::NOT_FOUND, format!("No file named {}", name)))
+    }
+}
+
+// Run commands on a job and wait for them to finish
+async fn execute(jobs: Arc<Jobs>, id: usize, lines: Vec<String>) -> Result<(), (StatusCode, String)> {
+    let state = take_state(&jobs, id, &lines.join("; "))?;
+    run_lines(jobs, id, state, lines).await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))
+}
+
+// Take the job's state to run a command with, marking it as running
+fn take_state(jobs: &Jobs, id: usize, command: &str) -> Result<State, (StatusCode, String)> {
+    let mut map = jobs.jobs.lock().unwrap();
+    let job = map.get_mut(&id).unwrap();
+    let state = job.state.take().ok_or((StatusCode::CONFLICT, format!("Job is {}", job.status)))?;
+
+    job.status = phase(command);
+    job.command = Some(command.to_string());
+    job.error = None;
+    save(id, job).map_err(internal)?;
+    Ok(state)
+}
+
+// Status of a job while a command runs
+fn phase(command: &str) -> &'static str {
+    match split_input(command).first() {
+        Some(&"fetch") => "fetching",
+        Some(&"match") => "matching",
+        _ => "running"
+    }
+}
+
+// Put a command on the queue, the job's state goes with it so nothing else can change the job
+// until it has run or been cancelled
+fn enqueue(jobs: &Jobs, id: usize, line: String) -> HttpResult {
+    let position = {
+        let mut map = jobs.jobs.lock().unwrap();
+        let job = map.get_mut(&id).unwrap();
+        let state = job.state.take().ok_or((StatusCode::CONFLICT, format!("Job is {}", job.status)))?;
+
+        job.status = "queued";
+        job.command = Some(line.clone());
+        job.error = None;
+        job.pending = Some(line);
+        save(id, job).map_err(internal)?;
+
+        let mut queue = jobs.queue.lock().unwrap();
+        queue.push_back((id, state));
+        queue.len()
+    };
+    jobs.queued.notify_one();
+
+    Ok(json_response(StatusCode::ACCEPTED, json!({"status": "queued", "position": position})))
+}
+
+// Run queued commands one after another for as long as the server is up
+async fn work(jobs: Arc<Jobs>) {
+    loop {
+        let next = jobs.queue.lock().unwrap().pop_front();
+        let (id, state) = match next {
+            Some(next) => next,
+            None => {
+                jobs.queued.notified().await;
+                continue;
+            }
+        };
+
+        let line = {
+            let mut map = jobs.jobs.lock().unwrap();
+            let job = map.get_mut(&id).unwrap();
+            let line = job.pending.clone().unwrap();
+            job.status = phase(&line);
+            if let Err(e) = save(id, job) {
+                println!("Failed to save job {}: {}", id, e);
+            }
+            line
+        };
+
+        let _ = run_lines(jobs.clone(), id, state, vec![line]).await;
+    }
+}
+
+fn metrics(jobs: &Jobs) -> Response<Body> {
+    let depth = jobs.queue.lock().unwrap().len();
+    let statuses: Vec<(String, f64)> = {
+        let map = jobs.jobs.lock().unwrap();
+        STATUSES.iter()
+            .map(|status| (format!("status=\"{}\"", status), map.values().filter(|e| e.status == *status).count() as f64))
+            .collect()
+    };
+
+    response(render(&[], &[
+        ("geomatch_queue_depth", "Commands waiting on the queue", vec![(String::new(), depth as f64)]),
+        ("geomatch_jobs", "Jobs by status", statuses),
+    ]))
+}
+
+fn cancel(jobs: &Jobs, id: &str) -> HttpResult {
+    let id = parse_id(jobs, id)?;
+    let mut map = jobs.jobs.lock().unwrap();
+    let job = map.get_mut(&id).unwrap();
+
+    // Still waiting, take it off the queue and give the job its state back
+    let mut queue = jobs.queue.lock().unwrap();
+    if let Some(position) = queue.iter().position(|e| e.0 == id) {
+        let (_, state) = queue.remove(position).unwrap();
+        job.state = Some(state);
+        job.status = "cancelled";
+        job.pending = None;
+        save(id, job).map_err(internal)?;
+        return Ok(json_response(StatusCode::OK, json!({"status": "cancelled"})));
+    }
+
+    // Running, the command stops the next time it waits on something like the geocoder
+    match job.cancel.take() {
+        Some(cancel) => {
+            let _ = cancel.send(());
+            Ok(json_response(StatusCode::ACCEPTED, json!({"status": "cancelling"})))
+        }
+        None => Err((StatusCode::CONFLICT, format!("Job is {}", job.status)))
+    }
+}
+
+// Run commands one after another, stopping at the first error or when cancelled, then give
+// the state back to the job. Commands run on a blocking thread since they aren't written to be
+// sent between threads while they await
+async fn run_lines(jobs: Arc<Jobs>, id: usize, mut state: State, lines: Vec<String>) -> Result<(), String> {
+    let command = lines.join("; ");
+    let (cancel, cancelled) = oneshot::channel();
+    jobs.jobs.lock().unwrap().get_mut(&id).unwrap().cancel = Some(cancel);
+
+    let handle = Handle::current();
+    let result = tokio::task::spawn_blocking(move || {
+        // Each line that finishes along with the fetches and matches it ran
+        let mut completed = Vec::new();
+        let result = handle.block_on(async {
+            let run = async {
+                for line in lines.iter() {
+                    let input = split_input(line);
+                    if matches!(input.first(), Some(&"quit") | Some(&"help")) {
+                        return Err(format!("{}: not available in serve mode", line));
+                    }
+                    if let Err(e) = run_command(&mut state, input).await {
+                        return Err(format!("{}: {}", line, e));
+                    }
+                    completed.push((line.clone(), state.take_runs()));
+                }
+                Ok(())
+            };
+
+            tokio::select! {
+                result = run => result.map(|_| "done"),
+                Ok(_) = cancelled => Ok("cancelled"),
+            }
+        });
+        (state, result, completed)
+    }).await;
+
+    let (result, runs) = {
+        let mut map = jobs.jobs.lock().unwrap();
+        let job = map.get_mut(&id).unwrap();
+        let (result, completed) = match result {
+            Ok((state, result, completed)) => {
+                job.state = Some(state);
+                (result, completed)
+            }
+            Err(e) => (Err(format!("Command failed, the job can't be used anymore: {}", e)), Vec::new())
+        };
+
+        let runs: Vec<Value> = completed.iter().flat_map(|e| e.1.clone()).collect();
+        for (line, runs) in completed.iter() {
+            job.session.record(line, runs);
+        }
+        job.status = match result {
+            Ok(status) => status,
+            Err(_) => "failed"
+        };
+        job.error = result.as_ref().err().cloned();
+        job.pending = None;
+        job.cancel = None;
+        if let Err(e) = save(id, job) {
+            println!("Failed to save job {}: {}", id, e);
+        }
+        (result, runs)
+    };
+
+    if let Some(url) = jobs.notify.as_ref() {
+        notify(url, &json!({
+            "mode": "serve",
+            "job": id,
+            "status": result.as_ref().map_or("failed", |e| *e),
+            "command": command,
+            "error": result.as_ref().err(),
+            "runs": runs,
+        })).await;
+    }
+
+    match result {
+        Ok("cancelled") => Err("Cancelled".to_string()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e)
+    }
+}
+
+// Write the job to its directory, through a temporary file so a crash can't leave half of it
+fn save(id: usize, job: &Job) -> std::io::Result<()> {
+    let record = json!({
+        "id": id,
+        "status": job.status,
+        "command": job.command,
+        "error": job.error,
+        "pending": job.pending,
+        "files": job.session.files,
+        "history": job.session.history,
+    });
+
+    atomic::write(&job.dir.join(JOB_FILE).to_string_lossy(), record.to_string())
+}
+
+// Load the jobs saved under the root directory, loading their files and running their commands
+// again to rebuild their state. Commands that were queued or running when the server stopped
+// go back on the queue
+async fn restore(jobs: &Jobs) -> Result<(), Box<dyn Error>> {
+    let mut ids: Vec<usize> = read_dir(&jobs.root)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str()?.parse().ok())
+        .collect();
+    ids.sort_unstable();
+
+    let mut map = BTreeMap::new();
+    for id in ids {
+        let dir = jobs.root.join(id.to_string());
+        // A half written or corrupt record doesn't keep the server from starting. The job is
+        // listed as failed without saving over the file, so it can still be looked into
+        let record: Value = match read_to_string(dir.join(JOB_FILE)).map(|e| serde_json::from_str(&e)) {
+            Ok(Ok(record)) => record,
+            Ok(Err(e)) => {
+                println!("Couldn't read job {}, marking it failed: {}", id, e);
+                let mut job = load_job(dir, &Value::Null);
+                job.status = "failed";
+                job.error = Some(format!("Couldn't restore the job, its {} is corrupt: {}", JOB_FILE, e));
+                map.insert(id, job);
+                continue;
+            }
+            Err(_) => continue
+        };
+
+        let mut job = load_job(dir, &record);
+        let mut state = (jobs.new_state)();
+        state.set_output_dir(&job.dir.to_string_lossy());
+
+        let restored = job.session.rebuild(&mut state).await;
+        if let Err(e) = restored {
+            job.status = "failed";
+            job.error = Some(format!("Couldn't restore the job: {}", e));
+            job.pending = None;
+        } else if job.pending.is_some() {
+            job.status = "queued";
+            jobs.queue.lock().unwrap().push_back((id, state));
+            if let Err(e) = save(id, &job) {
+                println!("Failed to save job {}: {}", id, e);
+            }
+            map.insert(id, job);
+            continue;
+        } else if matches!(job.status, "queued" | "fetching" | "matching" | "running") {
+            job.status = "failed";
+            job.error = Some("Interrupted by a restart".to_string());
+        }
+
+        job.state = Some(state);
+        if let Err(e) = save(id, &job) {
+            println!("Failed to save job {}: {}", id, e);
+        }
+        map.insert(id, job);
+    }
+
+    let queued = jobs.queue.lock().unwrap().len();
+    if !map.is_empty() {
+        println!("Restored {} jobs, {} queued", map.len(), queued);
+    }
+    *jobs.jobs.lock().unwrap() = map;
+    Ok(())
+}
+
+fn load_job(dir: PathBuf, record: &Value) -> Job {
+    let status = record["status"].as_str().unwrap_or("idle");
+
+    Job {
+        dir,
+        state: None,
+        status: STATUSES.iter().find(|e| **e == status).unwrap_or(&"idle"),
+        command: record["command"].as_str().map(String::from),
+        error: record["error"].as_str().map(String::from),
+        pending: record["pending"].as_str().map(String::from),
+        session: Session::from_json(record),
+        cancel: None,
+    }
+}
+
+fn parse_id(jobs: &Jobs, id: &str) -> Result<usize, (StatusCode, String)> {
+    match id.parse::<usize>() {
+        Ok(id) if jobs.jobs.lock().unwrap().contains_key(&id) => Ok(id),
+        _ => Err((StatusCode::NOT_FOUND, format!("No job {}", id)))
+    }
+}
+
+// Files live directly in the job's directory
+fn check_name(name: &str) -> Result<&str, (StatusCode, String)> {
+    if name.is_empty() || name.starts_with('.') || name == JOB_FILE || name.contains(['/', '\\']) {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid file name {}", name)));
+    }
+    Ok(name)
+}
+
+fn internal(e: std::io::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn json_response(status: StatusCode, value: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(value.to_string()))
+        .unwrap()
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, json!({"error": message}))
+}
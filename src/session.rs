@@ -0,0 +1,124 @@
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs::{read_to_string, remove_file};
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::path::Path;
+use super::atomic;
+use super::command::{run_command, split_input, OUTPUT_COMMANDS};
+use super::sidecar::timestamp;
+use super::state::State;
+
+// Saved in the working directory while the cli runs, and removed when it quits normally
+pub const RECOVERY_FILE: &str = ".geomatch_session.json";
+
+// Also left out of a session on top of OUTPUT_COMMANDS: a fetch's outputs are loaded in its place
+// and a match would only write its outputs again
+const RUN_COMMANDS: [&str; 2] = ["fetch", "match"];
+
+// Commands that change the rows of a file, a fetch writes their results out so they aren't run
+// again on the fetched files
+const DATA_COMMANDS: [&str; 10] = ["transform", "concat", "sort", "project", "datum", "clip", "dedup-exact", "enrich", "header-line", "limit"];
+
+// Files loaded and commands run, enough to rebuild a state after a restart or crash
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+    pub files: Vec<String>,
+    pub history: Vec<String>,
+}
+
+impl Session {
+    pub fn from_json(record: &Value) -> Session {
+        let strings = |key: &str| -> Vec<String> {
+            record[key].as_array()
+                .map_or(Vec::new(), |e| e.iter().filter_map(|e| e.as_str()).map(String::from).collect())
+        };
+        Session {
+            files: strings("files"),
+            history: strings("history"),
+        }
+    }
+
+    // Keep track of a command that finished, along with the fetches and matches it ran
+    pub fn record(&mut self, line: &str, runs: &[Value]) {
+        match split_input(line).first() {
+            // A fetch writes out everything done to the files so far, so its outputs are loaded
+            // in their place and the commands that changed their rows aren't needed anymore
+            Some(&"fetch") if !runs.is_empty() => {
+                self.files = runs.iter().filter_map(|e| e["outputs"][0].as_str()).map(String::from).collect();
+                self.history.retain(|e| !split_input(e).first().is_some_and(|e| DATA_COMMANDS.contains(e)));
+            }
+            Some(cmd) if !OUTPUT_COMMANDS.contains(cmd) && !RUN_COMMANDS.contains(cmd) => self.history.push(line.to_string()),
+            _ => {}
+        }
+    }
+
+    // Load the files and run the commands again
+    pub async fn rebuild(&self, state: &mut State) -> Result<(), Box<dyn Error>> {
+        for file in self.files.iter() {
+            if !Path::new(file).exists() {
+                return Err(format!("{} is missing", file).into());
+            }
+            state.add_file(file)?;
+        }
+        for line in self.history.iter() {
+            run_command(state, split_input(line)).await?;
+        }
+        Ok(())
+    }
+
+    // Write the recovery file, noting the fetch or match that is running if any. Goes through a
+    // temporary file so a crash can't leave half of it
+    pub fn autosave(&self, running: Option<&str>) -> std::io::Result<()> {
+        let record = json!({
+            "saved": timestamp(),
+            "files": self.files,
+            "history": self.history,
+            "running": running,
+        });
+
+        atomic::write(RECOVERY_FILE, record.to_string())
+    }
+}
+
+// Quitting normally leaves nothing to recover
+pub fn clear_recovery() {
+    let _ = remove_file(RECOVERY_FILE);
+}
+
+// Offer to restore the session a crash left behind. Only asked when there is someone at the
+// terminal to answer, so a script piped in isn't taken as the answer
+pub async fn recover(new_state: &dyn Fn() -> State) -> Option<(State, Session)> {
+    let record: Value = serde_json::from_str(&read_to_string(RECOVERY_FILE).ok()?).ok()?;
+    let session = Session::from_json(&record);
+    let saved = record["saved"].as_str().unwrap_or("an unknown time");
+
+    println!("The session saved at {} didn't quit normally. It had loaded {} and run {} commands.",
+        saved, session.files.join(", "), session.history.len());
+    if !stdin().is_terminal() {
+        println!("Not restoring it since input isn't a terminal, it will be replaced by this session");
+        return None;
+    }
+
+    print!("Restore it instead of the files given? [y/N] ");
+    let _ = stdout().flush();
+    let mut answer = String::new();
+    stdin().read_line(&mut answer).ok()?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        return None;
+    }
+
+    let mut state = new_state();
+    if let Err(e) = session.rebuild(&mut state).await {
+        println!("Couldn't restore the session: {}", e);
+        return None;
+    }
+    match record["running"].as_str() {
+        Some(line) if split_input(line).first() == Some(&"fetch") => {
+            let resume = if split_input(line).contains(&"inplace") {"fetch inplace resume"} else {"fetch resume"};
+            println!("It was running `{}`, run `{}` to carry on from its last checkpoint", line, resume);
+        }
+        Some(line) => println!("It was running `{}`, which will need to be run again", line),
+        None => {}
+    }
+    Some((state, session))
+}
@@ -0,0 +1,59 @@
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::atomic;
+
+// Current UTC date as YYYY-MM-DD and time as HH:MM:SS
+pub fn utc_now() -> (String, String) {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |e| e.as_secs()) as i64;
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // Days since the epoch to a civil date (Howard Hinnant's days_from_civil, inverted)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let day = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + if month <= 2 {1} else {0};
+
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!("{:02}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60),
+    )
+}
+
+// Current UTC time in ISO 8601
+pub fn timestamp() -> String {
+    let (date, time) = utc_now();
+    format!("{}T{}Z", date, time)
+}
+
+pub fn sha256_file(path: &str) -> Result<String, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize().iter().map(|e| format!("{:02x}", e)).collect())
+}
+
+// Write metadata describing how an output file was made next to it, as <output>.json
+pub fn write_sidecar(output: &str, metadata: &Value) -> Result<String, Box<dyn Error>> {
+    let path = format!("{}.json", output);
+    atomic::write(&path, serde_json::to_string_pretty(metadata)?)?;
+
+    Ok(path)
+}
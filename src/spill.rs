@@ -0,0 +1,148 @@
+use memmap2::Mmap;
+use std::error::Error;
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Numbers each spill file, so several columns spilled by one process don't collide
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+// The values of a column written out to a temporary file and mapped back into memory. The
+// operating system pages values in as they're read and drops them again under memory pressure,
+// so a column can be bigger than ram. The file is deleted as soon as it's mapped and goes away
+// for good when the spill is dropped
+pub struct Spill {
+    map: Option<Mmap>,      // None for a spill of blanks, which has nothing to map
+    offsets: Vec<u64>,      // where each value starts, with the end of the last one at the end
+}
+
+impl Spill {
+    pub fn write<'a>(values: impl Iterator<Item=&'a str>) -> io::Result<Spill> {
+        let mut writer = SpillWriter::new()?;
+        for val in values {
+            writer.push(val)?;
+        }
+        writer.finish()
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, row: usize) -> &str {
+        let start = self.offsets[row] as usize;
+        let end = self.offsets[row+1] as usize;
+
+        // Every value was written from a str and the offsets are its bounds
+        match &self.map {
+            Some(map) => std::str::from_utf8(&map[start..end]).unwrap_or_default(),
+            None => ""
+        }
+    }
+
+    // Memory kept for the spill itself, the values are on disk
+    pub fn heap_size(&self) -> usize {
+        self.offsets.capacity() * 8
+    }
+}
+
+impl std::fmt::Debug for Spill {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Spill({} values, {} bytes)", self.len(), self.offsets.last().unwrap())
+    }
+}
+
+// A spill written a value at a time, so a column can go to disk while a file is still being read
+// instead of only once it's all in memory
+pub struct SpillWriter {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    offsets: Vec<u64>,
+}
+
+impl SpillWriter {
+    pub fn new() -> io::Result<SpillWriter> {
+        let path = spill_path();
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        Ok(SpillWriter {
+            path,
+            writer: Some(BufWriter::new(file)),
+            offsets: vec![0],
+        })
+    }
+
+    pub fn push(&mut self, val: &str) -> io::Result<()> {
+        self.writer.as_mut().unwrap().write_all(val.as_bytes())?;
+        self.offsets.push(self.offsets.last().unwrap() + val.len() as u64);
+        Ok(())
+    }
+
+    // Map what was written back into memory
+    pub fn finish(mut self) -> io::Result<Spill> {
+        let file = self.writer.take().unwrap().into_inner().map_err(|e| e.into_error())?;
+        let map = match *self.offsets.last().unwrap() {
+            0 => None,
+            // The file is only ever written through this writer, which is done with it
+            _ => Some(unsafe { Mmap::map(&file)? })
+        };
+
+        Ok(Spill {
+            map,
+            offsets: std::mem::take(&mut self.offsets),
+        })
+    }
+}
+
+// The file isn't needed once it's mapped, or if it never will be
+impl Drop for SpillWriter {
+    fn drop(&mut self) {
+        self.writer = None;
+        let _ = remove_file(&self.path);
+    }
+}
+
+// Spill files go in the system temporary directory, TMPDIR on unix
+fn spill_path() -> PathBuf {
+    let count = SPILLS.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("geomatch-{}-{}.spill", std::process::id(), count))
+}
+
+// A number of bytes like 512M, 8G or 8GB, or just a number
+pub fn parse_size(text: &str) -> Result<usize, Box<dyn Error>> {
+    let upper = text.trim().to_uppercase();
+    let number = upper.trim_end_matches('B');
+    let (number, unit) = match number.chars().last() {
+        Some('K') => (&number[..number.len()-1], 1u64 << 10),
+        Some('M') => (&number[..number.len()-1], 1u64 << 20),
+        Some('G') => (&number[..number.len()-1], 1u64 << 30),
+        Some('T') => (&number[..number.len()-1], 1u64 << 40),
+        _ => (number, 1u64),
+    };
+
+    match number.trim().parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => Ok((number * unit as f64) as usize),
+        _ => Err(format!("Invalid size '{}', expected something like 512M or 8G", text).into())
+    }
+}
+
+// Size in bytes for messages, e.g. 1.5 GB
+pub fn format_size(bytes: usize) -> String {
+    let units = ["bytes", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
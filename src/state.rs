@@ -1,16 +1,128 @@
 use std::error::Error;
+use std::path::{Component, Path};
+use std::fs::copy;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use csv::WriterBuilder;
+use std::time::{Duration, Instant};
 use indicatif::ProgressBar;
-use fuzzywuzzy::fuzz::token_sort_ratio;
-use super::data_frame::DataFrame;
-
-const R: f64 = 3958.8; // Radius of Earth (miles)
+use serde_json::{json, Value};
+use std::io::{stdin, stdout, Write};
+use super::data_frame::{batch_stream, check_geocoder, fetch_single, geocode_client, geocode_stream, merge_streams, AddressFallback, DataFrame, FetchOptions, Geocode, LoadOptions, RowId, BATCH_FAILED};
+use super::country::{country_code, CountryScope};
+use super::transform::Transform;
+use super::projection::Projection;
+use super::coords::{format_coord, in_range, parse_coord, Datum};
+use super::geofence::Geofence;
+use super::enrich::{Boundaries, Enrichment};
+use super::map::write_map;
+use super::mapping::read_mapping;
+use super::pager::page;
+use super::pobox::PoBoxPolicy;
+use super::jitter::Jitter;
+use super::provider::{Keys, Provider};
+use super::lint::{Issue, Linter};
+use super::diff::{diff_matches, Change};
+use super::describe::summarize;
+use super::validate::Problem;
+use super::command::{join_input, split_input, OUTPUT_COMMANDS};
+use super::batch::{write_ipc, write_parquet};
+use super::gpkg::write_gpkg;
+use super::review::{self, Decision, Policy, Record, Review};
+use super::profile::Profile;
+use super::interrupt;
+use super::atomic::{self, Compression};
+use super::s2;
+use super::w3w::W3W_FAILED;
+use super::dates::DateOrder;
+use super::compare::Compare;
+use super::spill::{format_size, parse_size};
+use super::metrics::{count, MATCH_RUNS, ROWS_MATCHED};
+use super::distance::{haversine, Points};
+use super::routing::Router;
+use super::sidecar::{sha256_file, timestamp, utc_now, write_sidecar};
+use regex::Regex;
+use reqwest::Client;
+use tracing::{info, instrument};
+
+const ROUTE_CANDIDATES: usize = 5; // Nearest candidates re-scored by road distance
+const MAX_SPEED: f64 = 75.0; // Fastest assumed driving speed (mph), bounds a drive time radius
+const LINT_EXAMPLES: usize = 5; // Rows shown for each kind of address issue
+const DESCRIBE_TOP: usize = 10; // Most common values shown by describe
+const MAX_SANE_RADIUS: f64 = 50.0; // Match radius (miles) past which validate warns
+const MAX_CROSS_ROWS: usize = 1_000_000; // Most rows a cross join writes, past that matrix with a max distance fits better
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(120); // Between fetch checkpoints, for resuming after a crash
+const JITTER_SEED: &str = "[seed]"; // Stands in for the jitter seed in exported scripts
+
+// Matched row, straight line distance, and road distance and time if routed
+type Candidate = (usize, f64, Option<(f64, f64)>);
 
 #[derive(PartialEq, Debug)]
+#[allow(clippy::upper_case_acronyms)]
 enum MatchMode {
     LEFT,   // match onto leftmost file, thus only entries in the left file appear
     INNER,  // only print entries that match, from any file
     OUTER,  // Print all unique entries
+    CROSS,  // every row of the first file with every row of the second, for coverage analysis
+}
+
+// What to do when a fetch or match output already exists
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Overwrite {
+    Replace,    // the server and watch mode rerun jobs over their own outputs
+    Ask,        // the cli at a terminal
+    Refuse,     // a script piped in, unless --force is given
+}
+
+// What a match does with rows that have no coordinates
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MissingCoords {
+    Skip,       // leave them out of matching, they're still written unmatched where the method keeps them
+    Unmatched,  // skip them and list them in a report next to the match output
+    Geocode,    // geocode their addresses before matching, skipping the ones still not found
+    Abort,      // don't match at all
+}
+
+impl MissingCoords {
+    pub fn parse(text: &str) -> Option<MissingCoords> {
+        match text.to_lowercase().as_str() {
+            "skip" => Some(MissingCoords::Skip),
+            "unmatched" => Some(MissingCoords::Unmatched),
+            "geocode" => Some(MissingCoords::Geocode),
+            "abort" => Some(MissingCoords::Abort),
+            _ => None
+        }
+    }
+}
+
+// A single match made during a match run
+pub struct MatchPair {
+    pub row: usize,         // row in the output
+    pub file: usize,        // file the match came from
+    pub from: (f64, f64),   // coordinates of the output row before the match
+    pub to: (f64, f64),     // coordinates of the match
+    pub dist: f64,
+}
+
+// Results of the last match run, kept around for exporting
+pub struct MatchRun {
+    pub output: DataFrame,
+    pub match_mask: Vec<bool>,
+    pub pairs: Vec<MatchPair>,
+    pub origins: Vec<(usize, f64, f64)>,    // file and coordinates each output row started as
+    pub radius: f64,
+    pub inner: bool,
+}
+
+impl MatchRun {
+    // Whether the row was written to matches.csv
+    pub fn is_written(&self, row: usize) -> bool {
+        !self.inner || self.match_mask[row]
+    }
+
+    // Output rows that were written, in order
+    pub fn written_rows(&self) -> Vec<usize> {
+        (0..self.output.data()[0].len()).filter(|e| self.is_written(*e)).collect()
+    }
 }
 
 // Config object holds configs for each file, where each index acts as that
@@ -19,31 +131,277 @@ pub struct State {
     data_frames: Vec<DataFrame>,
     file_count: usize,
     match_mode: MatchMode,
-    api_key: String,
+    keys: Keys,
+    client: Client,     // for geocoding requests, with the configured User-Agent and headers
     radius: f64,
-    exclusive: bool
+    exclusive: bool,
+    row_ids: Option<RowId>,
+    load_options: LoadOptions,
+    bearing: bool,
+    routing: Option<Router>,
+    road_radius: Option<f64>,
+    drive_time: Option<f64>,
+    fetch_output: String,
+    match_output: String,
+    precision: Option<usize>,
+    tie_seed: Option<u64>,  // breaks ties between equally good candidates, first row wins without one
+    exact_precision: Option<i32>,   // decimals coordinates are rounded to before checking for an exact match, bit for bit without one
+    review: Option<Review>,     // borderline matches match stops to ask about
+    provider: Provider,         // geocoder for files loaded from now on, and for the geocode command
+    missing_coords: MissingCoords,
+    date_order: Option<DateOrder>,  // how compare values that are dates are read, so they compare by day
+    fetch_options: FetchOptions,
+    split_by: Option<String>,
+    sidecar: bool,
+    last_match: Option<MatchRun>,
+    profile: Option<Profile>,
+    output_dir: Option<String>,
+    country_scope: Option<CountryScope>,
+    overwrite: Overwrite,
+    interactive: bool,          // someone is there to answer questions, only the cli
+    runs: Vec<Value>,   // outputs and summary counts of each fetch and match, for notifications
+    loaded: Vec<String>,    // paths of the files added, in order, for export script
+    script: Vec<String>,    // commands that changed the session, for export script
+    aliases: HashMap<String, Vec<String>>,  // from the config file
+    w3w_key: Option<String>,    // what3words api key, from --w3w-key or the config file
+    memory_budget: Option<usize>,   // bytes the files can take before columns are spilled to disk
+    compression: Option<Compression>,   // what outputs are compressed with
 }
 
 
 impl State {
-    pub fn new(api_key: String) -> State {
+    pub fn new(api_key: Option<String>) -> State {
         State {
             data_frames: Vec::new(),
             file_count: 0,
             match_mode: MatchMode::LEFT,
-            api_key,
+            keys: Keys {google: api_key, mapbox: None},
+            client: geocode_client(None, &[]).unwrap(),
             radius: 0.25,
-            exclusive: true
+            exclusive: true,
+            row_ids: None,
+            load_options: LoadOptions::default(),
+            bearing: false,
+            routing: None,
+            road_radius: None,
+            drive_time: None,
+            fetch_output: "{stem}_coords.csv".to_string(),
+            match_output: "matches.csv".to_string(),
+            precision: None,
+            tie_seed: None,
+            exact_precision: None,
+            review: None,
+            provider: Provider::default(),
+            missing_coords: MissingCoords::Skip,
+            date_order: None,
+            fetch_options: FetchOptions::default(),
+            split_by: None,
+            sidecar: false,
+            last_match: None,
+            profile: None,
+            output_dir: None,
+            country_scope: None,
+            overwrite: Overwrite::Replace,
+            interactive: false,
+            runs: Vec::new(),
+            loaded: Vec::new(),
+            script: Vec::new(),
+            aliases: HashMap::new(),
+            w3w_key: None,
+            memory_budget: None,
+            compression: None,
+        }
+    }
+
+    // Options used for every file loaded after this is set
+    pub fn set_load_options(&mut self, options: LoadOptions) {
+        self.load_options = options;
+    }
+
+    pub fn set_aliases(&mut self, aliases: HashMap<String, Vec<String>>) {
+        self.aliases = aliases;
+    }
+
+    // Commands the alias runs, if there is one by that name
+    pub fn alias(&self, name: &str) -> Option<Vec<String>> {
+        self.aliases.get(name).cloned()
+    }
+
+    pub fn set_w3w_key(&mut self, key: Option<String>) {
+        self.w3w_key = key;
+    }
+
+    pub fn set_mapbox_token(&mut self, token: Option<String>) {
+        self.keys.mapbox = token;
+    }
+
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+    }
+
+    // Client to send geocoding requests with, see geocode_client
+    pub fn set_client(&mut self, client: Client) {
+        self.client = client;
+    }
+
+    // Write every output file into a directory instead of the working directory
+    pub fn set_output_dir(&mut self, dir: &str) {
+        self.output_dir = Some(dir.to_string());
+    }
+
+    // Names from a job or a request can't climb out of its directory, or be somewhere else
+    // entirely, since whoever sent them has no business writing anywhere but there
+    fn output_path(&self, name: &str) -> Result<String, Box<dyn Error>> {
+        let dir = match &self.output_dir {
+            Some(dir) => dir,
+            None => return Ok(name.to_string())
+        };
+
+        let inside = Path::new(name).components().all(|e| matches!(e, Component::Normal(_) | Component::CurDir));
+        if name.is_empty() || !inside {
+            return Err(format!("'{}' is outside the output directory", name))?;
+        }
+        Ok(Path::new(dir).join(name).to_string_lossy().to_string())
+    }
+
+    // Compressed outputs are named .gz or .zst, and a name already ending in either is always
+    // compressed with it
+    fn compressed_name(&self, path: String) -> String {
+        match self.compression {
+            Some(compression) if Compression::from_path(&path).is_none() => format!("{}{}", path, compression.extension()),
+            _ => path
+        }
+    }
+
+    pub fn set_overwrite(&mut self, overwrite: Overwrite) {
+        self.overwrite = overwrite;
+    }
+
+    pub fn set_interactive(&mut self) {
+        self.interactive = true;
+    }
+
+    // Check before replacing outputs left by an earlier run, asking at a terminal
+    fn confirm_overwrite(&self, paths: &[String]) -> Result<(), Box<dyn Error>> {
+        let existing: Vec<&str> = paths.iter().filter(|e| Path::new(e).exists()).map(|e| e.as_str()).collect();
+        if existing.is_empty() {
+            return Ok(());
+        }
+
+        let names = existing.join(", ");
+        let verb = if existing.len() == 1 {"exists"} else {"exist"};
+        match self.overwrite {
+            Overwrite::Replace => Ok(()),
+            Overwrite::Refuse => Err(format!("{} already {}, pass --force to overwrite", names, verb).into()),
+            Overwrite::Ask => {
+                print!("{} already {}, overwrite? [y/N] ", names, verb);
+                let _ = stdout().flush();
+                let mut answer = String::new();
+                stdin().read_line(&mut answer)?;
+                if answer.trim().eq_ignore_ascii_case("y") {
+                    Ok(())
+                } else {
+                    Err(format!("Left {} as it was", names).into())
+                }
+            }
+        }
+    }
+
+    // Time each phase of every command from now on
+    pub fn set_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    // Print and reset the phase timings, if profiling
+    pub fn report_profile(&mut self) {
+        if let Some(profile) = &mut self.profile {
+            if !profile.is_empty() {
+                profile.print();
+            }
+            *profile = Profile::default();
+        }
+    }
+
+    fn add_profile(&mut self, profile: Profile) {
+        profile.trace();
+        if let Some(total) = &mut self.profile {
+            total.merge(profile);
         }
     }
 
+    // Generate a row_id column for every file added after this is set
+    pub fn set_row_ids(&mut self, mode: RowId) {
+        self.row_ids = Some(mode);
+    }
+
     pub fn print(&self) {
         for (i, df) in self.data_frames.iter().enumerate() {
             println!("{}: {}", i, df);
         }
-        println!("Radius: {}", self.radius);
+        match self.drive_time {
+            Some(minutes) => println!("Radius: {} min ({} miles straight line)", minutes, self.radius),
+            None => println!("Radius: {}", self.radius),
+        }
         println!("MatchMode: {:?}", self.match_mode);
         println!("Exclusive: {}", self.exclusive);
+        println!("Bearing: {}", self.bearing);
+        match self.tie_seed {
+            Some(seed) => println!("TieSeed: {}", seed),
+            None => println!("TieSeed: off"),
+        }
+        match self.exact_precision {
+            Some(decimals) => println!("ExactPrecision: {}", decimals),
+            None => println!("ExactPrecision: full"),
+        }
+        match self.review {
+            Some(review) => println!("Review: {}", review),
+            None => println!("Review: off"),
+        }
+        println!("MissingCoords: {:?}", self.missing_coords);
+        println!("Dates: {}", self.date_order.map_or("off", |e| e.name()));
+        match &self.routing {
+            Some(router) => println!("Routing: {}", router),
+            None => println!("Routing: off"),
+        }
+        match self.road_radius {
+            Some(radius) => println!("RoadRadius: {}", radius),
+            None => println!("RoadRadius: none"),
+        }
+        println!("Provider: {}", self.provider.name());
+        println!("FetchOutput: {}", self.fetch_output);
+        println!("MatchOutput: {}", self.match_output);
+        match self.precision {
+            Some(decimals) => println!("Precision: {}", decimals),
+            None => println!("Precision: full"),
+        }
+        match (&self.fetch_options.norm_address, self.fetch_options.norm_address_position) {
+            (Some(name), Some(position)) => println!("NormAddress: {} at column {}", name, position),
+            (Some(name), None) => println!("NormAddress: {}", name),
+            (None, _) => println!("NormAddress: off"),
+        }
+        match self.fetch_options.s2_level {
+            Some(level) => println!("S2Level: {}", level),
+            None => println!("S2Level: off"),
+        }
+        println!("W3W: {}", self.fetch_options.w3w);
+        match &self.fetch_options.blank_address {
+            Some(fallbacks) if fallbacks.is_empty() => println!("BlankAddress: skip"),
+            Some(fallbacks) => println!("BlankAddress: {}", fallbacks.iter().map(|e| e.name()).collect::<Vec<&str>>().join(", ")),
+            None => println!("BlankAddress: off"),
+        }
+        println!("PoBox: {}", self.fetch_options.po_box.map_or("off", |e| e.name()));
+        println!("Jitter: {}", self.fetch_options.jitter.map_or("off".to_string(), |e| e.to_string()));
+        println!("Compress: {}", self.compression.map_or("off", |e| e.name()));
+        match self.memory_budget {
+            Some(budget) => println!("MemoryBudget: {}", format_size(budget)),
+            None => println!("MemoryBudget: none"),
+        }
+        println!("SplitBy: {}", self.split_by.as_deref().unwrap_or("None"));
+        match &self.country_scope {
+            Some(scope) => println!("Countries: {}", scope),
+            None => println!("Countries: any"),
+        }
+        println!("Sidecar: {}", self.sidecar);
     }
 
     // Check if the state is ready to fetch
@@ -64,11 +422,179 @@ impl State {
         true
     }
 
+    // Everything wrong with the files and settings that can be found without the network
+    pub fn problems(&self) -> Vec<Problem> {
+        if self.data_frames.is_empty() {
+            return vec![Problem::error("No files loaded. Start geomatch with the files to use".to_string())];
+        }
+
+        let mut problems: Vec<Problem> = self.data_frames.iter()
+            .enumerate()
+            .flat_map(|(index, df)| df.problems(index))
+            .collect();
+
+        if self.data_frames.iter().all(|e| e.output_headers().is_empty()) {
+            problems.push(Problem::blocks("match", "No output columns. Use `add [index] output [col]`".to_string()));
+        }
+        if self.data_frames.len() == 1 {
+            problems.push(Problem::warning("Only one file is loaded, so match has nothing to match it against".to_string()));
+        }
+        if let Err(e) = self.check_compare_kinds() {
+            problems.push(Problem::blocks("match", e.to_string()));
+        }
+
+        if self.radius.is_nan() || self.radius < 0.0 {
+            problems.push(Problem::error(format!("Radius is {}, it can't be negative. Use `radius [radius]`", self.radius)));
+        } else if self.radius == 0.0 {
+            problems.push(Problem::warning("Radius is 0, so only rows at exactly the same coordinates will match".to_string()));
+        } else if self.drive_time.is_none() && self.radius > MAX_SANE_RADIUS {
+            problems.push(Problem::warning(format!(
+                "Radius is {} miles, rows that far apart are rarely the same place. Use `radius [radius]`", self.radius
+            )));
+        }
+        if self.drive_time.is_some() && self.routing.is_none() {
+            problems.push(Problem::blocks("match", "A drive time radius needs route set. Use `route osrm` or `route google`".to_string()));
+        }
+        if let Some(col) = &self.split_by {
+            let outputs: Vec<String> = self.data_frames.iter().flat_map(|e| e.output_headers()).collect();
+            if !outputs.contains(col) {
+                problems.push(Problem::blocks("match", format!("Can't split by '{}', it isn't an output column. Use `split off` or add it", col)));
+            }
+        }
+
+        if self.country_scope.is_some() {
+            for (index, df) in self.data_frames.iter().enumerate() {
+                if df.country().is_none() {
+                    problems.push(Problem::warning(format!(
+                        "file {} has no country column, so only its geocoded rows are checked against the countries. Use `set {} country [col]`",
+                        index, index
+                    )));
+                }
+            }
+        }
+
+        problems
+    }
+
+    // Problems that stop a command, as one error listing each
+    pub fn blocked(&self, command: &str) -> Box<dyn Error> {
+        let problems: Vec<String> = self.problems()
+            .into_iter()
+            .filter(|e| e.blocks == Some(command))
+            .map(|e| format!("\t{}", e.message))
+            .collect();
+
+        format!("Can't {}:\n{}", command, problems.join("\n")).into()
+    }
+
+    // Print every problem with the session, including whether the geocoder can be reached
+    pub async fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let mut problems = self.problems();
+        // Only the providers the files use need their keys
+        let mut providers: Vec<Provider> = Vec::new();
+        for provider in self.data_frames.iter().map(|e| e.provider()) {
+            if !providers.contains(&provider) {
+                providers.push(provider);
+            }
+        }
+        for provider in providers {
+            match self.keys.get(provider) {
+                Err(e) => problems.push(Problem::blocks("fetch", e.to_string())),
+                Ok(key) if provider == Provider::Google => if let Err(e) = check_geocoder(&self.client, key).await {
+                    problems.push(Problem::error(format!("Geocoder can't be used: {}. Check the api key and network", e)));
+                }
+                Ok(_) => {}
+            }
+        }
+
+        if problems.is_empty() {
+            println!("No problems found");
+            return Ok(());
+        }
+        for problem in problems.iter() {
+            println!("{}", problem);
+        }
+
+        let errors = problems.iter().filter(|e| !e.warning).count();
+        if errors > 0 {
+            return Err(format!("{} problems found, {} of them errors", problems.len(), errors))?;
+        }
+        Ok(())
+    }
+
     // Add the file name and set all column indexes to None
     // Then try to guess which columns are which indexes, but not to loosely
-    pub fn add_file(&mut self, file_name: &str) {
+    pub fn add_file(&mut self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        let mut df = self.load_file(file_name, self.file_count, self.load_options.offset, &self.load_options)?;
+        df.set_provider(self.provider.name())?;
+
         self.file_count+=1;
-        self.data_frames.push(DataFrame::from_path(file_name));
+        self.data_frames.push(df);
+        self.loaded.push(file_name.to_string());
+        self.apply_country_scope(self.file_count-1, None);
+        self.fit_memory_budget()?;
+
+        let mut profile = Profile::default();
+        profile.since("load", start);
+        self.add_profile(profile);
+        Ok(())
+    }
+
+    // Load a file as file index, reporting how its columns were guessed. start is the row number
+    // its row ids count from
+    fn load_file(&self, file_name: &str, index: usize, start: usize, options: &LoadOptions) -> Result<DataFrame, Box<dyn Error>> {
+        // Whatever the files already loaded leave of the budget, so a big file spills as it's read
+        // instead of having to fit in memory first
+        let mut options = options.clone();
+        options.memory_budget = self.memory_budget
+            .map(|e| e.saturating_sub(self.data_frames.iter().map(|e| e.heap_size()).sum()));
+        let mut df = DataFrame::from_path(file_name, &options)?;
+        if let Some(mode) = self.row_ids {
+            df.add_row_ids(index, mode, start)?;
+        }
+
+        // Say which header was picked when several fit or when no header fit, since a wrong guess
+        // is easy to miss
+        for (role, header) in df.roles_from_values() {
+            println!("Guessed '{}' as {} in file {} from its values", header, role, index);
+        }
+        for (role, candidates) in df.ambiguous_roles() {
+            let names: Vec<String> = candidates.iter().map(|e| format!("'{}'", e)).collect();
+            println!("Warning: {} could be {} in file {}, using '{}'. Use `set {} {} [col]` to choose another",
+                role, names.join(" or "), index, candidates[0], index, role.name());
+        }
+
+        Ok(df)
+    }
+
+    // Load a file again with its header on another line, for exports with banner lines above
+    // the real header. Columns set on the file before are reset
+    pub fn set_header_line(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        if input.len() < 3 {
+            return Err("file_index and line required")?;
+        }
+
+        let file_index = input[1].parse::<usize>()?;
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+        let line = input[2].parse::<usize>()?;
+        if line == 0 {
+            return Err("Lines count from 1")?;
+        }
+
+        // Kept so files appended from the same path later skip the same lines
+        let path = self.data_frames[file_index].path().to_string();
+        let mut options = self.load_options.clone();
+        options.header_lines.insert(path.clone(), line);
+        let df = self.load_file(&path, file_index, options.offset, &options)?;
+        println!("Loaded {} with the header on line {}, {} rows", path, line, df.shape.1);
+        self.data_frames[file_index] = df;
+        self.load_options = options;
+        self.apply_country_scope(file_index, None);
+
+        Ok(())
     }
 
     // Get reader using current config for file
@@ -76,7 +602,7 @@ impl State {
         &self.data_frames[index]
     }
 
-    pub fn set_method<'a>(&mut self, input: Vec<&'a str>) -> Result<(), Box<dyn Error>> {
+    pub fn set_method(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
         let method = input.get(1);
         if method.is_none() {
             return Err("method required")?;
@@ -92,6 +618,9 @@ impl State {
             "outer" => {
                 self.match_mode = MatchMode::OUTER;
             }
+            "cross" => {
+                self.match_mode = MatchMode::CROSS;
+            }
             _ => {
                 return Err("Invalid match mode")?;
             }
@@ -100,7 +629,7 @@ impl State {
         Ok(())
     }
 
-    pub fn set_exclusive<'a>(&mut self, input: Vec<&'a str>) -> Result<(), Box<dyn Error>> {
+    pub fn set_exclusive(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
         let val = input.get(1);
         if val.is_none() {
             return Err("val required")?;
@@ -122,308 +651,2464 @@ impl State {
         Ok(())
     }
 
-    // Add column to output, will be prefixed with prefixes
-    pub fn add_match_column<'a>(&mut self, input: Vec<&'a str>) -> Result<(), Box<dyn Error>> {
-        let file_index = input.get(1);
-        if file_index.is_none() {
-            return Err("file_index required")?;
-        }
-        let file_index = file_index.unwrap().parse::<usize>()?;
-
-        let col_type = input.get(2);
-        if col_type.is_none() {
-            return Err("type required")?;
+    // Whether to write the bearing from each entry to its match
+    pub fn set_bearing(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let val = input.get(1);
+        if val.is_none() {
+            return Err("val required")?;
         }
-        let col_type = col_type.unwrap();
 
-        if input.len() < 4 {
-            return Err("output_col required")?;
+        match val.unwrap().to_lowercase().as_str() {
+            "true" => self.bearing = true,
+            "false" => self.bearing = false,
+            _ => return Err("val must be true or false")?
         }
 
-        let output_col = input[3..].join(" ");
+        Ok(())
+    }
 
-        if file_index >= self.file_count {
-            return Err("Index out of Bounds")?;
+    // Number of decimals written for coordinates and distances, or full precision
+    pub fn set_precision(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let decimals = input.get(1);
+        if decimals.is_none() {
+            return Err("decimals required")?;
         }
 
-        if col_type.eq(&"output") {
-            self.data_frames[file_index].add_output_column(output_col.as_str())?;
-        } else if col_type.eq(&"compare") {
-            self.data_frames[file_index].add_compare_column(output_col.as_str())?;
+        if decimals.unwrap().eq_ignore_ascii_case("full") {
+            self.precision = None;
         } else {
-            return Err("Invalid type")?;
+            self.precision = Some(decimals.unwrap().parse::<usize>()?);
         }
 
         Ok(())
     }
 
-    // Add a prefix for all columns from a certain file
-    pub fn set_prefix<'a>(&mut self, input: Vec<&'a str>) -> Result<(), Box<dyn Error>> {
+    // Seed for breaking ties between candidates that are equally close, or off to pick the first
+    pub fn set_seed(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let seed = input.get(1);
+        if seed.is_none() {
+            return Err("seed required")?;
+        }
 
-        let file_index = input.get(1);
-        if file_index.is_none() {
-            return Err("file_index required")?;
+        if seed.unwrap().eq_ignore_ascii_case("off") {
+            self.tie_seed = None;
+        } else {
+            self.tie_seed = Some(seed.unwrap().parse::<u64>()?);
         }
-        let file_index = file_index.unwrap().parse::<usize>()?;
 
-        let prefix = input.get(2);
-        if prefix.is_none() {
-            return Err("prefix required")?;
+        Ok(())
+    }
+
+    // Decimals coordinates are rounded to before checking whether two rows are at exactly the same
+    // place, so float noise and provider rounding don't keep them apart, or full to compare every digit
+    pub fn set_exact_precision(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let decimals = input.get(1);
+        if decimals.is_none() {
+            return Err("decimals required")?;
         }
-        let prefix = prefix.unwrap();
 
-        if file_index >= self.file_count {
-            return Err("Index out of Bounds")?;
+        if decimals.unwrap().eq_ignore_ascii_case("full") {
+            self.exact_precision = None;
+        } else {
+            match decimals.unwrap().parse::<i32>() {
+                Ok(decimals) if (0..=15).contains(&decimals) => self.exact_precision = Some(decimals),
+                _ => return Err("decimals must be 0 to 15 or full")?
+            }
         }
 
-        self.data_frames[file_index].set_prefix(prefix);
+        Ok(())
+    }
 
+    // Have match ask before keeping borderline matches, those near the edge of the radius or with
+    // compare columns that don't look alike, or off to keep every match
+    pub fn set_review(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        self.review = Review::parse(&input[1..])?;
         Ok(())
     }
 
-    // Set matching radius
-    pub fn set_radius<'a>(&mut self, input: Vec<&'a str>) -> Result<(), Box<dyn Error>> {
-        let radius = input.get(1);
-        if radius.is_none() {
-            return Err("radius required")?;
+    // What a match does with rows that have no coordinates
+    pub fn set_missing(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let policy = input.get(1);
+        if policy.is_none() {
+            return Err("policy required")?;
+        }
+
+        match MissingCoords::parse(policy.unwrap()) {
+            Some(policy) => self.missing_coords = policy,
+            None => return Err(format!("Unknown policy '{}', use skip, unmatched, geocode or abort", policy.unwrap()))?
         }
-        self.radius = radius.unwrap().parse::<f64>()?;
 
         Ok(())
     }
 
-    pub fn get_columns<'a>(&mut self, input: Vec<&'a str>) -> Result<&Vec<String>, Box<dyn Error>> {
-        // Check for file_index
-        let file_index = input.get(1);
-        if file_index.is_none() {
-            return Err("file_index required")?;
-        }
+    // Order of month and day in compare values that are dates, or off to compare them as text
+    pub fn set_dates(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let order = match input.get(1) {
+            Some(order) => order,
+            None => return Err("mdy, dmy or off required")?
+        };
 
-        // Check that file_index is valid and retreive its name
-        let file_index = file_index.unwrap().parse::<usize>()?;
-        if file_index >= self.file_count {
-            return Err("Index out of Bounds")?;
-        }
+        self.date_order = match DateOrder::parse(order) {
+            Some(order) => Some(order),
+            None if order.eq_ignore_ascii_case("off") => None,
+            None => return Err(format!("Unknown date order '{}', use mdy, dmy or off", order))?
+        };
 
-        Ok(self.data_frames[file_index].get_headers())
+        Ok(())
     }
 
-    pub fn set_param<'a>(&mut self, input: Vec<&'a str>) -> Result<(), Box<dyn Error>> {
-        let index = input.get(1);
-        let key = input.get(2);
+    // Whether to write a json file of run metadata next to every output file
+    pub fn set_sidecar(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let val = input.get(1);
+        if val.is_none() {
+            return Err("val required")?;
+        }
 
-        if index.is_none() {
-            return Err("index is required")?;
-        }else if key.is_none() {
-            return Err("key is required")?;
-        } else if input.len() < 3 {
-            return Err("val is required")?;
+        match val.unwrap().to_lowercase().as_str() {
+            "true" => self.sidecar = true,
+            "false" => self.sidecar = false,
+            _ => return Err("val must be true or false")?
         }
 
-        let val = &input[3..].join(" ");
-        let index = index.unwrap().parse::<usize>()?;
-        let key = key.unwrap();
+        Ok(())
+    }
 
-        if index >= self.file_count {
-            return Err("Index out of Bounds")?;
+    // Write one match file per value of an output column instead of a single file
+    pub fn set_split(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        if input.len() < 2 {
+            return Err("col required")?;
         }
 
-        let df = &mut self.data_frames[index];
-
-        match key.to_lowercase().as_str() {
-            "addr1" => df.set_addr1(val)?,
-            "addr2" => df.set_addr2(val)?,
-            "city" => df.set_city(val)?,
-            "state" => df.set_state(val)?,
-            "zipcode" => df.set_zipcode(val)?,
-            "lat" => df.set_lat(val)?,
-            "lng" => df.set_lng(val)?,
-            _ => {}
+        let col = input[1..].join(" ");
+        if col.eq_ignore_ascii_case("off") {
+            self.split_by = None;
+        } else {
+            self.split_by = Some(col);
         }
 
         Ok(())
     }
 
-    pub async fn fetch(&mut self) -> Result<(), Box<dyn Error>> {
+    // Geocoder for every file, and for files loaded after. A file can still be given its own
+    // with `set [index] provider`
+    pub fn set_provider(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let name = input.get(1).ok_or("provider required")?;
+        let provider = Provider::parse(name).ok_or_else(|| format!("Unknown provider '{}', expected google, census, census-batch or mapbox", name))?;
         for df in self.data_frames.iter_mut() {
-            df.fetch(self.api_key.clone()).await?;
+            df.set_provider(provider.name())?;
         }
+        self.provider = provider;
 
         Ok(())
     }
 
-    pub fn find_matches(&mut self) -> Result<(), Box<dyn Error>> {
-        let (width, height) = {
-            let mut width = 0;
-            let mut height = 0;
-
-            for (index, df) in self.data_frames.iter().enumerate() {
-                width += df.output_headers().len();
-
-                if index != 0 {
-                    width += 1; // Distance column
-                }
+    // Re-score matches by driving distance from a routing service, or turn it off
+    pub fn set_routing(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let provider = input.get(1);
+        if provider.is_none() {
+            return Err("provider required")?;
+        }
 
-                height += df.shape.1;
-            }
-            if self.match_mode==MatchMode::LEFT {
-                width+=1;
+        if provider.unwrap().eq_ignore_ascii_case("off") {
+            self.routing = None;
+        } else {
+            let router = Router::parse(&input[1..])?;
+            if router == Router::Google {
+                self.keys.get(Provider::Google)?;
             }
+            self.routing = Some(router);
+        }
 
-            (width, height)
-        };
+        Ok(())
+    }
 
-        // if width is 0 no output columns were supplied
-        if width == 0 {
-            return Err("No output columns supplied")?;
+    // Limit rows to some countries, by the file's country column or the country they geocode
+    // to. Rows elsewhere are set aside, or only flagged in an in_scope column. Rows an earlier
+    // scope set aside are put back first, so turning it off or changing it gets them back
+    pub fn set_country_scope(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        self.country_scope = CountryScope::parse(&input[1..])?;
+        for index in 0..self.file_count {
+            let restored = self.data_frames[index].restore_rows();
+            if restored > 0 {
+                println!("Put back {} rows in file {}", restored, index);
+            }
+            self.apply_country_scope(index, None);
         }
 
-        let bar = ProgressBar::new(height as u64);
+        Ok(())
+    }
 
-        // create output dataframe, technically overprovisioned for the height
-        let mut output = DataFrame::with_capacity(width, height);
+    // Set aside or flag a file's rows outside the country scope. Rows are placed by their country
+    // column, then by the country they were geocoded to if given. Rows with no known country
+    // are kept
+    fn apply_country_scope(&mut self, index: usize, geocoded: Option<&[String]>) {
+        let scope = match &self.country_scope {
+            Some(scope) => scope,
+            None => return
+        };
 
-        // Keep track of which columns inside output contain a match
-        let mut match_mask: Vec<bool> = Vec::with_capacity(height);
-        for _ in 0..height {
-            match_mask.push(false);
+        let df = &mut self.data_frames[index];
+        let column = df.country();
+        let countries: Vec<Option<String>> = (0..df.shape.1)
+            .map(|row| {
+                column.and_then(|e| country_code(&e[row]))
+                    .or_else(|| geocoded.map(|e| e[row].clone()).filter(|e| !e.is_empty()))
+            })
+            .collect();
+        if column.is_none() && geocoded.is_none() {
+            return;
         }
 
+        let outside = countries.iter().filter(|e| e.as_ref().is_some_and(|e| !scope.contains(e))).count();
+        if scope.flag {
+            let flags = countries.iter()
+                .map(|e| e.as_ref().map_or("", |e| if scope.contains(e) {"true"} else {"false"}).to_string())
+                .collect();
+            df.set_column("in_scope", flags);
+            println!("Flagged {} rows outside {} in file {}", outside, scope.codes.join(", "), index);
+        } else if outside > 0 {
+            let keep: Vec<bool> = countries.iter().map(|e| e.as_ref().is_none_or(|e| scope.contains(e))).collect();
+            df.set_aside_rows(&keep);
+            println!("Set aside {} rows outside {} from file {}, `country off` puts them back", outside, scope.codes.join(", "), index);
+        }
+    }
+
+    // Max driving distance for a routed match, none to only rank candidates by it
+    pub fn set_road_radius(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let radius = input.get(1);
+        if radius.is_none() {
+            return Err("radius required")?;
+        }
+
+        if radius.unwrap().eq_ignore_ascii_case("none") {
+            self.road_radius = None;
+        } else {
+            self.road_radius = Some(radius.unwrap().parse::<f64>()?);
+        }
+
+        Ok(())
+    }
+
+    // Add column to output, will be prefixed with prefixes
+    pub fn add_match_column(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        let col_type = input.get(2);
+        if col_type.is_none() {
+            return Err("type required")?;
+        }
+        let col_type = col_type.unwrap();
+
+        if input.len() < 4 {
+            return Err("output_col required")?;
+        }
+
+        let output_col = input[3..].join(" ");
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        if col_type.eq(&"output") {
+            self.data_frames[file_index].add_output_column(output_col.as_str())?;
+        } else if let Some(kind) = Compare::parse(col_type) {
+            self.data_frames[file_index].add_compare_column(output_col.as_str(), kind)?;
+        } else {
+            return Err("Invalid type")?;
+        }
+
+        Ok(())
+    }
+
+    // Apply every setting in a mapping file to a file, see read_mapping. Nothing changes if any
+    // of them fails
+    pub fn apply_mapping(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        let path = input.get(2);
+        if path.is_none() {
+            return Err("mapping file required")?;
+        }
+        let path = self.output_path(path.unwrap())?;
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let settings = read_mapping(&path)?;
+        let saved = self.data_frames[file_index].clone();
+        let index = file_index.to_string();
+        for (setting, value) in settings.iter() {
+            let result = match setting.as_str() {
+                "prefix" => self.set_prefix(vec!["prefix", &index, value]),
+                "output" | "compare" | "phone" | "name" | "soundex" | "metaphone" => self.add_match_column(vec!["add", &index, setting, value]),
+                _ => self.set_param(vec!["set", &index, setting, value]),
+            };
+            if let Err(e) = result {
+                self.data_frames[file_index] = saved;
+                return Err(format!("{}: {} {}: {}", path, setting, value, e))?;
+            }
+        }
+
+        println!("Applied {} settings from {} to file {}", settings.len(), path, file_index);
+        Ok(())
+    }
+
+    // Add a prefix for all columns from a certain file
+    pub fn set_prefix(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        let prefix = input.get(2);
+        if prefix.is_none() {
+            return Err("prefix required")?;
+        }
+        let prefix = prefix.unwrap();
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        self.data_frames[file_index].set_prefix(prefix);
+
+        Ok(())
+    }
+
+    // Write a column of a file under another name, without renaming it for commands. Without a
+    // name the column goes back to its header
+    pub fn set_alias(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        let col = input.get(2);
+        if col.is_none() {
+            return Err("col required")?;
+        }
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let alias = input.get(3).copied();
+        if alias.is_some_and(|e| e.trim().is_empty()) {
+            return Err("alias can't be blank")?;
+        }
+        self.data_frames[file_index].set_alias(col.unwrap(), alias)
+    }
+
+    // Set matching radius
+    pub fn set_radius(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let radius = input.get(1);
+        if radius.is_none() {
+            return Err("radius required")?;
+        }
+        let radius = radius.unwrap().parse::<f64>()?;
+
+        // A drive time radius searches as far as could be driven in a straight line
+        // and leaves the rest to the routing service
+        match input.get(2).map(|e| e.to_lowercase()).as_deref() {
+            None | Some("mi") | Some("miles") => {
+                self.radius = radius;
+                self.drive_time = None;
+            }
+            Some("min") | Some("minutes") => {
+                self.radius = radius * MAX_SPEED / 60.0;
+                self.drive_time = Some(radius);
+            }
+            _ => return Err("unit must be miles or min")?
+        }
+
+        Ok(())
+    }
+
+    // Transform a column in place, e.g. `transform 0 Name upper`
+    pub fn transform(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        let col = input.get(2);
+        if col.is_none() {
+            return Err("col required")?;
+        }
+        let col = col.unwrap();
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let transform = Transform::parse(&input[3..])?;
+        self.data_frames[file_index].transform_column(col, &transform)
+    }
+
+    // Stack more files with the same headers onto the file at index
+    pub fn concat(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        if input.len() < 3 {
+            return Err("file required")?;
+        }
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        // Files are gathered into a frame of their own first, so one that fails to load leaves
+        // the file as it was rather than with only the files before it added
+        let mut added: Option<DataFrame> = None;
+        for file_name in input[2..].iter() {
+            if !Path::new(file_name).is_file() {
+                return Err(format!("No file named {}", file_name))?;
+            }
+            let start = self.data_frames[file_index].total_rows() + added.as_ref().map_or(0, |e| e.total_rows());
+            let df = self.load_file(file_name, file_index, start, &self.load_options)?;
+            match added.as_mut() {
+                Some(added) => added.append(df)?,
+                None => added = Some(df)
+            }
+        }
+        self.data_frames[file_index].append(added.unwrap())?;
+        self.fit_memory_budget()?;
+
+        println!("{} now has {} rows", file_index, self.data_frames[file_index].shape.1);
+        Ok(())
+    }
+
+    // Sort the rows of a file, which also sets the order rows are matched in
+    pub fn sort(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        let col = input.get(2);
+        if col.is_none() {
+            return Err("col required")?;
+        }
+        let col = col.unwrap();
+
+        let descending = match input.get(3).map(|e| e.to_lowercase()) {
+            None => false,
+            Some(order) if order == "asc" => false,
+            Some(order) if order == "desc" => true,
+            _ => return Err("order must be asc or desc")?
+        };
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        self.data_frames[file_index].sort_by_column(col, descending)
+    }
+
+    // Convert projected coordinates (UTM, State Plane) in lat/lng to real lat/lng
+    pub fn project(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let projection = Projection::parse(&input[2..])?;
+        self.data_frames[file_index].project(projection)
+    }
+
+    // Declare which datum a file's coordinates are in
+    pub fn set_datum(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        let datum = input.get(2);
+        if datum.is_none() {
+            return Err("datum required")?;
+        }
+        let datum = Datum::parse(datum.unwrap());
+        if datum.is_none() {
+            return Err("datum must be wgs84 or nad83")?;
+        }
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        self.data_frames[file_index].set_datum(datum.unwrap())
+    }
+
+    // Drop every row outside of a bounding box or polygon
+    pub fn clip(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let geofence = Geofence::parse(&input[2..])?;
+        let removed = self.data_frames[file_index].clip(&geofence)?;
+        println!("Removed {} rows outside the area", removed);
+
+        Ok(())
+    }
+
+    // Add a column of each row's distance from a point like headquarters or a depot, optionally
+    // dropping rows further away than a max distance
+    pub fn distance(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        if input.len() < 4 {
+            return Err("file_index, lat and lng required")?;
+        }
+
+        let file_index = input[1].parse::<usize>()?;
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let lat = parse_coord(input[2]);
+        let lng = parse_coord(input[3]);
+        if lat.is_none() || lng.is_none() || !in_range(lat.unwrap(), lng.unwrap()) {
+            return Err("Invalid coordinates")?;
+        }
+        let name = input.get(4).copied().unwrap_or("distance");
+        let max = match input.get(5) {
+            Some(max) => Some(max.parse::<f64>().map_err(|_| format!("Invalid max distance '{}'", max))?),
+            None => None
+        };
+
+        let removed = self.data_frames[file_index].distance_from(lat.unwrap(), lng.unwrap(), name, max)?;
+        match max {
+            Some(max) => println!("Added {} and removed {} rows further than {} miles away", name, removed, max),
+            None => println!("Added {}", name),
+        }
+
+        Ok(())
+    }
+
+    // Add columns describing the boundary each row falls in, e.g. its county, from a
+    // GeoJSON file or the Census geographies api if no path is given
+    pub async fn enrich(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        if input.len() < 3 {
+            return Err("file_index and kind required")?;
+        }
+
+        let file_index = input[1].parse::<usize>()?;
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let df = &mut self.data_frames[file_index];
+        if !df.ready_to_match() {
+            return Err("lat and lng must be set to enrich")?;
+        }
+
+        let enrichment = Enrichment::parse(input[2])?;
+        let points: Vec<(f64, f64)> = df.lat().unwrap().iter().cloned()
+            .zip(df.lng().unwrap().iter().cloned())
+            .collect();
+
+        // Census lookups keep going past a failed request, which leaves its row blank with the
+        // reason in enrich_error
+        let census = input.len() <= 3;
+        let lookups: Vec<Result<Option<Vec<String>>, String>> = if !census {
+            let boundaries = Boundaries::load(enrichment, &input[3..].join(" "))?;
+            points.iter()
+                .map(|(lat, lng)| Ok(boundaries.lookup(*lat, *lng).map(|e| e.to_vec())))
+                .collect()
+        } else if enrichment.census_layer().is_none() {
+            return Err(format!("{} needs a boundary file", input[2]))?;
+        } else {
+            println!("Looking up {} rows with the Census geographies api:", points.len());
+            enrichment.census_lookup_all(points).await
+        };
+
+        let columns = enrichment.columns();
+        let mut values = vec![Vec::with_capacity(df.shape.1); columns.len()];
+        let mut errors = Vec::with_capacity(df.shape.1);
+        let found = lookups.iter().filter(|e| matches!(e, Ok(Some(_)))).count();
+        let failed = lookups.iter().filter(|e| e.is_err()).count();
+
+        for lookup in lookups {
+            match lookup {
+                Ok(Some(row_values)) => {
+                    for (col, val) in values.iter_mut().zip(row_values) {
+                        col.push(val);
+                    }
+                    errors.push(String::new());
+                }
+                lookup => {
+                    for col in values.iter_mut() {
+                        col.push("".to_string());
+                    }
+                    errors.push(lookup.err().unwrap_or_default());
+                }
+            }
+        }
+
+        for (name, col) in columns.iter().zip(values) {
+            df.set_column(name, col);
+        }
+        if census {
+            df.set_column("enrich_error", errors);
+        }
+        println!("Found a {} for {} of {} rows", input[2], found, df.shape.1);
+        if failed > 0 {
+            println!("{} lookups failed, their reasons are in enrich_error. Run enrich again to retry every row", failed);
+        }
+
+        Ok(())
+    }
+
+    // Keep only count rows of a file starting at offset, to try a setup on part of it before the
+    // full run
+    pub fn limit(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        if input.len() < 3 {
+            return Err("file_index and count required")?;
+        }
+
+        let file_index = input[1].parse::<usize>()?;
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+        let count = input[2].parse::<usize>()?;
+        let offset = match input.get(3) {
+            Some(offset) => offset.parse::<usize>()?,
+            None => 0
+        };
+
+        let df = &mut self.data_frames[file_index];
+        let keep: Vec<bool> = (0..df.shape.1).map(|e| e >= offset && e - offset < count).collect();
+        df.retain_rows(&keep);
+        println!("{} now has {} rows", file_index, df.shape.1);
+
+        Ok(())
+    }
+
+    // Remove duplicate rows, optionally only comparing the given columns
+    pub fn dedup_exact(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let removed = self.data_frames[file_index].dedup_exact(&input[2..])?;
+        println!("Removed {} duplicate rows", removed);
+
+        Ok(())
+    }
+
+    // Columns of a file, only the ones matching a pattern if one is given. The pattern is a case
+    // insensitive regex, or plain text if it isn't a valid one
+    pub fn get_columns(&mut self, input: Vec<&str>) -> Result<Vec<String>, Box<dyn Error>> {
+        // Check for file_index
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+
+        // Check that file_index is valid and retreive its name
+        let file_index = file_index.unwrap().parse::<usize>()?;
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let headers = self.data_frames[file_index].get_headers();
+        let pattern = match input.get(2..) {
+            Some(words) if !words.is_empty() => words.join(" "),
+            _ => return Ok(headers.clone())
+        };
+        let filter = Regex::new(&format!("(?i){}", pattern))
+            .or_else(|_| Regex::new(&format!("(?i){}", regex::escape(&pattern))))?;
+
+        Ok(headers.iter().filter(|e| filter.is_match(e)).cloned().collect())
+    }
+
+    pub fn set_param(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        // Output names apply to every file so they don't take an index
+        match input.get(1).map(|e| e.to_lowercase()).as_deref() {
+            Some("fetchoutput") => return self.set_fetch_output(input[2..].join(" ")),
+            Some("matchoutput") => return self.set_match_output(input[2..].join(" ")),
+            Some("normaddress") => return self.set_norm_address(&input[2..]),
+            Some("s2") => return self.set_s2_level(input.get(2).copied()),
+            Some("w3w") => return self.set_w3w(input.get(2).copied()),
+            Some("blankaddress") => return self.set_blank_address(&input[2..]),
+            Some("pobox") => return self.set_po_box(input.get(2).copied()),
+            Some("jitter") => return self.set_jitter(&input[2..]),
+            Some("memory") => return self.set_memory(input.get(2).copied()),
+            Some("compress") => return self.set_compress(input.get(2).copied()),
+            _ => {}
+        }
+
+        let index = input.get(1);
+        let key = input.get(2);
+
+        if index.is_none() {
+            return Err("index is required")?;
+        }else if key.is_none() {
+            return Err("key is required")?;
+        } else if input.len() < 3 {
+            return Err("val is required")?;
+        }
+
+        let val = &input[3..].join(" ");
+        let index = index.unwrap().parse::<usize>()?;
+        let key = key.unwrap();
+
+        if index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let df = &mut self.data_frames[index];
+
+        match key.to_lowercase().as_str() {
+            "id" => df.set_id(val)?,
+            "addr1" => df.set_addr1(val)?,
+            "addr2" => df.set_addr2(val)?,
+            "city" => df.set_city(val)?,
+            "state" => df.set_state(val)?,
+            "zipcode" => df.set_zipcode(val)?,
+            "country" => df.set_country(val)?,
+            "lat" => df.set_lat(val)?,
+            "lng" => df.set_lng(val)?,
+            "latlng" => df.set_latlng(val)?,
+            "template" => df.set_template(val)?,
+            "provider" => df.set_provider(val)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // Template for the name of each fetched file, e.g. "{stem}_{date}_geocoded.csv"
+    fn set_fetch_output(&mut self, template: String) -> Result<(), Box<dyn Error>> {
+        if template.is_empty() {
+            return Err("template required")?;
+        }
+
+        render_output_name(&template, &fetch_output_vars("stem", 0))?;
+        self.fetch_output = template;
+
+        Ok(())
+    }
+
+    // Template for the name of the match file, e.g. "matches_{radius}mi_{method}.csv"
+    fn set_match_output(&mut self, template: String) -> Result<(), Box<dyn Error>> {
+        if template.is_empty() {
+            return Err("template required")?;
+        }
+
+        render_output_name(&template, &self.match_output_vars())?;
+        self.match_output = template;
+
+        Ok(())
+    }
+
+    // Write the normalized address as true, false or a column name, optionally at a column index
+    fn set_norm_address(&mut self, input: &[&str]) -> Result<(), Box<dyn Error>> {
+        let name = input.first();
+        if name.is_none() {
+            return Err("true, false or a column name required")?;
+        }
+
+        let position = match input.get(1) {
+            Some(position) => Some(position.parse::<usize>()?),
+            None => None
+        };
+
+        self.fetch_options.norm_address = match name.unwrap().to_lowercase().as_str() {
+            "false" => None,
+            "true" => Some("norm_address".to_string()),
+            _ => Some(name.unwrap().to_string())
+        };
+        self.fetch_options.norm_address_position = position;
+
+        Ok(())
+    }
+
+    // Level of the S2 cell token fetch writes for each row, or off
+    fn set_s2_level(&mut self, level: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let level = match level {
+            Some(level) => level,
+            None => return Err("level or off required")?
+        };
+
+        self.fetch_options.s2_level = match level.to_lowercase().as_str() {
+            "off" => None,
+            _ => match level.parse::<u8>() {
+                Ok(level) if level <= s2::MAX_LEVEL => Some(level),
+                _ => return Err(format!("S2 level must be 0 to {} or off", s2::MAX_LEVEL))?
+            }
+        };
+
+        Ok(())
+    }
+
+    // What outputs are compressed with as they're written, if anything
+    fn set_compress(&mut self, compression: Option<&str>) -> Result<(), Box<dyn Error>> {
+        self.compression = match compression {
+            Some(compression) => Compression::parse(compression)?,
+            None => return Err("gzip, zstd or off required")?
+        };
+
+        Ok(())
+    }
+
+    // Memory the files can take before columns spill to disk, or off
+    fn set_memory(&mut self, size: Option<&str>) -> Result<(), Box<dyn Error>> {
+        self.memory_budget = match size {
+            Some(size) if size.eq_ignore_ascii_case("off") => None,
+            Some(size) => Some(parse_size(size)?),
+            None => return Err("size or off required")?
+        };
+
+        self.fit_memory_budget()
+    }
+
+    // Spill the biggest columns matching doesn't need to disk until the files fit in the memory
+    // budget. They are read back from disk when written out
+    fn fit_memory_budget(&mut self) -> Result<(), Box<dyn Error>> {
+        let budget = match self.memory_budget {
+            Some(budget) => budget,
+            None => return Ok(())
+        };
+
+        let mut used: usize = self.data_frames.iter().map(|e| e.heap_size()).sum();
+        if used <= budget {
+            return Ok(());
+        }
+
+        let mut columns: Vec<(usize, usize, usize)> = self.data_frames.iter().enumerate()
+            .flat_map(|(index, df)| df.spillable_columns().into_iter().map(move |(col, bytes)| (index, col, bytes)))
+            .collect();
+        columns.sort_by_key(|e| std::cmp::Reverse(e.2));
+
+        let mut spilled = 0;
+        let mut freed = 0;
+        for (index, col, _) in columns {
+            if used <= budget {
+                break;
+            }
+            let bytes = self.data_frames[index].spill_column(col)
+                .map_err(|e| format!("Couldn't spill to disk: {}", e))?;
+            used = used.saturating_sub(bytes);
+            freed += bytes;
+            spilled += 1;
+        }
+
+        if spilled > 0 {
+            println!("Spilled {} columns to disk, freeing {}.", spilled, format_size(freed));
+            if used > budget {
+                println!("Warning: files still take {}, over the memory budget of {}. Role and compare columns stay in memory.",
+                    format_size(used), format_size(budget));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether fetch looks up the what3words address of each row, which needs a key for it
+    fn set_w3w(&mut self, val: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let val = match val {
+            Some(val) => val.parse::<bool>()?,
+            None => return Err("true or false required")?
+        };
+        if val && self.w3w_key.is_none() {
+            return Err("what3words needs a key, pass --w3w-key, set W3W_API_KEY, add w3w_key to the config file or run `geomatch auth set w3w`")?;
+        }
+        self.fetch_options.w3w = val;
+
+        Ok(())
+    }
+
+    // What fetch geocodes rows with a blank street address, city or state on. city and zip are
+    // tried in the order given, skip flags the rows without trying anything, and off skips them
+    // without a status column
+    fn set_blank_address(&mut self, input: &[&str]) -> Result<(), Box<dyn Error>> {
+        self.fetch_options.blank_address = match input.first().map(|e| e.to_lowercase()).as_deref() {
+            None => return Err("city, zip, skip or off required")?,
+            Some("off") => None,
+            Some("skip") => Some(Vec::new()),
+            _ => Some(input.iter()
+                .map(|e| AddressFallback::parse(e).ok_or(format!("Invalid fallback '{}', expected city or zip", e)))
+                .collect::<Result<Vec<AddressFallback>, String>>()?),
+        };
+
+        Ok(())
+    }
+
+    // What fetch does with PO Box and private mailbox addresses, off geocodes them without
+    // looking for them
+    fn set_po_box(&mut self, policy: Option<&str>) -> Result<(), Box<dyn Error>> {
+        self.fetch_options.po_box = match policy.map(|e| e.to_lowercase()).as_deref() {
+            None => return Err("skip, city, geocode or off required")?,
+            Some("off") => None,
+            Some(policy) => Some(PoBoxPolicy::parse(policy).ok_or("Invalid PO Box policy, expected skip, city, geocode or off")?),
+        };
+
+        Ok(())
+    }
+
+    // Coarsen the coordinates fetch and the exports write, e.g. `snap 1 km` or `random 500 m 8817`
+    fn set_jitter(&mut self, input: &[&str]) -> Result<(), Box<dyn Error>> {
+        self.fetch_options.jitter = Jitter::parse(input)?;
+        Ok(())
+    }
+
+    fn match_output_vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("radius", self.drive_time.map_or(self.radius, |e| e).to_string()),
+            ("method", format!("{:?}", self.match_mode).to_lowercase()),
+        ];
+        vars.extend(time_vars());
+        vars
+    }
+
+    // Geocode every file. `fetch inplace` adds the columns to the source files, keeping a
+    // .bak copy of each, rather than writing new files
+    #[instrument(name = "fetch", skip_all, fields(files = self.data_frames.len()))]
+    pub async fn fetch(&mut self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let mut in_place = false;
+        let mut resume = false;
+        for mode in input.iter().skip(1) {
+            match mode.to_lowercase().as_str() {
+                "inplace" => in_place = true,
+                "resume" => resume = true,
+                _ => return Err("Invalid fetch mode, expected inplace or resume")?
+            }
+        }
+        let _running = interrupt::running();
+
+        // The three words are of the exact point, so they'd give away what the jitter hides
+        if self.fetch_options.jitter.is_some() && self.fetch_options.w3w {
+            return Err("w3w gives away the exact coordinates the jitter hides, turn one of them off")?;
+        }
+        if self.fetch_options.w3w && self.data_frames.iter().any(|e| e.provider().batched()) {
+            return Err("w3w is looked up one row at a time, which census-batch files aren't")?;
+        }
+        for df in self.data_frames.iter() {
+            self.keys.get(df.provider())?;
+            df.check_norm_address(&self.fetch_options)?;
+        }
+
+        // Hash inputs before they can be changed in place
+        let started = timestamp();
+        let inputs = if self.sidecar {self.input_metadata()?} else {Vec::new()};
+        let config = self.config_metadata();
+
+        // Take rows from each file in turn so every file shares the one rate limit and
+        // finishes at about the same time
+        let addresses: Vec<Vec<Option<String>>> = self.data_frames.iter().map(|e| e.addresses(&self.fetch_options)).collect();
+        if self.fetch_options.status() {
+            for (file, df) in self.data_frames.iter().enumerate() {
+                let statuses = df.address_statuses(&self.fetch_options);
+                let count = |status: &str| statuses.iter().filter(|e| **e == status).count();
+                println!("File {}: {} rows geocoded by address, {} by intersection, {} by city, {} by zip, {} PO Boxes and {} skipped.",
+                    file, count("address"), count("intersection"), count("city"), count("zip"), count("pobox"), count("skipped"));
+            }
+        }
+        if self.load_options.strict {
+            for (file, file_addresses) in addresses.iter().enumerate() {
+                let missing: Vec<String> = file_addresses.iter()
+                    .enumerate()
+                    .filter(|e| e.1.is_none())
+                    .map(|e| e.0.to_string())
+                    .collect();
+                if !missing.is_empty() {
+                    return Err(format!("{} rows in file {} have no address to geocode, rows: {}", missing.len(), file, missing.join(", ")))?;
+                }
+            }
+        }
+        // Start every file's output before fetching, rows are written as their results come in.
+        // Resumed files start with the rows their checkpoint has
+        let mut profile = Profile::default();
+        let start = Instant::now();
+        let mut writers = Vec::with_capacity(self.data_frames.len());
+        let outputs = self.data_frames.iter()
+            .enumerate()
+            .map(|(index, df)| match in_place {
+                true => Ok(df.path().to_string()),
+                false => render_output_name(&self.fetch_output, &fetch_output_vars(&df.stem(), index))
+                    .and_then(|e| self.output_path(&e))
+                    .map(|e| self.compressed_name(e)),
+            })
+            .collect::<Result<Vec<String>, Box<dyn Error>>>()?;
+
+        // Replacing the input is what inplace asks for, and it's backed up first
+        if !in_place {
+            self.confirm_overwrite(&outputs)?;
+        }
+        for (df, output) in self.data_frames.iter().zip(outputs) {
+            if in_place {
+                let backup = format!("{}.bak", df.path());
+                copy(df.path(), &backup)?;
+                println!("Backed up {} to {}.", df.path(), backup);
+            }
+            let writer = if resume {
+                df.resume_writer(&output, self.precision, &self.fetch_options)?
+            } else {
+                df.fetch_writer(&output, self.precision, &self.fetch_options)?
+            };
+            writers.push((output.clone(), writer));
+        }
+
+        let longest = addresses.iter().map(|e| e.len()).max().unwrap_or(0);
+        let mut order = Vec::with_capacity(addresses.iter().map(|e| e.len()).sum());
+        for row in 0..longest {
+            for (file, file_addresses) in addresses.iter().enumerate() {
+                if row < file_addresses.len() && row >= writers[file].1.rows() {
+                    order.push((file, row));
+                }
+            }
+        }
+
+        println!("Fetching {} coords for {} files:", order.len(), self.data_frames.len());
+        // Files sent to the Census batch geocoder go up many rows at a time, alongside the rows
+        // geocoded one by one
+        let (batched, single): (Vec<usize>, Vec<usize>) = (0..order.len())
+            .partition(|e| self.data_frames[order[*e].0].provider().batched());
+        let queries = single.iter()
+            .map(|e| order[*e])
+            .map(|(file, row)| (self.data_frames[file].provider(), addresses[file][row].clone()))
+            .collect();
+        let uploads = batched.iter()
+            .map(|e| order[*e])
+            .map(|(file, row)| self.data_frames[file].batch_fields(row, &self.fetch_options))
+            .collect();
+        let words_key = if self.fetch_options.w3w {self.w3w_key.clone()} else {None};
+        let mut results = merge_streams(vec![
+            (geocode_stream(queries, self.keys.clone(), words_key, self.client.clone()), single),
+            (batch_stream(uploads, self.client.clone()), batched),
+        ]);
+        let bar = ProgressBar::new(order.len() as u64);
+        let opening = start.elapsed();
+
+        // Country each row was geocoded to, for flagging rows outside the country scope
+        let mut countries: Vec<Vec<String>> = self.data_frames.iter().map(|e| vec![String::new(); e.shape.1]).collect();
+        let mut outside = 0;
+        let mut upload_failed = 0;
+        let mut words_failed = 0;
+
+        // Writing happens while waiting on the geocoder, so it's taken back out of the geocoding time
+        let start = Instant::now();
+        let mut writing = Duration::ZERO;
+        let mut checkpointed = Instant::now();
+        while let Some((index, result)) = results.recv().await {
+            let (file, row) = order[index];
+            if result.as_ref().is_ok_and(|e| e.quality == BATCH_FAILED) {
+                upload_failed += 1;
+            }
+            if result.as_ref().is_ok_and(|e| e.words == W3W_FAILED) {
+                words_failed += 1;
+            }
+
+            // Coordinates in another country are garbage when rows are limited to some countries
+            let result = result.map(|result| {
+                countries[file][row] = result.country.clone();
+                match &self.country_scope {
+                    Some(scope) if !result.country.is_empty() && !scope.contains(&result.country) => {
+                        outside += 1;
+                        if scope.flag {result} else {Geocode::not_found()}
+                    }
+                    _ => result
+                }
+            });
+
+            // Strict fetches stop at the first row that didn't get coordinates
+            let result = result.and_then(|result| match result.found() {
+                false if self.load_options.strict => Err(format!("no coordinates for row {} in file {}", row, file)),
+                _ => Ok(result)
+            });
+
+            let write_start = Instant::now();
+            let mut written = result.map_err(|e| e.into())
+                .and_then(|result| self.data_frames[file].write_fetched(&mut writers[file].1, row, result));
+            if written.is_ok() && checkpointed.elapsed() >= CHECKPOINT_INTERVAL {
+                written = writers.iter_mut().try_for_each(|e| e.1.checkpoint());
+                checkpointed = Instant::now();
+            }
+            writing += write_start.elapsed();
+
+            // Keep whatever was written so far, results still in flight are dropped
+            if let Err(e) = written {
+                bar.abandon();
+                for (_, writer) in writers.iter_mut() {
+                    println!("{}", writer.stop()?);
+                }
+                return Err(format!("Fetch stopped: {}. Run `fetch{} resume` to carry on from there", e, if in_place {" inplace"} else {""}))?;
+            }
+            bar.inc(1);
+        }
+
+        // Ctrl-C stops new requests, the ones already made are written above before stopping
+        if interrupt::requested() {
+            bar.abandon();
+            for (_, writer) in writers.iter_mut() {
+                println!("{}", writer.stop()?);
+            }
+            return Err(format!("Fetch interrupted. Run `fetch{} resume` to carry on from there", if in_place {" inplace"} else {""}))?;
+        }
+        bar.finish();
+        if upload_failed > 0 {
+            println!("{} rows couldn't be uploaded to the Census batch geocoder and were left without coordinates, \
+                their match_quality is {}", upload_failed, BATCH_FAILED);
+        }
+        if words_failed > 0 {
+            println!("{} rows were found but their what3words lookup failed, their w3w is {}", words_failed, W3W_FAILED);
+        }
+        if let Some(scope) = &self.country_scope {
+            let action = if scope.flag {"flagged"} else {"left without coordinates"};
+            println!("{} rows geocoded outside {}, {}", outside, scope.codes.join(", "), action);
+        }
+        let mut providers: Vec<&str> = self.data_frames.iter().map(|e| e.provider().name()).collect();
+        providers.sort();
+        providers.dedup();
+        profile.add(&format!("geocode ({})", providers.join(", ")), start.elapsed() - writing);
+        profile.add("write", opening + writing);
+
+        let start = Instant::now();
+        for (index, (df, (output, writer))) in self.data_frames.iter_mut().zip(writers).enumerate() {
+            df.finish_fetch(writer)?;
+
+            let command = if in_place {"fetch inplace"} else {"fetch"};
+            let geocoded = df.lat().unwrap().iter().filter(|e| !e.is_nan()).count();
+            let summary = json!({
+                "rows": df.shape.1,
+                "geocoded": geocoded,
+                "failed": df.shape.1 - geocoded,
+            });
+
+            if self.sidecar {
+                let path = write_sidecar(&output, &json!({
+                    "command": command,
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "started": started,
+                    "finished": timestamp(),
+                    "provider": df.provider().name(),
+                    "outputs": [output],
+                    "inputs": [inputs[index]],
+                    "config": config,
+                    "summary": summary,
+                }))?;
+                println!("Wrote metadata to {}.", path);
+            }
+            info!(file = index, output = output.as_str(), rows = df.shape.1, geocoded, "file fetched");
+            self.runs.push(json!({"command": command, "outputs": [output], "summary": summary}));
+        }
+        profile.since("write", start);
+        self.add_profile(profile);
+
+        if self.country_scope.as_ref().is_some_and(|e| e.flag) {
+            for (index, countries) in countries.iter().enumerate() {
+                self.apply_country_scope(index, Some(countries));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Geocode one address with the provider, keys and client this session uses
+    pub async fn lookup(&self, addr: &str) -> Result<Geocode, Box<dyn Error>> {
+        fetch_single(&self.client, self.provider, addr, &self.keys).await
+    }
+
+    // Geocode one address and print what the provider returns, for spot checks
+    pub async fn geocode(&self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        if input.len() < 2 {
+            return Err("address required")?;
+        }
+        let addr = input[1..].join(" ");
+
+        let result = self.lookup(&addr).await?;
+        if !result.found() {
+            return Err(format!("No results for '{}'", addr))?;
+        }
+
+        println!("lat: {}", result.lat);
+        println!("lng: {}", result.lng);
+        println!("norm_address: {}", result.address);
+        println!("country: {}", result.country);
+        if let Some(column) = self.provider.quality_column() {
+            println!("{}: {}", column, result.quality);
+        }
+
+        Ok(())
+    }
+
+    #[instrument(name = "match", skip_all, fields(files = self.data_frames.len()))]
+    pub async fn find_matches(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.drive_time.is_some() && self.routing.is_none() {
+            return Err("A drive time radius needs route set")?;
+        }
+
+        if self.match_mode == MatchMode::CROSS {
+            if self.data_frames.len() != 2 {
+                return Err(format!("A cross join needs exactly 2 files, not {}", self.data_frames.len()))?;
+            }
+            if self.routing.is_some() {
+                return Err("A cross join doesn't route, use `route off`")?;
+            }
+            let rows = self.data_frames[0].shape.1 * self.data_frames[1].shape.1;
+            if rows > MAX_CROSS_ROWS {
+                return Err(format!("A cross join of these files is {} rows, more than {}. Use matrix with a max distance instead", rows, MAX_CROSS_ROWS))?;
+            }
+        }
+
+        let _running = interrupt::running();
+        self.fit_memory_budget()?;
+
+        // Rows without coordinates never match anything, so strict matches won't run with any
+        let policy = if self.load_options.strict {MissingCoords::Abort} else {self.missing_coords};
+
+        // Coordinates off the globe would give nonsense distances, so those rows count as missing
+        for (index, df) in self.data_frames.iter_mut().enumerate() {
+            let outside = df.clear_out_of_range();
+            if outside > 0 {
+                println!("{} rows in file {} have a lat past ±90 or lng past ±180, treating them as missing.", outside, index);
+            }
+        }
+        let mut missing = self.missing_rows();
+        let total: usize = missing.iter().map(|e| e.len()).sum();
+        for (index, rows) in missing.iter().enumerate().filter(|e| !e.1.is_empty()) {
+            println!("{} rows in file {} have no coordinates.", rows.len(), index);
+        }
+        if total > 0 {
+            match policy {
+                MissingCoords::Abort => return Err(format!("{} rows have no coordinates, not matching", total))?,
+                MissingCoords::Geocode => {
+                    self.geocode_missing(&mut missing).await?;
+                    let left: usize = missing.iter().map(|e| e.len()).sum();
+                    println!("Geocoded {} of {} rows without coordinates, {} left out of matching.", total - left, total, left);
+                }
+                _ => println!("Leaving {} rows without coordinates out of matching.", total),
+            }
+        }
+        let missing_count: usize = missing.iter().map(|e| e.len()).sum();
+
+        let started = timestamp();
+        let inputs = if self.sidecar {self.input_metadata()?} else {Vec::new()};
+
+        let (width, height) = {
+            let mut width = 0;
+            let mut height = 0;
+
+            for (index, df) in self.data_frames.iter().enumerate() {
+                width += df.output_headers().len();
+
+                if index != 0 {
+                    width += self.match_columns();
+                }
+
+                height += df.shape.1;
+            }
+
+            // A cross join writes a row for every pair
+            if self.match_mode == MatchMode::CROSS {
+                height = self.data_frames[0].shape.1 * self.data_frames[1].shape.1;
+            }
+
+            (width, height)
+        };
+
+        // if width is 0 no output columns were supplied
+        if width == 0 {
+            return Err("No output columns supplied")?;
+        }
+        self.check_compare_kinds()?;
+
+        // The bar would draw over the questions when reviewing
+        let bar = if self.review.is_some_and(|e| e.policy == Policy::Ask) {ProgressBar::hidden()} else {ProgressBar::new(height as u64)};
+
+        // create output dataframe, technically overprovisioned for the height
+        let mut output = DataFrame::with_capacity(width, height);
+
+        // Keep track of which columns inside output contain a match
+        let mut match_mask: Vec<bool> = vec![false; height];
+
         // Set the headers
         let mut headers = Vec::with_capacity(width);
+        let mut sources = Vec::with_capacity(width);
         for (index, df) in self.data_frames.iter().enumerate() {
             for header in df.output_headers() {
-                headers.push(header.clone());
+                headers.push(header.clone());
+                sources.push(index);
+            }
+            if index != 0 {
+                headers.push(format!("{}_dist", df.prefix,));
+                sources.push(index);
+                if self.bearing {
+                    headers.push(format!("{}_bearing", df.prefix));
+                    sources.push(index);
+                }
+                if self.routing.is_some() {
+                    headers.push(format!("{}_road_dist", df.prefix));
+                    headers.push(format!("{}_road_time", df.prefix));
+                    sources.push(index);
+                    sources.push(index);
+                }
+            }
+        }
+
+        disambiguate_headers(&mut headers, &sources);
+        if let Some(col) = &self.split_by {
+            if !headers.contains(col) {
+                return Err(format!("Can't split by '{}', it isn't an output column", col))?;
+            }
+        }
+        output.set_headers(headers);
+
+        // Make sure every column is an output column
+        for i in 0..width {
+            output.output_cols.push(i);
+        }
+
+        // Spilled output columns stay on disk, the output only keeps which row each value is from
+        if self.match_mode != MatchMode::CROSS {
+            let mut offset = 0;
+            for (index, df) in self.data_frames.iter().enumerate() {
+                for (col, source) in df.coarse_output_columns(self.fetch_options.jitter, self.precision).into_iter().enumerate() {
+                    if source.is_spilled() {
+                        output.data_mut()[offset+col] = source.share();
+                    }
+                }
+                offset += df.output_headers().len() + if index != 0 {self.match_columns()} else {0};
+            }
+        }
+
+        // We start by assuming that each file is internally consistent, meaning
+        // that if a location is duplicated inside it that is by design as they
+        // represent two separate entities.
+        // Now we begin the matching process. For each of the remaining dataframes,
+        // we need to find the nearest match, add it to its respective rows, and then
+        // average the latitude and longitude. If no match is found, we create a new row
+        // for the entry. On the first run no matches will be found so the dataframe will be
+        // essentially copied into the output
+        let mut col_index = 0;
+        let mut pairs = Vec::new();
+        let mut origins = Vec::with_capacity(height);
+        let mut first_rows: Vec<(usize, usize)> = Vec::with_capacity(height);
+        let client = Client::new();
+
+        // Routing and index building happen inside the match loop, so they're taken back out of it
+        let mut profile = Profile::default();
+        let start = Instant::now();
+        let mut indexing = Duration::ZERO;
+        let mut routing = Duration::ZERO;
+        let mut stopped = None;
+        let mut reviewing = self.review;
+        let mut reviewed = 0;
+        let mut rejected = 0;
+
+        // Answers from reviewing earlier runs are applied whenever review is on, and with the
+        // accept policy they're applied without asking. Numbered row ids are only positions, so an
+        // answer saved by one would land on whatever row is there next time
+        let decisions_path = self.output_path(review::DECISIONS_FILE)?;
+        let mut decisions = match self.review {
+            Some(review) if review.policy == Policy::Ask && !self.interactive => {
+                return Err("Nobody is here to answer review questions, add a policy for borderline matches: review [near] [similarity] accept or reject")?;
+            }
+            Some(_) if self.row_ids == Some(RowId::Number) && self.data_frames.iter().any(|e| e.id().is_none()) => {
+                return Err("Review answers are saved by row id and --row-id number ids are only positions. Set an id column with `set [index] id [col]` or use --row-id hash")?;
+            }
+            Some(_) => review::read_decisions(&decisions_path)?,
+            None => HashMap::new()
+        };
+        let mut replayed = 0;
+        let mut answered = false;
+
+        // A cross join pairs up every row itself instead of matching them file by file
+        let files = match self.match_mode {
+            MatchMode::CROSS => {
+                stopped = self.cross_join(&mut output, &mut match_mask, &mut pairs, &mut origins, &bar);
+                0
+            }
+            _ => self.data_frames.len()
+        };
+
+        'files: for df_index in 0..files {
+            // Clone dataframe so we can subtract from it as we match
+            let df = &self.data_frames[df_index];
+            let file_start = Instant::now();
+            let mut written_mask = vec![false; df.shape.1];
+            let cols = df.output_headers().len();
+            let sources = df.coarse_output_columns(self.fetch_options.jitter, self.precision);
+            let span = cols + if df_index != 0 {self.match_columns()} else {0};
+            let index_start = Instant::now();
+            let points = self.routing.as_ref().map(|_| Points::new(df.lat().unwrap(), df.lng().unwrap()));
+            indexing += index_start.elapsed();
+
+            // This part is a little bizarre, we are going to iterate throught the existing entries
+            // in the output dataframe. This keeps us from overwriting our matches and allows for a
+            // more uniform process for each dataframe
+            for row in 0..output.data()[0].len() {
+                // Ctrl-C keeps the rows matched so far
+                if interrupt::requested() {
+                    stopped = Some(df_index);
+                    break 'files;
+                }
+
+                let mut result = self.find_single_match(row, &output, df, &written_mask, first_rows[row])
+                    .map(|(index, dist)| (index, dist, None));
+
+                // Exact matches are left alone, anything else is re-scored by road distance
+                if let Some(router) = &self.routing {
+                    result = match result {
+                        Some((index, dist, _)) if dist == 0.0 => Some((index, dist, Some((0.0, 0.0)))),
+                        Some(_) => {
+                            let route_start = Instant::now();
+                            let from = (output.lat().unwrap()[row], output.lng().unwrap()[row]);
+                            let routed = self.find_routed_match(router, &client, (row, from.0, from.1), df, points.as_ref().unwrap(), &written_mask).await?;
+                            routing += route_start.elapsed();
+                            routed
+                        }
+                        None => None
+                    };
+                }
+
+                // Matches answered in an earlier review go the same way again, and other borderline
+                // matches go by the policy, only kept if the user says so when asking. Anything
+                // turned down is left unmatched
+                if let Some((index, dist, _)) = result.filter(|_| self.review.is_some()) {
+                    let (file, first) = first_rows[row];
+                    let first_df = &self.data_frames[file];
+                    let pair = (first_df.path().to_string(), first_df.stable_id(first), df.path().to_string(), df.stable_id(index));
+                    let mut keep = decisions.get(&pair).copied();
+
+                    if keep.is_some() {
+                        replayed += 1;
+                    } else if let Some(review) = reviewing.filter(|e| e.policy != Policy::Accept) {
+                        let similarity = self.compare_similarity(first_rows[row], df, index);
+                        let reason = review.reason(dist, self.radius, similarity);
+                        if reason.is_some() && review.policy == Policy::Reject {
+                            keep = Some(false);
+                        } else if let Some(reason) = reason {
+                            let (left, right) = self.review_records(first_rows[row], df_index, index);
+                            reviewed += 1;
+                            match review::ask(&reason, &left, &right)? {
+                                Decision::Accept => keep = Some(true),
+                                Decision::Reject => keep = Some(false),
+                                Decision::AcceptRest => reviewing = None,
+                            }
+                            if let Some(keep) = keep {
+                                decisions.insert(pair, keep);
+                                answered = true;
+                            }
+                        }
+                    }
+
+                    if keep == Some(false) {
+                        result = None;
+                        rejected += 1;
+                    }
+                }
+
+                if let Some((index, dist, road)) = result {
+                    // Add to output
+                    for (col, source) in sources.iter().enumerate() {
+                        output.data_mut()[col_index+col].set_from(row, source, index);
+                    }
+
+                    // Add distance to output
+                    if df_index != 0 {
+                        output.data_mut()[col_index+cols].set(row, format_coord(dist, self.precision));
+                    }
+
+                    // And bearing, from the entry to its match
+                    let mut col = col_index + cols + 1;
+                    if df_index != 0 && self.bearing {
+                        let bearing = bearing(
+                            output.lat().unwrap()[row], output.lng().unwrap()[row],
+                            df.lat().unwrap()[index], df.lng().unwrap()[index]
+                        );
+                        output.data_mut()[col].set(row, format_coord(bearing, self.precision));
+                        col += 1;
+                    }
+
+                    // And driving distance and time
+                    if let Some((road_dist, road_time)) = road {
+                        output.data_mut()[col].set(row, format_coord(road_dist, self.precision));
+                        output.data_mut()[col+1].set(row, format_coord(road_time, self.precision));
+                    }
+
+                    pairs.push(MatchPair {
+                        row,
+                        file: df_index,
+                        from: (output.lat().unwrap()[row], output.lng().unwrap()[row]),
+                        to: (df.lat().unwrap()[index], df.lng().unwrap()[index]),
+                        dist,
+                    });
+
+                    // Average coordinates
+                    let lat = (output.lat().unwrap()[row] + df.lat().unwrap()[index]) * 0.5;
+                    let lng = (output.lng().unwrap()[row] + df.lng().unwrap()[index]) * 0.5;
+
+                    output.lat_mut().unwrap()[row] = lat;
+                    output.lng_mut().unwrap()[row] = lng;
+
+                    // Set mask to not include for writing at the end
+                    written_mask[index] = true;
+
+                    // Set match_mask
+                    match_mask[row] = true;
+
+                    bar.inc(1);
+                }
+            }
+
+            // Now that we've fitered out all the matches, we can just append all the rest of the
+            // rows. On a left join we only do this if the dataframe index is 0
+            if self.match_mode!=MatchMode::LEFT || df_index==0 {
+                for (row, written) in written_mask.iter().enumerate() {
+                    if !self.exclusive || !written {
+                        // Fill previous slots with blanks
+                        for col in 0..col_index {
+                            output.data_mut()[col].push("".to_string());
+                        }
+
+                        // Fill in the actual data
+                        for (col, source) in sources.iter().enumerate() {
+                            output.data_mut()[col+col_index].push_from(source, row);
+                        }
+
+                        // Add distance, bearing and road columns
+                        for col in col_index+cols..col_index+span {
+                            output.data_mut()[col].push(f32::NAN.to_string());
+                        }
+
+                        output.lat_mut().unwrap().push(df.lat().unwrap()[row]);
+                        output.lng_mut().unwrap().push(df.lng().unwrap()[row]);
+                        origins.push((df_index, df.lat().unwrap()[row], df.lng().unwrap()[row]));
+                        first_rows.push((df_index, row));
+
+                        // Fill rest of slots with blanks
+                        for col in col_index+span..width {
+                            output.data_mut()[col].push("".to_string());
+                        }
+
+                        bar.inc(1);
+                    }
+                }
+            } else {
+                bar.inc(written_mask.iter().filter(|e| !*e).count() as u64)
+            }
+
+            let matched = written_mask.iter().filter(|e| **e).count();
+            info!(file = df_index, rows = df.shape.1, matched, seconds = file_start.elapsed().as_secs_f64(), "file matched");
+            col_index += span;
+        }
+
+        if stopped.is_some() {
+            bar.abandon();
+        } else {
+            bar.finish();
+        }
+        if reviewed > 0 || replayed > 0 {
+            println!("Reviewed {} borderline matches and applied {} earlier answers, turning down {}.", reviewed, replayed, rejected);
+        }
+        if answered {
+            review::write_decisions(&decisions_path, &decisions)?;
+            println!("Saved review answers to {}.", decisions_path);
+        }
+
+        if self.routing.is_some() {
+            profile.add("index", indexing);
+        }
+        profile.add("match", start.elapsed() - indexing - routing);
+        if let Some(router) = &self.routing {
+            profile.add(&format!("route ({})", router.name()), routing);
+        }
+
+        // Every row is in, so the output is sealed into Arrow arrays like a loaded file
+        for col in output.data_mut().iter_mut() {
+            col.seal();
+        }
+
+        // At this point we theoretically have a complete dataset, lets write it to the filesystem
+        // and be done
+
+        let start = Instant::now();
+        let path = self.output_path(&render_output_name(&self.match_output, &self.match_output_vars())?)?;
+        let mut path = self.compressed_name(path);
+        let compression = Compression::from_path(&path);
+        if stopped.is_some() {
+            path = atomic::partial_path(&path);
+        }
+        let headers = output.output_headers();
+        let split_col = self.split_by.as_ref().and_then(|col| headers.iter().position(|e| e == col));
+
+        // If match_mode is left, we only have items from the leftmost table already so no checks are
+        // required. If inner, we can use our match_mask to make sure only columns with existing matches exist
+        // Outer we just write everything as is
+        let rows: Vec<usize> = (0..output.data()[0].len())
+            .filter(|e| self.match_mode!=MatchMode::INNER || match_mask[*e])
+            .collect();
+
+        // Every row goes to the same file unless splitting by a column. Rows are grouped by file
+        // first so only one is open at a time, however many values the column has
+        let mut files: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        match split_col {
+            Some(col) => {
+                let values: HashSet<String> = rows.iter().map(|e| output.output_row(*e)[col].clone()).collect();
+                let names = split_output_names(&path, values);
+                for row in rows {
+                    files.entry(names[&output.output_row(row)[col]].clone()).or_default().push(row);
+                }
+            }
+            // Still write the headers when there are no rows
+            None => {
+                files.insert(path.clone(), rows);
+            }
+        }
+
+        // Rows matched before an interrupt go to their own file, which can always be replaced
+        if stopped.is_none() {
+            let mut targets: Vec<String> = files.keys().cloned().collect();
+            if policy == MissingCoords::Unmatched && missing_count > 0 {
+                targets.push(no_coords_output_name(&path));
+            }
+            targets.sort();
+            self.confirm_overwrite(&targets)?;
+        }
+
+        if split_col.is_none() {
+            println!("Writing output to {}.", path);
+        }
+
+        // Each file only shows up once it is complete
+        let mut outputs = Vec::with_capacity(files.len());
+        for (file, rows) in files {
+            let mut writer = WriterBuilder::new()
+                .delimiter(b'|')
+                .from_writer(atomic::create_output(&file, compression)?);
+            writer.write_record(headers.as_slice())?;
+            for row in rows {
+                writer.write_record(output.output_row(row).as_slice())?;
+            }
+            atomic::finish_output(writer.into_inner().map_err(|e| e.to_string())?, &file)?;
+            outputs.push(file);
+        }
+
+        if split_col.is_some() {
+            println!("Wrote {} files split by {}.", outputs.len(), self.split_by.as_ref().unwrap());
+        }
+
+        // Rows left out for having no coordinates, to follow up on by hand
+        if stopped.is_none() && policy == MissingCoords::Unmatched && missing_count > 0 {
+            let report = no_coords_output_name(&path);
+            self.write_no_coords(&report, &missing, compression)?;
+            println!("Wrote {} rows without coordinates to {}.", missing_count, report);
+            outputs.push(report);
+        }
+        outputs.sort();
+
+        // What was matched before Ctrl-C is kept apart from the real output, since files after
+        // the one it stopped in weren't matched at all
+        if let Some(file) = stopped {
+            return Err(format!("Match interrupted in file {}, rows matched so far are in {}. Run match again for the full output", file, path))?;
+        }
+
+        let written = (0..output.data()[0].len()).filter(|e| self.match_mode!=MatchMode::INNER || match_mask[*e]).count();
+        let dists: Vec<f64> = pairs.iter().map(|e| e.dist).collect();
+        let matched = match_mask.iter().filter(|e| **e).count();
+        count(&ROWS_MATCHED, matched);
+        count(&MATCH_RUNS, 1);
+        let summary = json!({
+            "rows": written,
+            "matched": matched,
+            "pairs": pairs.len(),
+            "no_coords": missing_count,
+            "mean_dist": if dists.is_empty() {None} else {Some(dists.iter().sum::<f64>() / dists.len() as f64)},
+            "max_dist": dists.iter().cloned().fold(None, |max: Option<f64>, e| Some(max.map_or(e, |m| m.max(e)))),
+        });
+
+        if self.sidecar {
+            let path = write_sidecar(&path, &json!({
+                "command": "match",
+                "version": env!("CARGO_PKG_VERSION"),
+                "started": started,
+                "finished": timestamp(),
+                "provider": self.routing.as_ref().map(|e| e.to_string()),
+                "outputs": outputs,
+                "inputs": inputs,
+                "config": self.config_metadata(),
+                "summary": summary,
+            }))?;
+            println!("Wrote metadata to {}.", path);
+        }
+        let run = json!({"command": "match", "outputs": outputs, "summary": summary});
+        self.runs.push(run);
+        profile.since("write", start);
+        self.add_profile(profile);
+
+        self.last_match = Some(MatchRun {
+            output,
+            match_mask,
+            pairs,
+            origins,
+            radius: self.radius,
+            inner: self.match_mode==MatchMode::INNER,
+        });
+
+        Ok(())
+    }
+
+    // List every row of every file within the radius of a point, closest first
+    pub fn near(&self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        if input.len() < 3 {
+            return Err("lat and lng required")?;
+        }
+
+        let lat = parse_coord(input[1]);
+        let lng = parse_coord(input[2]);
+        if lat.is_none() || lng.is_none() || !in_range(lat.unwrap(), lng.unwrap()) {
+            return Err("Invalid coordinates")?;
+        }
+        let (lat, lng) = (lat.unwrap(), lng.unwrap());
+
+        let radius = match input.get(3) {
+            Some(val) => val.parse::<f64>()?,
+            None => self.radius
+        };
+
+        let mut found = Vec::new();
+        let mut hits = Vec::new();
+        for (file_index, df) in self.data_frames.iter().enumerate() {
+            if !df.ready_to_match() {
+                continue;
+            }
+
+            Points::new(df.lat().unwrap(), df.lng().unwrap()).within(lat, lng, radius, &mut hits);
+            found.extend(hits.iter().map(|(row, dist)| (*dist, file_index, *row)));
+        }
+        found.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut lines = Vec::with_capacity(found.len());
+        for (dist, file_index, row) in found.iter() {
+            let df = &self.data_frames[*file_index];
+
+            // Show output columns if any are set, otherwise the whole row
+            let values = if df.output_cols.is_empty() {
+                df.data().iter().map(|col| col[*row].to_string()).collect()
+            } else {
+                df.output_row(*row)
+            };
+
+            lines.push(format!("{}:{}\t{:.4} mi\t{}", file_index, row, dist, values.join(", ")));
+        }
+        page(&lines);
+        println!("{} rows within {} miles", found.len(), radius);
+
+        Ok(())
+    }
+
+    // Report the addresses in a file most likely to geocode badly, before paying to fetch them
+    pub fn lint(&self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
+        }
+        let file_index = file_index.unwrap().parse::<usize>()?;
+
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let df = &self.data_frames[file_index];
+        let addr1 = match df.addr1() {
+            Some(col) => col,
+            None => return Err(format!("addr1 not set for file {}", file_index))?
+        };
+        let addr2 = df.addr2();
+
+        let linter = Linter::new();
+        let mut found: Vec<Vec<usize>> = vec![Vec::new(); Issue::ALL.len()];
+        let mut flagged = 0;
+        for row in 0..df.shape.1 {
+            let issues = linter.check(&addr1[row], addr2.map_or("", |e| &e[row]));
+            if !issues.is_empty() {
+                flagged += 1;
+            }
+            for issue in issues {
+                found[Issue::ALL.iter().position(|e| *e == issue).unwrap()].push(row);
+            }
+        }
+
+        for (issue, rows) in Issue::ALL.iter().zip(found.iter()) {
+            if rows.is_empty() {
+                continue;
+            }
+
+            println!("{}\t{}", rows.len(), issue);
+            for row in rows.iter().take(LINT_EXAMPLES) {
+                let line2 = addr2.map_or("", |e| &e[*row]);
+                let address = if line2.is_empty() {addr1[*row].to_string()} else {format!("{}, {}", &addr1[*row], line2)};
+                println!("\t{}:{}\t{}", file_index, row, address);
+            }
+            if rows.len() > LINT_EXAMPLES {
+                println!("\t...");
+            }
+        }
+        println!("{} of {} rows have address issues", flagged, df.shape.1);
+
+        Ok(())
+    }
+
+    // Print the spread of distances from each row of one file to the nearest row of another, for
+    // picking a radius before matching. A file compared with itself leaves each row's own point out
+    pub fn nn_stats(&self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        if input.len() < 3 {
+            return Err("from and to required")?;
+        }
+
+        let from = input[1].parse::<usize>()?;
+        let to = input[2].parse::<usize>()?;
+        if from >= self.file_count || to >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let df1 = &self.data_frames[from];
+        let df2 = &self.data_frames[to];
+        if !df1.ready_to_match() || !df2.ready_to_match() {
+            return Err("Both files need lat and lng set")?;
+        }
+
+        let points = Points::new(df2.lat().unwrap(), df2.lng().unwrap());
+        let mut chords = Vec::with_capacity(df2.shape.1);
+        let mut nearest = Vec::with_capacity(df1.shape.1);
+        let bar = ProgressBar::new(df1.shape.1 as u64);
+        for row in 0..df1.shape.1 {
+            let lat = df1.lat().unwrap()[row];
+            let lng = df1.lng().unwrap()[row];
+            bar.inc(1);
+
+            if lat.is_nan() || lng.is_nan() {
+                continue;
+            }
+            let skip = if from == to {Some(row)} else {None};
+            if let Some((_, dist)) = points.nearest(lat, lng, skip, &mut chords) {
+                nearest.push(dist);
+            }
+        }
+        bar.finish();
+
+        if nearest.is_empty() {
+            return Err(format!("No rows of file {} have a neighbor in file {}", from, to))?;
+        }
+        nearest.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        println!("Nearest neighbor distances from file {} to file {}, {} of {} rows:", from, to, nearest.len(), df1.shape.1);
+        println!("\tmin:\t\t{:.4} mi", nearest[0]);
+        println!("\tmedian:\t\t{:.4} mi", percentile(&nearest, 0.5));
+        println!("\tp90:\t\t{:.4} mi", percentile(&nearest, 0.9));
+        println!("\tmax:\t\t{:.4} mi", nearest[nearest.len()-1]);
+        if self.drive_time.is_none() {
+            let within = nearest.iter().filter(|e| **e <= self.radius).count();
+            println!("\twithin radius:\t{} ({:.1}%) at {} mi", within, 100.0 * within as f64 / nearest.len() as f64, self.radius);
+        }
+
+        Ok(())
+    }
+
+    // Write the distance between every pair of rows in two files, or only pairs within
+    // max_dist miles, as one row per pair
+    pub fn matrix(&self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        if input.len() < 4 {
+            return Err("from, to and path required")?;
+        }
+
+        let from = input[1].parse::<usize>()?;
+        let to = input[2].parse::<usize>()?;
+        let path = &self.compressed_name(self.output_path(input[3])?);
+        let compression = Compression::from_path(path);
+        if from >= self.file_count || to >= self.file_count {
+            return Err("Index out of Bounds")?;
+        }
+
+        let max_dist = match input.get(4) {
+            Some(val) => Some(val.parse::<f64>()?),
+            None => None
+        };
+
+        let df1 = &self.data_frames[from];
+        let df2 = &self.data_frames[to];
+        if !df1.ready_to_match() || !df2.ready_to_match() {
+            return Err("Both files need lat and lng set")?;
+        }
+
+        let mut headers = vec!["from_row".to_string(), "to_row".to_string()];
+        let mut sources = vec![from, to];
+        for (index, df) in [(from, df1), (to, df2)] {
+            for header in df.output_headers() {
+                headers.push(header);
+                sources.push(index);
             }
-            if index != 0 {
-                headers.push(format!("{}_dist", df.prefix,))
+        }
+        headers.push("dist".to_string());
+        sources.push(to);
+        disambiguate_headers(&mut headers, &sources);
+
+        let mut writer = WriterBuilder::new()
+            .delimiter(b'|')
+            .from_writer(atomic::create_output(path, compression)?);
+        writer.write_record(headers.as_slice())?;
+
+        let bar = ProgressBar::new(df1.shape.1 as u64);
+        let mut count = 0;
+        let points = Points::new(df2.lat().unwrap(), df2.lng().unwrap());
+        let mut dists = Vec::with_capacity(df2.shape.1);
+
+        for row1 in 0..df1.shape.1 {
+            let lat1 = df1.lat().unwrap()[row1];
+            let lng1 = df1.lng().unwrap()[row1];
+            bar.inc(1);
+
+            if lat1.is_nan() || lng1.is_nan() {
+                continue;
+            }
+            let output1 = df1.coarse_output_row(row1, self.fetch_options.jitter, self.precision);
+            points.distances(lat1, lng1, &mut dists);
+
+            for (row2, dist) in dists.iter().cloned().enumerate() {
+
+                // NaN coordinates fail both comparisons, so they are skipped either way
+                if dist.is_nan() || max_dist.is_some_and(|max| dist > max) {
+                    continue;
+                }
+
+                let mut record = Vec::with_capacity(headers.len());
+                record.push(row1.to_string());
+                record.push(row2.to_string());
+                record.extend(output1.iter().cloned());
+                record.extend(df2.coarse_output_row(row2, self.fetch_options.jitter, self.precision));
+                record.push(format_coord(dist, self.precision));
+
+                writer.write_record(record.as_slice())?;
+                count += 1;
+            }
+        }
+
+        atomic::finish_output(writer.into_inner().map_err(|e| e.to_string())?, path)?;
+        bar.finish();
+        println!("Wrote {} pairs to {}", count, path);
+
+        Ok(())
+    }
+
+    // Export results of the last match run
+    pub fn export(&self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let kind = input.get(1);
+        if kind.is_none() {
+            return Err("type required")?;
+        }
+
+        let path = input.get(2);
+        if path.is_none() {
+            return Err("path required")?;
+        }
+        // A GeoPackage is a database to open in place and Parquet compresses itself, the rest
+        // are compressed like any output
+        let mut path = self.output_path(path.unwrap())?;
+        if ["script", "map", "arrow"].contains(kind.unwrap()) {
+            path = self.compressed_name(path);
+        }
+        let path = &path;
+        let compression = Compression::from_path(path);
+
+        match *kind.unwrap() {
+            "script" => atomic::write_output(path, self.script_text(), compression)?,
+            "map" => {
+                if self.last_match.is_none() {
+                    return Err("Run match before exporting a map")?;
+                }
+                let leaflet = input.get(3).copied();
+                write_map(path, self.last_match.as_ref().unwrap(), self.fetch_options.jitter, leaflet, compression)?;
+            }
+            "arrow" | "parquet" => {
+                if self.last_match.is_none() {
+                    return Err("Run match before exporting results")?;
+                }
+                let run = self.last_match.as_ref().unwrap();
+                if *kind.unwrap() == "arrow" {
+                    write_ipc(path, run, compression)?;
+                } else {
+                    write_parquet(path, run, self.compression)?;
+                }
             }
+            "gpkg" => {
+                if self.last_match.is_none() {
+                    return Err("Run match before exporting results")?;
+                }
+                write_gpkg(path, self.last_match.as_ref().unwrap(), self.fetch_options.jitter)?;
+            }
+            _ => return Err("Invalid export type")?
+        }
+
+        println!("Wrote {}", path);
+        Ok(())
+    }
+
+    // Show how many values of a column are blank and distinct, the most common ones, and the
+    // range of a numeric column. A good compare column has few blanks and many distinct values
+    pub fn describe(&self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let file_index = input.get(1);
+        if file_index.is_none() {
+            return Err("file_index required")?;
         }
+        let file_index = file_index.unwrap().parse::<usize>()?;
 
-        output.set_headers(headers);
+        if input.len() < 3 {
+            return Err("col required")?;
+        }
+        let col = input[2..].join(" ");
 
-        // Make sure every column is an output column
-        for i in 0..width {
-            output.output_cols.push(i);
+        if file_index >= self.file_count {
+            return Err("Index out of Bounds")?;
         }
 
-        // We start by assuming that each file is internally consistent, meaning
-        // that if a location is duplicated inside it that is by design as they
-        // represent two separate entities.
-        // Now we begin the matching process. For each of the remaining dataframes,
-        // we need to find the nearest match, add it to its respective rows, and then
-        // average the latitude and longitude. If no match is found, we create a new row
-        // for the entry. On the first run no matches will be found so the dataframe will be
-        // essentially copied into the output
-        let mut col_index = 0;
+        let values = self.data_frames[file_index].column_values(&col)?;
+        let summary = summarize(&values, DESCRIBE_TOP);
+        let share = |count: usize, of: usize| if of == 0 {0.0} else {count as f64 * 100.0 / of as f64};
 
-        for df_index in 0..self.data_frames.len() {
-            // Clone dataframe so we can subtract from it as we match
-            let df = &self.data_frames[df_index];
-            let mut written_mask = Vec::with_capacity(df.shape.1);
-            for _ in 0..df.shape.1 {
-                written_mask.push(false);
+        println!("{} in file {}: {} rows", col, file_index, summary.rows);
+        println!("\tblank:\t\t{} ({:.1}%)", summary.blank, share(summary.blank, summary.rows));
+        println!("\tdistinct:\t{} ({:.1}% of non-blank)", summary.distinct, share(summary.distinct, summary.rows - summary.blank));
+        if let Some((min, max)) = summary.range {
+            println!("\tmin:\t\t{}", min);
+            println!("\tmax:\t\t{}", max);
+        }
+        if !summary.top.is_empty() {
+            println!("\ttop values:");
+            for (value, count) in summary.top.iter() {
+                println!("\t\t{}\t{}", count, value);
             }
-            let cols = df.output_headers().len();
-
-            // This part is a little bizarre, we are going to iterate throught the existing entries
-            // in the output dataframe. This keeps us from overwriting our matches and allows for a
-            // more uniform process for each dataframe
-            for row in 0..output.data()[0].len() {
-                let result = self.find_single_match(row, &output, &df, &written_mask);
+        }
 
-                if let Some((index, dist)) = result {
-                    // Add to output
-                    let output_cols = df.output_row(index);
-                    for col in 0..cols {
-                        output.data_mut()[col_index+col][row] = output_cols[col].clone();
-                    }
+        Ok(())
+    }
 
-                    // Add distance to output
-                    if df_index != 0 {
-                        output.data_mut()[col_index+cols][row] = dist.to_string();
-                    }
+    // Report the matches gained, lost and changed between two match files, e.g. from before and
+    // after changing the radius
+    pub fn diff(&self, input: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        let (old, new) = match (input.get(1), input.get(2)) {
+            (Some(old), Some(new)) => (self.output_path(old)?, self.output_path(new)?),
+            _ => return Err("old and new match files required")?
+        };
 
-                    // Average coordinates
-                    let lat = (output.lat().unwrap()[row] + df.lat().unwrap()[index]) * 0.5;
-                    let lng = (output.lng().unwrap()[row] + df.lng().unwrap()[index]) * 0.5;
+        let (differences, unchanged) = diff_matches(&old, &new, input.get(3).copied())?;
+        let lines: Vec<String> = differences.iter()
+            .map(|e| {
+                let old = e.old.as_deref().unwrap_or("unmatched");
+                let new = e.new.as_deref().unwrap_or("unmatched");
+                format!("{}\t{}\t{} -> {}", e.change, e.key, old, new)
+            })
+            .collect();
+        page(&lines);
+
+        let counts: Vec<String> = Change::ALL.iter()
+            .map(|change| format!("{} {}", differences.iter().filter(|e| e.change == *change).count(), change))
+            .collect();
+        println!("{}, {} unchanged", counts.join(", "), unchanged);
+        Ok(())
+    }
 
-                    output.lat_mut().unwrap()[row] = lat;
-                    output.lng_mut().unwrap()[row] = lng;
+    // Keep a command that ran without error for export script. Commands that only print or write
+    // extra outputs are left out, and a setting changed again since the last fetch or match only
+    // keeps its latest value
+    pub fn record_command(&mut self, input: &[&str]) {
+        if input.first().is_none_or(|e| OUTPUT_COMMANDS.contains(e)) {
+            return;
+        }
 
-                    // Set mask to not include for writing at the end
-                    written_mask[index] = true;
+        // A resumed fetch is the same step as the one it carries on from
+        let input: Vec<&str> = match input[0] {
+            "fetch" => input.iter().copied().filter(|e| *e != "resume").collect(),
+            _ => input.to_vec()
+        };
 
-                    // Set match_mask
-                    match_mask[row] = true;
+        // Anyone with the jitter seed can undo the offsets, so a script only gets a placeholder
+        let input: Vec<&str> = match input.as_slice() {
+            ["set", "jitter", mode, .., _] if mode.eq_ignore_ascii_case("random") => {
+                let mut input = input.clone();
+                *input.last_mut().unwrap() = JITTER_SEED;
+                input
+            }
+            _ => input
+        };
 
-                    bar.inc(1);
+        if let Some(key) = setting_key(&input) {
+            let start = self.script.iter()
+                .rposition(|e| matches!(split_input(e).first(), Some(&"fetch") | Some(&"match")))
+                .map_or(0, |e| e+1);
+            let mut i = start;
+            while i < self.script.len() {
+                if setting_key(&split_input(&self.script[i])).as_ref() == Some(&key) {
+                    self.script.remove(i);
+                } else {
+                    i += 1;
                 }
             }
+        }
 
-            // Now that we've fitered out all the matches, we can just append all the rest of the
-            // rows. On a left join we only do this if the dataframe index is 0
-            if self.match_mode!=MatchMode::LEFT || df_index==0 {
-                for row in 0..self.data_frames[df_index].shape.1 {
-                    if !self.exclusive || !written_mask[row] {
-                        // Fill previous slots with blanks
-                        for col in 0..col_index {
-                            output.data_mut()[col].push("".to_string());
-                        }
+        self.script.push(join_input(&input));
+    }
 
-                        // Fill in the actual data
-                        let output_cols = df.output_row(row);
-                        for col in 0..cols {
-                            output.data_mut()[col+col_index].push(output_cols[col].clone());
-                        }
+    // The commands recorded so far, with a header saying which files to run them against
+    fn script_text(&self) -> String {
+        let files: Vec<String> = self.loaded.iter().map(|e| join_input(&[e])).collect();
+        let mut text = format!("# Written by geomatch {} at {}\n", env!("CARGO_PKG_VERSION"), timestamp());
+        text += &format!("# Replay with: geomatch -k [key] {} < [this file]\n", files.join(" "));
+        text += "# or run it on new files with: geomatch -k [key] watch [dir] --pipeline [this file]\n";
+        if self.script.iter().any(|e| e.ends_with(JITTER_SEED)) {
+            text += &format!("# Put your jitter seed in place of {} first, it's left out so it isn't shared\n", JITTER_SEED);
+        }
+        for line in self.script.iter() {
+            text += line;
+            text += "\n";
+        }
+        text
+    }
 
-                        // Add distance column
-                        if df_index != 0 {
-                            output.data_mut()[col_index+cols].push(f32::NAN.to_string());
-                        }
+    // Outputs and summary counts of each fetch and match since the last call
+    pub fn take_runs(&mut self) -> Vec<Value> {
+        std::mem::take(&mut self.runs)
+    }
 
-                        output.lat_mut().unwrap().push(df.lat().unwrap()[row]);
-                        output.lng_mut().unwrap().push(df.lng().unwrap()[row]);
+    pub fn last_match(&self) -> Option<&MatchRun> {
+        self.last_match.as_ref()
+    }
 
-                        // Fill rest of slots with blanks
-                        for col in col_index+cols..width {
-                            output.data_mut()[col].push("".to_string());
-                        }
+    pub fn file_count(&self) -> usize {
+        self.file_count
+    }
 
-                        bar.inc(1);
-                    }
+    // Configuration and files, for reporting what a server job is set up to do
+    pub fn metadata(&self) -> Value {
+        json!({
+            "config": self.config_metadata(),
+            "files": self.data_frames.iter().map(|e| e.metadata()).collect::<Vec<Value>>(),
+        })
+    }
+
+    // Settings shared by every command, for run metadata
+    fn config_metadata(&self) -> Value {
+        json!({
+            "radius": self.radius,
+            "drive_time": self.drive_time,
+            "method": format!("{:?}", self.match_mode).to_lowercase(),
+            "exclusive": self.exclusive,
+            "bearing": self.bearing,
+            "routing": self.routing.as_ref().map(|e| e.to_string()),
+            "road_radius": self.road_radius,
+            "precision": self.precision,
+            "tie_seed": self.tie_seed,
+            "exact_precision": self.exact_precision,
+            "review": self.review.map(|e| e.to_string()),
+            "provider": self.provider.name(),
+            "s2_level": self.fetch_options.s2_level,
+            "w3w": self.fetch_options.w3w,
+            "blank_address": self.fetch_options.blank_address.as_ref().map(|e| e.iter().map(|e| e.name()).collect::<Vec<&str>>()),
+            "po_box": self.fetch_options.po_box.map(|e| e.name()),
+            "jitter": self.fetch_options.jitter.map(|e| e.to_string()),
+            "compress": self.compression.map(|e| e.name()),
+            "missing_coords": format!("{:?}", self.missing_coords).to_lowercase(),
+            "dates": self.date_order.map(|e| e.name()),
+            "split_by": self.split_by,
+            "fetch_output": self.fetch_output,
+            "match_output": self.match_output,
+            "countries": self.country_scope.as_ref().map(|e| e.to_string()),
+        })
+    }
+
+    // Configuration and content hash of every file, for run metadata
+    fn input_metadata(&self) -> Result<Vec<Value>, Box<dyn Error>> {
+        let mut inputs = Vec::with_capacity(self.data_frames.len());
+        for df in self.data_frames.iter() {
+            let mut metadata = df.metadata();
+            metadata["sha256"] = Value::String(sha256_file(df.path())?);
+            inputs.push(metadata);
+        }
+
+        Ok(inputs)
+    }
+
+    // Fill the output with every row of the first file next to every row of the second, with the
+    // distance between them. Pairs where either row has no coordinates are written without one
+    // and don't count as matched. Some(0) if Ctrl-C stopped it part way
+    fn cross_join(&self, output: &mut DataFrame, match_mask: &mut [bool], pairs: &mut Vec<MatchPair>, origins: &mut Vec<(usize, f64, f64)>, bar: &ProgressBar) -> Option<usize> {
+        let (df1, df2) = (&self.data_frames[0], &self.data_frames[1]);
+
+        for row1 in 0..df1.shape.1 {
+            if interrupt::requested() {
+                return Some(0);
+            }
+
+            let (lat1, lng1) = (df1.lat().unwrap()[row1], df1.lng().unwrap()[row1]);
+            let output1 = df1.coarse_output_row(row1, self.fetch_options.jitter, self.precision);
+            for row2 in 0..df2.shape.1 {
+                let (lat2, lng2) = (df2.lat().unwrap()[row2], df2.lng().unwrap()[row2]);
+                let row = output.data()[0].len();
+                let dist = haversine(lat1, lng1, lat2, lng2);
+
+                let mut record = output1.clone();
+                record.extend(df2.coarse_output_row(row2, self.fetch_options.jitter, self.precision));
+                record.push(format_coord(dist, self.precision));
+                if self.bearing {
+                    record.push(format_coord(bearing(lat1, lng1, lat2, lng2), self.precision));
                 }
-            } else {
-                bar.inc(written_mask.iter().filter(|e| !*e).count() as u64)
+                for (col, value) in record.into_iter().enumerate() {
+                    output.data_mut()[col].push(value);
+                }
+
+                output.lat_mut().unwrap().push(lat1);
+                output.lng_mut().unwrap().push(lng1);
+                origins.push((0, lat1, lng1));
+
+                if !dist.is_nan() {
+                    pairs.push(MatchPair {
+                        row,
+                        file: 1,
+                        from: (lat1, lng1),
+                        to: (lat2, lng2),
+                        dist,
+                    });
+                    match_mask[row] = true;
+                }
+                bar.inc(1);
             }
+        }
+
+        None
+    }
 
-            col_index += cols;
+    // Rows of each file without coordinates
+    fn missing_rows(&self) -> Vec<Vec<usize>> {
+        self.data_frames.iter()
+            .map(|df| match (df.lat(), df.lng()) {
+                (Some(lat), Some(lng)) => (0..df.shape.1).filter(|e| lat[*e].is_nan() || lng[*e].is_nan()).collect(),
+                _ => Vec::new()
+            })
+            .collect()
+    }
+
+    // Geocode rows without coordinates from their addresses before matching. The coordinates are
+    // only kept in memory, fetch writes them to a file. Rows the geocoder can't place, and rows of
+    // files without address columns, are left in missing
+    async fn geocode_missing(&mut self, missing: &mut [Vec<usize>]) -> Result<(), Box<dyn Error>> {
+        let mut order = Vec::new();
+        let mut queries = Vec::new();
+        for (file, rows) in missing.iter().enumerate() {
+            let df = &self.data_frames[file];
+            if !df.ready_to_fetch() {
+                println!("File {} has no address columns to geocode from.", file);
+                continue;
+            }
+            let addresses = df.addresses(&self.fetch_options);
+            for row in rows {
+                order.push((file, *row));
+                queries.push((df.provider(), addresses[*row].clone()));
+            }
         }
 
+        println!("Geocoding {} rows without coordinates:", order.len());
+        let bar = ProgressBar::new(order.len() as u64);
+        let mut results = geocode_stream(queries, self.keys.clone(), None, self.client.clone());
+        while let Some((index, result)) = results.recv().await {
+            let (file, row) = order[index];
+            let result = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    bar.abandon();
+                    return Err(format!("Geocoding rows without coordinates stopped: {}", e))?;
+                }
+            };
+            if result.found() {
+                let df = &mut self.data_frames[file];
+                df.lat_mut().unwrap()[row] = result.lat;
+                df.lng_mut().unwrap()[row] = result.lng;
+            }
+            bar.inc(1);
+        }
         bar.finish();
 
-        // At this point we theoretically have a complete dataset, lets write it to the filesystem
-        // and be done
+        // Ctrl-C stops geocoding, so interrupted rows are still missing too
+        for (file, rows) in missing.iter_mut().enumerate() {
+            let df = &self.data_frames[file];
+            rows.retain(|e| df.lat().unwrap()[*e].is_nan() || df.lng().unwrap()[*e].is_nan());
+        }
+
+        Ok(())
+    }
 
+    // Report of rows left out of a match for having no coordinates, by file and row with the
+    // address that would be geocoded
+    fn write_no_coords(&self, path: &str, missing: &[Vec<usize>], compression: Option<Compression>) -> Result<(), Box<dyn Error>> {
         let mut writer = WriterBuilder::new()
-            .delimiter('|' as u8)
-            .from_path("matches.csv")?;
+            .delimiter(b'|')
+            .from_writer(atomic::create_output(path, compression)?);
+        writer.write_record(["file", "row", "address"])?;
+        for (file, rows) in missing.iter().enumerate() {
+            let df = &self.data_frames[file];
+            let addresses = if df.ready_to_fetch() {df.addresses(&self.fetch_options)} else {Vec::new()};
+            for row in rows {
+                let address = addresses.get(*row).cloned().flatten().unwrap_or_default();
+                writer.write_record([file.to_string(), row.to_string(), address])?;
+            }
+        }
+        atomic::finish_output(writer.into_inner().map_err(|e| e.to_string())?, path)?;
 
-        writer.write_record(output.output_headers().as_slice())?;
+        Ok(())
+    }
 
-        // If match_mode is left, we only have items from the leftmost table already so no checks are
-        // required. If inner, we can use our match_mask to make sure only columns with existing matches exist
-        // Outer we just write everything as is
-        for row in 0..output.data()[0].len() {
-            if self.match_mode!=MatchMode::INNER || match_mask[row] {
-                writer.write_record(output.output_row(row).as_slice())?;
+    // Number of columns describing a match, added for every file after the first
+    fn match_columns(&self) -> usize {
+        1 + if self.bearing {1} else {0} + if self.routing.is_some() {2} else {0}
+    }
+
+    // Order of two equally good candidates for a row. Drawn from the seed when there is one, so the
+    // same seed always picks the same candidate whatever order the rows come in. Without one
+    // they stay in file order and the first wins
+    fn tie_order(&self, row: usize, a: usize, b: usize) -> std::cmp::Ordering {
+        match self.tie_seed {
+            Some(seed) => tie_rank(seed, row, a).cmp(&tie_rank(seed, row, b)),
+            None => std::cmp::Ordering::Equal,
+        }
+    }
+
+    fn wins_tie(&self, row: usize, candidate: usize, current: usize) -> bool {
+        self.tie_order(row, candidate, current) == std::cmp::Ordering::Less
+    }
+
+    // Pick whichever of the nearest candidates within the radius is closest by road, or by
+    // time with a drive time radius, along with its driving distance and time. Candidates over
+    // the road radius or drive time are dropped
+    // from is the row being matched and its coordinates
+    async fn find_routed_match(&self, router: &Router, client: &Client, from: (usize, f64, f64), df2: &DataFrame, points: &Points, written_mask: &[bool]) -> Result<Option<Candidate>, Box<dyn Error>> {
+        let (row, lat, lng) = from;
+
+        let mut candidates = Vec::new();
+        points.within(lat, lng, self.radius, &mut candidates);
+        candidates.retain(|(test_index, _)| !(self.exclusive && written_mask[*test_index]));
+
+        candidates.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(self.tie_order(row, a.0, b.0)));
+        candidates.truncate(ROUTE_CANDIDATES);
+
+        let mut best: Option<Candidate> = None;
+        for (test_index, dist) in candidates {
+            let to = (df2.lat().unwrap()[test_index], df2.lng().unwrap()[test_index]);
+            let road = router.route(client, self.keys.google.as_deref().unwrap_or(""), (lat, lng), to).await?;
+
+            if let Some((road_dist, road_time)) = road {
+                if self.road_radius.is_some_and(|max| road_dist > max) || self.drive_time.is_some_and(|max| road_time > max) {
+                    continue;
+                }
+
+                let better = match best.and_then(|e| e.2.map(|road| (e.0, road))) {
+                    Some((best_index, (_, best_time))) if self.drive_time.is_some() =>
+                        road_time < best_time || (road_time == best_time && self.wins_tie(row, test_index, best_index)),
+                    Some((best_index, (best_dist, _))) =>
+                        road_dist < best_dist || (road_dist == best_dist && self.wins_tie(row, test_index, best_index)),
+                    None => true
+                };
+                if better {
+                    best = Some((test_index, dist, Some((road_dist, road_time))));
+                }
+            }
+        }
+
+        Ok(best)
+    }
+
+    // A compare column is only checked against columns of the same type in the other file, so once
+    // any file has one every file needs a column of each type, or those candidates would all tie
+    fn check_compare_kinds(&self) -> Result<(), Box<dyn Error>> {
+        if self.data_frames.iter().all(|e| e.compare_kinds().is_empty()) {
+            return Ok(());
+        }
+
+        for (index, df) in self.data_frames.iter().enumerate() {
+            for kind in df.compare_kinds() {
+                if let Some((other, other_df)) = self.data_frames.iter().enumerate().find(|e| !e.1.compare_kinds().contains(kind)) {
+                    return Err(format!(
+                        "file {} ({}) compares {} columns but file {} ({}) has none to compare them with. Use `add {} {} [col]`",
+                        index, df.path(), kind.name(), other, other_df.path(), other, kind.name()
+                    ))?;
+                }
             }
         }
 
         Ok(())
     }
 
-    fn find_single_match(&self, record_index: usize, df1: &DataFrame, df2: &DataFrame, written_mask: &Vec<bool>) -> Option<(usize, f64)> {
+    // For each compare column of a candidate, how far it is from the closest compare column of the
+    // same type in the row being matched, leaving out columns with nothing to compare
+    fn compare_distances(&self, first_row: (usize, usize), df2: &DataFrame, test_index: usize) -> Vec<usize> {
+        let src_df = &self.data_frames[first_row.0];
+        let src_compare = src_df.compare_row(first_row.1);
+        let test_compare = df2.compare_row(test_index);
+
+        test_compare.iter().zip(df2.compare_kinds())
+            .filter_map(|(test_col, kind)| {
+                src_compare.iter().zip(src_df.compare_kinds())
+                    .filter(|e| e.1 == kind)
+                    .filter_map(|(src_col, _)| kind.distance(src_col, test_col, self.date_order))
+                    .min()
+            })
+            .collect()
+    }
+
+    // Out of 100, how alike a candidate's compare columns are to the row's, going by the column
+    // that is least alike. None without any to compare
+    fn compare_similarity(&self, first_row: (usize, usize), df2: &DataFrame, test_index: usize) -> Option<usize> {
+        self.compare_distances(first_row, df2, test_index).into_iter().max().map(|e| 100 - e.min(100))
+    }
+
+    // Both sides of a borderline match, the row as it started and the candidate, by output columns
+    fn review_records(&self, first_row: (usize, usize), df_index: usize, test_index: usize) -> (Record, Record) {
+        let record = |file: usize, row: usize| {
+            let df = &self.data_frames[file];
+            Record {
+                title: format!("file {} row {}", file, row),
+                fields: df.output_headers().into_iter().zip(df.output_row(row)).collect(),
+            }
+        };
+        (record(first_row.0, first_row.1), record(df_index, test_index))
+    }
+
+    // The output row's compare values are those of the file and row it started as, in first_row
+    fn find_single_match(&self, record_index: usize, df1: &DataFrame, df2: &DataFrame, written_mask: &[bool], first_row: (usize, usize)) -> Option<(usize, f64)> {
         let lat = df1.lat().unwrap()[record_index];
         let lng = df1.lng().unwrap()[record_index];
 
@@ -431,11 +3116,16 @@ impl State {
             return None;
         }
 
+        // Coordinates that round to the same thing count as exact
+        let scale = self.exact_precision.map(|e| 10f64.powi(e));
+        let snap = |val: f64| scale.map_or(val, |e| (val * e).round());
+        let (snapped_lat, snapped_lng) = (snap(lat), snap(lng));
+
         let mut exact: Vec<usize> = Vec::new();
         let mut min: Option<(usize, f64, f64, f64)> = None;
 
-        for test_index in 0..df2.shape.1 {
-            if self.exclusive && written_mask[test_index] {
+        for (test_index, written) in written_mask.iter().enumerate().take(df2.shape.1) {
+            if self.exclusive && *written {
                 continue;
             }
 
@@ -446,15 +3136,15 @@ impl State {
                 continue;
             }
 
-            if lat==test_lat && lng==test_lng {
+            if snapped_lat==snap(test_lat) && snapped_lng==snap(test_lng) {
                 exact.push(test_index);
                 continue;
-            } else if exact.len() != 0 {
+            } else if !exact.is_empty() {
                 continue;
             }
 
             let dist = linear(lat, lng, test_lat, test_lng);
-            if min.is_none() || dist < min.unwrap().3 {
+            if min.is_none_or(|e| dist < e.3 || (dist == e.3 && self.wins_tie(record_index, test_index, e.0))) {
                 min = Some((test_index, test_lat, test_lng, dist));
             }
         }
@@ -467,30 +3157,13 @@ impl State {
         // If we have multiple exact matches we have to guess with compare
         // columns which one suits it best
         if exact.len() > 1 {
-            let src_compare = df1.compare_row(record_index);
-
             // The basic idea here is to find the row that has the minimum squared 
             // distance from the compare row
             let mut min: Option<(usize, usize)> = None;
             for test_index in exact {
-                let test_compare = df2.compare_row(test_index);
-                let mut dist = 0;
-
-                // For each column find the closest compare column
-                for test_col in test_compare.iter() {
-                    let mut min_col_dist = None;
-                    for src_col in src_compare.iter() {
-                        let col_dist = 100-token_sort_ratio(&src_col, &test_col, true, true) as usize;
-                        if min_col_dist.is_none() || min_col_dist.unwrap() > col_dist {
-                            min_col_dist = Some(col_dist);
-                        }
-                    }
-                    if min_col_dist.is_some() {
-                        dist += min_col_dist.unwrap().pow(2);
-                    }
-                }
+                let dist: usize = self.compare_distances(first_row, df2, test_index).iter().map(|e| e.pow(2)).sum();
 
-                if min.is_none() || min.unwrap().1 > dist {
+                if min.is_none_or(|e| e.1 > dist || (e.1 == dist && self.wins_tie(record_index, test_index, e.0))) {
                     min = Some((test_index, dist));
                 }
             }
@@ -498,8 +3171,8 @@ impl State {
             return Some((min.unwrap().0, 0.0))
         }
 
-        if let Some((min_index, min_lat, min_lng, mut dist)) = min {
-            dist = haversine(lat, lng, min_lat, min_lng);
+        if let Some((min_index, min_lat, min_lng, _)) = min {
+            let dist = haversine(lat, lng, min_lat, min_lng);
             if dist > self.radius {
                 return None;
             }
@@ -512,15 +3185,367 @@ impl State {
     }
 }
 
+// Files without prefixes can easily share column names, which would give matches.csv
+// duplicate headers. Rename any repeats by suffixing the index of the file they came from
+fn disambiguate_headers(headers: &mut [String], sources: &[usize]) {
+    let mut seen: HashSet<String> = HashSet::with_capacity(headers.len());
+
+    for (header, source) in headers.iter_mut().zip(sources) {
+        if seen.insert(header.clone()) {
+            continue;
+        }
+
+        let mut renamed = format!("{}_{}", header, source);
+        let mut attempt = 2;
+        while seen.contains(&renamed) {
+            renamed = format!("{}_{}_{}", header, source, attempt);
+            attempt += 1;
+        }
+
+        println!("Output column '{}' from file {} is already used, writing it as '{}'. Use prefix to choose a name", header, source, renamed);
+        seen.insert(renamed.clone());
+        *header = renamed;
+    }
+}
+
+// Fill in {name} placeholders of an output file name, erroring on unknown names
+fn render_output_name(template: &str, vars: &[(&str, String)]) -> Result<String, Box<dyn Error>> {
+    let mut name = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}');
+        if end.is_none() {
+            return Err(format!("Unclosed {{ in '{}'", template).into());
+        }
+        let end = start + end.unwrap();
+
+        let key = &rest[start+1..end];
+        match vars.iter().find(|e| e.0 == key) {
+            Some((_, val)) => {
+                name.push_str(&rest[..start]);
+                name.push_str(val);
+            }
+            None => {
+                let known: Vec<&str> = vars.iter().map(|e| e.0).collect();
+                return Err(format!("Unknown field {{{}}}, expected one of {}", key, known.join(", ")).into());
+            }
+        }
+        rest = &rest[end+1..];
+    }
+    name.push_str(rest);
+
+    Ok(name)
+}
+
+// Add a column value to a match file name, e.g. matches.csv to matches_MN.csv
+fn split_output_name(path: &str, value: &str) -> String {
+    // Compression stays the last extension, so matches.csv.gz splits into matches_MN.csv.gz
+    if let Some(extension) = Compression::from_path(path).map(|e| e.extension()) {
+        return format!("{}{}", split_output_name(path.strip_suffix(extension).unwrap(), value), extension);
+    }
+
+    let mut value: String = value.trim().chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' {c} else {'_'})
+        .collect();
+    if value.is_empty() {
+        value = "blank".to_string();
+    }
+
+    let path = Path::new(path);
+    let stem = path.file_stem().map_or("matches".into(), |e| e.to_string_lossy());
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, value, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, value)
+    };
+
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+fn fetch_output_vars(stem: &str, index: usize) -> Vec<(&'static str, String)> {
+    let mut vars = vec![
+        ("stem", stem.to_string()),
+        ("index", index.to_string()),
+    ];
+    vars.extend(time_vars());
+    vars
+}
+
+// Current UTC date as YYYY-MM-DD and time as HHMMSS
+fn time_vars() -> Vec<(&'static str, String)> {
+    let (date, time) = utc_now();
+    vec![
+        ("date", date),
+        ("time", time.replace(':', "")),
+    ]
+}
+
+// Match file names for each value of the split column. Values that clean up to the same name, like
+// A/B and A_B, get a hash of the value added so their rows don't end up in one file
+fn split_output_names(path: &str, values: HashSet<String>) -> HashMap<String, String> {
+    let mut names: HashMap<String, Vec<String>> = HashMap::new();
+    for value in values {
+        names.entry(split_output_name(path, &value)).or_default().push(value);
+    }
+
+    let no_coords = no_coords_output_name(path);
+    let mut paths = HashMap::new();
+    for (name, values) in names {
+        let shared = values.len() > 1 || name == no_coords;
+        for value in values {
+            let name = if shared {
+                let hash = value.bytes().fold(0, |hash, e| splitmix64(hash ^ e as u64));
+                split_output_name(path, &format!("{}_{:08x}", value, hash as u32))
+            } else {
+                name.clone()
+            };
+            paths.insert(value, name);
+        }
+    }
+
+    paths
+}
+
+// Report of rows without coordinates next to the match output, matches_no_coords.csv for matches.csv
+fn no_coords_output_name(path: &str) -> String {
+    split_output_name(path, "no_coords")
+}
+
+// What a command sets, so a later command setting the same thing replaces it in export script.
+// None for commands that do something rather than set something
+fn setting_key(input: &[&str]) -> Option<String> {
+    match *input.first()? {
+        "set" if input.get(1).is_some_and(|e| e.parse::<usize>().is_ok()) => Some(input.get(..3)?.join(" ")),
+        "set" | "prefix" => Some(input.get(..2)?.join(" ")),
+        "method" | "radius" | "exclusive" | "bearing" | "sidecar" | "split" | "precision" | "seed" | "exact-precision"
+            | "review" | "provider" | "country" | "route" | "road-radius" | "missing" | "dates" => Some(input[0].to_string()),
+        "header-line" => Some(input.get(..2)?.join(" ")),
+        "alias" => Some(input.get(..3)?.join(" ")),
+        _ => None
+    }
+}
+
+// Value at fraction p of the way through sorted values, interpolating between the two either side
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p * (sorted.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+}
+
+// Pseudo random rank of a candidate for a row, the same for the same seed every run
+fn tie_rank(seed: u64, row: usize, candidate: usize) -> u64 {
+    splitmix64(seed ^ splitmix64(row as u64) ^ splitmix64(candidate as u64).rotate_left(32))
+}
+
+pub fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
 fn linear(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
     ((lat2 - lat1).powi(2) + (lng2 - lng1).powi(2)).sqrt()
 }
 
-fn haversine(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
-    let delta_lat = (lat2-lat1).to_radians();
+// Initial bearing in degrees clockwise from north, NaN if the points are the same
+fn bearing(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    if lat1 == lat2 && lng1 == lng2 {
+        return f64::NAN;
+    }
+
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
     let delta_lng = (lng2-lng1).to_radians();
 
-    let a = (delta_lat*0.5).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (delta_lng*0.5).sin().powi(2);
-    let c = 2.0 * a.sqrt().atan2((1.0-a).sqrt());
-    R * c
+    let y = delta_lng.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * delta_lng.cos();
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::command::run_command;
+
+    const A_CSV: &str = "name,lat,lng\none,44.97,-93.26\ntwo,44.98,-93.27\n";
+    const B_CSV: &str = "name,lat,lng\nuno,44.9701,-93.2601\ndos,44.9801,-93.2701\n";
+
+    // Session with each of files loaded in order, as a.csv, b.csv and so on, writing to dir
+    fn state_in(dir: &Path, files: &[&str]) -> State {
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut state = State::new(None);
+        for (index, contents) in files.iter().enumerate() {
+            let path = dir.join(format!("{}.csv", (b'a' + index as u8) as char));
+            std::fs::write(&path, contents).unwrap();
+            state.add_file(&path.to_string_lossy()).unwrap();
+        }
+        state.set_output_dir(&dir.to_string_lossy());
+        state
+    }
+
+    #[test]
+    fn output_path_stays_in_output_dir() {
+        let mut state = State::new(None);
+        state.set_output_dir("/tmp/job");
+        assert_eq!(state.output_path("matches.csv").unwrap(), "/tmp/job/matches.csv");
+        assert_eq!(state.output_path("out/matches.csv").unwrap(), "/tmp/job/out/matches.csv");
+        for name in ["../../etc/x", "/etc/x", "out/../../x", ""] {
+            assert!(state.output_path(name).is_err(), "{}", name);
+        }
+
+        // Without an output directory it's the user's own shell, so any path goes
+        state.output_dir = None;
+        assert_eq!(state.output_path("../x").unwrap(), "../x");
+    }
+
+    #[test]
+    fn split_values_that_clean_up_alike_get_their_own_files() {
+        let values = ["A/B", "A_B", "MN", "no_coords"].iter().map(|e| e.to_string()).collect();
+        let names = split_output_names("out/matches.csv", values);
+        assert_eq!(names["MN"], "out/matches_MN.csv");
+        assert_ne!(names["A/B"], names["A_B"]);
+        assert!(names["A/B"].starts_with("out/matches_A_B_"), "{}", names["A/B"]);
+        assert_ne!(names["no_coords"], no_coords_output_name("out/matches.csv"));
+    }
+
+    #[tokio::test]
+    async fn match_refuses_output_outside_output_dir() {
+        let dir = std::env::temp_dir().join(format!("geomatch_test_output_{}", std::process::id()));
+        let mut state = state_in(&dir, &[A_CSV, B_CSV]);
+
+        run_command(&mut state, split_input("set matchoutput ../../etc/x")).await.unwrap();
+        let err = run_command(&mut state, split_input("match")).await.unwrap_err();
+        assert!(err.to_string().contains("outside the output directory"), "{}", err);
+
+        for line in ["matrix 0 1 ../../etc/x", "export script /etc/x", "diff ../a.csv b.csv"] {
+            let err = run_command(&mut state, split_input(line)).await.unwrap_err();
+            assert!(err.to_string().contains("outside the output directory"), "{}: {}", line, err);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn compare_values_come_from_the_row_a_match_started_as() {
+        let dir = std::env::temp_dir().join(format!("geomatch_test_compare_{}", std::process::id()));
+        let mut state = state_in(&dir, &[
+            "name,lat,lng\nbeta,44.97,-93.26\n",
+            "name,lat,lng\nalpha,44.97,-93.26\nbeta,44.97,-93.26\n",
+        ]);
+
+        for line in ["add 0 compare name", "add 1 compare name", "add 0 output name", "add 1 output name", "match"] {
+            run_command(&mut state, split_input(line)).await.unwrap();
+        }
+        let run = state.last_match().unwrap();
+        let row = run.output.output_row(0);
+        assert_eq!(row.iter().filter(|e| *e == "beta").count(), 2, "{:?}", row);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn match_refuses_compare_columns_without_a_partner() {
+        let dir = std::env::temp_dir().join(format!("geomatch_test_compare_kinds_{}", std::process::id()));
+        let mut state = state_in(&dir, &[A_CSV, B_CSV]);
+        for line in ["add 0 output name", "add 1 output name", "add 0 phone name", "add 1 name name"] {
+            run_command(&mut state, split_input(line)).await.unwrap();
+        }
+
+        let err = run_command(&mut state, split_input("match")).await.unwrap_err();
+        assert!(err.to_string().contains("compares phone columns but file 1"), "{}", err);
+
+        run_command(&mut state, split_input("add 1 phone name")).await.unwrap();
+        run_command(&mut state, split_input("add 0 name name")).await.unwrap();
+        run_command(&mut state, split_input("match")).await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn exported_script_leaves_out_the_jitter_seed() {
+        let dir = std::env::temp_dir().join(format!("geomatch_test_seed_{}", std::process::id()));
+        let mut state = state_in(&dir, &[A_CSV, B_CSV]);
+        for line in ["set jitter random 500 m 8817", "export script script.txt"] {
+            run_command(&mut state, split_input(line)).await.unwrap();
+        }
+
+        let script = std::fs::read_to_string(dir.join("script.txt")).unwrap();
+        assert!(!script.contains("8817"), "{}", script);
+        assert!(script.contains("set jitter random 500 m [seed]"), "{}", script);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn review_needs_a_policy_without_anyone_to_ask() {
+        let dir = std::env::temp_dir().join(format!("geomatch_test_review_{}", std::process::id()));
+        let mut state = state_in(&dir, &[A_CSV, B_CSV]);
+        run_command(&mut state, split_input("radius 2")).await.unwrap();
+
+        run_command(&mut state, split_input("review 0")).await.unwrap();
+        let err = run_command(&mut state, split_input("match")).await.unwrap_err();
+        assert!(err.to_string().contains("review [near] [similarity] accept or reject"), "{}", err);
+
+        // Every match is borderline at near 0, so rejecting them leaves nothing matched
+        run_command(&mut state, split_input("review 0 reject")).await.unwrap();
+        run_command(&mut state, split_input("match")).await.unwrap();
+        assert!(state.last_match().unwrap().pairs.is_empty());
+
+        run_command(&mut state, split_input("review 0 accept")).await.unwrap();
+        run_command(&mut state, split_input("match")).await.unwrap();
+        assert_eq!(state.last_match().unwrap().pairs.len(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn country_off_puts_rows_back() {
+        let dir = std::env::temp_dir().join(format!("geomatch_test_country_{}", std::process::id()));
+        let mut state = state_in(&dir, &["name,country,lat,lng\none,US,44.97,-93.26\ntwo,UK,51.5,-0.12\nthree,GB,51.6,-0.13\nfour,CA,45.4,-75.7\n"]);
+        let names = |state: &State| state.data_frames[0].column_values("name").unwrap();
+
+        run_command(&mut state, split_input("country GB")).await.unwrap();
+        assert_eq!(names(&state), ["two", "three"]);
+        run_command(&mut state, split_input("country US CA")).await.unwrap();
+        assert_eq!(names(&state), ["one", "four"]);
+        run_command(&mut state, split_input("country off")).await.unwrap();
+        assert_eq!(names(&state), ["one", "two", "three", "four"]);
+        assert_eq!(state.data_frames[0].lat().unwrap()[1], 51.5);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn only_aliases_warn_about_duplicate_headers() {
+        let dir = std::env::temp_dir().join(format!("geomatch_test_headers_{}", std::process::id()));
+        let mut state = state_in(&dir, &["name,name,lat,lng\none,uno,44.97,-93.26\n"]);
+        let duplicates = |state: &State| state.problems().iter().filter(|e| e.message.contains("more than one column")).count();
+
+        // Numbered apart when loading, so nothing to warn about
+        for line in ["add 0 output name", "add 0 output name_2"] {
+            run_command(&mut state, split_input(line)).await.unwrap();
+        }
+        assert_eq!(duplicates(&state), 0);
+
+        run_command(&mut state, split_input("alias 0 name_2 name")).await.unwrap();
+        assert_eq!(duplicates(&state), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn concat_adds_nothing_when_a_file_fails() {
+        let dir = std::env::temp_dir().join(format!("geomatch_test_concat_{}", std::process::id()));
+        let mut state = state_in(&dir, &[A_CSV, B_CSV]);
+        std::fs::write(dir.join("c.csv"), "name,lat,lng\nthree,44.99,-93.28\n").unwrap();
+        std::fs::write(dir.join("d.csv"), "other,lat,lng\nfour,44.99,-93.28\n").unwrap();
+        let file = |name: &str| dir.join(name).to_string_lossy().to_string();
+
+        let (c, d) = (file("c.csv"), file("d.csv"));
+        assert!(state.concat(vec!["concat", "0", &c, &d]).is_err());
+        assert!(state.concat(vec!["concat", "0", &c, &file("missing.csv")]).is_err());
+        assert_eq!(state.data_frames[0].shape.1, 2);
+
+        state.concat(vec!["concat", "0", &c, &c]).unwrap();
+        assert_eq!(state.data_frames[0].shape.1, 4);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,46 @@
+// Names closest to one that wasn't found, for a "did you mean" hint. Case is ignored since long
+// vendor headers are rarely typed with the right capitals, and only names a few edits away are
+// kept so a hint is never a wild guess. Closest first, at most three
+pub fn closest<'a>(name: &str, candidates: &'a [String]) -> Vec<&'a str> {
+    let name = name.to_lowercase();
+    let most = (name.chars().count() / 3).max(2);
+
+    let mut found: Vec<(usize, &str)> = candidates.iter()
+        .map(|e| (edit_distance(&name, &e.to_lowercase()), e.as_str()))
+        .filter(|e| e.0 <= most)
+        .collect();
+    found.sort_by_key(|e| e.0);
+    found.into_iter().take(3).map(|e| e.1).collect()
+}
+
+// "; did you mean 'A'?" or "; did you mean 'A', 'B' or 'C'?", blank without any
+pub fn did_you_mean(name: &str, candidates: &[String]) -> String {
+    let names: Vec<String> = closest(name, candidates).iter().map(|e| format!("'{}'", e)).collect();
+    match names.as_slice() {
+        [] => String::new(),
+        [only] => format!("; did you mean {}?", only),
+        [rest @ .., last] => format!("; did you mean {} or {}?", rest.join(", "), last),
+    }
+}
+
+// Levenshtein distance, the fewest single character inserts, deletes and swaps between the two
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j+1];
+            row[j+1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+
+    row[b.len()]
+}
@@ -0,0 +1,45 @@
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use std::error::Error;
+use std::fs::File;
+use std::sync::Mutex;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
+
+// Send the spans and events fetch and match record, like each geocoding request and the time
+// spent in each phase, to a file as json lines or to an OTLP collector given as an http(s) url.
+// Nothing is recorded unless this is called
+pub fn init(dest: &str) -> Result<(), Box<dyn Error>> {
+    if dest.starts_with("http://") || dest.starts_with("https://") {
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(dest))
+            .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                KeyValue::new("service.name", "geomatch"),
+                KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+            ])))
+            .install_batch(runtime::Tokio)?;
+
+        tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .try_init()?;
+    } else {
+        // Closed spans carry how long they took, e.g. the latency of each request
+        let file = File::create(dest).map_err(|e| format!("{}: {}", dest, e))?;
+        tracing_subscriber::fmt()
+            .json()
+            .with_span_events(FmtSpan::CLOSE)
+            .with_current_span(true)
+            .with_writer(Mutex::new(file))
+            .try_init()
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+// Send spans still waiting in the batch before quitting
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,69 @@
+use regex::Regex;
+use std::error::Error;
+
+// Simple cleanup operations that can be applied to a column in place
+pub enum Transform {
+    Trim,
+    Upper,
+    Lower,
+    Replace(Regex, String),
+    Substring(usize, Option<usize>),
+}
+
+impl Transform {
+    // Parse an expression such as `upper`, `replace \s+ " "` or `substr 0 5`
+    pub fn parse(expr: &[&str]) -> Result<Transform, Box<dyn Error>> {
+        let op = expr.first();
+        if op.is_none() {
+            return Err("expr required")?;
+        }
+
+        match op.unwrap().to_lowercase().as_str() {
+            "trim" => Ok(Transform::Trim),
+            "upper" => Ok(Transform::Upper),
+            "lower" => Ok(Transform::Lower),
+            "replace" => {
+                let pattern = expr.get(1);
+                if pattern.is_none() {
+                    return Err("pattern required")?;
+                }
+                let pattern = Regex::new(pattern.unwrap())?;
+                let replacement = expr.get(2).unwrap_or(&"").to_string();
+
+                Ok(Transform::Replace(pattern, replacement))
+            }
+            "substr" => {
+                let start = expr.get(1);
+                if start.is_none() {
+                    return Err("start required")?;
+                }
+                let start = start.unwrap().parse::<usize>()?;
+                let len = match expr.get(2) {
+                    Some(len) => Some(len.parse::<usize>()?),
+                    None => None
+                };
+
+                Ok(Transform::Substring(start, len))
+            }
+            _ => Err("Invalid transform, expected trim, upper, lower, replace or substr")?
+        }
+    }
+
+    pub fn apply(&self, val: &str) -> String {
+        match self {
+            Transform::Trim => val.trim().to_string(),
+            Transform::Upper => val.to_uppercase(),
+            Transform::Lower => val.to_lowercase(),
+            Transform::Replace(pattern, replacement) => {
+                pattern.replace_all(val, replacement.as_str()).to_string()
+            }
+            Transform::Substring(start, len) => {
+                let chars = val.chars().skip(*start);
+                match len {
+                    Some(len) => chars.take(*len).collect(),
+                    None => chars.collect()
+                }
+            }
+        }
+    }
+}
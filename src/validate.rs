@@ -0,0 +1,34 @@
+use std::fmt::{Display, Formatter};
+
+// Something wrong with the session, found by the validate command
+pub struct Problem {
+    pub blocks: Option<&'static str>,   // command it stops from running, if any
+    pub warning: bool,                  // worth knowing about, but nothing fails because of it
+    pub message: String,                // what is wrong and how to fix it
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.blocks {
+            Some(command) => command,
+            None if self.warning => "warning",
+            None => "error",
+        };
+        write!(f, "[{}] {}", kind, self.message)
+    }
+}
+
+impl Problem {
+    // Stops fetch or match from running
+    pub fn blocks(command: &'static str, message: String) -> Problem {
+        Problem {blocks: Some(command), warning: false, message}
+    }
+
+    pub fn error(message: String) -> Problem {
+        Problem {blocks: None, warning: false, message}
+    }
+
+    pub fn warning(message: String) -> Problem {
+        Problem {blocks: None, warning: true, message}
+    }
+}
@@ -0,0 +1,35 @@
+use reqwest::Client;
+use serde_json::Value;
+use std::error::Error;
+
+const W3W_URL: &str = "https://api.what3words.com/v3/convert-to-3wa";
+
+// Column fetch writes the three word address of each row to
+pub const W3W_COLUMN: &str = "w3w";
+
+// Written in place of the words for a row whose lookup failed. Real ones always have dots
+pub const W3W_FAILED: &str = "failed";
+
+// The what3words address of a point, e.g. filled.count.soap, for field teams that navigate by it
+pub async fn three_words(client: &Client, lat: f64, lng: f64, key: &str) -> Result<String, Box<dyn Error>> {
+    let coordinates = format!("{},{}", lat, lng);
+    let json: Value = client.get(W3W_URL)
+        .query(&[("coordinates", coordinates.as_str()), ("key", key), ("format", "json")])
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    // Errors come back as {"error": {"code": "InvalidKey", "message": "..."}}
+    if let Some(error) = json.get("error") {
+        return Err(format!("what3words {}: {}",
+            error["code"].as_str().unwrap_or("error"),
+            error["message"].as_str().unwrap_or("no reason given")
+        ).into());
+    }
+
+    match json["words"].as_str() {
+        Some(words) => Ok(words.to_string()),
+        None => Err("what3words sent back no words".into())
+    }
+}
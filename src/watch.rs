@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{create_dir_all, read_dir, read_to_string};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use super::command::{run_command, split_input};
+use super::metrics::{count, render, serve_metrics};
+use super::notify::notify;
+use super::state::State;
+
+// What to watch for and the pipeline to run on each new file
+pub struct WatchOptions {
+    pub dir: String,
+    pub pattern: String,        // file names to pick up, with * and ? wildcards
+    pub pipeline: String,       // file of commands to run, one per line
+    pub out: String,            // results for each file go in <out>/<stem>
+    pub with: Vec<String>,      // files loaded after the new one, e.g. a reference file to match against
+    pub interval: Duration,
+    pub notify: Option<String>,     // webhook to post a summary to after each file
+    pub metrics: Option<SocketAddr>,    // where to serve GET /metrics, if anywhere
+}
+
+// Files the pipeline finished or failed on, and files seen that are waiting to settle
+static FILES_DONE: AtomicU64 = AtomicU64::new(0);
+static FILES_FAILED: AtomicU64 = AtomicU64::new(0);
+static FILES_PENDING: AtomicU64 = AtomicU64::new(0);
+static LAST_FILE: AtomicU64 = AtomicU64::new(0);    // unix time the last file finished, 0 before any
+
+// Size and modification time, a file is picked up once this stops changing between checks
+type Signature = (u64, SystemTime);
+
+// Check the directory every interval and run the pipeline on each new or changed file that
+// matches the pattern. The new file is loaded as file 0, followed by the with files. Files
+// already there when watching starts are left alone
+pub async fn watch<F: Fn() -> State>(options: WatchOptions, new_state: F) -> Result<(), Box<dyn Error>> {
+    let pipeline: Vec<String> = read_to_string(&options.pipeline)?
+        .lines()
+        .map(|e| e.trim().to_string())
+        .filter(|e| !e.is_empty() && !e.starts_with('#'))
+        .collect();
+    if pipeline.is_empty() {
+        return Err(format!("{} has no commands", options.pipeline).into());
+    }
+
+    let mut seen = scan(&options)?;
+    let mut pending: HashMap<PathBuf, Signature> = HashMap::new();
+    if let Some(addr) = options.metrics {
+        serve_metrics(addr, metrics)?;
+    }
+    println!("Watching {} for {}", options.dir, options.pattern);
+
+    loop {
+        tokio::time::sleep(options.interval).await;
+
+        let files = scan(&options)?;
+        let waiting = files.iter().filter(|(path, signature)| seen.get(*path) != Some(*signature)).count();
+        FILES_PENDING.store(waiting as u64, Ordering::Relaxed);
+
+        for (path, signature) in files {
+            if seen.get(&path) == Some(&signature) {
+                continue;
+            }
+
+            // Wait for one more check without changes so files still being copied in are skipped
+            if pending.get(&path) != Some(&signature) {
+                pending.insert(path, signature);
+                continue;
+            }
+
+            pending.remove(&path);
+            seen.insert(path.clone(), signature);
+
+            println!("Processing {}", path.display());
+            let mut state = new_state();
+            let result = run_pipeline(&path, &pipeline, &options, &mut state).await;
+            match result.as_ref() {
+                Ok(out) => println!("Finished {}, results in {}", path.display(), out),
+                Err(e) => println!("Failed {}: {}", path.display(), e),
+            }
+            count(if result.is_ok() {&FILES_DONE} else {&FILES_FAILED}, 1);
+            FILES_PENDING.fetch_sub(1, Ordering::Relaxed);
+            let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |e| e.as_secs());
+            LAST_FILE.store(now, Ordering::Relaxed);
+
+            if let Some(url) = options.notify.as_ref() {
+                notify(url, &json!({
+                    "mode": "watch",
+                    "file": path,
+                    "status": if result.is_ok() {"done"} else {"failed"},
+                    "out": result.as_ref().ok(),
+                    "error": result.as_ref().err().map(|e| e.to_string()),
+                    "runs": state.take_runs(),
+                })).await;
+            }
+        }
+    }
+}
+
+async fn run_pipeline(path: &Path, pipeline: &[String], options: &WatchOptions, state: &mut State) -> Result<String, Box<dyn Error>> {
+    let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+    let out = Path::new(&options.out).join(stem).to_string_lossy().to_string();
+    create_dir_all(&out)?;
+    state.set_output_dir(&out);
+
+    state.add_file(&path.to_string_lossy())?;
+    for file in options.with.iter() {
+        state.add_file(file)?;
+    }
+
+    for line in pipeline {
+        let input = split_input(line);
+        if matches!(input.first(), Some(&"quit") | Some(&"help")) {
+            continue;
+        }
+        if let Err(e) = run_command(state, input).await {
+            return Err(format!("{}: {}", line, e).into());
+        }
+    }
+
+    Ok(out)
+}
+
+// The provider and match counters every mode keeps, along with how watching is going
+fn metrics() -> String {
+    let load = |e: &AtomicU64| e.load(Ordering::Relaxed) as f64;
+    render(&[
+        ("geomatch_watch_files_total", "Files the pipeline ran on, by how it went", vec![
+            ("status=\"done\"".to_string(), load(&FILES_DONE)),
+            ("status=\"failed\"".to_string(), load(&FILES_FAILED)),
+        ]),
+    ], &[
+        ("geomatch_watch_files_pending", "New or changed files waiting for the pipeline", vec![(String::new(), load(&FILES_PENDING))]),
+        ("geomatch_watch_last_file_timestamp_seconds", "Unix time the pipeline last finished a file", vec![(String::new(), load(&LAST_FILE))]),
+    ])
+}
+
+// Files in the directory matching the pattern
+fn scan(options: &WatchOptions) -> Result<HashMap<PathBuf, Signature>, Box<dyn Error>> {
+    let mut files = HashMap::new();
+    for entry in read_dir(&options.dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() || !glob_match(&options.pattern, &entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        files.insert(entry.path(), (metadata.len(), metadata.modified()?));
+    }
+
+    Ok(files)
+}
+
+// Match a name against a pattern where * is any run of characters and ? is any one character
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Last * seen and the name position it is currently standing in for, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|e| *e == '*')
+}